@@ -4,7 +4,6 @@ use rayon::prelude::*;
 use ungoliant::identifiers::FastText;
 use ungoliant::sources::commoncrawl::Wet;
 
-
 const NB_RECORDS: usize = 250;
 // bench protocol:
 //
@@ -71,7 +70,7 @@ fn parallel_on_records(nb_shards: usize) {
         .map(|d| Wet::from_path_gzip(d.unwrap().path()).unwrap())
         .take(nb_shards);
     for wetfile in results {
-        let records = wetfile.iter.into_iter().take(NB_RECORDS).par_bridge();
+        let records = wetfile.iter.take(NB_RECORDS).par_bridge();
         records.for_each(|record| {
             let record = record.unwrap();
             let body = String::from_utf8(record.body().to_vec()).ok();
@@ -0,0 +1,93 @@
+//! Record throughput of [OscarMetadata::process_shard], over generated sample shards.
+//!
+//! Shards of small (single-line) records are processed with different record chunk sizes
+//! (see [OscarMetadata::with_record_chunk_size]), to show the effect of per-task overhead.
+//!
+//! Needs `lid.176.bin` in the working directory.
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use flate2::{write::GzEncoder, Compression};
+use ungoliant::identifiers::FastText;
+use ungoliant::pipelines::OscarMetadata;
+use warc::{BufferedBody, Record, WarcWriter};
+
+const NB_RECORDS: usize = 200;
+const NB_SMALL_RECORDS: usize = 2000;
+
+const SENTENCES: [&str; 4] = [
+    "This is an english sentence that is long enough to be identified by the pipeline, hopefully.",
+    "Ceci est une phrase en français, suffisamment longue pour être identifiée par le pipeline.",
+    "Dies ist ein deutscher Satz, der lang genug ist, um von der Pipeline erkannt zu werden.",
+    "short line",
+];
+
+/// Write a gzipped shard of `nb_records` records of `nb_lines` lines, mixing languages and short lines.
+fn sample_shard(path: &Path, nb_records: usize, nb_lines: usize) {
+    let mut enc = GzEncoder::new(File::create(path).unwrap(), Compression::default());
+    {
+        let mut writer = WarcWriter::new(&mut enc);
+        for i in 0..nb_records {
+            let body: Vec<&str> = (0..nb_lines)
+                .map(|j| SENTENCES[(i + j) % SENTENCES.len()])
+                .collect();
+            let record: Record<BufferedBody> = Record::default().add_body(body.join("\n"));
+            writer.write(&record).unwrap();
+        }
+    }
+    enc.finish().unwrap();
+}
+
+fn process_shard(c: &mut Criterion) {
+    let dir = tempfile::tempdir().unwrap();
+    let shard = dir.path().join("0.txt.gz");
+    sample_shard(&shard, NB_RECORDS, 20);
+
+    let cls = FastText::new_lid().unwrap();
+    let p = OscarMetadata::new(
+        vec![dir.path().to_path_buf()],
+        dir.path().to_path_buf(),
+        PathBuf::from("lid.176.bin"),
+        1,
+        None,
+    );
+
+    let mut group = c.benchmark_group("process_shard");
+    group.throughput(Throughput::Elements(NB_RECORDS as u64));
+    group.bench_function("records", |b| {
+        b.iter(|| p.process_shard(0, &shard, &cls).unwrap())
+    });
+    group.finish();
+}
+
+fn process_small_records(c: &mut Criterion) {
+    let dir = tempfile::tempdir().unwrap();
+    let shard = dir.path().join("0.txt.gz");
+    sample_shard(&shard, NB_SMALL_RECORDS, 1);
+
+    let cls = FastText::new_lid().unwrap();
+
+    let mut group = c.benchmark_group("process_small_records");
+    group.throughput(Throughput::Elements(NB_SMALL_RECORDS as u64));
+    for chunk_size in [1, 8, 64] {
+        let p = OscarMetadata::new(
+            vec![dir.path().to_path_buf()],
+            dir.path().to_path_buf(),
+            PathBuf::from("lid.176.bin"),
+            1,
+            None,
+        )
+        .with_record_chunk_size(chunk_size)
+        .unwrap();
+        group.bench_with_input(
+            BenchmarkId::from_parameter(chunk_size),
+            &chunk_size,
+            |b, _| b.iter(|| p.process_shard(0, &shard, &cls).unwrap()),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, process_shard, process_small_records);
+criterion_main!(benches);
@@ -23,6 +23,16 @@ pub enum Ungoliant {
     Rebuild(Rebuild),
     #[structopt(about = "check for corpus validity")]
     Check(Check),
+    #[structopt(about = "merge per-language outputs of multiple runs")]
+    Merge(Merge),
+}
+
+#[derive(Debug, StructOpt)]
+pub struct Merge {
+    #[structopt(parse(from_os_str), help = "destination corpus location")]
+    pub dst: PathBuf,
+    #[structopt(parse(from_os_str), help = "source corpus locations, merged in order")]
+    pub inputs: Vec<PathBuf>,
 }
 
 #[derive(Debug, StructOpt)]
@@ -149,14 +159,23 @@ pub struct Download {
 ///     -m, --with_metadata    extract metadata
 ///
 /// ARGS:
-///     <src>    source (contains n.txt.gz)
+///     <src>    source (contains n.txt.gz, or a single n.txt.gz shard)
 ///     <dst>    pipeline result destination
 /// ```
 pub struct Pipeline {
-    #[structopt(parse(from_os_str), help = "source (contains n.txt.gz)")]
+    #[structopt(
+        parse(from_os_str),
+        help = "source (contains n.txt.gz, or a single n.txt.gz shard)"
+    )]
     pub src: PathBuf,
     #[structopt(parse(from_os_str), help = "pipeline result destination")]
     pub dst: PathBuf,
+    #[structopt(
+        parse(from_os_str),
+        long = "extra-src",
+        help = "Additional source, whose shards are written to the same destination (can be repeated). Shard numbers have to be unique across sources."
+    )]
+    pub extra_src: Vec<PathBuf>,
     #[structopt(
         parse(from_os_str),
         long = "lid-path",
@@ -170,4 +189,25 @@ pub struct Pipeline {
         help = "Optional path to blocklist."
     )]
     pub blocklist: Option<PathBuf>,
+    #[structopt(
+        parse(from_os_str),
+        long = "lang-thresholds",
+        help = "Optional path to a JSON file mapping languages to identification thresholds (ex. {\"br\": 0.6})."
+    )]
+    pub lang_thresholds: Option<PathBuf>,
+    #[structopt(
+        long = "json-logs",
+        help = "Log run events (shard start/end/failure, per-language writes) as JSON lines."
+    )]
+    pub json_logs: bool,
+    #[structopt(
+        long = "deterministic",
+        help = "Write documents in shard order, so that runs on the same input produce the same files (slower, uses more memory)."
+    )]
+    pub deterministic: bool,
+    #[structopt(
+        long = "min-doc-confidence",
+        help = "Drop documents whose identification probability is below this value (multilingual documents are kept)."
+    )]
+    pub min_doc_confidence: Option<f32>,
 }
@@ -1,4 +1,6 @@
 //! Error enum
+use std::fmt;
+use std::path::{Path, PathBuf};
 use std::string::FromUtf8Error;
 
 use crate::pipelines::oscardoc::types::IncompleteLocation;
@@ -8,6 +10,8 @@ use crate::pipelines::oscardoc::types::IncompleteLocation;
 pub enum Error {
     Io(std::io::Error),
     Warc(warc::Error),
+    /// Last record of a shard that ends unexpectedly (see [crate::sources::commoncrawl::Records]).
+    TruncatedRecord(warc::Error),
     UnknownLang(String),
     MetadataConversion(FromUtf8Error),
     Custom(String),
@@ -19,6 +23,104 @@ pub enum Error {
     IncompleteLocation(IncompleteLocation),
     Avro(avro_rs::Error),
     Csv(csv::Error),
+    ThreadPool(rayon::ThreadPoolBuildError),
+    Parquet(parquet::errors::ParquetError),
+    /// Error of a record, located by its index in its shard and/or its WARC record id (see [Error::in_record]).
+    Record {
+        record_idx: Option<usize>,
+        record_id: Option<String>,
+        source: Box<Error>,
+    },
+    /// Error of a shard, located by its index and path if known (see [Error::in_shard]).
+    Shard {
+        shard_idx: usize,
+        path: Option<PathBuf>,
+        source: Box<Error>,
+    },
+}
+
+impl Error {
+    /// Locate the error in a record.
+    pub fn in_record(self, record_idx: Option<usize>, record_id: Option<String>) -> Self {
+        Self::Record {
+            record_idx,
+            record_id,
+            source: Box::new(self),
+        }
+    }
+
+    /// Locate the error in a shard.
+    pub fn in_shard(self, shard_idx: usize, path: Option<&Path>) -> Self {
+        Self::Shard {
+            shard_idx,
+            path: path.map(Path::to_path_buf),
+            source: Box::new(self),
+        }
+    }
+
+    /// Get the underlying error, without location context.
+    pub fn root(&self) -> &Error {
+        match self {
+            Self::Record { source, .. } | Self::Shard { source, .. } => source.root(),
+            e => e,
+        }
+    }
+}
+
+/// Errors with location context are displayed as `shard 1 ("path"): record 2 (<urn:uuid:...>): error`,
+/// while other errors are displayed using their [fmt::Debug] implementation.
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Record {
+                record_idx,
+                record_id,
+                source,
+            } => {
+                write!(f, "record")?;
+                if let Some(record_idx) = record_idx {
+                    write!(f, " {}", record_idx)?;
+                }
+                if let Some(record_id) = record_id {
+                    write!(f, " ({})", record_id)?;
+                }
+                write!(f, ": {}", source)
+            }
+            Self::Shard {
+                shard_idx,
+                path,
+                source,
+            } => {
+                write!(f, "shard {}", shard_idx)?;
+                if let Some(path) = path {
+                    write!(f, " ({:?})", path)?;
+                }
+                write!(f, ": {}", source)
+            }
+            e => write!(f, "{:?}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Record { source, .. } | Self::Shard { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<parquet::errors::ParquetError> for Error {
+    fn from(v: parquet::errors::ParquetError) -> Self {
+        Self::Parquet(v)
+    }
+}
+
+impl From<rayon::ThreadPoolBuildError> for Error {
+    fn from(v: rayon::ThreadPoolBuildError) -> Self {
+        Self::ThreadPool(v)
+    }
 }
 
 impl From<csv::Error> for Error {
@@ -78,3 +180,27 @@ impl From<serde_json::Error> for Error {
         Error::Serde(e)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::Error;
+
+    #[test]
+    fn context() {
+        let e = Error::Warc(warc::Error::ReadOverflow)
+            .in_record(Some(2), Some("<urn:uuid:0>".to_string()))
+            .in_shard(1, Some(Path::new("1.txt.gz")));
+        assert_eq!(
+            e.to_string(),
+            r#"shard 1 ("1.txt.gz"): record 2 (<urn:uuid:0>): Warc(ReadOverflow)"#
+        );
+        assert!(matches!(e.root(), Error::Warc(_)));
+
+        let e = Error::Custom("oops".to_string())
+            .in_record(None, None)
+            .in_shard(1, None);
+        assert_eq!(e.to_string(), r#"shard 1: record: Custom("oops")"#);
+    }
+}
@@ -0,0 +1,62 @@
+//! Content-level filtering.
+//!
+//! Those filters take the newline-separated content of a merged piece or of a document.
+use super::Filter;
+
+/// Minimum content length, either in unicode codepoints or in lines.
+///
+/// Returns `false` if provided content is shorter than the minimum.
+/// Newlines do not count towards [ContentLength::Chars].
+///
+/// The default minimum is 0 characters, which keeps everything.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentLength {
+    Chars(usize),
+    Lines(usize),
+}
+
+impl Filter<&str> for ContentLength {
+    fn detect(&self, content: &str) -> bool {
+        match self {
+            Self::Chars(min) => {
+                content
+                    .lines()
+                    .map(|line| line.chars().count())
+                    .sum::<usize>()
+                    >= *min
+            }
+            Self::Lines(min) => content.lines().count() >= *min,
+        }
+    }
+}
+
+impl Default for ContentLength {
+    fn default() -> Self {
+        Self::Chars(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chars() {
+        let f = ContentLength::Chars(6);
+        assert!(f.detect("abc\ndef"));
+        assert!(!f.detect("abc\nde"));
+        assert!(!f.detect(""));
+    }
+
+    #[test]
+    fn lines() {
+        let f = ContentLength::Lines(2);
+        assert!(f.detect("abc\ndef"));
+        assert!(!f.detect("abcdef"));
+    }
+
+    #[test]
+    fn default_keeps_everything() {
+        assert!(ContentLength::default().detect(""));
+    }
+}
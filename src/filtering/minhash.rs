@@ -0,0 +1,216 @@
+//! Near-duplicate sentence filtering using MinHash.
+//!
+//! Sentences are split into shingles of consecutive words, and summarized by a MinHash signature
+//! whose agreement with another signature estimates the Jaccard similarity of their shingle sets.
+//! Signatures are bucketed by bands (Locality-Sensitive Hashing), so that a sentence is only compared to
+//! previously kept sentences that share a band with it.
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+use twox_hash::XxHash64;
+
+use super::FilterMut;
+use crate::error::Error;
+
+/// Number of hashes of each signature.
+const NB_HASHES: usize = 128;
+
+/// Scramble `x` (splitmix64 finalizer), used to derive the signature hashes from a single shingle hash.
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+/// Near-duplicate filter, holding the signatures of kept sentences.
+///
+/// [FilterMut::detect_mut] returns `false` for sentences whose estimated Jaccard similarity
+/// to an already kept sentence is at least [NearDuplicates::threshold], and keeps track of the other ones.
+pub struct NearDuplicates {
+    shingle_size: usize,
+    threshold: f32,
+    /// number of rows of each band
+    rows: usize,
+    signatures: Vec<[u64; NB_HASHES]>,
+    /// indices of signatures, by (band, band hash)
+    buckets: HashMap<(usize, u64), Vec<usize>>,
+}
+
+impl NearDuplicates {
+    /// Create a new filter using shingles of `shingle_size` words
+    /// and dropping sentences that are at least `threshold` similar to a kept one.
+    ///
+    /// Bands are sized so that pairs around `threshold` similarity are likely to be compared.
+    ///
+    /// # Errors
+    /// Returns an error if `shingle_size` is 0 or if `threshold` is not in `(0, 1]`.
+    pub fn new(shingle_size: usize, threshold: f32) -> Result<Self, Error> {
+        if shingle_size == 0 {
+            return Err(Error::Custom("shingle size has to be positive".to_string()));
+        }
+        if !(threshold > 0.0 && threshold <= 1.0) {
+            return Err(Error::Custom(format!(
+                "similarity threshold has to be in (0, 1]: {}",
+                threshold
+            )));
+        }
+
+        // pick the band size whose LSH threshold ((1/b)^(1/r)) is the closest to `threshold`
+        let rows = (0..=NB_HASHES.trailing_zeros())
+            .map(|exp| 1 << exp)
+            .min_by(|a: &usize, b: &usize| {
+                let lsh_threshold = |rows: usize| {
+                    let bands = (NB_HASHES / rows) as f32;
+                    (1.0 / bands).powf(1.0 / rows as f32)
+                };
+                (lsh_threshold(*a) - threshold)
+                    .abs()
+                    .total_cmp(&(lsh_threshold(*b) - threshold).abs())
+            })
+            // there's always at least one candidate
+            .unwrap();
+
+        Ok(Self {
+            shingle_size,
+            threshold,
+            rows,
+            signatures: Vec::new(),
+            buckets: HashMap::new(),
+        })
+    }
+
+    /// Get the shingle size, in words.
+    pub fn shingle_size(&self) -> usize {
+        self.shingle_size
+    }
+
+    /// Get the similarity threshold.
+    pub fn threshold(&self) -> f32 {
+        self.threshold
+    }
+
+    /// Compute the MinHash signature of `sentence`.
+    ///
+    /// Sentences shorter than a shingle are a single shingle.
+    fn signature(&self, sentence: &str) -> [u64; NB_HASHES] {
+        let words: Vec<&str> = sentence.split_whitespace().collect();
+        let mut signature = [u64::MAX; NB_HASHES];
+        for shingle in words.windows(self.shingle_size.min(words.len().max(1))) {
+            let mut hasher = XxHash64::default();
+            shingle.hash(&mut hasher);
+            let hash = hasher.finish();
+            for (i, min) in signature.iter_mut().enumerate() {
+                *min = (*min).min(mix(hash ^ mix(i as u64)));
+            }
+        }
+        signature
+    }
+
+    /// Estimate the Jaccard similarity of two signatures.
+    fn similarity(a: &[u64; NB_HASHES], b: &[u64; NB_HASHES]) -> f32 {
+        let agreeing = a.iter().zip(b.iter()).filter(|(a, b)| a == b).count();
+        agreeing as f32 / NB_HASHES as f32
+    }
+
+    /// Get the (band, band hash) buckets of a signature.
+    fn bands(&self, signature: &[u64; NB_HASHES]) -> Vec<(usize, u64)> {
+        signature
+            .chunks(self.rows)
+            .enumerate()
+            .map(|(band, rows)| {
+                let mut hasher = XxHash64::default();
+                rows.hash(&mut hasher);
+                (band, hasher.finish())
+            })
+            .collect()
+    }
+}
+
+impl Default for NearDuplicates {
+    /// Shingles of 3 words and a `0.8` similarity threshold.
+    fn default() -> Self {
+        // parameters are valid
+        Self::new(3, 0.8).unwrap()
+    }
+}
+
+impl FilterMut<&str> for NearDuplicates {
+    /// Check if `sentence` is not a near-duplicate of a kept sentence, keeping track of it if so.
+    ///
+    /// Empty sentences (without words) are always kept, and don't affect the filter.
+    fn detect_mut(&mut self, sentence: &str) -> bool {
+        if sentence.split_whitespace().next().is_none() {
+            return true;
+        }
+
+        let signature = self.signature(sentence);
+        let bands = self.bands(&signature);
+
+        let is_duplicate = bands.iter().any(|band| {
+            self.buckets.get(band).is_some_and(|candidates| {
+                candidates.iter().any(|idx| {
+                    Self::similarity(&signature, &self.signatures[*idx]) >= self.threshold
+                })
+            })
+        });
+        if is_duplicate {
+            return false;
+        }
+
+        let idx = self.signatures.len();
+        self.signatures.push(signature);
+        for band in bands {
+            self.buckets.entry(band).or_default().push(idx);
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid() {
+        assert!(NearDuplicates::new(0, 0.8).is_err());
+        assert!(NearDuplicates::new(3, 0.0).is_err());
+        assert!(NearDuplicates::new(3, 1.1).is_err());
+        assert!(NearDuplicates::new(3, 1.0).is_ok());
+    }
+
+    #[test]
+    fn exact_duplicates() {
+        let mut f = NearDuplicates::new(3, 1.0).unwrap();
+        assert!(f.detect_mut("accept cookies to continue browsing"));
+        assert!(!f.detect_mut("accept cookies to continue browsing"));
+        // whitespace is not part of shingles
+        assert!(!f.detect_mut("accept  cookies to continue\tbrowsing"));
+        assert!(f.detect_mut("accept cookies to continue reading"));
+    }
+
+    #[test]
+    fn near_duplicates() {
+        let template = |product: &str| {
+            format!(
+                "buy the {} today at the best price with free shipping and a two year warranty on every order placed before midnight on our online store",
+                product
+            )
+        };
+        let mut f = NearDuplicates::new(2, 0.7).unwrap();
+        assert!(f.detect_mut(&template("red kettle")));
+        assert!(!f.detect_mut(&template("blue kettle")));
+        assert!(f.detect_mut(
+            "the weather in paris is expected to be sunny for the rest of the week according to forecasts"
+        ));
+    }
+
+    #[test]
+    fn short_sentences() {
+        let mut f = NearDuplicates::default();
+        assert!(f.detect_mut("hello"));
+        assert!(!f.detect_mut("hello"));
+        assert!(f.detect_mut("hello world"));
+        assert!(f.detect_mut(""));
+        assert!(f.detect_mut(" "));
+    }
+}
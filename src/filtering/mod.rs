@@ -1,6 +1,6 @@
 /*! Filtering utilities
 
-Filters can operate on sentence or record level.
+Filters can operate on sentence, record or content (merged piece/document) level.
 
 Filters implement [filter::Filter], [filter::FilterMut] or both:
 - [filter::Filter] is implemented for filters that do not have state (see [sentence::Length] for example)
@@ -8,10 +8,17 @@ Filters implement [filter::Filter], [filter::FilterMut] or both:
 
 Both can be implemented for a given filter,
 in order to provide a mutable detection that could be used to "train" the filter, then an immutable one to effectively filter content.
+
+Sentences can be cleaned up beforehand using a [normalizer::Normalizer],
+and lines can be split into finer-grained sentences using a [splitter::SentenceSplitter].
 !*/
+pub mod content;
 mod filter;
+pub mod minhash;
+pub mod normalizer;
 pub mod record;
 pub mod sentence;
+pub mod splitter;
 
 pub use filter::Filter;
 pub use filter::FilterMut;
@@ -0,0 +1,191 @@
+//! Sentence normalization.
+//!
+//! Normalizers clean sentences up before they're filtered and identified,
+//! so that invisible characters don't count towards sentence length or skew identification.
+//!
+//! A [TextTransform] can also be applied to sentences once identified, right before they're written,
+//! for downstream tools that expect normalized text.
+use std::borrow::Cow;
+
+use unicode_normalization::{is_nfc_quick, is_nfkc_quick, IsNormalized, UnicodeNormalization};
+
+/// Normalizes a sentence.
+///
+/// Implementors should return [Cow::Borrowed] when the sentence is left untouched
+/// to avoid allocations on already clean content.
+pub trait Normalizer: Send + Sync {
+    fn normalize<'a>(&self, sentence: &'a str) -> Cow<'a, str>;
+}
+
+/// Whitespace normalizer:
+/// - strips control characters and zero-width spaces (`U+200B`, `U+2060`, `U+FEFF`),
+/// - collapses runs of whitespace (including tabs and non-breaking spaces) into a single space,
+/// - trims leading and trailing whitespace.
+///
+/// Zero-width (non-)joiners (`U+200C`, `U+200D`) are kept,
+/// as they're part of the orthography of some languages (e.g. Persian).
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Whitespace;
+
+impl Whitespace {
+    /// Check if `c` has to be removed.
+    fn is_stripped(c: char) -> bool {
+        matches!(c, '\u{200B}' | '\u{2060}' | '\u{FEFF}') || (c.is_control() && !c.is_whitespace())
+    }
+
+    /// Check if `sentence` is already normalized.
+    fn is_normalized(sentence: &str) -> bool {
+        // leading whitespace has to be trimmed
+        let mut prev_space = true;
+        for c in sentence.chars() {
+            if Self::is_stripped(c) {
+                return false;
+            }
+            if c.is_whitespace() {
+                if c != ' ' || prev_space {
+                    return false;
+                }
+                prev_space = true;
+            } else {
+                prev_space = false;
+            }
+        }
+
+        // trailing whitespace has to be trimmed
+        !sentence.ends_with(' ')
+    }
+}
+
+impl Normalizer for Whitespace {
+    fn normalize<'a>(&self, sentence: &'a str) -> Cow<'a, str> {
+        if Self::is_normalized(sentence) {
+            return Cow::Borrowed(sentence);
+        }
+
+        let mut normalized = String::with_capacity(sentence.len());
+        let mut pending_space = false;
+        for c in sentence.chars() {
+            if Self::is_stripped(c) {
+                continue;
+            }
+            if c.is_whitespace() {
+                // only add a space if there's something before
+                pending_space = !normalized.is_empty();
+                continue;
+            }
+            if pending_space {
+                normalized.push(' ');
+                pending_space = false;
+            }
+            normalized.push(c);
+        }
+
+        Cow::Owned(normalized)
+    }
+}
+
+/// Transform applied to sentences before writing.
+///
+/// Transforms are applied line by line and must not change line structure,
+/// since line numbers and offsets of written pieces refer to the original lines
+/// (see [TextTransform::preserves_line_structure]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextTransform {
+    /// Unicode canonical composition (NFC).
+    Nfc,
+    /// Unicode compatibility composition (NFKC).
+    Nfkc,
+    /// Lowercasing (see [str::to_lowercase]).
+    Lowercase,
+}
+
+impl TextTransform {
+    /// Count line breaks (as in [str::lines], along with Unicode line and paragraph separators).
+    #[cfg(test)]
+    fn nb_line_breaks(line: &str) -> usize {
+        line.chars()
+            .filter(|c| matches!(c, '\n' | '\r' | '\u{85}' | '\u{2028}' | '\u{2029}'))
+            .count()
+    }
+
+    /// Check if the transform never adds nor removes line breaks
+    /// (as in [str::lines], along with Unicode line and paragraph separators).
+    ///
+    /// Built-in transforms do: line breaks have no case and are their own NFC/NFKC forms,
+    /// and no other character composes or decomposes into one.
+    pub fn preserves_line_structure(&self) -> bool {
+        match self {
+            Self::Nfc | Self::Nfkc | Self::Lowercase => true,
+        }
+    }
+
+    /// Transform `line`, returning [Cow::Borrowed] when it's left untouched.
+    ///
+    /// Line structure is kept (see [TextTransform::preserves_line_structure]).
+    pub fn apply<'a>(&self, line: &'a str) -> Cow<'a, str> {
+        match self {
+            Self::Nfc if is_nfc_quick(line.chars()) == IsNormalized::Yes => Cow::Borrowed(line),
+            Self::Nfc => Cow::Owned(line.nfc().collect()),
+            Self::Nfkc if is_nfkc_quick(line.chars()) == IsNormalized::Yes => Cow::Borrowed(line),
+            Self::Nfkc => Cow::Owned(line.nfkc().collect()),
+            Self::Lowercase if !line.chars().any(char::is_uppercase) => Cow::Borrowed(line),
+            Self::Lowercase => Cow::Owned(line.to_lowercase()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use super::*;
+
+    #[test]
+    fn whitespace_borrowed() {
+        let n = Whitespace;
+        assert!(matches!(n.normalize("already clean"), Cow::Borrowed(_)));
+        assert!(matches!(n.normalize(""), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn whitespace() {
+        let n = Whitespace;
+        assert_eq!(n.normalize("  a\t\tb\u{00A0} c  "), "a b c");
+        assert_eq!(n.normalize("a\u{200B}b\u{FEFF}c\u{0007}"), "abc");
+        assert_eq!(n.normalize(" \u{200B} "), "");
+        assert_eq!(n.normalize("می\u{200C}خواهم"), "می\u{200C}خواهم");
+    }
+
+    #[test]
+    fn text_transform() {
+        // e + combining acute accent
+        let decomposed = "cafe\u{0301}";
+        assert_eq!(TextTransform::Nfc.apply(decomposed), "café");
+        assert_eq!(TextTransform::Nfkc.apply("ﬁne ①"), "fine 1");
+        assert_eq!(TextTransform::Nfc.apply("ﬁne"), "ﬁne");
+        assert_eq!(TextTransform::Lowercase.apply("Hello ÉTÉ"), "hello été");
+        assert!(matches!(
+            TextTransform::Lowercase.apply("already lower"),
+            Cow::Borrowed(_)
+        ));
+        assert!(matches!(TextTransform::Nfc.apply("café"), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn text_transform_line_structure() {
+        let line = "A\u{85}B\u{2028}C\u{2029}D\r\nÉ\u{0301}\u{FE64}\u{2424}";
+        for transform in [
+            TextTransform::Nfc,
+            TextTransform::Nfkc,
+            TextTransform::Lowercase,
+        ] {
+            assert!(transform.preserves_line_structure());
+            assert_eq!(
+                TextTransform::nb_line_breaks(&transform.apply(line)),
+                TextTransform::nb_line_breaks(line),
+                "{:?}",
+                transform
+            );
+        }
+    }
+}
@@ -0,0 +1,160 @@
+//! Sentence splitting.
+//!
+//! WET lines are coarse: a line can hold a whole paragraph, or even several of them.
+//! A [SentenceSplitter] splits lines into finer-grained sentences before they're filtered and identified.
+//!
+//! Sentences are returned as byte ranges of their line, so that they can be traced back to it:
+//! pipelines keep the line number of the line for each of its sentences.
+use std::ops::Range;
+
+/// Splits a line into sentences.
+pub trait SentenceSplitter: Send + Sync {
+    /// Split `line` into sentences, returned as non-overlapping byte ranges of `line`, in line order.
+    ///
+    /// Implementors may leave out whitespace between sentences, but should not return empty ranges.
+    fn split(&self, line: &str) -> Vec<Range<usize>>;
+}
+
+/// Keeps lines as a whole.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NoSplit;
+
+impl SentenceSplitter for NoSplit {
+    #[allow(clippy::single_range_in_vec_init)]
+    fn split(&self, line: &str) -> Vec<Range<usize>> {
+        vec![0..line.len()]
+    }
+}
+
+/// Punctuation-based splitter.
+///
+/// A sentence ends after a terminator (`.`, `!`, `?`, `…`, `؟`, `।`),
+/// optionally followed by closing quotes or brackets, if it is followed by whitespace.
+/// Full-width terminators (`。`, `！`, `？`) end a sentence even without whitespace.
+/// Whitespace around sentences is left out.
+///
+/// This is a simple heuristic: abbreviations (such as `e.g. this`) are split too,
+/// while decimal numbers and URLs are not, since they're not followed by whitespace.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Punctuation;
+
+impl Punctuation {
+    fn is_terminator(c: char) -> bool {
+        matches!(c, '.' | '!' | '?' | '…' | '؟' | '।')
+    }
+
+    fn is_full_width_terminator(c: char) -> bool {
+        matches!(c, '。' | '！' | '？')
+    }
+
+    fn is_closing(c: char) -> bool {
+        matches!(
+            c,
+            '"' | '\'' | ')' | ']' | '»' | '”' | '’' | '」' | '』' | '）'
+        )
+    }
+
+    /// Push the trimmed `range` of `line` into `sentences`, if it is not blank.
+    fn push(line: &str, range: Range<usize>, sentences: &mut Vec<Range<usize>>) {
+        let sentence = &line[range.clone()];
+        let start = range.start + (sentence.len() - sentence.trim_start().len());
+        let end = range.start + sentence.trim_end().len();
+        if start < end {
+            sentences.push(start..end);
+        }
+    }
+}
+
+impl SentenceSplitter for Punctuation {
+    fn split(&self, line: &str) -> Vec<Range<usize>> {
+        let mut sentences = Vec::new();
+        let mut start = 0;
+        let mut chars = line.char_indices().peekable();
+
+        while let Some((idx, c)) = chars.next() {
+            let full_width = Self::is_full_width_terminator(c);
+            if !full_width && !Self::is_terminator(c) {
+                continue;
+            }
+
+            // include repeated terminators and closing quotes/brackets
+            let mut end = idx + c.len_utf8();
+            while let Some(&(next_idx, next)) = chars.peek() {
+                if Self::is_terminator(next)
+                    || Self::is_full_width_terminator(next)
+                    || Self::is_closing(next)
+                {
+                    end = next_idx + next.len_utf8();
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+
+            let at_boundary = match chars.peek() {
+                Some((_, next)) => full_width || next.is_whitespace(),
+                None => true,
+            };
+            if at_boundary {
+                Self::push(line, start..end, &mut sentences);
+                start = end;
+            }
+        }
+        Self::push(line, start..line.len(), &mut sentences);
+
+        sentences
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split<'a>(splitter: &dyn SentenceSplitter, line: &'a str) -> Vec<&'a str> {
+        splitter
+            .split(line)
+            .into_iter()
+            .map(|range| &line[range])
+            .collect()
+    }
+
+    #[test]
+    fn no_split() {
+        assert_eq!(split(&NoSplit, "Hello. World!"), vec!["Hello. World!"]);
+    }
+
+    #[test]
+    fn punctuation() {
+        assert_eq!(
+            split(
+                &Punctuation,
+                "Hello there. How are you?! Fine... \"Really.\" Yes"
+            ),
+            vec![
+                "Hello there.",
+                "How are you?!",
+                "Fine...",
+                "\"Really.\"",
+                "Yes"
+            ]
+        );
+    }
+
+    #[test]
+    fn punctuation_no_whitespace() {
+        // decimals, urls, and terminators that are not followed by whitespace
+        assert_eq!(
+            split(&Punctuation, "Pi is 3.14 see example.com!"),
+            vec!["Pi is 3.14 see example.com!"]
+        );
+        assert_eq!(split(&Punctuation, "  "), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn punctuation_full_width() {
+        assert_eq!(
+            split(&Punctuation, "你好。你好吗？很好"),
+            vec!["你好。", "你好吗？", "很好"]
+        );
+    }
+}
@@ -1,10 +1,10 @@
 //! Fasttext identifier
-use std::{collections::HashMap, path::Path, str::Lines};
+use std::{collections::HashMap, io::Write, path::Path, sync::Arc};
 
-use crate::{error::Error, lang::Lang};
+use crate::{error::Error, lang::LANG};
 use fasttext::{FastText as FastTextLib, Prediction};
 
-use super::{identifier, Identification, Identifier};
+use super::{identifier, Identification, LanguageIdentifier};
 
 /// Clean the prediction label field from `__label__xx` into `xx`.
 ///
@@ -27,13 +27,22 @@ fn clean_prediction(prediction: &Prediction) -> Result<Prediction, String> {
     })
 }
 
+/// Loaded fasttext model, shareable between [FastText] instances (and threads).
+pub type FastTextModel = Arc<FastTextLib>;
+
 /// Holds a [fasttext::FastText] instance and its parameters:
 /// - [fasttext::FastText::k], number of predicted languages on a sentence
 /// - [FastText::threshold], prediction threshold
+/// - optional per-language thresholds, overriding [FastText::threshold] for some labels
+///
+/// The model is shared (see [FastTextModel]): cloning is cheap and doesn't reload it.
+/// Predictions only need a shared reference, so that a [FastText] can be used concurrently.
+#[derive(Clone)]
 pub struct FastText {
-    predictor: FastTextLib,
+    predictor: FastTextModel,
     pub k: i32,
     pub threshold: f32,
+    lang_thresholds: HashMap<&'static str, f32>,
 }
 
 impl FastText {
@@ -57,6 +66,17 @@ impl FastText {
     ///
     /// See [fasttext::FastText::predict] for other parameters explanation
     pub fn new(filename: &Path, k: i32, threshold: f32) -> Result<Self, Error> {
+        let predictor = Self::load_model(filename)?;
+        Ok(Self::from_model(predictor, k, threshold))
+    }
+
+    /// Load a fasttext model, so that it can be shared between classifiers (see [Self::from_model]).
+    ///
+    /// filename has to be a path to a `bin` file.
+    ///
+    /// # Errors
+    /// Propagates [fasttext::FastText] errors.
+    pub fn load_model(filename: &Path) -> Result<FastTextModel, Error> {
         let mut predictor = FastTextLib::new();
         let filename_str = filename.to_str();
         match filename_str {
@@ -66,88 +86,166 @@ impl FastText {
             ))),
             Some(filename) => {
                 predictor.load_model(filename)?;
-                Ok(Self {
-                    predictor,
-                    k,
-                    threshold,
-                })
+                Ok(Arc::new(predictor))
             }
         }
     }
 
+    /// Create a new fasttext classifier from the content of a `bin` file
+    /// (such as an embedded resource or a downloaded buffer).
+    ///
+    /// See [Self::load_model_from_bytes] for caveats,
+    /// and [fasttext::FastText::predict] for other parameters explanation.
+    pub fn from_bytes(bytes: &[u8], k: i32, threshold: f32) -> Result<Self, Error> {
+        let predictor = Self::load_model_from_bytes(bytes)?;
+        Ok(Self::from_model(predictor, k, threshold))
+    }
+
+    /// Load a fasttext model from the content of a `bin` file (see [Self::load_model]).
+    ///
+    /// The fasttext bindings can only load models from a path, so `bytes` are written to a temporary file
+    /// (a new one in [std::env::temp_dir] for each call) that is removed once the model is loaded,
+    /// whether loading succeeded or not.
+    /// This needs a writable temporary directory with room for the model.
+    ///
+    /// # Errors
+    /// Returns an error if the temporary file can't be written, and propagates [fasttext::FastText] errors.
+    pub fn load_model_from_bytes(bytes: &[u8]) -> Result<FastTextModel, Error> {
+        let f = tempfile::Builder::new()
+            .prefix("ungoliant-lid-")
+            .suffix(".bin")
+            .tempfile()?;
+        Self::load_model_from_file(bytes, f)
+    }
+
+    /// Write `bytes` to `f` and load them as a model, removing `f` afterwards.
+    fn load_model_from_file(
+        bytes: &[u8],
+        mut f: tempfile::NamedTempFile,
+    ) -> Result<FastTextModel, Error> {
+        let model = f
+            .write_all(bytes)
+            .and_then(|_| f.flush())
+            .map_err(Error::from)
+            .and_then(|_| Self::load_model(f.path()));
+        f.close()?;
+        model
+    }
+
+    /// Create a new fasttext classifier from an already loaded model.
+    ///
+    /// See [fasttext::FastText::predict] for other parameters explanation
+    pub fn from_model(predictor: FastTextModel, k: i32, threshold: f32) -> Self {
+        Self {
+            predictor,
+            k,
+            threshold,
+            lang_thresholds: HashMap::new(),
+        }
+    }
+
+    /// Get a handle on the underlying model.
+    pub fn model(&self) -> FastTextModel {
+        self.predictor.clone()
+    }
+
+    /// Ensures that every label of `lang_thresholds` is a known language (see [LANG]).
+    ///
+    /// # Errors
+    /// Returns [Error::UnknownLang] on the first unknown label.
+    pub fn check_lang_thresholds(
+        lang_thresholds: &HashMap<&'static str, f32>,
+    ) -> Result<(), Error> {
+        match lang_thresholds.keys().find(|label| !LANG.contains(*label)) {
+            Some(label) => Err(Error::UnknownLang(label.to_string())),
+            None => Ok(()),
+        }
+    }
+
+    /// Set per-language thresholds, that override [Self::threshold] for the provided labels.
+    ///
+    /// # Errors
+    /// Returns an error if a label is not in [LANG].
+    pub fn set_lang_thresholds(
+        &mut self,
+        lang_thresholds: HashMap<&'static str, f32>,
+    ) -> Result<(), Error> {
+        Self::check_lang_thresholds(&lang_thresholds)?;
+        self.lang_thresholds = lang_thresholds;
+        Ok(())
+    }
+
+    /// Get the threshold to use for a given (cleaned) label,
+    /// falling back to [Self::threshold] if there's no specific one.
+    pub fn threshold_for(&self, label: &str) -> f32 {
+        *self.lang_thresholds.get(label).unwrap_or(&self.threshold)
+    }
+
+    /// lowest threshold, used to query the model before filtering by label.
+    fn min_threshold(&self) -> f32 {
+        self.lang_thresholds
+            .values()
+            .fold(self.threshold, |acc, t| acc.min(*t))
+    }
+
     /// predict for supplied sentence.
     /// returns Ok(None) if no reliable identification has been done.
     pub fn predict(&self, sentence: &str) -> Result<Option<Vec<Prediction>>, String> {
-        let predictions = self.predictor.predict(sentence, self.k, self.threshold)?;
+        self.predict_with_k(sentence, self.k)
+    }
+
+    /// predict up to `k` labels for supplied sentence, ordered by decreasing probability.
+    ///
+    /// Predictions that do not meet [Self::threshold] are discarded,
+    /// so fewer than `k` labels can be returned.
+    /// returns Ok(None) if no reliable identification has been done.
+    pub fn predict_topk(
+        &self,
+        sentence: &str,
+        k: usize,
+    ) -> Result<Option<Vec<Prediction>>, String> {
+        let k = i32::try_from(k).map_err(|e| format!("invalid k ({}): {:?}", k, e))?;
+        self.predict_with_k(sentence, k)
+    }
+
+    fn predict_with_k(&self, sentence: &str, k: i32) -> Result<Option<Vec<Prediction>>, String> {
+        let mut predictions = self.predictor.predict(sentence, k, self.min_threshold())?;
+
+        // fasttext should already return sorted predictions,
+        // but we ensure it since callers rely on index 0 being the best one.
+        predictions.sort_by(|a, b| b.prob.total_cmp(&a.prob));
+
+        // attempt to clean labels, then filter using label-specific thresholds
+        let predictions: Vec<Prediction> = predictions
+            .into_iter()
+            .map(|p| clean_prediction(&p).unwrap_or(p))
+            .filter(|p| p.prob >= self.threshold_for(&p.label))
+            .collect();
 
         if predictions.is_empty() {
             Ok(None)
         } else {
-            // attempt to clean labels before returning
-            Ok(Some(
-                predictions
-                    .into_iter()
-                    .map(|p| clean_prediction(&p).unwrap_or(p))
-                    .collect(),
-            ))
+            Ok(Some(predictions))
         }
     }
+}
 
-    /// Identifies each line, then returns both identifications for each line _and_
-    /// a HashMap holding (byte_count, sum(byte_count*prob) / total count).
-    pub fn get_weighted_ids(
-        &self,
-        lines: Lines,
-    ) -> Result<
-        (
-            Vec<Option<Identification>>,
-            HashMap<Option<Lang>, (usize, f32)>,
-            usize,
-        ),
-        Error,
-    > {
-        // per-lang and total byte counts
-        // lang_count maps Lang -> (lang_byte_count, sum(byte_count*prob))
-        let mut lang_count = HashMap::new();
-        let mut total_count = 0;
-
-        // filter out unicode null chars
-        // this prevents fasttext errors and hopefully improves
-        // corpus quality
-        let lines = lines.map(|l| l.replace(char::from(0), ""));
-        let ids: Vec<Option<Identification>> = lines
-            .map(|line| {
-                // identify
-                let id = self.identify(line.as_str());
-
-                // add to byte count for document-level identification
-                if let Ok(ref ide) = id {
-                    // map Identification to its lang, or keep None to store the "None" language identification
-                    let ide_label = ide.as_ref().map(|i| *i.label());
-                    let ide_prob = ide.as_ref().map(|i| *i.prob());
-                    // get length of current line
-                    let byte_count = line.bytes().count();
-
-                    lang_count
-                        .entry(ide_label)
-                        .and_modify(|(count, count_times_prob)| {
-                            *count += byte_count;
-                            *count_times_prob += byte_count as f32 * ide_prob.unwrap_or(1.0f32);
-                        })
-                        .or_insert((byte_count, byte_count as f32 * ide_prob.unwrap_or(1.0f32)));
-
-                    total_count += byte_count;
-                }
-                id
-            })
-            .collect::<Result<_, Error>>()?;
-
-        // divide by total count to get probs between 0 and 1.
-        for (_, count_times_prob) in lang_count.values_mut() {
-            *count_times_prob /= total_count as f32;
-        }
+impl LanguageIdentifier for FastText {
+    /// predict up to [FastText::k] labels (see [FastText::predict]).
+    fn predict(&self, text: &str) -> Result<Option<Vec<Prediction>>, Error> {
+        FastText::predict(self, text).map_err(Error::FastText)
+    }
 
-        Ok((ids, lang_count, total_count))
+    /// predict the most probable label, regardless of thresholds.
+    fn predict_best(&self, text: &str) -> Result<Option<Prediction>, Error> {
+        let predictions = self
+            .predictor
+            .predict(text, 1, 0.0)
+            .map_err(Error::FastText)?;
+        Ok(predictions
+            .into_iter()
+            .max_by(|a, b| a.prob.total_cmp(&b.prob))
+            .map(|p| clean_prediction(&p).unwrap_or(p)))
     }
 }
 
@@ -155,23 +253,69 @@ impl identifier::Identifier<&str> for FastText {
     fn identify(&self, sentence: &str) -> Result<Option<Identification>, Error> {
         let prediction = self
             .predictor
-            .predict(sentence, 1, self.threshold)
+            .predict(sentence, 1, self.min_threshold())
             .map_err(Error::FastText)?;
         // let prediction = prediction.sort_by(|a, b| a.prob.partial_cmp(&b.prob)).iter().take(1);
 
-        if !prediction.is_empty() {
+        match prediction.first() {
             // TODO: There should be a solution without resorting to clone()
-            let prediction = prediction[0].clone();
-            Ok(Some(prediction.into()))
-        } else {
-            Ok(None)
+            Some(p) => {
+                let identification = Identification::from(p.clone());
+                if identification.prob() >= &self.threshold_for(identification.label().to_static())
+                {
+                    Ok(Some(identification))
+                } else {
+                    Ok(None)
+                }
+            }
+            None => Ok(None),
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use rayon::prelude::*;
+
     use super::*;
+    use crate::lang::Lang;
+
+    #[test]
+    fn test_from_bytes() {
+        let bytes = std::fs::read("lid.176.bin").unwrap();
+        let cls = FastText::from_bytes(&bytes, 1, 0.8).unwrap();
+        let sentence = "This sentence is an english sentence, that should be identified as being from the english language.";
+        let ids = cls.predict(sentence).unwrap().unwrap();
+        assert_eq!(ids[0].label, "en");
+    }
+
+    #[test]
+    fn test_from_bytes_invalid() {
+        assert!(FastText::from_bytes(b"not a model", 1, 0.8).is_err());
+
+        // the temporary file is removed
+        let f = tempfile::NamedTempFile::new().unwrap();
+        let path = f.path().to_path_buf();
+        assert!(FastText::load_model_from_file(b"not a model", f).is_err());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_shared_model() {
+        let model = FastText::load_model(Path::new("lid.176.bin")).unwrap();
+        let topk = FastText::from_model(model.clone(), 3, 0.0);
+        let top1 = FastText::from_model(model, 1, 0.8);
+        assert!(Arc::ptr_eq(&topk.model(), &top1.model()));
+
+        let sentence = "This sentence is an english sentence, that should be identified as being from the english language.";
+        (0..8).into_par_iter().for_each(|_| {
+            let cls = top1.clone();
+            let ids = cls.predict(sentence).unwrap().unwrap();
+            assert_eq!(ids.len(), 1);
+            assert_eq!(ids[0].label, "en");
+        });
+        assert!(topk.predict(sentence).unwrap().unwrap().len() > 1);
+    }
 
     // ambiguous/multilingual sentence that shouldn't yield a single lang with a high confidence
     #[test]
@@ -198,6 +342,56 @@ mod tests {
         let pred = &pred[0];
         assert_eq!(pred.label, "en");
     }
+    // unilingual sentence with a permissive threshold should yield several ordered labels
+    #[test]
+    fn test_id_topk() {
+        let classifier = FastText::new(Path::new("lid.176.bin"), 1, 0.0)
+            .expect("could not instantiate a classifier");
+        let sentence = "a perfectly, innocent, quite lengthy sentence. How lengthy and normal this sentence is, oh my! Lengthy lengthy.";
+        let pred = classifier
+            .predict_topk(sentence, 3)
+            .expect("could not launch prediction")
+            .unwrap();
+        assert_eq!(pred.len(), 3);
+        assert_eq!(pred[0].label, "en");
+        assert!(pred.windows(2).all(|w| w[0].prob >= w[1].prob));
+    }
+
+    // the best prediction is kept even if an unreachable threshold discards every prediction
+    #[test]
+    fn test_predict_best() {
+        let classifier = FastText::new(Path::new("lid.176.bin"), 1, 1.1)
+            .expect("could not instantiate a classifier");
+        let sentence = "a perfectly, innocent, quite lengthy sentence. How lengthy and normal this sentence is, oh my! Lengthy lengthy.";
+        assert!(LanguageIdentifier::predict(&classifier, sentence)
+            .unwrap()
+            .is_none());
+        let best = classifier.predict_best(sentence).unwrap().unwrap();
+        assert_eq!(best.label, "en");
+    }
+
+    #[test]
+    fn test_check_lang_thresholds() {
+        let mut thresholds = HashMap::new();
+        thresholds.insert("fr", 0.5);
+        assert!(FastText::check_lang_thresholds(&thresholds).is_ok());
+
+        thresholds.insert("not_a_lang", 0.5);
+        assert!(FastText::check_lang_thresholds(&thresholds).is_err());
+    }
+
+    #[test]
+    fn test_lang_thresholds() {
+        let mut classifier = FastText::new_lid().expect("could not instantiate a classifier");
+        let mut thresholds = HashMap::new();
+        thresholds.insert("en", 0.3);
+        classifier.set_lang_thresholds(thresholds).unwrap();
+
+        assert_eq!(classifier.threshold_for("en"), 0.3);
+        assert_eq!(classifier.threshold_for("fr"), classifier.threshold);
+        assert_eq!(classifier.min_threshold(), 0.3);
+    }
+
     // test that garbage unicode from CC does not procees to crash the underlying C++ code.
     // when escaped with C++ friendly escape_default() method.
     #[test]
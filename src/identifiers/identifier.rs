@@ -1,8 +1,13 @@
-/*! Identifier trait
+/*! Identifier traits
 
 All identifiers should implement [Identifier] to be useable in processing and pipelines.
+
+Language identification backends implement [LanguageIdentifier], which pipelines depend on,
+so that [super::FastText] can be swapped for another model.
 !*/
+use std::collections::HashMap;
 use std::str::FromStr;
+use std::str::Lines;
 
 use fasttext::Prediction;
 use schemars::JsonSchema;
@@ -68,11 +73,143 @@ pub trait Identifier<T> {
     fn identify(&self, sentence: T) -> Result<Option<Identification>, Error>;
 }
 
+/// Language identification backend.
+///
+/// Backends are shared by the threads processing records, so predictions only take a shared reference.
+pub trait LanguageIdentifier: Send + Sync {
+    /// predict languages of supplied text, ordered by decreasing probability.
+    ///
+    /// Labels have to be normalized by the backend into [crate::lang::LANG] keys (such as `en`),
+    /// and predictions that are not reliable enough have to be discarded.
+    /// returns Ok(None) if no reliable identification has been done.
+    fn predict(&self, text: &str) -> Result<Option<Vec<Prediction>>, Error>;
+
+    /// predict the most probable language of supplied text, even if it is not reliable enough.
+    ///
+    /// This is only used for diagnostics (such as explaining why a sentence has not been identified),
+    /// so backends that discard unreliable predictions should override it.
+    /// Defaults to the first prediction of [LanguageIdentifier::predict].
+    fn predict_best(&self, text: &str) -> Result<Option<Prediction>, Error> {
+        Ok(self
+            .predict(text)?
+            .and_then(|predictions| predictions.into_iter().next()))
+    }
+
+    /// Identifies each line, then returns both identifications for each line _and_
+    /// a HashMap holding (byte_count, sum(byte_count*prob) / total count).
+    ///
+    /// Each line is identified as its most probable prediction (see [LanguageIdentifier::predict]).
+    ///
+    /// # Errors
+    /// Returns an error if a prediction failed or if a predicted label is not a known language.
+    #[allow(clippy::type_complexity)]
+    fn get_weighted_ids(
+        &self,
+        lines: Lines,
+    ) -> Result<
+        (
+            Vec<Option<Identification>>,
+            HashMap<Option<Lang>, (usize, f32)>,
+            usize,
+        ),
+        Error,
+    > {
+        // per-lang and total byte counts
+        // lang_count maps Lang -> (lang_byte_count, sum(byte_count*prob))
+        let mut lang_count = HashMap::new();
+        let mut total_count = 0;
+
+        // filter out unicode null chars
+        // this prevents fasttext errors and hopefully improves
+        // corpus quality
+        let lines = lines.map(|l| l.replace(char::from(0), ""));
+        let ids: Vec<Option<Identification>> = lines
+            .map(|line| {
+                // identify
+                let id = match self.predict(line.as_str()) {
+                    Ok(predictions) => predictions
+                        .and_then(|predictions| predictions.into_iter().next())
+                        .map(|p| {
+                            Lang::from_str(&p.label)
+                                .map(|label| Identification::new(label, p.prob))
+                                .map_err(|_| Error::UnknownLang(p.label))
+                        })
+                        .transpose(),
+                    Err(e) => Err(e),
+                };
+
+                // add to byte count for document-level identification
+                if let Ok(ref ide) = id {
+                    // map Identification to its lang, or keep None to store the "None" language identification
+                    let ide_label = ide.as_ref().map(|i| *i.label());
+                    let ide_prob = ide.as_ref().map(|i| *i.prob());
+                    // get length of current line
+                    let byte_count = line.len();
+
+                    lang_count
+                        .entry(ide_label)
+                        .and_modify(|(count, count_times_prob)| {
+                            *count += byte_count;
+                            *count_times_prob += byte_count as f32 * ide_prob.unwrap_or(1.0f32);
+                        })
+                        .or_insert((byte_count, byte_count as f32 * ide_prob.unwrap_or(1.0f32)));
+
+                    total_count += byte_count;
+                }
+                id
+            })
+            .collect::<Result<_, Error>>()?;
+
+        // divide by total count to get probs between 0 and 1.
+        for (_, count_times_prob) in lang_count.values_mut() {
+            *count_times_prob /= total_count as f32;
+        }
+
+        Ok((ids, lang_count, total_count))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use fasttext::Prediction;
 
-    use super::Identification;
+    use super::{Identification, LanguageIdentifier};
+    use crate::error::Error;
+
+    /// identifies lines starting with a label as being of that label.
+    struct Prefix;
+
+    impl LanguageIdentifier for Prefix {
+        fn predict(&self, text: &str) -> Result<Option<Vec<Prediction>>, Error> {
+            Ok(text
+                .split(' ')
+                .next()
+                .filter(|l| !l.is_empty())
+                .map(|label| {
+                    vec![Prediction {
+                        prob: 0.5,
+                        label: label.to_string(),
+                    }]
+                }))
+        }
+    }
+
+    #[test]
+    fn test_weighted_ids_backend() {
+        let (ids, lang_count, total_count) = Prefix
+            .get_weighted_ids("en hello\nfr salut\n".lines())
+            .unwrap();
+        assert_eq!(ids.len(), 2);
+        assert_eq!(&ids[0].as_ref().unwrap().label().to_string(), "en");
+        assert_eq!(ids[1].as_ref().unwrap().prob(), &0.5);
+        assert_eq!(total_count, 16);
+        assert_eq!(lang_count.len(), 2);
+    }
+
+    #[test]
+    fn test_weighted_ids_unknown_label() {
+        assert!(Prefix.get_weighted_ids("xx hello".lines()).is_err());
+    }
 
     #[test]
     fn test_from_pred() {
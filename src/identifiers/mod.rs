@@ -1,15 +1,20 @@
 /*! Language identification models
 
-Holds an [Identifier] trait for implementing other ones.
+Holds an [Identifier] trait for implementing other ones,
+and a [LanguageIdentifier] trait for plugging other language identification backends into pipelines.
 
 The current identifier used is [fasttext](https://fasttext.cc)
 !*/
 mod fasttext;
 mod identifier;
 mod multilingual;
+mod timeout;
 
 pub use self::fasttext::FastText;
+pub use self::fasttext::FastTextModel;
 pub use identifier::Identification;
 pub use identifier::Identifier;
+pub use identifier::LanguageIdentifier;
 pub use multilingual::Multilingual;
 pub use multilingual::StrictMultilingual;
+pub use timeout::Timeout;
@@ -0,0 +1,222 @@
+/*! Bounded predictions
+
+Some pathological inputs can stall a backend (such as fastText) on a single prediction,
+and since predictions can't be cancelled, the thread running it would be blocked indefinitely.
+
+[Timeout] wraps a backend and runs its predictions on worker threads, giving up on predictions
+that take longer than a given duration: the caller gets an error, and the stalled worker is abandoned.
+Each abandoned worker leaks its thread, so the number of abandoned workers is capped (see [Timeout::with_max_abandoned]).
+!*/
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use fasttext::Prediction;
+use log::warn;
+
+use super::LanguageIdentifier;
+use crate::error::Error;
+
+/// Prediction request sent to a worker.
+enum Job {
+    Predict(String),
+    PredictBest(String),
+}
+
+type Reply = Result<Option<Vec<Prediction>>, Error>;
+
+/// Worker thread running the predictions sent to it, one at a time.
+///
+/// The thread stops once its worker is dropped (once its current prediction, if any, is done).
+struct Worker {
+    jobs: Sender<Job>,
+    replies: Receiver<Reply>,
+}
+
+impl Worker {
+    fn spawn(inner: Arc<dyn LanguageIdentifier>) -> Result<Self, Error> {
+        let (jobs, jobs_rx) = mpsc::channel();
+        let (replies_tx, replies) = mpsc::channel();
+        std::thread::Builder::new()
+            .name("lid-worker".to_string())
+            .spawn(move || {
+                for job in jobs_rx {
+                    let reply = match job {
+                        Job::Predict(text) => inner.predict(&text),
+                        Job::PredictBest(text) => inner
+                            .predict_best(&text)
+                            .map(|best| best.map(|best| vec![best])),
+                    };
+                    if replies_tx.send(reply).is_err() {
+                        break;
+                    }
+                }
+            })?;
+        Ok(Self { jobs, replies })
+    }
+}
+
+/// Language identification backend that bounds the duration of the predictions of another one.
+///
+/// Predictions run on worker threads, that are spawned as needed (one per concurrent prediction)
+/// and reused afterwards. A prediction that doesn't complete within the timeout returns an error,
+/// and its worker is abandoned: its thread is left running until the prediction completes, if ever.
+/// Once [Timeout::with_max_abandoned] workers have been abandoned, every prediction fails.
+///
+/// Sending texts to workers has a cost, so bounded predictions are slightly slower.
+pub struct Timeout {
+    inner: Arc<dyn LanguageIdentifier>,
+    timeout: Duration,
+    idle: Mutex<Vec<Worker>>,
+    abandoned: AtomicUsize,
+    max_abandoned: usize,
+}
+
+impl Timeout {
+    /// Default maximum number of abandoned workers.
+    pub const DEFAULT_MAX_ABANDONED: usize = 64;
+
+    /// Bound the predictions of `inner` to `timeout`.
+    pub fn new(inner: Arc<dyn LanguageIdentifier>, timeout: Duration) -> Self {
+        Self {
+            inner,
+            timeout,
+            idle: Mutex::new(Vec::new()),
+            abandoned: AtomicUsize::new(0),
+            max_abandoned: Self::DEFAULT_MAX_ABANDONED,
+        }
+    }
+
+    /// Set the maximum number of abandoned workers (that is of leaked threads, see [Timeout]).
+    ///
+    /// Once it is reached, predictions fail right away rather than risking to leak more threads.
+    /// Defaults to [Timeout::DEFAULT_MAX_ABANDONED].
+    pub fn with_max_abandoned(mut self, max_abandoned: usize) -> Self {
+        self.max_abandoned = max_abandoned;
+        self
+    }
+
+    /// Get the timeout of predictions.
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Run `job` on an idle worker (or on a new one), waiting for at most the timeout.
+    fn run(&self, job: Job) -> Reply {
+        let abandoned = self.abandoned.load(Ordering::Relaxed);
+        if abandoned >= self.max_abandoned {
+            return Err(Error::Custom(format!(
+                "{} predictions did not complete within {:?}: giving up on predictions",
+                abandoned, self.timeout
+            )));
+        }
+        let worker = self.idle.lock().unwrap().pop();
+        let worker = match worker {
+            Some(worker) => worker,
+            None => Worker::spawn(self.inner.clone())?,
+        };
+        worker
+            .jobs
+            .send(job)
+            .map_err(|_| Error::Custom("prediction worker stopped".to_string()))?;
+
+        match worker.replies.recv_timeout(self.timeout) {
+            Ok(reply) => {
+                self.idle.lock().unwrap().push(worker);
+                reply
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                let abandoned = self.abandoned.fetch_add(1, Ordering::Relaxed) + 1;
+                warn!(
+                    "prediction did not complete within {:?}: abandoning its thread ({}/{} abandoned)",
+                    self.timeout, abandoned, self.max_abandoned
+                );
+                Err(Error::Custom(format!(
+                    "prediction timed out after {:?}",
+                    self.timeout
+                )))
+            }
+            Err(RecvTimeoutError::Disconnected) => Err(Error::Custom(
+                "prediction worker stopped (the backend may have panicked)".to_string(),
+            )),
+        }
+    }
+}
+
+impl LanguageIdentifier for Timeout {
+    fn predict(&self, text: &str) -> Result<Option<Vec<Prediction>>, Error> {
+        self.run(Job::Predict(text.to_string()))
+    }
+
+    fn predict_best(&self, text: &str) -> Result<Option<Prediction>, Error> {
+        Ok(self
+            .run(Job::PredictBest(text.to_string()))?
+            .and_then(|best| best.into_iter().next()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use fasttext::Prediction;
+    use rayon::prelude::*;
+
+    use super::Timeout;
+    use crate::error::Error;
+    use crate::identifiers::LanguageIdentifier;
+
+    /// identifies everything as french, stalling on texts holding "stall".
+    struct Stalling;
+
+    impl LanguageIdentifier for Stalling {
+        fn predict(&self, text: &str) -> Result<Option<Vec<Prediction>>, Error> {
+            if text.contains("stall") {
+                std::thread::sleep(Duration::from_secs(2));
+            }
+            Ok(Some(vec![Prediction {
+                prob: 0.9,
+                label: "fr".to_string(),
+            }]))
+        }
+    }
+
+    #[test]
+    fn predict() {
+        let cls = Timeout::new(Arc::new(Stalling), Duration::from_millis(100));
+        let labels: Vec<_> = (0..50)
+            .into_par_iter()
+            .map(|_| cls.predict("bonjour").unwrap().unwrap()[0].label.clone())
+            .collect();
+        assert!(labels.iter().all(|label| label == "fr"));
+        assert_eq!(cls.predict_best("bonjour").unwrap().unwrap().label, "fr");
+
+        // workers are reused
+        assert!(cls.idle.lock().unwrap().len() <= rayon::current_num_threads());
+    }
+
+    #[test]
+    fn timeout() {
+        let cls = Timeout::new(Arc::new(Stalling), Duration::from_millis(100));
+        let err = cls.predict("stall").unwrap_err();
+        assert!(err.to_string().contains("timed out"), "{}", err);
+
+        // the stalled worker is not reused
+        assert!(cls.idle.lock().unwrap().is_empty());
+        assert!(cls.predict("bonjour").unwrap().is_some());
+        assert_eq!(cls.idle.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn max_abandoned() {
+        let cls =
+            Timeout::new(Arc::new(Stalling), Duration::from_millis(100)).with_max_abandoned(1);
+        assert!(cls.predict("stall").is_err());
+
+        // no more threads are abandoned
+        let err = cls.predict("bonjour").unwrap_err();
+        assert!(err.to_string().contains("giving up"), "{}", err);
+    }
+}
@@ -0,0 +1,223 @@
+/*! Channel-based, language-separated writing.
+
+[LangChannels] spawns a writer thread for each writer of a [LangFiles],
+fed by a bounded channel.
+This enables writing while other records are being processed,
+and bounds the number of pieces waiting to be written.
+
+Pieces are sent along with the index of their shard, so that write errors are reported to the right shard
+(see [LangChannels::sync]).
+!*/
+use std::{
+    collections::HashMap,
+    sync::{
+        mpsc::{self, Receiver, Sender, SyncSender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+
+use log::error;
+
+use crate::error::Error;
+use crate::pipelines::oscarmeta::types::MergedPiece;
+
+use super::{LangFiles, LangWriter};
+
+/// Message sent to a writer thread.
+enum Message {
+    /// Pieces to write, along with their shard index.
+    Pieces(usize, Vec<MergedPiece>),
+    /// Request the write result of a shard.
+    Sync(usize, Sender<Result<(), Error>>),
+}
+
+/// Holds a channel to a writer thread for each language.
+pub struct LangChannels {
+    senders: HashMap<&'static str, SyncSender<Message>>,
+    handles: Vec<JoinHandle<()>>,
+}
+
+impl LangChannels {
+    /// Spawn a writer thread for each writer of `langfiles`.
+    ///
+    /// `bound` is the number of messages that can wait in each channel before sending blocks.
+    pub fn new(langfiles: &LangFiles, bound: usize) -> Self {
+        let mut senders = HashMap::with_capacity(langfiles.writers().len());
+        let mut handles = Vec::with_capacity(langfiles.writers().len());
+        for (lang, writer) in langfiles.writers() {
+            let (tx, rx) = mpsc::sync_channel(bound);
+            let writer = writer.clone();
+            handles.push(thread::spawn(move || Self::write_loop(writer, rx)));
+            senders.insert(*lang, tx);
+        }
+
+        Self { senders, handles }
+    }
+
+    /// Write received pieces until every sender is dropped.
+    ///
+    /// Once a write fails for a shard, its subsequent pieces are discarded.
+    fn write_loop(writer: Arc<Mutex<LangWriter>>, rx: Receiver<Message>) {
+        let mut failed: HashMap<usize, Error> = HashMap::new();
+        for message in rx {
+            match message {
+                Message::Pieces(shard, pieces) => {
+                    if failed.contains_key(&shard) {
+                        continue;
+                    }
+                    if let Err(e) = writer.lock().unwrap().write(pieces) {
+                        failed.insert(shard, e);
+                    }
+                }
+                Message::Sync(shard, ack) => {
+                    let result = failed.remove(&shard).map_or(Ok(()), Err);
+                    // the shard may have been given up on: ignore
+                    let _ = ack.send(result);
+                }
+            }
+        }
+    }
+
+    /// Send `pieces` of `shard` to the `lang` writer, blocking if its channel is full.
+    ///
+    /// # Errors
+    /// Returns an error if there's no writer for `lang` or if its thread has stopped.
+    pub fn send(
+        &self,
+        shard: usize,
+        lang: &'static str,
+        pieces: Vec<MergedPiece>,
+    ) -> Result<(), Error> {
+        let sender = self
+            .senders
+            .get(lang)
+            .ok_or_else(|| Error::Custom(format!("no writer for language {}", lang)))?;
+        sender
+            .send(Message::Pieces(shard, pieces))
+            .map_err(|_| Error::Custom(format!("writer thread for {} has stopped", lang)))
+    }
+
+    /// Wait for every piece of `shard` sent so far to be written.
+    ///
+    /// # Errors
+    /// Returns the first write error of `shard`, if any.
+    pub fn sync(&self, shard: usize) -> Result<(), Error> {
+        let (tx, rx) = mpsc::channel();
+        for (lang, sender) in &self.senders {
+            sender
+                .send(Message::Sync(shard, tx.clone()))
+                .map_err(|_| Error::Custom(format!("writer thread for {} has stopped", lang)))?;
+        }
+        drop(tx);
+
+        let mut result = Ok(());
+        for _ in 0..self.senders.len() {
+            match rx.recv() {
+                Ok(Ok(())) => (),
+                Ok(Err(e)) => {
+                    if result.is_ok() {
+                        result = Err(e);
+                    } else {
+                        error!("{:?}", e);
+                    }
+                }
+                Err(_) => {
+                    return Err(Error::Custom("writer thread has stopped".to_string()));
+                }
+            }
+        }
+
+        result
+    }
+
+    /// Stop writer threads once every sent piece is written.
+    ///
+    /// # Errors
+    /// Returns an error if a writer thread panicked.
+    pub fn finish(self) -> Result<(), Error> {
+        drop(self.senders);
+        for handle in self.handles {
+            handle
+                .join()
+                .map_err(|_| Error::Custom("writer thread panicked".to_string()))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use tempfile::tempdir;
+
+    use crate::io::{FileNaming, LayoutStrategy, OutputFormat};
+
+    use super::*;
+
+    fn piece(sentences: &str, identification: &'static str) -> MergedPiece {
+        MergedPiece::new(
+            HashMap::new(),
+            sentences.lines().map(String::from).collect(),
+            identification,
+        )
+    }
+
+    #[test]
+    fn send_sync() {
+        let dst = tempdir().unwrap();
+        let languages = vec!["en", "fr"].into_iter().collect();
+        let langfiles = LangFiles::with_languages(
+            dst.path(),
+            &languages,
+            None,
+            OutputFormat::Jsonl,
+            None,
+            LayoutStrategy::Flat,
+            &FileNaming::default(),
+        )
+        .unwrap();
+        let channels = LangChannels::new(&langfiles, 2);
+
+        for i in 0..5 {
+            channels
+                .send(0, "en", vec![piece(&format!("hello {}", i), "en")])
+                .unwrap();
+        }
+        channels.sync(0).unwrap();
+        channels.finish().unwrap();
+
+        let content = std::fs::read_to_string(dst.path().join("en.jsonl")).unwrap();
+        assert_eq!(content.lines().count(), 5);
+        assert!(!dst.path().join("fr.jsonl").exists());
+    }
+
+    #[test]
+    fn sync_error() {
+        let dst = tempdir().unwrap();
+        let languages = vec!["en"].into_iter().collect();
+        let langfiles = LangFiles::with_languages(
+            dst.path(),
+            &languages,
+            None,
+            OutputFormat::Jsonl,
+            None,
+            LayoutStrategy::Flat,
+            &FileNaming::default(),
+        )
+        .unwrap();
+        let channels = LangChannels::new(&langfiles, 2);
+
+        // wrong identification
+        channels
+            .send(0, "en", vec![piece("bonjour", "fr")])
+            .unwrap();
+        channels.send(1, "en", vec![piece("hello", "en")]).unwrap();
+
+        assert!(channels.sync(0).is_err());
+        assert!(channels.sync(1).is_ok());
+        assert!(channels.send(0, "de", vec![]).is_err());
+        channels.finish().unwrap();
+    }
+}
@@ -6,24 +6,356 @@ Each language (provided by [crate::lang::LANG]) is given a [self::Writer] wrappe
 
 !*/
 use std::{
-    collections::HashMap,
-    path::Path,
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
     str::FromStr,
-    sync::{Arc, Mutex},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
 };
 
+use flate2::Compression;
+use log::error;
+
 use crate::io::writer::Writer;
 use crate::lang::LANG;
-use crate::{error, lang::Lang};
+use crate::pipelines::oscardoc::types::{Document, Location};
+use crate::pipelines::oscarmeta::types::MergedPiece;
+use crate::{
+    error,
+    lang::{Lang, LangNaming},
+};
+
+use super::staging::Staging;
+use super::writer::{JsonlWriter, ParquetWriter, TextWriter, WriterDoc, WriterTrait};
+#[cfg(any(test, feature = "testing"))]
+use super::writer::{MemPieces, MemWriter};
+
+/// Writer held by [LangFiles] for each language.
+pub type LangWriter = Box<dyn WriterTrait<Item = MergedPiece> + Send>;
+
+/// Languages that have been written, shared by the writers of a [LangFiles].
+type Written = Arc<Mutex<HashSet<&'static str>>>;
+
+/// Writer recording the languages of the pieces it writes (see [LangFiles::nonempty_languages]).
+///
+/// Languages are recorded from pieces rather than from the writer,
+/// so that writers shared by several languages (see [LangFiles::shared]) are tracked too.
+struct Tracked {
+    inner: LangWriter,
+    written: Written,
+}
+
+impl Tracked {
+    fn wrap(inner: LangWriter, written: &Written) -> LangWriter {
+        Box::new(Self {
+            inner,
+            written: written.clone(),
+        })
+    }
+
+    fn record(&self, piece: &MergedPiece) {
+        self.written.lock().unwrap().insert(piece.identification());
+    }
+}
+
+impl WriterTrait for Tracked {
+    type Item = MergedPiece;
+
+    /// Tracked writers only wrap existing writers (see [Tracked::wrap]).
+    fn new(
+        _dst: &Path,
+        lang: &'static str,
+        _size_limit: Option<u64>,
+    ) -> Result<Self, error::Error> {
+        Err(error::Error::Custom(format!(
+            "tracked writer of {} has to wrap an existing writer",
+            lang
+        )))
+    }
+
+    fn write(&mut self, pieces: Vec<MergedPiece>) -> Result<(), error::Error> {
+        let langs: HashSet<&'static str> = pieces.iter().map(|p| p.identification()).collect();
+        self.inner.write(pieces)?;
+        self.written.lock().unwrap().extend(langs);
+        Ok(())
+    }
+
+    fn write_single(&mut self, piece: &MergedPiece) -> Result<(), error::Error> {
+        self.inner.write_single(piece)?;
+        self.record(piece);
+        Ok(())
+    }
+
+    fn close_meta(&mut self) -> Result<(), error::Error> {
+        self.inner.close_meta()
+    }
+
+    fn set_bom(&mut self, bom: bool) {
+        self.inner.set_bom(bom)
+    }
+
+    fn set_prob_digits(&mut self, digits: usize) {
+        self.inner.set_prob_digits(digits)
+    }
+}
+
+/// Output format of [LangFiles].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Text files along with metadata sidecar files (see [Writer]).
+    #[default]
+    TextMeta,
+    /// One JSON object per line, bundling text and metadata (see [JsonlWriter]).
+    Jsonl,
+    /// Parquet files, written by row groups of `row_group_size` pieces (see [ParquetWriter]).
+    ///
+    /// Parquet files are not compressed nor split into parts.
+    Parquet { row_group_size: usize },
+    /// A single JSON Lines file for every language ([crate::io::writer::COMBINED_FILE], at the root of the destination folder),
+    /// each line carrying its language (see [JsonlWriter::combined]).
+    ///
+    /// Languages share the same writer, so that pieces of different languages are interleaved
+    /// record by record. The layout only applies to short sentences files.
+    Combined,
+}
+
+/// Output format of [LangFilesDoc].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DocFormat {
+    /// One JSON document per line (`<lang>_meta.jsonl`, see [WriterDoc]).
+    #[default]
+    Jsonl,
+    /// Parquet files, written by row groups of `row_group_size` documents along with their location
+    /// (see [ParquetWriter]).
+    ///
+    /// Parquet files can't be appended to.
+    Parquet { row_group_size: usize },
+}
+
+/// Writer held by [LangFilesDoc] for each language.
+pub enum DocWriter {
+    Jsonl(WriterDoc),
+    Parquet(Box<ParquetWriter<(Document, Location)>>),
+}
+
+impl DocWriter {
+    /// Write `documents`, found at `locations` in their shard.
+    ///
+    /// Locations are only written in Parquet files.
+    ///
+    /// # Errors
+    /// Returns an error if a Parquet writer doesn't get a location for each document.
+    pub fn write(
+        &mut self,
+        documents: Vec<Document>,
+        locations: &[Location],
+    ) -> Result<(), error::Error> {
+        match self {
+            Self::Jsonl(w) => w.write(documents),
+            Self::Parquet(w) => {
+                if documents.len() != locations.len() {
+                    return Err(error::Error::Custom(format!(
+                        "got {} locations for {} documents",
+                        locations.len(),
+                        documents.len()
+                    )));
+                }
+                w.write(
+                    documents
+                        .into_iter()
+                        .zip(locations.iter().cloned())
+                        .collect(),
+                )
+            }
+        }
+    }
 
-use super::writer::{WriterDoc, WriterTrait};
-/// Holds references to [Writer].
+    /// Close the current file (see [WriterTrait::close_meta]).
+    pub fn close_meta(&mut self) -> Result<(), error::Error> {
+        match self {
+            Self::Jsonl(w) => w.close_meta(),
+            Self::Parquet(w) => w.close_meta(),
+        }
+    }
+}
+
+/// Directory layout of output files.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LayoutStrategy {
+    /// Every file at the root of the destination folder (`<dst>/<lang>.txt`).
+    #[default]
+    Flat,
+    /// One folder per language (`<dst>/<lang>/<lang>.txt`).
+    PerLangDir,
+}
+
+/// Naming of text and metadata files (`<prefix><lang><suffix>.<extension>`, `<prefix><lang><suffix>_meta.jsonl`).
+///
+/// Defaults to `<lang>.txt` and `<lang>_meta.jsonl`.
+/// Only text and metadata files ([OutputFormat::TextMeta], and short sentences files) follow it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileNaming {
+    prefix: String,
+    suffix: String,
+    extension: String,
+}
+
+impl Default for FileNaming {
+    fn default() -> Self {
+        Self {
+            prefix: String::new(),
+            suffix: String::new(),
+            extension: "txt".to_string(),
+        }
+    }
+}
+
+impl FileNaming {
+    /// Create a new naming, adding `prefix` and `suffix` around language labels
+    /// and using `extension` (without leading dot) for text files.
+    ///
+    /// # Errors
+    /// Returns an error if `extension` is empty or starts with a dot,
+    /// or if a part holds a path separator.
+    pub fn new(prefix: &str, suffix: &str, extension: &str) -> Result<Self, error::Error> {
+        if extension.is_empty() || extension.starts_with('.') {
+            return Err(error::Error::Custom(format!(
+                "invalid text file extension: {:?}",
+                extension
+            )));
+        }
+        if let Some(part) = [prefix, suffix, extension]
+            .iter()
+            .find(|part| part.contains(std::path::is_separator))
+        {
+            return Err(error::Error::Custom(format!(
+                "file names can't hold path separators: {:?}",
+                part
+            )));
+        }
+
+        Ok(Self {
+            prefix: prefix.to_string(),
+            suffix: suffix.to_string(),
+            extension: extension.to_string(),
+        })
+    }
+
+    /// Get the file stem of `lang`.
+    pub fn stem(&self, lang: &str) -> String {
+        format!("{}{}{}", self.prefix, lang, self.suffix)
+    }
+
+    /// Get the extension of text files.
+    pub fn extension(&self) -> &str {
+        &self.extension
+    }
+}
+
+/// Unit of the caps of [LangCaps].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CapUnit {
+    /// Number of pieces (documents).
+    Pieces,
+    /// Number of bytes of piece sentences, excluding document separators.
+    Bytes,
+}
+
+impl CapUnit {
+    /// Get the amount of `piece`, in this unit.
+    fn amount(&self, piece: &MergedPiece) -> usize {
+        match self {
+            Self::Pieces => 1,
+            Self::Bytes => piece.sentences.len(),
+        }
+    }
+}
+
+/// Per-language caps on the output of a [LangFiles] (see [LangFiles::with_caps]).
+///
+/// Languages without a cap of their own get the default cap, if any.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LangCaps {
+    unit: CapUnit,
+    caps: HashMap<&'static str, usize>,
+    default: Option<usize>,
+}
+
+impl LangCaps {
+    /// Create caps of `unit`, for each language of `caps`.
+    ///
+    /// # Errors
+    /// Returns an [error::Error::UnknownLang] if a label is not in [LANG].
+    pub fn new(unit: CapUnit, caps: HashMap<&'static str, usize>) -> Result<Self, error::Error> {
+        crate::lang::check_langs(&caps.keys().copied().collect())?;
+        Ok(Self {
+            unit,
+            caps,
+            default: None,
+        })
+    }
+
+    /// Set the cap of languages that don't have one of their own. Defaults to `None` (uncapped).
+    pub fn with_default(mut self, default: Option<usize>) -> Self {
+        self.default = default;
+        self
+    }
+
+    /// Get the cap of `lang`, if it is capped.
+    pub fn cap(&self, lang: &str) -> Option<usize> {
+        self.caps.get(lang).copied().or(self.default)
+    }
+
+    /// Get the unit of the caps.
+    pub fn unit(&self) -> CapUnit {
+        self.unit
+    }
+}
+
+/// Caps of a [LangFiles], along with the usage of each language.
+#[derive(Debug)]
+struct Capped {
+    caps: LangCaps,
+    /// (admitted amount, number of dropped pieces) of each language.
+    usage: Mutex<HashMap<&'static str, (usize, usize)>>,
+}
+
+impl LayoutStrategy {
+    /// Get the folder that holds the files of `lang`, creating it (along with missing parents) if needed.
+    pub fn lang_dir(&self, dst: &Path, lang: &str) -> Result<PathBuf, error::Error> {
+        match self {
+            Self::Flat => Ok(dst.to_path_buf()),
+            Self::PerLangDir => {
+                let dir = dst.join(lang);
+                std::fs::create_dir_all(&dir)?;
+                Ok(dir)
+            }
+        }
+    }
+}
+
+/// Holds references to [LangWriter].
+///
+/// Can also hold text writers for sentences that are too short to be part of the corpus
+/// (see [LangFiles::with_short_writers]).
+///
+/// Files are only complete once [LangFiles::close] has been called:
+/// an error is logged if a LangFiles is dropped before.
+/// Files can be staged until then, so that they're never read while incomplete (see [LangFiles::with_staging]).
 pub struct LangFiles {
-    writers: HashMap<&'static str, Arc<Mutex<Writer>>>,
+    writers: HashMap<&'static str, Arc<Mutex<LangWriter>>>,
+    short_writers: HashMap<&'static str, Arc<Mutex<TextWriter>>>,
+    written: Written,
+    caps: Option<Capped>,
+    staging: Option<Staging>,
+    write_bom: bool,
+    closed: AtomicBool,
 }
 
 pub struct LangFilesDoc {
-    writers: HashMap<Lang, Arc<Mutex<WriterDoc>>>,
+    writers: HashMap<Lang, Arc<Mutex<DocWriter>>>,
 }
 
 impl LangFiles {
@@ -32,33 +364,346 @@ impl LangFiles {
     /// Note that if it is set too low and a unique record can't be stored in an unique part
     /// then a part will still be created, being larger than the `part_size_bytes`. This is expected behaviour.
     ///
-    /// Also keep in mind that [Self::close_meta] has to be called once every write is done.
+    /// `format` selects the writer used for each language,
+    /// `compression` enables gzip compression of every output file (text and metadata, Parquet files excepted),
+    /// `layout` sets where files are put in `dst` and `naming` how text and metadata files are named.
     ///
-    // [Self::close_meta] could be integrated in an `impl Drop`
-    pub fn new(dst: &Path, part_size_bytes: Option<u64>) -> Result<Self, error::Error> {
-        let mut writers = HashMap::with_capacity(LANG.len());
-        let mut w;
-        for lang in LANG.iter() {
-            w = Writer::new(dst, lang, part_size_bytes)?;
-            writers.insert(*lang, Arc::new(Mutex::new(w)));
+    /// Also keep in mind that [Self::close] has to be called once every write is done.
+    pub fn new(
+        dst: &Path,
+        part_size_bytes: Option<u64>,
+        format: OutputFormat,
+        compression: Option<Compression>,
+        layout: LayoutStrategy,
+        naming: &FileNaming,
+    ) -> Result<Self, error::Error> {
+        Self::with_languages(
+            dst,
+            &LANG,
+            part_size_bytes,
+            format,
+            compression,
+            layout,
+            naming,
+        )
+    }
+
+    /// Create a new LangFiles holding writers for `languages` only (see [Self::new]).
+    ///
+    /// Labels are not checked against [LANG] (see [crate::lang::check_langs]).
+    pub fn with_languages(
+        dst: &Path,
+        languages: &HashSet<&'static str>,
+        part_size_bytes: Option<u64>,
+        format: OutputFormat,
+        compression: Option<Compression>,
+        layout: LayoutStrategy,
+        naming: &FileNaming,
+    ) -> Result<Self, error::Error> {
+        if format == OutputFormat::Combined {
+            let w: LangWriter = Box::new(JsonlWriter::combined(dst, compression));
+            return Ok(Self::shared(languages, w));
         }
 
-        Ok(Self { writers })
+        Self::with_factory(languages, |lang| {
+            let dst = layout.lang_dir(dst, lang)?;
+            let dst = dst.as_path();
+            let w: LangWriter = match format {
+                OutputFormat::TextMeta => Box::new(Writer::with_naming(
+                    dst,
+                    lang,
+                    part_size_bytes,
+                    compression,
+                    naming,
+                )),
+                OutputFormat::Jsonl => {
+                    Box::new(JsonlWriter::with_compression(dst, lang, compression))
+                }
+                OutputFormat::Parquet { row_group_size } => Box::new(
+                    ParquetWriter::with_row_group_size(dst, lang, row_group_size)?,
+                ),
+                // handled above
+                OutputFormat::Combined => unreachable!(),
+            };
+            Ok(w)
+        })
+    }
+
+    /// Create a new LangFiles holding writers for `languages`, created by `factory`.
+    ///
+    /// This allows using other writers, such as a `MemWriter` that keeps pieces in memory
+    /// (see `LangFiles::in_memory`, with the `testing` feature).
+    ///
+    /// # Errors
+    /// Propagates the first `factory` error.
+    pub fn with_factory<F>(
+        languages: &HashSet<&'static str>,
+        mut factory: F,
+    ) -> Result<Self, error::Error>
+    where
+        F: FnMut(&'static str) -> Result<LangWriter, error::Error>,
+    {
+        let written = Written::default();
+        let mut writers = HashMap::with_capacity(languages.len());
+        for lang in languages.iter() {
+            let writer = Tracked::wrap(factory(lang)?, &written);
+            writers.insert(*lang, Arc::new(Mutex::new(writer)));
+        }
+
+        Ok(Self {
+            writers,
+            short_writers: HashMap::new(),
+            written,
+            caps: None,
+            staging: None,
+            write_bom: false,
+            closed: AtomicBool::new(false),
+        })
+    }
+
+    /// Create a new LangFiles where every one of `languages` shares `writer`.
+    ///
+    /// Writes of different languages are serialized by the writer mutex.
+    pub fn shared(languages: &HashSet<&'static str>, writer: LangWriter) -> Self {
+        let written = Written::default();
+        let writer = Arc::new(Mutex::new(Tracked::wrap(writer, &written)));
+        let writers = languages
+            .iter()
+            .map(|lang| (*lang, writer.clone()))
+            .collect();
+
+        Self {
+            writers,
+            short_writers: HashMap::new(),
+            written,
+            caps: None,
+            staging: None,
+            write_bom: false,
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    /// Create a new LangFiles holding a `MemWriter` for each of `languages`.
+    ///
+    /// Returns the pieces written for each language along with the LangFiles,
+    /// so that they can be checked without reading files back.
+    ///
+    /// Only available in tests and with the `testing` feature.
+    #[cfg(any(test, feature = "testing"))]
+    pub fn in_memory(
+        languages: &HashSet<&'static str>,
+    ) -> (Self, HashMap<&'static str, MemPieces>) {
+        let mut pieces = HashMap::with_capacity(languages.len());
+        let langfiles = Self::with_factory(languages, |lang| {
+            let w = MemWriter::with_pieces(lang, MemPieces::default());
+            pieces.insert(lang, w.pieces());
+            Ok(Box::new(w))
+        })
+        // MemWriter creation can't fail
+        .unwrap();
+
+        (langfiles, pieces)
+    }
+
+    /// Add a text writer of short sentences (`<lang>_short.txt`) for each language of the LangFiles.
+    ///
+    /// `dst`, `compression`, `layout` and `naming` should be the ones used to create the LangFiles,
+    /// so that short sentences end up next to the other files of their language.
+    /// Short files are not split into parts.
+    pub fn with_short_writers(
+        mut self,
+        dst: &Path,
+        compression: Option<Compression>,
+        layout: LayoutStrategy,
+        naming: &FileNaming,
+    ) -> Result<Self, error::Error> {
+        for lang in self.writers.keys() {
+            let w = TextWriter::with_stem(
+                &layout.lang_dir(dst, lang)?,
+                &format!("{}_short", naming.stem(lang)),
+                None,
+                compression,
+            )
+            .with_extension(naming.extension())
+            .with_bom(self.write_bom);
+            self.short_writers.insert(*lang, Arc::new(Mutex::new(w)));
+        }
+
+        Ok(self)
+    }
+
+    /// Move files from the staging folder of `staging` to its destination once they're complete
+    /// (see [LangFiles::close]), so that files in the destination are never partial.
+    ///
+    /// The LangFiles (and its short sentences writers) should write in the staging folder,
+    /// that is be created with [Staging::dir] as destination.
+    ///
+    /// Staged files never replace existing ones, so files can't be appended to across runs:
+    /// closing fails if a destination file already exists.
+    pub fn with_staging(mut self, staging: Option<Staging>) -> Self {
+        self.staging = staging;
+        self
+    }
+
+    /// Write a UTF-8 byte order mark at the start of each text file, short sentences files included
+    /// (see [TextWriter::with_bom]).
+    ///
+    /// Only text files get a BOM: metadata, JSON Lines and Parquet files are left as is.
+    /// The BOM isn't a line, so metadata offsets (and rebuilt documents) are not affected.
+    /// Defaults to `false`.
+    pub fn with_bom(mut self, write_bom: bool) -> Self {
+        self.write_bom = write_bom;
+        for writer in self.writers.values() {
+            writer.lock().unwrap().set_bom(write_bom);
+        }
+        for writer in self.short_writers.values() {
+            writer.lock().unwrap().set_bom(write_bom);
+        }
+        self
+    }
+
+    /// Set the number of significant digits of probabilities written in JSON Lines and metadata files
+    /// (see [JsonlWriter::with_prob_digits]).
+    ///
+    /// Parquet files are left as is. Defaults to [crate::io::writer::DEFAULT_PROB_DIGITS].
+    pub fn with_prob_digits(self, digits: usize) -> Self {
+        for writer in self.writers.values() {
+            writer.lock().unwrap().set_prob_digits(digits);
+        }
+        self
+    }
+
+    /// Cap the output of each language (see [LangFiles::admit]).
+    ///
+    /// Usage starts from zero: files that already exist in the destination are not accounted for.
+    pub fn with_caps(mut self, caps: Option<LangCaps>) -> Self {
+        self.caps = caps.map(|caps| Capped {
+            caps,
+            usage: Mutex::new(HashMap::new()),
+        });
+        self
+    }
+
+    /// Keep the pieces that fit within the caps of their language (see [LangFiles::with_caps]),
+    /// dropping (and counting) the others. Kept pieces should then be written.
+    ///
+    /// A language is capped once its admitted amount reaches its cap:
+    /// the piece that reaches it is still admitted, so that a byte cap can be exceeded by a piece.
+    /// Admission is atomic, so that concurrent writers never admit more than their caps allow.
+    ///
+    /// Every piece is kept if the LangFiles has no caps.
+    pub fn admit(&self, mut pieces: Vec<MergedPiece>) -> Vec<MergedPiece> {
+        let capped = match &self.caps {
+            Some(capped) => capped,
+            None => return pieces,
+        };
+
+        let mut usage = capped.usage.lock().unwrap();
+        pieces.retain(|piece| {
+            let lang = piece.identification();
+            let cap = match capped.caps.cap(lang) {
+                Some(cap) => cap,
+                None => return true,
+            };
+            let (admitted, dropped) = usage.entry(lang).or_default();
+            if *admitted >= cap {
+                *dropped += 1;
+                false
+            } else {
+                *admitted += capped.caps.unit().amount(piece);
+                true
+            }
+        });
+        pieces
+    }
+
+    /// Get the number of pieces that have been dropped by caps (see [LangFiles::admit]),
+    /// for each capped language that dropped some.
+    pub fn capped_pieces(&self) -> HashMap<&'static str, usize> {
+        match &self.caps {
+            Some(capped) => capped
+                .usage
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(_, (_, dropped))| *dropped > 0)
+                .map(|(lang, (_, dropped))| (*lang, *dropped))
+                .collect(),
+            None => HashMap::new(),
+        }
     }
 
     /// Get a non-mutable reference to the writers.
-    pub fn writers(&self) -> &HashMap<&'static str, Arc<Mutex<Writer>>> {
+    pub fn writers(&self) -> &HashMap<&'static str, Arc<Mutex<LangWriter>>> {
         &self.writers
     }
 
+    /// Get a non-mutable reference to the short sentences writers.
+    ///
+    /// Empty unless [LangFiles::with_short_writers] has been called.
+    pub fn short_writers(&self) -> &HashMap<&'static str, Arc<Mutex<TextWriter>>> {
+        &self.short_writers
+    }
+
+    /// Get the languages for which at least one piece has been written, sorted by label.
+    ///
+    /// Languages whose writes only failed are not included.
+    /// Short sentences (see [LangFiles::with_short_writers]) are not accounted for.
+    pub fn nonempty_languages(&self) -> Vec<&'static str> {
+        let mut langs: Vec<&'static str> = self.written.lock().unwrap().iter().copied().collect();
+        langs.sort_unstable();
+        langs
+    }
+
     /// Fix open metadata files by removing trailing comma and closing the array.
+    ///
+    /// Short sentences files are closed too.
+    /// Prefer [LangFiles::close], that ensures that nothing is written afterwards.
     pub fn close_meta(&self) -> Result<(), error::Error> {
         for writer in self.writers.values() {
             let mut writer_lock = writer.lock().unwrap();
             writer_lock.close_meta()?;
         }
+        for writer in self.short_writers.values() {
+            writer.lock().unwrap().close_file()?;
+        }
+        self.closed.store(true, Ordering::Relaxed);
         Ok(())
     }
+
+    /// Finalize every file (see [LangFiles::close_meta]), once every write is done.
+    ///
+    /// If files are staged (see [LangFiles::with_staging]), they're then moved into their destination.
+    ///
+    /// # Errors
+    /// Returns the first error encountered while finalizing or moving a file.
+    /// Staged files are left in the staging folder if they could not be finalized.
+    /// The LangFiles is considered closed anyway, since it can't be retried.
+    pub fn close(self) -> Result<(), error::Error> {
+        let result = self.close_meta();
+        self.closed.store(true, Ordering::Relaxed);
+        result?;
+        match &self.staging {
+            Some(staging) => staging.publish(),
+            None => Ok(()),
+        }
+    }
+
+    /// Check if the LangFiles has been closed (see [LangFiles::close]).
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for LangFiles {
+    fn drop(&mut self) {
+        if !self.is_closed() {
+            error!(
+                "language files dropped without being closed: metadata and compressed files of {} languages may be incomplete",
+                self.writers.len()
+            );
+        }
+    }
 }
 
 impl LangFilesDoc {
@@ -70,12 +715,133 @@ impl LangFilesDoc {
     /// Also keep in mind that [Self::close_meta] has to be called once every write is done.
     ///
     // [Self::close_meta] could be integrated in an `impl Drop`
-    pub fn new(dst: &Path, part_size_bytes: Option<u64>) -> Result<Self, error::Error> {
-        let mut writers = HashMap::with_capacity(LANG.len());
-        let mut w;
-        for lang in LANG.iter() {
-            w = WriterDoc::new(dst, lang, part_size_bytes)?;
-            let lang = Lang::from_str(lang)?;
+    ///
+    /// `layout` sets where files are put in `dst`.
+    pub fn new(
+        dst: &Path,
+        part_size_bytes: Option<u64>,
+        layout: LayoutStrategy,
+    ) -> Result<Self, error::Error> {
+        Self::with_languages(dst, &LANG, part_size_bytes, layout)
+    }
+
+    /// Create a new LangFilesDoc holding writers for `languages` only (see [Self::new]).
+    ///
+    /// # Errors
+    /// Returns an error if a label is not a [Lang].
+    pub fn with_languages(
+        dst: &Path,
+        languages: &HashSet<&'static str>,
+        part_size_bytes: Option<u64>,
+        layout: LayoutStrategy,
+    ) -> Result<Self, error::Error> {
+        Self::with_naming(
+            dst,
+            languages,
+            part_size_bytes,
+            layout,
+            LangNaming::default(),
+        )
+    }
+
+    /// Create a new LangFilesDoc holding writers for `languages` only,
+    /// whose files are named following `naming` (see [Self::new]).
+    ///
+    /// # Errors
+    /// Returns an error if a label is not a [Lang]. No folder is created then.
+    pub fn with_naming(
+        dst: &Path,
+        languages: &HashSet<&'static str>,
+        part_size_bytes: Option<u64>,
+        layout: LayoutStrategy,
+        naming: LangNaming,
+    ) -> Result<Self, error::Error> {
+        Self::with_format(
+            dst,
+            languages,
+            part_size_bytes,
+            layout,
+            naming,
+            DocFormat::default(),
+        )
+    }
+
+    /// Create a new LangFilesDoc holding writers for `languages` only, writing files in `format`
+    /// named following `naming` (see [Self::new]).
+    ///
+    /// # Errors
+    /// Returns an error if a label is not a [Lang], or if the row group size of a [DocFormat::Parquet] is 0.
+    pub fn with_format(
+        dst: &Path,
+        languages: &HashSet<&'static str>,
+        part_size_bytes: Option<u64>,
+        layout: LayoutStrategy,
+        naming: LangNaming,
+        format: DocFormat,
+    ) -> Result<Self, error::Error> {
+        Self::create(
+            dst,
+            languages,
+            part_size_bytes,
+            layout,
+            naming,
+            format,
+            false,
+        )
+    }
+
+    /// Create a new LangFilesDoc whose writers append to existing files (see [Self::with_naming]),
+    /// in order to add documents to an existing corpus.
+    ///
+    /// # Errors
+    /// See [Self::with_naming].
+    pub fn open_append(
+        dst: &Path,
+        languages: &HashSet<&'static str>,
+        part_size_bytes: Option<u64>,
+        layout: LayoutStrategy,
+        naming: LangNaming,
+    ) -> Result<Self, error::Error> {
+        Self::create(
+            dst,
+            languages,
+            part_size_bytes,
+            layout,
+            naming,
+            DocFormat::Jsonl,
+            true,
+        )
+    }
+
+    fn create(
+        dst: &Path,
+        languages: &HashSet<&'static str>,
+        part_size_bytes: Option<u64>,
+        layout: LayoutStrategy,
+        naming: LangNaming,
+        format: DocFormat,
+        append: bool,
+    ) -> Result<Self, error::Error> {
+        let names = languages
+            .iter()
+            .map(|lang| {
+                let lang = Lang::from_str(lang)?;
+                Ok((lang, naming.name(lang)))
+            })
+            .collect::<Result<Vec<(Lang, &'static str)>, error::Error>>()?;
+
+        let mut writers = HashMap::with_capacity(names.len());
+        for (lang, name) in names {
+            let dir = layout.lang_dir(dst, name)?;
+            let w = match format {
+                DocFormat::Jsonl => DocWriter::Jsonl(
+                    WriterDoc::new(&dir, name, part_size_bytes)?.with_append(append),
+                ),
+                DocFormat::Parquet { row_group_size } => DocWriter::Parquet(Box::new(
+                    ParquetWriter::with_row_group_size(&dir, lang.to_static(), row_group_size)?
+                        .with_stem(name),
+                )),
+            };
             writers.insert(lang, Arc::new(Mutex::new(w)));
         }
 
@@ -83,7 +849,7 @@ impl LangFilesDoc {
     }
 
     /// Get a non-mutable reference to the writers.
-    pub fn writers(&self) -> &HashMap<Lang, Arc<Mutex<WriterDoc>>> {
+    pub fn writers(&self) -> &HashMap<Lang, Arc<Mutex<DocWriter>>> {
         &self.writers
     }
 
@@ -100,7 +866,13 @@ impl LangFilesDoc {
 #[cfg(test)]
 mod tests {
 
-    use std::{fs::File, path::PathBuf};
+    use std::{
+        fs::File,
+        io::{BufRead, BufReader, Read, Write},
+        path::PathBuf,
+    };
+
+    use rayon::prelude::*;
 
     use crate::{
         identifiers::Identification,
@@ -123,6 +895,10 @@ mod tests {
         MergedPiece {
             sentences,
             identification,
+            confidence: 1.0,
+            line_ranges: Vec::new(),
+            source: None,
+            shard_location: None,
             headers,
             nb_sentences,
         }
@@ -131,7 +907,14 @@ mod tests {
     fn init() {
         let dst = Path::new("dst_langfiles_init");
         std::fs::create_dir(dst).unwrap();
-        let _ = LangFiles::new(dst, Some(10));
+        let _ = LangFiles::new(
+            dst,
+            Some(10),
+            OutputFormat::TextMeta,
+            None,
+            LayoutStrategy::Flat,
+            &FileNaming::default(),
+        );
         std::fs::remove_dir_all(dst).unwrap();
     }
 
@@ -139,7 +922,15 @@ mod tests {
     fn write_one() {
         let dst = Path::new("dst_langfiles_write_one");
         std::fs::create_dir(dst).unwrap();
-        let langfiles = LangFiles::new(dst, Some(10)).unwrap();
+        let langfiles = LangFiles::new(
+            dst,
+            Some(10),
+            OutputFormat::TextMeta,
+            None,
+            LayoutStrategy::Flat,
+            &FileNaming::default(),
+        )
+        .unwrap();
 
         let sentences = "essai d'écriture
 de trois lignes
@@ -157,16 +948,591 @@ hehe :)"
         std::fs::remove_dir_all(dst).unwrap();
     }
 
+    #[test]
+    fn write_one_jsonl() {
+        let dst = tempdir().unwrap();
+        let langfiles = LangFiles::new(
+            dst.path(),
+            None,
+            OutputFormat::Jsonl,
+            None,
+            LayoutStrategy::Flat,
+            &FileNaming::default(),
+        )
+        .unwrap();
+
+        let headers = vec![(WarcHeader::ContentType, Vec::from("blogpost".as_bytes()))]
+            .into_iter()
+            .collect();
+        let mp = vec![create_merged_piece(
+            "hello\nworld".to_string(),
+            "en",
+            headers,
+        )];
+
+        let en_writer = langfiles.writers().get("en").unwrap().clone();
+        en_writer.lock().unwrap().write(mp).unwrap();
+        langfiles.close_meta().unwrap();
+
+        let content = std::fs::read_to_string(dst.path().join("en.jsonl")).unwrap();
+        let line: serde_json::Value = serde_json::from_str(content.trim_end()).unwrap();
+        assert_eq!(line["text"], "hello\nworld");
+        assert!(!dst.path().join("en.txt").exists());
+    }
+
+    #[test]
+    fn write_one_parquet() {
+        let dst = tempdir().unwrap();
+        let langfiles = LangFiles::new(
+            dst.path(),
+            None,
+            OutputFormat::Parquet { row_group_size: 10 },
+            None,
+            LayoutStrategy::Flat,
+            &FileNaming::default(),
+        )
+        .unwrap();
+
+        let mp = vec![create_merged_piece(
+            "hello\nworld".to_string(),
+            "en",
+            HashMap::new(),
+        )];
+
+        let en_writer = langfiles.writers().get("en").unwrap().clone();
+        en_writer.lock().unwrap().write(mp).unwrap();
+        langfiles.close_meta().unwrap();
+
+        assert!(dst.path().join("en.parquet").is_file());
+        assert!(!dst.path().join("en.txt").exists());
+        assert!(!dst.path().join("fr.parquet").exists());
+    }
+
+    #[test]
+    fn close() {
+        let languages = vec!["en", "fr"].into_iter().collect();
+        let (langfiles, _) = LangFiles::in_memory(&languages);
+        assert!(!langfiles.is_closed());
+        langfiles.close_meta().unwrap();
+        assert!(langfiles.is_closed());
+
+        let dst = tempdir().unwrap();
+        let langfiles = LangFiles::new(
+            dst.path(),
+            None,
+            OutputFormat::TextMeta,
+            Some(Compression::default()),
+            LayoutStrategy::Flat,
+            &FileNaming::default(),
+        )
+        .unwrap();
+        let mp = vec![create_merged_piece(
+            "hello\nworld".to_string(),
+            "en",
+            HashMap::new(),
+        )];
+        langfiles.writers()["en"].lock().unwrap().write(mp).unwrap();
+        langfiles.close().unwrap();
+
+        // gzip streams are complete once closed
+        let mut text = String::new();
+        flate2::read::GzDecoder::new(File::open(dst.path().join("en.txt.gz")).unwrap())
+            .read_to_string(&mut text)
+            .unwrap();
+        assert_eq!(text.trim_end(), "hello\nworld");
+    }
+
+    #[test]
+    fn in_memory() {
+        let languages = vec!["en", "fr"].into_iter().collect();
+        let (langfiles, pieces) = LangFiles::in_memory(&languages);
+
+        let mp = vec![create_merged_piece(
+            "hello\nworld".to_string(),
+            "en",
+            HashMap::new(),
+        )];
+        let en_writer = langfiles.writers().get("en").unwrap().clone();
+        en_writer.lock().unwrap().write(mp).unwrap();
+        langfiles.close_meta().unwrap();
+
+        let en = pieces["en"].lock().unwrap();
+        assert_eq!(en.len(), 1);
+        assert_eq!(en[0].sentences, "hello\nworld");
+        assert!(pieces["fr"].lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn with_factory_error() {
+        let languages = vec!["en", "fr"].into_iter().collect();
+        let langfiles = LangFiles::with_factory(&languages, |lang| match lang {
+            "fr" => Err(error::Error::Custom("no fr writer".to_string())),
+            lang => Ok(Box::new(MemWriter::new(Path::new(""), lang, None)?)),
+        });
+        assert!(langfiles.is_err());
+    }
+
+    #[test]
+    fn write_one_compressed() {
+        let dst = tempdir().unwrap();
+        let langfiles = LangFiles::new(
+            dst.path(),
+            None,
+            OutputFormat::TextMeta,
+            Some(Compression::default()),
+            LayoutStrategy::Flat,
+            &FileNaming::default(),
+        )
+        .unwrap();
+
+        let headers = vec![(WarcHeader::ContentType, Vec::from("blogpost".as_bytes()))]
+            .into_iter()
+            .collect();
+        let mp = vec![create_merged_piece(
+            "hello\nworld".to_string(),
+            "en",
+            headers,
+        )];
+
+        let en_writer = langfiles.writers().get("en").unwrap().clone();
+        en_writer.lock().unwrap().write(mp).unwrap();
+        langfiles.close_meta().unwrap();
+
+        assert!(dst.path().join("en.txt.gz").exists());
+        assert!(dst.path().join("en_meta.jsonl.gz").exists());
+        assert!(!dst.path().join("en.txt").exists());
+    }
+
+    #[test]
+    fn with_languages() {
+        let dst = tempdir().unwrap();
+        let languages = vec!["en", "fr"].into_iter().collect();
+        let langfiles = LangFiles::with_languages(
+            dst.path(),
+            &languages,
+            None,
+            OutputFormat::TextMeta,
+            None,
+            LayoutStrategy::Flat,
+            &FileNaming::default(),
+        )
+        .unwrap();
+
+        assert_eq!(langfiles.writers().len(), 2);
+        assert!(langfiles.writers().contains_key("fr"));
+        assert!(!langfiles.writers().contains_key("de"));
+    }
+
+    #[test]
+    fn nonempty_languages() {
+        let languages = vec!["de", "en", "fr"].into_iter().collect();
+        let (langfiles, _) = LangFiles::in_memory(&languages);
+        assert!(langfiles.nonempty_languages().is_empty());
+
+        let fr = create_merged_piece("bonjour".to_string(), "fr", HashMap::new());
+        langfiles.writers()["fr"]
+            .lock()
+            .unwrap()
+            .write(vec![fr.clone()])
+            .unwrap();
+        langfiles.writers()["en"]
+            .lock()
+            .unwrap()
+            .write(Vec::new())
+            .unwrap();
+        // failed writes are not accounted for
+        assert!(langfiles.writers()["de"]
+            .lock()
+            .unwrap()
+            .write_single(&fr)
+            .is_err());
+        assert_eq!(langfiles.nonempty_languages(), vec!["fr"]);
+
+        let en = create_merged_piece("hello".to_string(), "en", HashMap::new());
+        langfiles.writers()["en"]
+            .lock()
+            .unwrap()
+            .write_single(&en)
+            .unwrap();
+        assert_eq!(langfiles.nonempty_languages(), vec!["en", "fr"]);
+        langfiles.close().unwrap();
+
+        // languages sharing a writer are tracked from pieces
+        let dst = tempdir().unwrap();
+        let langfiles = LangFiles::with_languages(
+            dst.path(),
+            &languages,
+            None,
+            OutputFormat::Combined,
+            None,
+            LayoutStrategy::Flat,
+            &FileNaming::default(),
+        )
+        .unwrap();
+        langfiles.writers()["fr"]
+            .lock()
+            .unwrap()
+            .write(vec![en])
+            .unwrap();
+        assert_eq!(langfiles.nonempty_languages(), vec!["en"]);
+        langfiles.close().unwrap();
+    }
+
+    #[test]
+    fn caps() {
+        let pieces = |lang, nb| {
+            (0..nb)
+                .map(|_| create_merged_piece("abcd".to_string(), lang, HashMap::new()))
+                .collect::<Vec<_>>()
+        };
+        let languages = vec!["de", "en", "fr"].into_iter().collect();
+
+        // no caps
+        let (langfiles, _) = LangFiles::in_memory(&languages);
+        assert_eq!(langfiles.admit(pieces("en", 3)).len(), 3);
+        assert!(langfiles.capped_pieces().is_empty());
+
+        assert!(LangCaps::new(CapUnit::Pieces, vec![("xx", 1)].into_iter().collect()).is_err());
+        let caps = LangCaps::new(CapUnit::Pieces, vec![("en", 2)].into_iter().collect())
+            .unwrap()
+            .with_default(Some(3));
+        let (langfiles, _) = LangFiles::in_memory(&languages);
+        let langfiles = langfiles.with_caps(Some(caps));
+        let mut batch = pieces("en", 3);
+        batch.extend(pieces("fr", 2));
+        assert_eq!(langfiles.admit(batch).len(), 4);
+        assert_eq!(langfiles.admit(pieces("en", 1)).len(), 0);
+        assert_eq!(langfiles.admit(pieces("fr", 2)).len(), 1);
+        assert_eq!(
+            langfiles.capped_pieces(),
+            vec![("en", 2), ("fr", 1)].into_iter().collect()
+        );
+
+        // the piece reaching a byte cap is still admitted
+        let caps = LangCaps::new(CapUnit::Bytes, vec![("en", 6)].into_iter().collect()).unwrap();
+        let (langfiles, _) = LangFiles::in_memory(&languages);
+        let langfiles = langfiles.with_caps(Some(caps));
+        assert_eq!(langfiles.admit(pieces("en", 3)).len(), 2);
+        assert_eq!(langfiles.admit(pieces("de", 3)).len(), 3);
+        assert_eq!(
+            langfiles.capped_pieces(),
+            vec![("en", 1)].into_iter().collect()
+        );
+        langfiles.close().unwrap();
+    }
+
+    #[test]
+    fn short_writers() {
+        let dst = tempdir().unwrap();
+        let languages = vec!["en", "fr"].into_iter().collect();
+        let langfiles = LangFiles::with_languages(
+            dst.path(),
+            &languages,
+            None,
+            OutputFormat::TextMeta,
+            None,
+            LayoutStrategy::PerLangDir,
+            &FileNaming::default(),
+        )
+        .unwrap();
+        assert!(langfiles.short_writers().is_empty());
+
+        let langfiles = langfiles
+            .with_short_writers(
+                dst.path(),
+                None,
+                LayoutStrategy::PerLangDir,
+                &FileNaming::default(),
+            )
+            .unwrap();
+        assert_eq!(langfiles.short_writers().len(), 2);
+        assert!(!langfiles.short_writers().contains_key("de"));
+
+        let en_writer = langfiles.short_writers().get("en").unwrap().clone();
+        en_writer.lock().unwrap().write_all(b"hi").unwrap();
+        langfiles.close_meta().unwrap();
+
+        let content = std::fs::read_to_string(dst.path().join("en").join("en_short.txt")).unwrap();
+        assert_eq!(content, "hi\n\n");
+        assert!(!dst.path().join("en").join("en.txt").exists());
+    }
+
+    #[test]
+    fn staging() {
+        let dst = tempfile::tempdir().unwrap();
+        let staging = Staging::new(dst.path(), None).unwrap();
+        let langs = ["fr"].into_iter().collect();
+        let langfiles = LangFiles::with_languages(
+            staging.dir(),
+            &langs,
+            None,
+            OutputFormat::TextMeta,
+            None,
+            LayoutStrategy::PerLangDir,
+            &FileNaming::default(),
+        )
+        .unwrap()
+        .with_staging(Some(staging.clone()));
+
+        let piece = create_merged_piece("Bonjour".to_string(), "fr", HashMap::new());
+        langfiles.writers()["fr"]
+            .lock()
+            .unwrap()
+            .write(vec![piece])
+            .unwrap();
+        let fr_dir = dst.path().join("fr");
+        assert!(!fr_dir.exists());
+        assert!(staging.dir().join("fr").join("fr.txt").exists());
+
+        langfiles.close().unwrap();
+        assert!(std::fs::read_to_string(fr_dir.join("fr.txt"))
+            .unwrap()
+            .starts_with("Bonjour"));
+        assert!(fr_dir.join("fr_meta.jsonl").exists());
+        assert!(!staging.dir().exists());
+    }
+
+    #[test]
+    fn bom() {
+        let dst = tempdir().unwrap();
+        let langs = ["fr"].into_iter().collect();
+        let langfiles = LangFiles::with_languages(
+            dst.path(),
+            &langs,
+            None,
+            OutputFormat::TextMeta,
+            None,
+            LayoutStrategy::Flat,
+            &FileNaming::default(),
+        )
+        .unwrap()
+        .with_bom(true)
+        .with_short_writers(
+            dst.path(),
+            None,
+            LayoutStrategy::Flat,
+            &FileNaming::default(),
+        )
+        .unwrap();
+
+        let pieces = vec![
+            create_merged_piece("Bonjour\nToi".to_string(), "fr", HashMap::new()),
+            create_merged_piece("Salut".to_string(), "fr", HashMap::new()),
+        ];
+        langfiles.writers()["fr"]
+            .lock()
+            .unwrap()
+            .write(pieces)
+            .unwrap();
+        langfiles.short_writers()["fr"]
+            .lock()
+            .unwrap()
+            .write_all(b"Oui")
+            .unwrap();
+        langfiles.close().unwrap();
+
+        let text = std::fs::read_to_string(dst.path().join("fr.txt")).unwrap();
+        assert_eq!(text, "\u{feff}Bonjour\nToi\n\nSalut\n\n");
+        let short = std::fs::read_to_string(dst.path().join("fr_short.txt")).unwrap();
+        assert!(short.starts_with('\u{feff}'));
+
+        // offsets still count lines from the start of the file
+        let meta = std::fs::read_to_string(dst.path().join("fr_meta.jsonl")).unwrap();
+        assert!(!meta.starts_with('\u{feff}'));
+        let offsets: Vec<_> = meta
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["offset"].clone())
+            .collect();
+        assert_eq!(offsets, [0, 3]);
+    }
+
+    #[test]
+    fn prob_digits() {
+        let dst = tempdir().unwrap();
+        let langs = ["en", "fr"].into_iter().collect();
+        let langfiles = LangFiles::with_languages(
+            dst.path(),
+            &langs,
+            None,
+            OutputFormat::Combined,
+            None,
+            LayoutStrategy::Flat,
+            &FileNaming::default(),
+        )
+        .unwrap()
+        .with_prob_digits(2);
+
+        for lang in ["en", "fr"] {
+            let mut piece = create_merged_piece("hello".to_string(), lang, HashMap::new());
+            piece.confidence = 0.876;
+            langfiles.writers()[lang]
+                .lock()
+                .unwrap()
+                .write_single(&piece)
+                .unwrap();
+        }
+        langfiles.close_meta().unwrap();
+
+        let content =
+            std::fs::read_to_string(dst.path().join(crate::io::writer::COMBINED_FILE)).unwrap();
+        assert_eq!(content.matches(r#""confidence":0.88"#).count(), 2);
+    }
+
+    #[test]
+    fn per_lang_dir() {
+        let dst = tempdir().unwrap();
+        let languages = vec!["en", "fr"].into_iter().collect();
+        let langfiles = LangFiles::with_languages(
+            dst.path(),
+            &languages,
+            None,
+            OutputFormat::Jsonl,
+            None,
+            LayoutStrategy::PerLangDir,
+            &FileNaming::default(),
+        )
+        .unwrap();
+
+        let headers = vec![(WarcHeader::ContentType, Vec::from("blogpost".as_bytes()))]
+            .into_iter()
+            .collect();
+        let mp = vec![create_merged_piece("hello".to_string(), "en", headers)];
+        let en_writer = langfiles.writers().get("en").unwrap().clone();
+        en_writer.lock().unwrap().write(mp).unwrap();
+
+        assert!(dst.path().join("en").join("en.jsonl").is_file());
+        assert!(dst.path().join("fr").is_dir());
+        assert!(!dst.path().join("en.jsonl").exists());
+    }
+
+    #[test]
+    fn file_naming_invalid() {
+        assert!(FileNaming::new("", "", "").is_err());
+        assert!(FileNaming::new("", "", ".txt").is_err());
+        assert!(FileNaming::new("oscar/", "", "txt").is_err());
+        assert_eq!(
+            FileNaming::new("", "", "txt").unwrap(),
+            FileNaming::default()
+        );
+    }
+
+    #[test]
+    fn file_naming() {
+        let dst = tempdir().unwrap();
+        let languages = vec!["en"].into_iter().collect();
+        let naming = FileNaming::new("oscar_", "_v1", "raw").unwrap();
+        let langfiles = LangFiles::with_languages(
+            dst.path(),
+            &languages,
+            None,
+            OutputFormat::TextMeta,
+            None,
+            LayoutStrategy::Flat,
+            &naming,
+        )
+        .unwrap()
+        .with_short_writers(dst.path(), None, LayoutStrategy::Flat, &naming)
+        .unwrap();
+
+        let headers = vec![(WarcHeader::ContentType, Vec::from("blogpost".as_bytes()))]
+            .into_iter()
+            .collect();
+        let mp = vec![create_merged_piece("hello".to_string(), "en", headers)];
+        let en_writer = langfiles.writers().get("en").unwrap().clone();
+        en_writer.lock().unwrap().write(mp).unwrap();
+        langfiles.short_writers()["en"]
+            .lock()
+            .unwrap()
+            .write_all(b"hi\n")
+            .unwrap();
+        langfiles.close().unwrap();
+
+        let mut names: Vec<String> = dst
+            .path()
+            .read_dir()
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(
+            names,
+            vec![
+                "oscar_en_v1.raw",
+                "oscar_en_v1_meta.jsonl",
+                "oscar_en_v1_short.raw"
+            ]
+        );
+    }
+
+    #[test]
+    fn combined() {
+        let dst = tempdir().unwrap();
+        let languages = vec!["en", "fr"].into_iter().collect();
+        let langfiles = LangFiles::with_languages(
+            dst.path(),
+            &languages,
+            None,
+            OutputFormat::Combined,
+            None,
+            LayoutStrategy::PerLangDir,
+            &FileNaming::default(),
+        )
+        .unwrap();
+
+        // concurrent writes of both languages
+        let sentences = "a line\n".repeat(1000);
+        (0..100).into_par_iter().for_each(|i| {
+            let lang = if i % 2 == 0 { "en" } else { "fr" };
+            let mp = vec![create_merged_piece(sentences.clone(), lang, HashMap::new()); 3];
+            let writer = langfiles.writers().get(lang).unwrap();
+            writer.lock().unwrap().write(mp).unwrap();
+        });
+        langfiles.close().unwrap();
+
+        // no language file nor folder
+        let entries: Vec<_> = dst.path().read_dir().unwrap().collect();
+        assert_eq!(entries.len(), 1);
+
+        let f = File::open(dst.path().join(crate::io::writer::COMBINED_FILE)).unwrap();
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        for line in BufReader::new(f).lines() {
+            let line: serde_json::Value = serde_json::from_str(&line.unwrap()).unwrap();
+            assert_eq!(line["text"], sentences.as_str());
+            let lang = line["meta"]["identification"].as_str().unwrap().to_string();
+            *counts.entry(lang).or_default() += 1;
+        }
+        assert_eq!(counts["en"], 150);
+        assert_eq!(counts["fr"], 150);
+    }
+
     #[test]
     fn init_doc() {
         let dst = tempdir().unwrap();
-        LangFilesDoc::new(dst.path(), None).unwrap();
+        LangFilesDoc::new(dst.path(), None, LayoutStrategy::Flat).unwrap();
+    }
+
+    #[test]
+    fn init_doc_naming() {
+        let dst = tempdir().unwrap();
+        let languages = vec!["en", "multi"].into_iter().collect();
+        let lf = LangFilesDoc::with_naming(
+            dst.path(),
+            &languages,
+            None,
+            LayoutStrategy::PerLangDir,
+            LangNaming::Iso639_3,
+        )
+        .unwrap();
+        assert!(lf.writers().get(&Lang::En).is_some());
+        assert!(dst.path().join("eng").is_dir());
+        assert!(dst.path().join("multi").is_dir());
+        assert!(!dst.path().join("en").exists());
     }
 
     #[test]
     fn write_one_doc() {
         let dst = tempdir().unwrap();
-        let lf = LangFilesDoc::new(dst.path(), None).unwrap();
+        let lf = LangFilesDoc::new(dst.path(), None, LayoutStrategy::Flat).unwrap();
 
         let content = "Hello!".to_string();
 
@@ -192,7 +1558,7 @@ hehe :)"
             .clone();
 
         if let Ok(mut w) = w.try_lock() {
-            w.write(docs.to_vec()).unwrap();
+            w.write(docs.to_vec(), &[]).unwrap();
         }
 
         let mut read_path = PathBuf::from(dst.path());
@@ -203,4 +1569,81 @@ hehe :)"
 
         assert_eq!(doc_from_file, docs[0]);
     }
+
+    #[test]
+    fn append_doc() {
+        let dst = tempdir().unwrap();
+        let languages = vec!["en"].into_iter().collect();
+        let id = Identification::new(Lang::En, 1.0);
+        let doc = |content: &str| {
+            Document::new(
+                content.to_string(),
+                HashMap::new(),
+                Metadata::new(&id, &[Some(id.clone())]),
+            )
+        };
+
+        // a first run, then an appending one
+        for (content, append) in [("Hello!", false), ("Hello again!", true)] {
+            let lf = if append {
+                LangFilesDoc::open_append(
+                    dst.path(),
+                    &languages,
+                    None,
+                    LayoutStrategy::Flat,
+                    LangNaming::default(),
+                )
+            } else {
+                LangFilesDoc::with_languages(dst.path(), &languages, None, LayoutStrategy::Flat)
+            }
+            .unwrap();
+            let writer = lf.writers().get(&Lang::En).unwrap();
+            writer
+                .lock()
+                .unwrap()
+                .write(vec![doc(content)], &[])
+                .unwrap();
+            lf.close_meta().unwrap();
+        }
+
+        let f = File::open(dst.path().join("en_meta.jsonl")).unwrap();
+        let docs: Vec<Document> = BufReader::new(f)
+            .lines()
+            .map(|l| serde_json::from_str(&l.unwrap()).unwrap())
+            .collect();
+        assert_eq!(docs, vec![doc("Hello!"), doc("Hello again!")]);
+    }
+
+    #[test]
+    fn write_parquet_doc() {
+        let dst = tempdir().unwrap();
+        let languages = vec!["en"].into_iter().collect();
+        let lf = LangFilesDoc::with_format(
+            dst.path(),
+            &languages,
+            None,
+            LayoutStrategy::Flat,
+            LangNaming::Iso639_3,
+            DocFormat::Parquet { row_group_size: 10 },
+        )
+        .unwrap();
+
+        let id = Identification::new(Lang::En, 1.0);
+        let doc = Document::new(
+            "Hello!".to_string(),
+            HashMap::new(),
+            Metadata::new(&id, &[Some(id.clone())]),
+        );
+        let location = Location::new(0, "<urn:uuid:0>".to_string(), 0, 0, 0);
+
+        let mut writer = lf.writers()[&Lang::En].lock().unwrap();
+        // every document needs a location
+        assert!(writer.write(vec![doc.clone()], &[]).is_err());
+        writer.write(vec![doc], &[location]).unwrap();
+        drop(writer);
+        lf.close_meta().unwrap();
+
+        assert!(dst.path().join("eng.parquet").is_file());
+        assert!(!dst.path().join("eng_meta.jsonl").exists());
+    }
 }
@@ -5,9 +5,22 @@ Textual/contextual data saving and loading.
 
 Currently only saving is implemented but loading is planned in order to facilitate operations on already generated corpora.
 !*/
+mod langchannels;
 mod langfiles;
 pub mod reader;
+mod staging;
 pub mod writer;
+pub use langchannels::LangChannels;
+pub use langfiles::CapUnit;
+pub use langfiles::DocFormat;
+pub use langfiles::DocWriter;
+pub use langfiles::FileNaming;
+pub use langfiles::LangCaps;
 pub use langfiles::LangFiles;
 pub use langfiles::LangFilesDoc;
+pub use langfiles::LangWriter;
+pub use langfiles::LayoutStrategy;
+pub use langfiles::OutputFormat;
+pub use staging::Staging;
+pub use staging::STAGING_DIR;
 pub use writer::Writer;
@@ -12,7 +12,7 @@ use super::{metareader::MetaReader, textreader::TextReader};
 /// Analoguous to [MergedPiece] but containing [Metadata].
 ///
 /// Is convertible to [MergedPiece].  
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct PieceMeta {
     pub sentences: Vec<String>,
     pub headers: Metadata,
@@ -31,6 +31,10 @@ impl From<PieceMeta> for MergedPiece {
             sentences: pm.sentences.join("\n"),
             nb_sentences: pm.headers.nb_sentences,
             identification: pm.identification,
+            confidence: pm.headers.confidence,
+            line_ranges: pm.headers.line_ranges,
+            source: pm.headers.source,
+            shard_location: None,
         }
     }
 }
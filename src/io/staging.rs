@@ -0,0 +1,166 @@
+/*! Staging of output files.
+
+Files written by a [super::LangFiles] only become complete once it is closed,
+so that readers picking up files of a running pipeline may read partial ones.
+
+A [Staging] holds files in a separate folder while they're written,
+and moves them into their destination once they're complete (see [super::LangFiles::with_staging]).
+!*/
+use std::path::{Path, PathBuf};
+
+use log::{debug, warn};
+
+use crate::error::Error;
+
+/// Name of the default staging folder, in the destination folder.
+pub const STAGING_DIR: &str = ".staging";
+
+/// Staging folder of files that are to be moved into a destination folder once complete.
+///
+/// Files are moved by renaming them, which is atomic as long as the staging folder
+/// is on the same filesystem as the destination: the default staging folder (`<dst>/.staging`) always is.
+/// Files staged on another filesystem are copied (then removed) instead, and a warning is logged.
+#[derive(Debug, Clone)]
+pub struct Staging {
+    dir: PathBuf,
+    dst: PathBuf,
+}
+
+impl Staging {
+    /// Stage files of `dst` in `dir`, defaulting to [STAGING_DIR] in `dst`.
+    ///
+    /// The staging folder is created if needed.
+    ///
+    /// # Errors
+    /// Returns an error if the staging folder can't be created, or if it is not empty
+    /// (such as after an interrupted run, whose partial files have to be removed manually).
+    pub fn new(dst: &Path, dir: Option<&Path>) -> Result<Self, Error> {
+        let dir = dir.map_or_else(|| dst.join(STAGING_DIR), Path::to_path_buf);
+        std::fs::create_dir_all(&dir)?;
+        if std::fs::read_dir(&dir)?.next().is_some() {
+            return Err(Error::Custom(format!(
+                "staging folder {:?} is not empty: remove leftover files of a previous run",
+                dir
+            )));
+        }
+
+        Ok(Self {
+            dir,
+            dst: dst.to_path_buf(),
+        })
+    }
+
+    /// Get the staging folder, where files should be written.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Get the destination folder.
+    pub fn dst(&self) -> &Path {
+        &self.dst
+    }
+
+    /// Move every staged file into the destination folder, keeping its path relative to the staging folder.
+    ///
+    /// The staging folder is removed once empty.
+    ///
+    /// # Errors
+    /// Returns an error if a file can't be moved, or if it would replace an existing file:
+    /// remaining files are then left in the staging folder.
+    pub fn publish(&self) -> Result<(), Error> {
+        Self::publish_dir(&self.dir, &self.dst)?;
+        std::fs::remove_dir(&self.dir)?;
+        Ok(())
+    }
+
+    /// Move the content of `src` into `dst`, removing emptied subfolders of `src`.
+    fn publish_dir(src: &Path, dst: &Path) -> Result<(), Error> {
+        std::fs::create_dir_all(dst)?;
+        for entry in std::fs::read_dir(src)? {
+            let entry = entry?;
+            let from = entry.path();
+            let to = dst.join(entry.file_name());
+            if entry.file_type()?.is_dir() {
+                Self::publish_dir(&from, &to)?;
+                std::fs::remove_dir(&from)?;
+            } else {
+                Self::move_file(&from, &to)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Move the file at `from` to `to`, copying it if they're not on the same filesystem.
+    fn move_file(from: &Path, to: &Path) -> Result<(), Error> {
+        if to.exists() {
+            return Err(Error::Custom(format!(
+                "could not publish {:?}: {:?} already exists",
+                from, to
+            )));
+        }
+
+        debug!("publishing {:?} to {:?}", from, to);
+        match std::fs::rename(from, to) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {
+                warn!(
+                    "{:?} is not on the same filesystem as {:?}: copying it, readers may see a partial file",
+                    from, to
+                );
+                std::fs::copy(from, to)?;
+                std::fs::remove_file(from)?;
+                Ok(())
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Staging, STAGING_DIR};
+
+    #[test]
+    fn publish() {
+        let dst = tempfile::tempdir().unwrap();
+        let staging = Staging::new(dst.path(), None).unwrap();
+        assert_eq!(staging.dir(), dst.path().join(STAGING_DIR));
+
+        std::fs::create_dir(staging.dir().join("fr")).unwrap();
+        std::fs::write(staging.dir().join("fr").join("fr.txt"), "bonjour").unwrap();
+        std::fs::write(staging.dir().join("en.txt"), "hello").unwrap();
+        assert!(!dst.path().join("en.txt").exists());
+
+        staging.publish().unwrap();
+        assert_eq!(
+            std::fs::read_to_string(dst.path().join("fr").join("fr.txt")).unwrap(),
+            "bonjour"
+        );
+        assert_eq!(
+            std::fs::read_to_string(dst.path().join("en.txt")).unwrap(),
+            "hello"
+        );
+        assert!(!staging.dir().exists());
+    }
+
+    #[test]
+    fn publish_existing() {
+        let dst = tempfile::tempdir().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let staging = Staging::new(dst.path(), Some(dir.path())).unwrap();
+
+        std::fs::write(dst.path().join("en.txt"), "previous").unwrap();
+        std::fs::write(staging.dir().join("en.txt"), "hello").unwrap();
+
+        // existing files are never replaced
+        assert!(staging.publish().is_err());
+        assert_eq!(
+            std::fs::read_to_string(dst.path().join("en.txt")).unwrap(),
+            "previous"
+        );
+        assert!(staging.dir().join("en.txt").exists());
+
+        // leftovers prevent staging again
+        assert!(Staging::new(dst.path(), Some(dir.path())).is_err());
+    }
+}
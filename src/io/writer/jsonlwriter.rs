@@ -0,0 +1,341 @@
+/*! JSON Lines writer for a given language.
+
+Writes each [MergedPiece] as a single JSON object (`{"text": ..., "meta": {...}}`) in a `lang.jsonl` file.
+As with [super::Writer], identification is checked, preventing the writing of differently identified [MergedPiece] into a given language writer.
+
+A combined writer ([JsonlWriter::combined]) writes pieces of every language in a single [COMBINED_FILE] file,
+each line carrying its language in `meta.identification`.
+
+Probabilities (such as `meta.confidence`) are rounded to a number of significant digits
+(see [JsonlWriter::with_prob_digits]), so that a `0.8` confidence is written as `0.8` rather than `0.800000011920929`.
+!*/
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use flate2::Compression;
+use serde::{Serialize, Serializer};
+use warc::WarcHeader;
+
+use crate::error;
+use crate::pipelines::oscarmeta::types::{MergedPiece, Metadata, Source};
+
+use super::{OutputFile, WriterTrait};
+
+/// Name of the file written by a combined writer (see [JsonlWriter::combined]).
+pub const COMBINED_FILE: &str = "corpus.jsonl";
+
+/// Default number of significant digits of serialized probabilities (see [JsonlWriter::with_prob_digits]).
+pub const DEFAULT_PROB_DIGITS: usize = 4;
+
+/// Probability, serialized with a given number of significant digits.
+pub(super) struct Probability {
+    pub(super) value: f32,
+    pub(super) digits: usize,
+}
+
+impl Probability {
+    /// Round the probability to its number of significant digits (at least one).
+    ///
+    /// Rounding goes through the decimal representation of the probability,
+    /// so that the rounded value is the closest float to a short decimal number (`0.8` rather than `0.800000011920929`),
+    /// which serde_json then writes as is.
+    /// Non-finite values are left as is.
+    pub(super) fn rounded(&self) -> f64 {
+        if !self.value.is_finite() {
+            return f64::from(self.value);
+        }
+        let precision = self.digits.max(1) - 1;
+        format!("{:.*e}", precision, self.value)
+            .parse()
+            .unwrap_or_else(|_| f64::from(self.value))
+    }
+}
+
+impl Serialize for Probability {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f64(self.rounded())
+    }
+}
+
+/// Serialized form of a [MergedPiece].
+#[derive(Serialize)]
+struct Entry<'a> {
+    text: &'a str,
+    meta: EntryMeta<'a>,
+}
+
+#[derive(Serialize)]
+struct EntryMeta<'a> {
+    headers: HashMap<WarcHeader, String>,
+    identification: &'a str,
+    nb_sentences: usize,
+    nb_chars: usize,
+    confidence: Probability,
+    line_ranges: &'a [(usize, usize)],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source: Option<&'a Source>,
+}
+
+pub struct JsonlWriter {
+    path: PathBuf,
+    file: Option<OutputFile>,
+    /// language of pieces, [None] for combined writers
+    lang: Option<&'static str>,
+    compression: Option<Compression>,
+    prob_digits: usize,
+}
+
+impl JsonlWriter {
+    /// Create a new JsonlWriter for provided language, gzipping the file (`lang.jsonl.gz`) if `compression` is set.
+    pub fn with_compression(
+        dst: &Path,
+        lang: &'static str,
+        compression: Option<Compression>,
+    ) -> Self {
+        let suffix = OutputFile::suffix(compression);
+        Self {
+            path: dst.join(format!("{}.jsonl{}", lang, suffix)),
+            file: None,
+            lang: Some(lang),
+            compression,
+            prob_digits: DEFAULT_PROB_DIGITS,
+        }
+    }
+
+    /// Create a new JsonlWriter accepting pieces of any language, in a single [COMBINED_FILE] file
+    /// (gzipped if `compression` is set).
+    ///
+    /// Each call to [WriterTrait::write] writes its pieces at once, so that pieces are never interleaved
+    /// when the writer is shared between languages behind a mutex.
+    pub fn combined(dst: &Path, compression: Option<Compression>) -> Self {
+        let suffix = OutputFile::suffix(compression);
+        Self {
+            path: dst.join(format!("{}{}", COMBINED_FILE, suffix)),
+            file: None,
+            lang: None,
+            compression,
+            prob_digits: DEFAULT_PROB_DIGITS,
+        }
+    }
+
+    /// Set the number of significant digits of serialized probabilities (at least one is kept).
+    ///
+    /// Defaults to [DEFAULT_PROB_DIGITS].
+    pub fn with_prob_digits(mut self, digits: usize) -> Self {
+        self.prob_digits = digits;
+        self
+    }
+
+    /// Get the current file handle, creating the file on first write.
+    fn file(&mut self) -> std::io::Result<&mut OutputFile> {
+        if self.file.is_none() {
+            let file = OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(&self.path)?;
+            self.file = Some(OutputFile::new(file, self.compression));
+        }
+
+        // file is always Some at this point
+        Ok(self.file.as_mut().unwrap())
+    }
+
+    /// Serialize a piece into a newline-terminated JSON string.
+    fn to_line(&self, piece: &MergedPiece) -> Result<String, error::Error> {
+        if let Some(lang) = self.lang {
+            if piece.identification() != lang {
+                return Err(error::Error::Custom(format!(
+                    "Wrong language. Tried to add a {} piece into a {} file.",
+                    piece.identification(),
+                    lang
+                )));
+            }
+        }
+
+        let metadata = Metadata::try_from(piece.headers.clone())?;
+        let entry = Entry {
+            text: &piece.sentences,
+            meta: EntryMeta {
+                headers: metadata.headers,
+                identification: piece.identification(),
+                nb_sentences: piece.nb_sentences,
+                nb_chars: piece.nb_chars(),
+                confidence: Probability {
+                    value: piece.confidence,
+                    digits: self.prob_digits,
+                },
+                line_ranges: &piece.line_ranges,
+                source: piece.source.as_ref(),
+            },
+        };
+
+        let mut line = serde_json::to_string(&entry)?;
+        line.push('\n');
+        Ok(line)
+    }
+}
+
+impl WriterTrait for JsonlWriter {
+    type Item = MergedPiece;
+
+    /// Create a new JsonlWriter for provided language.
+    /// The file will be written at the root of the `dst` folder.
+    ///
+    /// `size_limit` is ignored: a single file is used.
+    fn new(dst: &Path, lang: &'static str, _size_limit: Option<u64>) -> Result<Self, error::Error> {
+        Ok(Self::with_compression(dst, lang, None))
+    }
+
+    /// writes the provided [MergedPiece], one per line, checking language identification.
+    fn write(&mut self, pieces: Vec<MergedPiece>) -> Result<(), error::Error> {
+        let lines = pieces
+            .iter()
+            .map(|piece| self.to_line(piece))
+            .collect::<Result<String, error::Error>>()?;
+
+        self.file()?.write_all(lines.as_bytes())?;
+        Ok(())
+    }
+
+    fn write_single(&mut self, piece: &MergedPiece) -> Result<(), error::Error> {
+        let line = self.to_line(piece)?;
+        self.file()?.write_all(line.as_bytes())?;
+        Ok(())
+    }
+
+    /// Closes current file.
+    ///
+    /// There is no trailing structure to fix for JSON Lines.
+    fn close_meta(&mut self) -> Result<(), error::Error> {
+        if let Some(file) = self.file.take() {
+            file.finish()?;
+        }
+        Ok(())
+    }
+
+    fn set_prob_digits(&mut self, digits: usize) {
+        self.prob_digits = digits;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::{BufRead, BufReader};
+
+    use serde_json::Value;
+
+    use super::*;
+
+    type WarcHeaders = HashMap<WarcHeader, Vec<u8>>;
+
+    fn piece(sentences: &str, identification: &'static str) -> MergedPiece {
+        let headers: WarcHeaders =
+            vec![(WarcHeader::Filename, Vec::from("filenametest".as_bytes()))]
+                .into_iter()
+                .collect();
+        MergedPiece {
+            sentences: sentences.to_string(),
+            nb_sentences: sentences.lines().count(),
+            identification,
+            confidence: 1.0,
+            line_ranges: Vec::new(),
+            source: None,
+            shard_location: None,
+            headers,
+        }
+    }
+
+    #[test]
+    fn write() {
+        let dst = tempfile::tempdir().unwrap();
+        let mut wr = JsonlWriter::new(dst.path(), "fr", None).unwrap();
+
+        let pieces = vec![piece("Bonjour!\nÇa va?", "fr"), piece("Salut.", "fr")];
+        wr.write(pieces.clone()).unwrap();
+        wr.close_meta().unwrap();
+
+        let f = File::open(dst.path().join("fr.jsonl")).unwrap();
+        let lines: Vec<Value> = BufReader::new(f)
+            .lines()
+            .map(|l| serde_json::from_str(&l.unwrap()).unwrap())
+            .collect();
+
+        assert_eq!(lines.len(), 2);
+        for (line, piece) in lines.iter().zip(pieces.iter()) {
+            assert_eq!(line["text"], piece.sentences.as_str());
+            assert_eq!(line["meta"]["identification"], "fr");
+            assert_eq!(line["meta"]["nb_sentences"], piece.nb_sentences);
+            assert_eq!(line["meta"]["headers"]["warc-filename"], "filenametest");
+        }
+        assert_eq!(lines[0]["meta"]["nb_chars"], 14);
+        assert_eq!(lines[1]["meta"]["nb_chars"], 6);
+    }
+
+    #[test]
+    fn write_combined() {
+        let dst = tempfile::tempdir().unwrap();
+        let mut wr = JsonlWriter::combined(dst.path(), None);
+
+        wr.write(vec![piece("Bonjour!", "fr")]).unwrap();
+        wr.write_single(&piece("Hello!", "en")).unwrap();
+        wr.close_meta().unwrap();
+
+        let f = File::open(dst.path().join(COMBINED_FILE)).unwrap();
+        let langs: Vec<String> = BufReader::new(f)
+            .lines()
+            .map(|l| {
+                let line: Value = serde_json::from_str(&l.unwrap()).unwrap();
+                line["meta"]["identification"].as_str().unwrap().to_string()
+            })
+            .collect();
+        assert_eq!(langs, vec!["fr", "en"]);
+    }
+
+    #[test]
+    fn probability() {
+        let rounded = |value, digits| Probability { value, digits }.rounded();
+        assert_eq!(rounded(0.8, 4), 0.8);
+        assert_eq!(rounded(0.123456, 4), 0.1235);
+        assert_eq!(rounded(0.99996, 4), 1.0);
+        assert_eq!(rounded(0.000123456, 2), 0.00012);
+        assert_eq!(rounded(0.123456, 0), 0.1);
+        assert!(rounded(f32::NAN, 4).is_nan());
+    }
+
+    #[test]
+    fn write_prob_digits() {
+        let dst = tempfile::tempdir().unwrap();
+        let mut wr = JsonlWriter::new(dst.path(), "fr", None).unwrap();
+        let mut p = piece("Bonjour!", "fr");
+        p.confidence = 0.8;
+        wr.write_single(&p).unwrap();
+        p.confidence = 0.123456;
+        wr.write_single(&p).unwrap();
+        wr.set_prob_digits(2);
+        wr.write_single(&p).unwrap();
+        wr.close_meta().unwrap();
+
+        let content = std::fs::read_to_string(dst.path().join("fr.jsonl")).unwrap();
+        let confidences: Vec<_> = content
+            .lines()
+            .map(|l| {
+                let line: Value = serde_json::from_str(l).unwrap();
+                line["meta"]["confidence"].to_string()
+            })
+            .collect();
+        assert_eq!(confidences, vec!["0.8", "0.1235", "0.12"]);
+    }
+
+    #[test]
+    fn write_wrong_lang() {
+        let dst = tempfile::tempdir().unwrap();
+        let mut wr = JsonlWriter::new(dst.path(), "fr", None).unwrap();
+
+        assert!(wr.write(vec![piece("Hello!", "en")]).is_err());
+        assert!(!dst.path().join("fr.jsonl").exists());
+    }
+}
@@ -0,0 +1,126 @@
+/*! In-memory writer for a given language.
+
+Stores written [MergedPiece]s in a shared [Vec] rather than in files, so that tests can assert directly on
+what each language writer received (see [crate::io::LangFiles::with_factory]).
+As with [super::Writer], identification is checked, preventing the writing of differently identified [MergedPiece] into a given language writer.
+!*/
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use crate::error;
+use crate::pipelines::oscarmeta::types::MergedPiece;
+
+use super::WriterTrait;
+
+/// Pieces written by a [MemWriter], shared with its owner.
+pub type MemPieces = Arc<Mutex<Vec<MergedPiece>>>;
+
+pub struct MemWriter {
+    lang: &'static str,
+    pieces: MemPieces,
+    closed: bool,
+}
+
+impl MemWriter {
+    /// Create a new MemWriter for provided language, storing written pieces in `pieces`.
+    pub fn with_pieces(lang: &'static str, pieces: MemPieces) -> Self {
+        Self {
+            lang,
+            pieces,
+            closed: false,
+        }
+    }
+
+    /// Get a handle on written pieces.
+    pub fn pieces(&self) -> MemPieces {
+        self.pieces.clone()
+    }
+
+    /// Check if [WriterTrait::close_meta] has been called.
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    fn check_lang(&self, piece: &MergedPiece) -> Result<(), error::Error> {
+        if piece.identification() != self.lang {
+            return Err(error::Error::Custom(format!(
+                "Wrong language. Tried to add a {} piece into a {} writer.",
+                piece.identification(),
+                self.lang
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl WriterTrait for MemWriter {
+    type Item = MergedPiece;
+
+    /// Create a new MemWriter for provided language, with an empty store.
+    ///
+    /// `dst` and `size_limit` are ignored: nothing is written on disk.
+    fn new(
+        _dst: &Path,
+        lang: &'static str,
+        _size_limit: Option<u64>,
+    ) -> Result<Self, error::Error> {
+        Ok(Self::with_pieces(lang, MemPieces::default()))
+    }
+
+    fn write(&mut self, pieces: Vec<MergedPiece>) -> Result<(), error::Error> {
+        pieces.into_iter().try_for_each(|piece| {
+            self.check_lang(&piece)?;
+            self.pieces.lock().unwrap().push(piece);
+            Ok(())
+        })
+    }
+
+    fn write_single(&mut self, piece: &MergedPiece) -> Result<(), error::Error> {
+        self.check_lang(piece)?;
+        self.pieces.lock().unwrap().push(piece.clone());
+        Ok(())
+    }
+
+    fn close_meta(&mut self) -> Result<(), error::Error> {
+        self.closed = true;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn piece(sentences: &str, lang: &'static str) -> MergedPiece {
+        MergedPiece::new(
+            HashMap::new(),
+            sentences.lines().map(String::from).collect(),
+            lang,
+        )
+    }
+
+    #[test]
+    fn write() {
+        let mut w = MemWriter::new(Path::new(""), "fr", None).unwrap();
+        let pieces = w.pieces();
+        w.write(vec![piece("a\nb", "fr")]).unwrap();
+        w.write_single(&piece("c", "fr")).unwrap();
+        assert!(!w.is_closed());
+        w.close_meta().unwrap();
+        assert!(w.is_closed());
+
+        let pieces = pieces.lock().unwrap();
+        let sentences: Vec<&str> = pieces.iter().map(|p| p.sentences.as_str()).collect();
+        assert_eq!(sentences, vec!["a\nb", "c"]);
+    }
+
+    #[test]
+    fn wrong_lang() {
+        let mut w = MemWriter::new(Path::new(""), "fr", None).unwrap();
+        assert!(w.write(vec![piece("a", "en")]).is_err());
+        assert!(w.write_single(&piece("a", "en")).is_err());
+        assert!(w.pieces().lock().unwrap().is_empty());
+    }
+}
@@ -1,69 +1,134 @@
 //! Rotating file writer for metadata.
 use crate::error;
-use log::{debug, warn};
+use flate2::Compression;
+use log::debug;
 use std::fs::OpenOptions;
 use std::path::Path;
-use std::{fs::File, io::Write, path::PathBuf};
+use std::{io::Write, path::PathBuf};
+
+use super::OutputFile;
 
 /// Rotating file writer.
 ///
 /// Implements [std::io::Write]
 ///
 /// *Note:* Contrary to TextWriter, [MetaWriter] has no limit and new file creation has to be triggered manually by invoking [MetaWriter::create_next_file].
+///
+/// When `compression` is set, files are gzipped and get a `.gz` suffix.
 pub struct MetaWriter {
     lang: &'static str,
+    stem: String,
     dst: PathBuf,
-    pub file: Option<File>,
+    pub file: Option<OutputFile>,
     nb_files: u64,
+    compression: Option<Compression>,
+    append: bool,
 }
 
 impl MetaWriter {
     /// Create a new [MetaWriter].
     /// Note that nothing is created/written unless a write is performed.
     /// size_limit is in bytes.
-    pub fn new(dst: &Path, lang: &'static str) -> Self {
+    pub fn new(dst: &Path, lang: &'static str, compression: Option<Compression>) -> Self {
         Self {
             lang,
+            stem: lang.to_string(),
             dst: dst.to_path_buf(),
             file: None,
             nb_files: 0,
+            compression,
+            append: false,
         }
     }
 
+    /// Name files after `stem` rather than after the language (`stem_meta.jsonl`...).
+    pub fn with_stem(mut self, stem: &str) -> Self {
+        self.stem = stem.to_string();
+        self
+    }
+
+    /// Append to existing files rather than overwriting them.
+    ///
+    /// Compressed files then hold several gzip members, that are read as one stream by multi-member decoders
+    /// (such as [flate2::read::MultiGzDecoder]).
+    pub fn with_append(mut self, append: bool) -> Self {
+        self.append = append;
+        self
+    }
+
+    /// Get the compression of files.
+    pub fn compression(&self) -> Option<Compression> {
+        self.compression
+    }
+
     /// attempt to close current file while ending json.
     pub fn close_file(&mut self) -> Result<(), error::Error> {
-        if self.file.is_some() {
-            self.file = None;
+        if let Some(file) = self.file.take() {
+            file.finish()?;
         } else {
-            warn!("{}: trying to close an unopened MetaWriter.", self.lang);
+            debug!(
+                "{}: closing an unopened MetaWriter (nothing was written).",
+                self.lang
+            );
         }
         Ok(())
     }
 
+    /// Get the path of the `nb`-th file (starting from 1), `lang_meta.jsonl` being the first one
+    /// as long as there's a single file.
+    pub fn part_path(&self, nb: u64) -> PathBuf {
+        let suffix = OutputFile::suffix(self.compression);
+        let filename = if nb <= 1 {
+            format!("{}_meta.jsonl{}", self.stem, suffix)
+        } else {
+            format!("{}_meta_part_{}.jsonl{}", self.stem, nb, suffix)
+        };
+        self.dst.join(filename)
+    }
+
+    /// Resume writing into the `nb_files`-th file (see [MetaWriter::part_path]), appending to it.
+    ///
+    /// Used along with [super::TextWriter::resume], so that metadata is written next to the text it describes.
+    pub fn resume(&mut self, nb_files: u64) -> std::io::Result<()> {
+        if let Some(file) = self.file.take() {
+            file.finish()?;
+        }
+        let file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(self.part_path(nb_files))?;
+        self.file = Some(OutputFile::new(file, self.compression));
+        self.nb_files = nb_files;
+        Ok(())
+    }
+
     /// Rotate file.
     ///
     /// The first file is named `lang_meta.json`, and is renamed `lang_meta_part_1.json` if there's > 1 number of files.
     pub fn create_next_file(&mut self) -> std::io::Result<()> {
-        let filename = if self.nb_files == 0 {
-            format!("{}_meta.jsonl", self.lang)
-        } else {
-            format!("{}_meta_part_{}.jsonl", self.lang, self.nb_files + 1)
-        };
+        // finish previous file before opening a new one
+        if let Some(file) = self.file.take() {
+            file.finish()?;
+        }
 
-        let mut path = self.dst.clone();
-        path.push(filename);
+        let suffix = OutputFile::suffix(self.compression);
+        let path = self.part_path(self.nb_files + 1);
 
         let mut options = OpenOptions::new();
-        options.read(true).write(true).create(true);
+        if self.append {
+            options.append(true).create(true);
+        } else {
+            options.write(true).create(true).truncate(true);
+        }
 
-        let file = options.open(path)?;
+        let file = OutputFile::new(options.open(path)?, self.compression);
 
         // if nb_files == 1
         if self.nb_files == 1 {
             let mut from = self.dst.clone();
-            from.push(format!("{}_meta.jsonl", self.lang));
+            from.push(format!("{}_meta.jsonl{}", self.stem, suffix));
             let mut to = self.dst.clone();
-            to.push(format!("{}_meta_part_1.jsonl", self.lang));
+            to.push(format!("{}_meta_part_1.jsonl{}", self.stem, suffix));
 
             debug!("renaming {:?} to {:?}", from, to);
             std::fs::rename(from, to)?;
@@ -9,13 +9,23 @@ Each [Writer] is composed of a [TextWriter]/[MetaWriter] couple, with [TextWrite
 
 This leads the [TextWriter]/[MetaWriter] couple to be cumbersome to use outside of [Writer].
 !*/
+mod jsonlwriter;
+#[cfg(any(test, feature = "testing"))]
+mod memwriter;
 mod metawriter;
+mod outputfile;
+mod parquetwriter;
 mod textwriter;
 pub mod writer;
 mod writer_doc;
 mod writertrait;
+pub use jsonlwriter::{JsonlWriter, COMBINED_FILE, DEFAULT_PROB_DIGITS};
+#[cfg(any(test, feature = "testing"))]
+pub use memwriter::{MemPieces, MemWriter};
 use metawriter::MetaWriter;
-use textwriter::TextWriter;
+use outputfile::OutputFile;
+pub use parquetwriter::ParquetWriter;
+pub use textwriter::{TextWriter, UTF8_BOM};
 pub use writer::Writer;
 pub use writer_doc::WriterDoc;
 pub use writertrait::WriterTrait;
@@ -0,0 +1,161 @@
+//! Optionally compressed output file.
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+/// File handle that may stream-compress written data using gzip.
+///
+/// Compressed files have to be finished (see [OutputFile::finish]) to get a valid trailer.
+/// Dropping the handle also finishes the stream, but ignores any error.
+pub enum OutputFile {
+    Plain(File),
+    Gzip(GzEncoder<File>),
+}
+
+impl OutputFile {
+    /// Wrap `file`, compressing written data if `compression` is set.
+    pub fn new(file: File, compression: Option<Compression>) -> Self {
+        match compression {
+            Some(level) => Self::Gzip(GzEncoder::new(file, level)),
+            None => Self::Plain(file),
+        }
+    }
+
+    /// Filename suffix to append when `compression` is set.
+    pub fn suffix(compression: Option<Compression>) -> &'static str {
+        match compression {
+            Some(_) => ".gz",
+            None => "",
+        }
+    }
+
+    /// Open the file at `path` for reading, decompressing it if `compression` is set.
+    ///
+    /// Appended compressed files hold several gzip members, that are read as a single stream.
+    pub fn reader(path: &Path, compression: Option<Compression>) -> std::io::Result<Box<dyn Read>> {
+        let file = File::open(path)?;
+        Ok(match compression {
+            Some(_) => Box::new(MultiGzDecoder::new(file)),
+            None => Box::new(file),
+        })
+    }
+
+    /// Find the last file of a rotating writer in `dst`, whose first file is named `first`
+    /// and whose parts are named `{part_prefix}N{part_suffix}`.
+    ///
+    /// Returns the number of the last file (`1` for `first`) along with its path,
+    /// or [None] if there's no file (or if `dst` doesn't exist).
+    pub fn last_part(
+        dst: &Path,
+        first: &str,
+        part_prefix: &str,
+        part_suffix: &str,
+    ) -> std::io::Result<Option<(u64, PathBuf)>> {
+        if !dst.is_dir() {
+            return Ok(None);
+        }
+        let mut last = None;
+        for entry in std::fs::read_dir(dst)? {
+            let name = entry?.file_name();
+            let part = name
+                .to_str()
+                .and_then(|name| name.strip_prefix(part_prefix))
+                .and_then(|name| name.strip_suffix(part_suffix))
+                .and_then(|nb| nb.parse::<u64>().ok());
+            if part > last {
+                last = part;
+            }
+        }
+
+        Ok(match last {
+            Some(nb) => Some((
+                nb,
+                dst.join(format!("{}{}{}", part_prefix, nb, part_suffix)),
+            )),
+            None if dst.join(first).is_file() => Some((1, dst.join(first))),
+            None => None,
+        })
+    }
+
+    /// Flush and, if compressed, write the gzip trailer.
+    pub fn finish(self) -> std::io::Result<()> {
+        match self {
+            Self::Plain(mut file) => file.flush(),
+            Self::Gzip(encoder) => encoder.finish()?.flush(),
+        }
+    }
+}
+
+impl Write for OutputFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Self::Plain(file) => file.write(buf),
+            Self::Gzip(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Self::Plain(file) => file.flush(),
+            Self::Gzip(encoder) => encoder.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use flate2::read::MultiGzDecoder;
+
+    use super::*;
+
+    #[test]
+    fn gzip_roundtrip() {
+        let dst = tempfile::tempdir().unwrap();
+        let path = dst.path().join("en.txt.gz");
+        let mut f = OutputFile::new(File::create(&path).unwrap(), Some(Compression::default()));
+        f.write_all(b"hello\nworld\n").unwrap();
+        f.finish().unwrap();
+
+        let mut content = String::new();
+        MultiGzDecoder::new(File::open(&path).unwrap())
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, "hello\nworld\n");
+    }
+
+    #[test]
+    fn last_part() {
+        let dst = tempfile::tempdir().unwrap();
+        let last = |dst: &Path| OutputFile::last_part(dst, "en.txt", "en_part_", ".txt").unwrap();
+        assert_eq!(last(&dst.path().join("missing")), None);
+        assert_eq!(last(dst.path()), None);
+
+        std::fs::write(dst.path().join("en.txt"), "").unwrap();
+        assert_eq!(last(dst.path()), Some((1, dst.path().join("en.txt"))));
+
+        for name in [
+            "en_part_1.txt",
+            "en_part_10.txt",
+            "en_part_2.txt",
+            "en_part_x.txt",
+        ] {
+            std::fs::write(dst.path().join(name), "").unwrap();
+        }
+        assert_eq!(
+            last(dst.path()),
+            Some((10, dst.path().join("en_part_10.txt")))
+        );
+    }
+
+    #[test]
+    fn suffix() {
+        assert_eq!(OutputFile::suffix(None), "");
+        assert_eq!(OutputFile::suffix(Some(Compression::fast())), ".gz");
+    }
+}
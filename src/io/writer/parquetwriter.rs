@@ -0,0 +1,482 @@
+/*! Parquet writer for a given language.
+
+Writes each [MergedPiece] (or each document, along with its [Location]) as a row of a `lang.parquet` file, with the following columns:
+
+- `text`: newline-separated sentences,
+- `lang`: identified language,
+- `probability`: confidence of the identification (see [MergedPiece::confidence]),
+- `record_id`: `WARC-Record-ID` of the record the piece comes from, if any,
+- `shard_id`/`loc_in_shard`: shard the piece comes from and index of its record in the shard
+  (see [MergedPiece::shard_location] and [Location]), if known,
+- `line_start`/`line_end`: first and last (excluded) lines of the piece in its record (see [MergedPiece::line_ranges]), if known.
+
+Rows are buffered and written by row groups of a configurable size.
+The file is only valid once the Parquet footer is written, by [WriterTrait::close_meta] or when the writer is dropped.
+As with [super::Writer], identification is checked, preventing the writing of differently identified items into a given language writer.
+!*/
+use std::fs::File;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use log::error;
+use parquet::data_type::{ByteArray, ByteArrayType, FloatType, Int64Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::parser::parse_message_type;
+use warc::WarcHeader;
+
+use crate::error;
+use crate::pipelines::oscardoc::types::{Document, Location};
+use crate::pipelines::oscarmeta::types::MergedPiece;
+
+use super::WriterTrait;
+
+/// Items written as rows by a [ParquetWriter].
+pub trait ParquetRow {
+    /// Get the identified language, checked against the one of the writer.
+    fn lang(&self) -> &str;
+    /// Get the newline-separated sentences.
+    fn text(&self) -> &str;
+    /// Get the confidence of the identification.
+    fn probability(&self) -> f32;
+    /// Get the `WARC-Record-ID` of the originating record, if any.
+    fn record_id(&self) -> Option<&[u8]>;
+    /// Get the shard id and the index of the originating record in the shard, if known.
+    fn shard_location(&self) -> Option<(u64, u64)>;
+    /// Get the first and last (excluded) lines in the originating record, if known.
+    fn line_range(&self) -> Option<(usize, usize)>;
+}
+
+impl ParquetRow for MergedPiece {
+    fn lang(&self) -> &str {
+        self.identification()
+    }
+
+    fn text(&self) -> &str {
+        &self.sentences
+    }
+
+    fn probability(&self) -> f32 {
+        self.confidence
+    }
+
+    fn record_id(&self) -> Option<&[u8]> {
+        self.headers.get(&WarcHeader::RecordID).map(Vec::as_slice)
+    }
+
+    /// The shard id is the shard index in processing order.
+    fn shard_location(&self) -> Option<(u64, u64)> {
+        self.shard_location
+            .map(|(shard, record)| (shard as u64, record as u64))
+    }
+
+    fn line_range(&self) -> Option<(usize, usize)> {
+        let (start, _) = self.line_ranges.first()?;
+        let (_, end) = self.line_ranges.last()?;
+        Some((*start, *end))
+    }
+}
+
+impl ParquetRow for (Document, Location) {
+    fn lang(&self) -> &str {
+        self.0.identification().label().to_static()
+    }
+
+    fn text(&self) -> &str {
+        self.0.content()
+    }
+
+    fn probability(&self) -> f32 {
+        *self.0.identification().prob()
+    }
+
+    fn record_id(&self) -> Option<&[u8]> {
+        self.0
+            .warc_headers()
+            .get(&WarcHeader::RecordID)
+            .map(Vec::as_slice)
+    }
+
+    fn shard_location(&self) -> Option<(u64, u64)> {
+        Some((self.1.shard_id(), self.1.loc_in_shard() as u64))
+    }
+
+    /// Location line ends are included, so they're shifted by one.
+    fn line_range(&self) -> Option<(usize, usize)> {
+        Some((self.1.line_start(), self.1.line_end() + 1))
+    }
+}
+
+/// Schema of written files.
+const SCHEMA: &str = "
+message piece {
+    REQUIRED BYTE_ARRAY text (UTF8);
+    REQUIRED BYTE_ARRAY lang (UTF8);
+    REQUIRED FLOAT probability;
+    OPTIONAL BYTE_ARRAY record_id (UTF8);
+    OPTIONAL INT64 shard_id;
+    OPTIONAL INT64 loc_in_shard;
+    OPTIONAL INT64 line_start;
+    OPTIONAL INT64 line_end;
+}
+";
+
+/// Buffered rows, by column.
+#[derive(Debug, Default)]
+struct Columns {
+    text: Vec<ByteArray>,
+    lang: Vec<ByteArray>,
+    probability: Vec<f32>,
+    record_id: Vec<Option<ByteArray>>,
+    shard_id: Vec<Option<i64>>,
+    loc_in_shard: Vec<Option<i64>>,
+    line_start: Vec<Option<i64>>,
+    line_end: Vec<Option<i64>>,
+}
+
+impl Columns {
+    fn len(&self) -> usize {
+        self.text.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    fn push<T: ParquetRow>(&mut self, row: &T) {
+        let shard_location = row.shard_location();
+        let line_range = row.line_range();
+        self.text.push(ByteArray::from(row.text()));
+        self.lang.push(ByteArray::from(row.lang()));
+        self.probability.push(row.probability());
+        self.record_id
+            .push(row.record_id().map(|id| ByteArray::from(id.to_vec())));
+        self.shard_id
+            .push(shard_location.map(|(shard, _)| shard as i64));
+        self.loc_in_shard
+            .push(shard_location.map(|(_, record)| record as i64));
+        self.line_start
+            .push(line_range.map(|(start, _)| start as i64));
+        self.line_end.push(line_range.map(|(_, end)| end as i64));
+    }
+}
+
+/// Split optional values into definition levels and defined values.
+fn levels<T: Clone>(values: &[Option<T>]) -> (Vec<i16>, Vec<T>) {
+    let def_levels = values.iter().map(|v| v.is_some() as i16).collect();
+    let values = values.iter().flatten().cloned().collect();
+    (def_levels, values)
+}
+
+/// Parquet writer of [MergedPiece] or, with `T = (Document, Location)`, of documents.
+///
+/// The file footer is written on [WriterTrait::close_meta] or, failing that, on drop
+/// (errors are then only logged).
+pub struct ParquetWriter<T: ParquetRow = MergedPiece> {
+    path: PathBuf,
+    writer: Option<SerializedFileWriter<File>>,
+    lang: &'static str,
+    row_group_size: usize,
+    rows: Columns,
+    _row: PhantomData<T>,
+}
+
+impl<T: ParquetRow> ParquetWriter<T> {
+    /// Default number of rows of a row group.
+    pub const DEFAULT_ROW_GROUP_SIZE: usize = 10_000;
+
+    /// Create a new ParquetWriter for provided language, writing row groups of `row_group_size` rows.
+    ///
+    /// The file (`lang.parquet`) is created on first write.
+    ///
+    /// # Errors
+    /// Returns an error if `row_group_size` is 0.
+    pub fn with_row_group_size(
+        dst: &Path,
+        lang: &'static str,
+        row_group_size: usize,
+    ) -> Result<Self, error::Error> {
+        if row_group_size == 0 {
+            return Err(error::Error::Custom(
+                "row group size has to be positive".to_string(),
+            ));
+        }
+
+        Ok(Self {
+            path: dst.join(format!("{}.parquet", lang)),
+            writer: None,
+            lang,
+            row_group_size,
+            rows: Columns::default(),
+            _row: PhantomData,
+        })
+    }
+
+    /// Name the file `<stem>.parquet` rather than `lang.parquet`.
+    ///
+    /// Only the file name changes: written items still have to be of the writer language.
+    pub fn with_stem(mut self, stem: &str) -> Self {
+        self.path.set_file_name(format!("{}.parquet", stem));
+        self
+    }
+
+    /// Get the file writer, creating the file on first write.
+    fn writer(&mut self) -> Result<&mut SerializedFileWriter<File>, error::Error> {
+        if self.writer.is_none() {
+            let schema = Arc::new(parse_message_type(SCHEMA)?);
+            let props = Arc::new(WriterProperties::builder().build());
+            let file = File::create(&self.path)?;
+            self.writer = Some(SerializedFileWriter::new(file, schema, props)?);
+        }
+
+        // writer is always Some at this point
+        Ok(self.writer.as_mut().unwrap())
+    }
+
+    /// Write buffered rows as a row group.
+    fn flush_rows(&mut self) -> Result<(), error::Error> {
+        if self.rows.is_empty() {
+            return Ok(());
+        }
+
+        let rows = std::mem::take(&mut self.rows);
+        let (record_id_levels, record_ids) = levels(&rows.record_id);
+        let (shard_id_levels, shard_ids) = levels(&rows.shard_id);
+        let (loc_in_shard_levels, locs_in_shard) = levels(&rows.loc_in_shard);
+        let (line_start_levels, line_starts) = levels(&rows.line_start);
+        let (line_end_levels, line_ends) = levels(&rows.line_end);
+
+        let mut row_group = self.writer()?.next_row_group()?;
+        let mut idx = 0;
+        while let Some(mut column) = row_group.next_column()? {
+            match idx {
+                0 => column
+                    .typed::<ByteArrayType>()
+                    .write_batch(&rows.text, None, None)?,
+                1 => column
+                    .typed::<ByteArrayType>()
+                    .write_batch(&rows.lang, None, None)?,
+                2 => column
+                    .typed::<FloatType>()
+                    .write_batch(&rows.probability, None, None)?,
+                3 => column.typed::<ByteArrayType>().write_batch(
+                    &record_ids,
+                    Some(&record_id_levels),
+                    None,
+                )?,
+                4 => column.typed::<Int64Type>().write_batch(
+                    &shard_ids,
+                    Some(&shard_id_levels),
+                    None,
+                )?,
+                5 => column.typed::<Int64Type>().write_batch(
+                    &locs_in_shard,
+                    Some(&loc_in_shard_levels),
+                    None,
+                )?,
+                6 => column.typed::<Int64Type>().write_batch(
+                    &line_starts,
+                    Some(&line_start_levels),
+                    None,
+                )?,
+                _ => column.typed::<Int64Type>().write_batch(
+                    &line_ends,
+                    Some(&line_end_levels),
+                    None,
+                )?,
+            };
+            column.close()?;
+            idx += 1;
+        }
+        row_group.close()?;
+
+        Ok(())
+    }
+}
+
+impl<T: ParquetRow> WriterTrait for ParquetWriter<T> {
+    type Item = T;
+
+    /// Create a new ParquetWriter for provided language, using [ParquetWriter::DEFAULT_ROW_GROUP_SIZE].
+    /// The file will be written at the root of the `dst` folder.
+    ///
+    /// `size_limit` is ignored: a single file is used.
+    fn new(dst: &Path, lang: &'static str, _size_limit: Option<u64>) -> Result<Self, error::Error> {
+        Self::with_row_group_size(dst, lang, Self::DEFAULT_ROW_GROUP_SIZE)
+    }
+
+    /// Buffers the provided items, checking language identification.
+    ///
+    /// Row groups are written once enough rows are buffered.
+    fn write(&mut self, rows: Vec<T>) -> Result<(), error::Error> {
+        rows.iter().try_for_each(|row| self.write_single(row))
+    }
+
+    fn write_single(&mut self, row: &T) -> Result<(), error::Error> {
+        if row.lang() != self.lang {
+            return Err(error::Error::Custom(format!(
+                "Wrong language. Tried to add a {} piece into a {} file.",
+                row.lang(),
+                self.lang
+            )));
+        }
+
+        self.rows.push(row);
+        if self.rows.len() >= self.row_group_size {
+            self.flush_rows()?;
+        }
+        Ok(())
+    }
+
+    /// Writes remaining rows and the file footer, closing the file.
+    ///
+    /// Nothing is written (and no file is created) if nothing has been written.
+    fn close_meta(&mut self) -> Result<(), error::Error> {
+        self.flush_rows()?;
+        if let Some(writer) = self.writer.take() {
+            writer.close()?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: ParquetRow> Drop for ParquetWriter<T> {
+    /// Writes the file footer if [WriterTrait::close_meta] hasn't been called.
+    fn drop(&mut self) {
+        if let Err(e) = self.close_meta() {
+            error!("could not close {:?}: {:?}", self.path, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use parquet::record::RowAccessor;
+
+    use crate::identifiers::Identification;
+    use crate::lang::Lang;
+    use crate::pipelines::oscardoc::types::Metadata;
+
+    use super::*;
+
+    fn piece(sentences: &str, lang: &'static str, record_id: Option<&str>) -> MergedPiece {
+        let headers = record_id
+            .map(|id| (WarcHeader::RecordID, id.as_bytes().to_vec()))
+            .into_iter()
+            .collect();
+        let mut piece =
+            MergedPiece::new(headers, sentences.lines().map(String::from).collect(), lang);
+        piece.confidence = 0.5;
+        piece
+    }
+
+    #[test]
+    fn roundtrip() {
+        let dst = tempfile::tempdir().unwrap();
+        let mut w: ParquetWriter = ParquetWriter::with_row_group_size(dst.path(), "fr", 2).unwrap();
+
+        let mut with_lines = piece("a\nb", "fr", Some("<urn:uuid:0>"));
+        with_lines.line_ranges = vec![(1, 2), (3, 4)];
+        with_lines.shard_location = Some((2, 7));
+        let pieces = vec![with_lines, piece("c", "fr", None), piece("d", "fr", None)];
+        w.write(pieces).unwrap();
+        w.close_meta().unwrap();
+
+        let reader =
+            SerializedFileReader::try_from(dst.path().join("fr.parquet").as_path()).unwrap();
+        // 3 rows in groups of 2
+        assert_eq!(reader.metadata().num_row_groups(), 2);
+
+        let rows: Vec<_> = reader.into_iter().map(|row| row.unwrap()).collect();
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0].get_string(0).unwrap(), "a\nb");
+        assert_eq!(rows[0].get_string(1).unwrap(), "fr");
+        assert_eq!(rows[0].get_float(2).unwrap(), 0.5);
+        assert_eq!(rows[0].get_string(3).unwrap(), "<urn:uuid:0>");
+        assert_eq!(rows[0].get_long(4).unwrap(), 2);
+        assert_eq!(rows[0].get_long(5).unwrap(), 7);
+        assert_eq!(rows[0].get_long(6).unwrap(), 1);
+        assert_eq!(rows[0].get_long(7).unwrap(), 4);
+        assert_eq!(rows[2].get_string(0).unwrap(), "d");
+        assert!(rows[2].get_string(3).is_err());
+        assert!(rows[2].get_long(4).is_err());
+        assert!(rows[2].get_long(6).is_err());
+    }
+
+    #[test]
+    fn roundtrip_documents() {
+        let dst = tempfile::tempdir().unwrap();
+        let mut w: ParquetWriter<(Document, Location)> =
+            ParquetWriter::with_row_group_size(dst.path(), "en", 10)
+                .unwrap()
+                .with_stem("eng");
+
+        let id = Identification::new(Lang::En, 0.75);
+        let headers = vec![(WarcHeader::RecordID, b"<urn:uuid:0>".to_vec())]
+            .into_iter()
+            .collect();
+        let doc = Document::new(
+            "hello\nworld".to_string(),
+            headers,
+            Metadata::new(&id, &[Some(id.clone()), Some(id.clone())]),
+        );
+        let location = Location::new(3, "<urn:uuid:0>".to_string(), 2, 3, 5);
+        w.write(vec![(doc, location)]).unwrap();
+        w.close_meta().unwrap();
+
+        let reader =
+            SerializedFileReader::try_from(dst.path().join("eng.parquet").as_path()).unwrap();
+        let rows: Vec<_> = reader.into_iter().map(|row| row.unwrap()).collect();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].get_string(0).unwrap(), "hello\nworld");
+        assert_eq!(rows[0].get_string(1).unwrap(), "en");
+        assert_eq!(rows[0].get_float(2).unwrap(), 0.75);
+        assert_eq!(rows[0].get_string(3).unwrap(), "<urn:uuid:0>");
+        assert_eq!(rows[0].get_long(4).unwrap(), 3);
+        assert_eq!(rows[0].get_long(5).unwrap(), 5);
+        // line ends are excluded
+        assert_eq!(rows[0].get_long(6).unwrap(), 2);
+        assert_eq!(rows[0].get_long(7).unwrap(), 4);
+    }
+
+    #[test]
+    fn close_on_drop() {
+        let dst = tempfile::tempdir().unwrap();
+        let mut w: ParquetWriter = ParquetWriter::with_row_group_size(dst.path(), "fr", 2).unwrap();
+        w.write(vec![
+            piece("a", "fr", None),
+            piece("b", "fr", None),
+            piece("c", "fr", None),
+        ])
+        .unwrap();
+        drop(w);
+
+        // the footer and remaining rows have been written
+        let reader =
+            SerializedFileReader::try_from(dst.path().join("fr.parquet").as_path()).unwrap();
+        assert_eq!(reader.into_iter().count(), 3);
+    }
+
+    #[test]
+    fn wrong_lang() {
+        let dst = tempfile::tempdir().unwrap();
+        let mut w: ParquetWriter = ParquetWriter::new(dst.path(), "fr", None).unwrap();
+        assert!(w.write_single(&piece("a", "en", None)).is_err());
+    }
+
+    #[test]
+    fn empty_row_group_size() {
+        let dst = tempfile::tempdir().unwrap();
+        assert!(ParquetWriter::<MergedPiece>::with_row_group_size(dst.path(), "fr", 0).is_err());
+    }
+
+    #[test]
+    fn nothing_written() {
+        let dst = tempfile::tempdir().unwrap();
+        let mut w: ParquetWriter = ParquetWriter::new(dst.path(), "fr", None).unwrap();
+        w.close_meta().unwrap();
+        assert!(!dst.path().join("fr.parquet").exists());
+    }
+}
@@ -1,21 +1,34 @@
 //! Rotating file writers for text and metadata.
+use flate2::Compression;
 use log::{debug, error, info};
 use std::convert::TryFrom;
 use std::fs::OpenOptions;
 use std::path::Path;
-use std::{fs::File, io::Write, path::PathBuf};
+use std::{io::Write, path::PathBuf};
+
+use super::OutputFile;
+
+/// UTF-8 byte order mark, written at the start of text files if enabled (see [TextWriter::with_bom]).
+pub const UTF8_BOM: &[u8] = b"\xef\xbb\xbf";
+
 /// Rotating file writers.
 ///
 /// Implement [std::io::Write] and holds a size (bytes) limit.
 ///
 /// Note: if a slice to write is larger than the whole limit, then it is an expected behaviour that
 /// the size limit is ignored and a file is created.
+///
+/// When `compression` is set, files are gzipped and get a `.gz` suffix.
+/// The size limit is still computed on uncompressed data.
 pub struct TextWriter {
-    lang: &'static str,
+    stem: String,
+    extension: String,
     dst: PathBuf,
-    text: Option<File>,
+    text: Option<OutputFile>,
     size: u64,
     size_limit: Option<u64>,
+    compression: Option<Compression>,
+    bom: bool,
     pub nb_files: u64,
     pub first_write_on_document: bool,
 }
@@ -24,26 +37,78 @@ impl TextWriter {
     /// Create a new [TextWriter].
     /// Note that nothing is created/written unless a write is performed.
     /// size_limit is in bytes.
-    pub fn new(dst: &Path, lang: &'static str, size_limit: Option<u64>) -> Self {
+    pub fn new(
+        dst: &Path,
+        lang: &'static str,
+        size_limit: Option<u64>,
+        compression: Option<Compression>,
+    ) -> Self {
+        Self::with_stem(dst, lang, size_limit, compression)
+    }
+
+    /// Create a new [TextWriter] whose files are named after `stem` rather than after a language
+    /// (`stem.txt`, `stem_part_1.txt`...).
+    pub fn with_stem(
+        dst: &Path,
+        stem: &str,
+        size_limit: Option<u64>,
+        compression: Option<Compression>,
+    ) -> Self {
         Self {
-            lang,
+            stem: stem.to_string(),
+            extension: "txt".to_string(),
             dst: dst.to_path_buf(),
             text: None,
             size: 0,
             size_limit,
+            compression,
+            bom: false,
             nb_files: 0,
             first_write_on_document: false,
         }
     }
 
+    /// Use `extension` in place of `txt` (`stem.raw`, `stem_part_1.raw`...).
+    pub fn with_extension(mut self, extension: &str) -> Self {
+        self.extension = extension.to_string();
+        self
+    }
+
+    /// Write a UTF-8 byte order mark ([UTF8_BOM]) at the start of each file, for tools that expect one.
+    ///
+    /// The BOM is not a line, and is not accounted for in the size limit.
+    /// Files that are appended to are left as is, so that a BOM is never written in the middle of a file.
+    /// Defaults to `false`.
+    pub fn with_bom(mut self, bom: bool) -> Self {
+        self.set_bom(bom);
+        self
+    }
+
+    /// Enable or disable the BOM of files created from now on (see [TextWriter::with_bom]).
+    pub fn set_bom(&mut self, bom: bool) {
+        self.bom = bom;
+    }
+
     /// Rotate file.
     ///
     /// The first file is named `lang.txt`, and is renamed `lang_part_1.txt` if there's > 1 number of files.
     pub fn create_next_file(&mut self) -> std::io::Result<()> {
+        // finish previous file before opening a new one
+        if let Some(text) = self.text.take() {
+            text.finish()?;
+        }
+
+        let suffix = OutputFile::suffix(self.compression);
         let filename = if self.nb_files == 0 {
-            format!("{}.txt", self.lang)
+            format!("{}.{}{}", self.stem, self.extension, suffix)
         } else {
-            format!("{}_part_{}.txt", self.lang, self.nb_files + 1)
+            format!(
+                "{}_part_{}.{}{}",
+                self.stem,
+                self.nb_files + 1,
+                self.extension,
+                suffix
+            )
         };
 
         let mut path = self.dst.clone();
@@ -53,14 +118,19 @@ impl TextWriter {
         options.read(true).append(true).create(true);
 
         info!("creating {:?}", path);
-        let text = options.open(path)?;
+        let file = options.open(path)?;
+        let is_new = file.metadata()?.len() == 0;
+        let mut text = OutputFile::new(file, self.compression);
+        if self.bom && is_new {
+            text.write_all(UTF8_BOM)?;
+        }
 
         // if nb_files == 1, rename lang.txt into lang_part_1.txt
         if self.nb_files == 1 {
             let mut from = self.dst.clone();
-            from.push(format!("{}.txt", self.lang));
+            from.push(format!("{}.{}{}", self.stem, self.extension, suffix));
             let mut to = self.dst.clone();
-            to.push(format!("{}_part_1.txt", self.lang));
+            to.push(format!("{}_part_1.{}{}", self.stem, self.extension, suffix));
 
             debug!("renaming {:?} to {:?}", from, to);
             std::fs::rename(from, to)?;
@@ -74,6 +144,44 @@ impl TextWriter {
         Ok(())
     }
 
+    /// Resume writing into the last file written in `dst` by a previous run (`lang.txt` or the last `lang_part_N.txt`),
+    /// rather than starting again from the first file.
+    ///
+    /// The file is appended to, and its (uncompressed) size counts toward the size limit.
+    /// Returns the number of the resumed file, or `0` if there's no file to resume (the writer is then left as is).
+    pub fn resume(&mut self) -> std::io::Result<u64> {
+        let suffix = OutputFile::suffix(self.compression);
+        let last = OutputFile::last_part(
+            &self.dst,
+            &format!("{}.{}{}", self.stem, self.extension, suffix),
+            &format!("{}_part_", self.stem),
+            &format!(".{}{}", self.extension, suffix),
+        )?;
+        let (nb_files, path) = match last {
+            Some(last) => last,
+            None => return Ok(0),
+        };
+
+        info!("resuming {:?}", path);
+        let size = match self.compression {
+            Some(_) => std::io::copy(
+                &mut OutputFile::reader(&path, self.compression)?,
+                &mut std::io::sink(),
+            )?,
+            None => std::fs::metadata(&path)?.len(),
+        };
+        let file = OpenOptions::new().append(true).open(&path)?;
+
+        if let Some(text) = self.text.take() {
+            text.finish()?;
+        }
+        self.text = Some(OutputFile::new(file, self.compression));
+        self.size = size;
+        self.nb_files = nb_files;
+        self.first_write_on_document = false;
+        Ok(nb_files)
+    }
+
     /// gets first_write_on_document and resets it to false.
     /// useful to check variable value, and to reset it to its default one
     // allow dead code if we decide to switch on it
@@ -84,9 +192,17 @@ impl TextWriter {
         ret
     }
 
+    /// Finish current file, writing the gzip trailer if applicable.
+    pub fn close_file(&mut self) -> std::io::Result<()> {
+        match self.text.take() {
+            Some(text) => text.finish(),
+            None => Ok(()),
+        }
+    }
+
     /// returns remaining size in file
     pub fn get_free_space(&self) -> Option<u64> {
-        self.size_limit.map(|sl| sl - self.size)
+        self.size_limit.map(|sl| sl.saturating_sub(self.size))
     }
 }
 
@@ -106,15 +222,18 @@ impl Write for TextWriter {
         }
 
         if let Some(text) = &mut self.text {
-            let bytes_written = text.write(buf)?;
+            // write the whole buffer at once so that separators are never
+            // inserted in the middle of a document.
+            text.write_all(buf)?;
+            let bytes_written = buf.len();
             text.write_all(b"\n\n")?;
             self.size += match u64::try_from(bytes_written) {
                 Ok(b) => b,
                 Err(e) => {
                     if let Some(sl) = self.size_limit {
                         error!(
-                            "potential size overflow on lang {} file {} ({:?}): size set to {:?}",
-                            self.lang, self.nb_files, e, self.size_limit
+                            "potential size overflow on {} file {} ({:?}): size set to {:?}",
+                            self.stem, self.nb_files, e, self.size_limit
                         );
                         sl
                     } else {
@@ -128,8 +247,8 @@ impl Write for TextWriter {
             Err(std::io::Error::new(
                 std::io::ErrorKind::Other,
                 format!(
-                    "Could not write to file {} for lang {}",
-                    self.nb_files, self.lang
+                    "Could not write to file {} for {}",
+                    self.nb_files, self.stem
                 ),
             ))
         }
@@ -152,7 +271,7 @@ mod tests {
     fn one_file() {
         std::fs::create_dir("tmp_one_file/").unwrap();
         let file_size = 10;
-        let mut tw = TextWriter::new(&PathBuf::from("tmp_one_file/"), "en", Some(file_size));
+        let mut tw = TextWriter::new(&PathBuf::from("tmp_one_file/"), "en", Some(file_size), None);
         let text = String::from("helloworld");
 
         assert_eq!(text.len() as u64, file_size);
@@ -178,7 +297,7 @@ mod tests {
     fn multiple_files() {
         std::fs::create_dir("tmp_multiple/").unwrap();
         let file_size = 10;
-        let mut tw = TextWriter::new(&PathBuf::from("tmp_multiple/"), "en", Some(file_size));
+        let mut tw = TextWriter::new(&PathBuf::from("tmp_multiple/"), "en", Some(file_size), None);
         let text = String::from("helloworld");
 
         for _ in 0..10 {
@@ -203,7 +322,12 @@ mod tests {
     fn multiple_files_different_sizes() {
         std::fs::create_dir("tmp_multiple_sizes/").unwrap();
         let file_size = 10;
-        let mut tw = TextWriter::new(&PathBuf::from("tmp_multiple_sizes/"), "en", Some(file_size));
+        let mut tw = TextWriter::new(
+            &PathBuf::from("tmp_multiple_sizes/"),
+            "en",
+            Some(file_size),
+            None,
+        );
         let texts = vec![
             "hello\nworld\n", // fits in file 1 (12bytes, overflow but unique document)
             "tiny\ntiny\n",   // fits in file 2 (10bytes, unique (maxed) document)
@@ -269,4 +393,40 @@ mod tests {
         }
         std::fs::remove_dir_all("tmp_multiple_sizes/").unwrap();
     }
+
+    #[test]
+    fn with_stem() {
+        let dst = tempfile::tempdir().unwrap();
+        let mut tw = TextWriter::with_stem(dst.path(), "en_short", Some(1), None);
+        tw.write_all(b"hello").unwrap();
+        tw.write_all(b"world").unwrap();
+
+        assert!(dst.path().join("en_short_part_1.txt").is_file());
+        assert!(dst.path().join("en_short_part_2.txt").is_file());
+        assert!(!dst.path().join("en_short.txt").exists());
+    }
+
+    #[test]
+    fn bom() {
+        let dst = tempfile::tempdir().unwrap();
+        let mut tw = TextWriter::new(dst.path(), "en", Some(1), None).with_bom(true);
+        tw.write_all(b"hello").unwrap();
+        tw.write_all(b"world").unwrap();
+        tw.close_file().unwrap();
+
+        for (part, text) in [(1, "hello"), (2, "world")] {
+            let content = std::fs::read(dst.path().join(format!("en_part_{}.txt", part))).unwrap();
+            assert_eq!(content, [UTF8_BOM, text.as_bytes(), b"\n\n"].concat());
+        }
+
+        // appended files already have their BOM
+        let mut tw = TextWriter::new(dst.path(), "fr", None, None).with_bom(true);
+        tw.write_all(b"bonjour").unwrap();
+        tw.close_file().unwrap();
+        let mut tw = TextWriter::new(dst.path(), "fr", None, None).with_bom(true);
+        tw.write_all(b"salut").unwrap();
+        tw.close_file().unwrap();
+        let content = std::fs::read_to_string(dst.path().join("fr.txt")).unwrap();
+        assert_eq!(content, "\u{feff}bonjour\n\nsalut\n\n");
+    }
 }
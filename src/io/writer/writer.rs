@@ -3,12 +3,16 @@
 Holds writing and rotating on both text and metadata files for a given language.
 Supports writing of numerous [MergedPiece], given that their identification are the same.
 Identification is checked too, preventing the writing of differently identified [MergedPiece] into a given language writer.
+
+As in JSON Lines files, confidences of metadata are rounded to a number of significant digits
+(see [super::JsonlWriter::with_prob_digits]).
 !*/
 use std::convert::TryFrom;
-use std::io::Write;
+use std::io::{BufRead, BufReader, Write};
 use std::path::Path;
 
 // use crate::processing::Metadata;
+use flate2::Compression;
 use itertools::Itertools;
 use log::{debug, error};
 
@@ -16,9 +20,11 @@ use crate::pipelines::oscarmeta::types::{MergedPiece, Metadata, PartChunk};
 // use crate::processing::{MergedPiece, PartChunk};
 use crate::{
     error,
-    io::writer::{MetaWriter, TextWriter},
+    io::writer::{MetaWriter, OutputFile, TextWriter, DEFAULT_PROB_DIGITS},
+    io::FileNaming,
 };
 
+use super::jsonlwriter::Probability;
 use super::WriterTrait;
 
 pub struct Writer {
@@ -26,6 +32,101 @@ pub struct Writer {
     handle_meta: MetaWriter,
     lang: &'static str,
     offset: usize,
+    resumed: bool,
+    prob_digits: usize,
+}
+
+impl Writer {
+    /// Create a new Writer for provided language, gzipping both text and metadata files if `compression` is set.
+    ///
+    /// Offsets recorded in metadata still refer to uncompressed lines.
+    pub fn with_compression(
+        dst: &Path,
+        lang: &'static str,
+        size_limit: Option<u64>,
+        compression: Option<Compression>,
+    ) -> Self {
+        Self::with_naming(dst, lang, size_limit, compression, &FileNaming::default())
+    }
+
+    /// Create a new Writer for provided language, whose text and metadata files are named following `naming`
+    /// (see [Self::with_compression]).
+    pub fn with_naming(
+        dst: &Path,
+        lang: &'static str,
+        size_limit: Option<u64>,
+        compression: Option<Compression>,
+        naming: &FileNaming,
+    ) -> Self {
+        let stem = naming.stem(lang);
+        Self {
+            handle_text: TextWriter::with_stem(dst, &stem, size_limit, compression)
+                .with_extension(naming.extension()),
+            handle_meta: MetaWriter::new(dst, lang, compression).with_stem(&stem),
+            lang,
+            offset: 0,
+            resumed: false,
+            prob_digits: DEFAULT_PROB_DIGITS,
+        }
+    }
+
+    /// Round the confidence of `metadata` to the significant digits of the writer.
+    fn round_confidence(&self, metadata: &mut Metadata) {
+        metadata.confidence = Probability {
+            value: metadata.confidence,
+            digits: self.prob_digits,
+        }
+        .rounded() as f32;
+    }
+
+    /// Resume after the files written by a previous run, if any, before the first write.
+    ///
+    /// Text and metadata are appended to the last text file and to its metadata file
+    /// (see [TextWriter::resume]), and offsets start after the last piece of the metadata file,
+    /// so that a resumed run writes the same files as a single one would.
+    fn resume(&mut self) -> Result<(), error::Error> {
+        if self.resumed {
+            return Ok(());
+        }
+        self.resumed = true;
+
+        let nb_files = self.handle_text.resume()?;
+        if nb_files == 0 {
+            return Ok(());
+        }
+        let meta_path = self.handle_meta.part_path(nb_files);
+        self.offset = if meta_path.is_file() {
+            Self::next_offset(&meta_path, self.handle_meta.compression())?
+        } else {
+            0
+        };
+        self.handle_meta.resume(nb_files)?;
+        debug!(
+            "{}: resuming file {} at offset {}",
+            self.lang, nb_files, self.offset
+        );
+        Ok(())
+    }
+
+    /// Get the offset following the last piece of the metadata file at `path`.
+    fn next_offset(path: &Path, compression: Option<Compression>) -> Result<usize, error::Error> {
+        let reader = BufReader::new(OutputFile::reader(path, compression)?);
+        let mut last = None;
+        for line in reader.lines() {
+            let line = line?;
+            if !line.trim().is_empty() {
+                last = Some(line);
+            }
+        }
+
+        match last {
+            Some(line) => {
+                let metadata: Metadata = serde_json::from_str(&line)?;
+                Ok(metadata.offset + metadata.nb_sentences + 1)
+            }
+            None => Ok(0),
+        }
+    }
 }
 
 impl WriterTrait for Writer {
@@ -35,15 +136,12 @@ impl WriterTrait for Writer {
     ///
     /// _See [TextWriter] to have an explanation about the *shouldn't*._
     fn new(dst: &Path, lang: &'static str, size_limit: Option<u64>) -> Result<Self, error::Error> {
-        Ok(Self {
-            handle_text: TextWriter::new(dst, lang, size_limit),
-            handle_meta: MetaWriter::new(dst, lang),
-            lang,
-            offset: 0,
-        })
+        Ok(Self::with_compression(dst, lang, size_limit, None))
     }
     /// writes the provided [MergedPiece], checking language identification.
     fn write(&mut self, pieces: Vec<MergedPiece>) -> Result<(), error::Error> {
+        self.resume()?;
+
         // get size of whole pieces.
         // If all the pieces fit, we bulk insert.
         let whole_size =
@@ -64,8 +162,11 @@ impl WriterTrait for Writer {
 
             let mut metadata: String = pc
                 .metadata
-                .iter()
-                .map(|x| serde_json::to_string(x).unwrap())
+                .iter_mut()
+                .map(|x| {
+                    self.round_confidence(x);
+                    serde_json::to_string(x).unwrap()
+                })
                 .join("\n");
 
             metadata.push('\n');
@@ -88,6 +189,7 @@ impl WriterTrait for Writer {
                 self.lang
             )));
         }
+        self.resume()?;
 
         self.handle_text.write_all(piece.sentences.as_bytes())?;
         // trigger new file creation for metadata if applicable
@@ -105,7 +207,12 @@ impl WriterTrait for Writer {
 
         // update defaulted values in metadata
         metadata.nb_sentences = piece.nb_sentences;
+        metadata.nb_chars = piece.nb_chars();
         metadata.offset = self.offset;
+        metadata.confidence = piece.confidence;
+        self.round_confidence(&mut metadata);
+        metadata.line_ranges = piece.line_ranges.clone();
+        metadata.source = piece.source.clone();
 
         // update lang offset
         self.offset += metadata.nb_sentences + 1;
@@ -118,10 +225,20 @@ impl WriterTrait for Writer {
     }
 
     /// Binds to [MetaWriter::close_file].
-    /// Closes current metadata file.
+    /// Closes current metadata file, and finishes current text file.
     fn close_meta(&mut self) -> Result<(), error::Error> {
+        self.handle_text.close_file()?;
         self.handle_meta.close_file()
     }
+
+    /// Binds to [TextWriter::with_bom]: metadata files never get a BOM, and offsets are unchanged.
+    fn set_bom(&mut self, bom: bool) {
+        self.handle_text.set_bom(bom);
+    }
+
+    fn set_prob_digits(&mut self, digits: usize) {
+        self.prob_digits = digits;
+    }
 }
 
 #[cfg(test)]
@@ -135,6 +252,8 @@ mod tests {
 
     use warc::WarcHeader;
 
+    use crate::pipelines::oscarmeta::types::Source;
+
     use super::*;
 
     type WarcHeaders = HashMap<WarcHeader, Vec<u8>>;
@@ -165,6 +284,10 @@ Ecoutez ça va plutôt bien."
                 .to_string(),
             nb_sentences: 4,
             identification: "fr",
+            confidence: 1.0,
+            line_ranges: Vec::new(),
+            source: None,
+            shard_location: None,
             headers,
         }];
 
@@ -190,9 +313,146 @@ Ecoutez ça va plutôt bien."
             .map(|m| serde_json::from_str(&m.unwrap()).unwrap())
             .collect();
         assert_eq!(metadata[0].nb_sentences, merged_pieces[0].nb_sentences);
+        assert_eq!(metadata[0].nb_chars, 78);
+        assert_eq!(metadata[0].source, None);
         std::fs::remove_dir_all(dst).unwrap();
     }
 
+    #[test]
+    fn write_source() {
+        let dst = tempfile::tempdir().unwrap();
+        let mut wr = Writer::new(dst.path(), "fr", None).unwrap();
+
+        let headers: WarcHeaders = vec![(WarcHeader::Date, b"2021-09-16T11:07:14Z".to_vec())]
+            .into_iter()
+            .collect();
+        let mut piece = MergedPiece::new(headers.clone(), vec!["Bonjour".to_string()], "fr");
+        piece.source = Some(Source::from_headers(&headers));
+        let no_source = MergedPiece::new(headers, vec!["Salut".to_string()], "fr");
+        wr.write(vec![piece, no_source]).unwrap();
+
+        let meta = std::fs::read_to_string(dst.path().join("fr_meta.jsonl")).unwrap();
+        let meta: Vec<serde_json::Value> = meta
+            .lines()
+            .map(|m| serde_json::from_str(m).unwrap())
+            .collect();
+        assert_eq!(meta[0]["source"]["url"], serde_json::Value::Null);
+        assert_eq!(meta[0]["source"]["date"], "2021-09-16T11:07:14Z");
+        assert!(meta[1].get("source").is_none());
+    }
+
+    #[test]
+    fn write_parts() {
+        let dst = tempfile::tempdir().unwrap();
+        let mut wr = Writer::new(dst.path(), "fr", Some(30)).unwrap();
+
+        let merged_pieces: Vec<MergedPiece> = (1..6)
+            .map(|i| {
+                let headers: WarcHeaders = vec![(
+                    WarcHeader::Filename,
+                    Vec::from(format!("filenametest{}", i).as_bytes()),
+                )]
+                .into_iter()
+                .collect();
+                MergedPiece {
+                    sentences: vec![format!("document {}", i); i].join("\n"),
+                    headers,
+                    nb_sentences: i,
+                    identification: "fr",
+                    confidence: 1.0,
+                    line_ranges: Vec::new(),
+                    source: None,
+                    shard_location: None,
+                }
+            })
+            .collect();
+
+        // write in two batches, one that fits in a part and one that does not.
+        wr.write(merged_pieces[..1].to_vec()).unwrap();
+        wr.write(merged_pieces[1..].to_vec()).unwrap();
+        wr.close_meta().unwrap();
+
+        let mut found = Vec::new();
+        for part in 1.. {
+            let text_path = dst.path().join(format!("fr_part_{}.txt", part));
+            if !text_path.exists() {
+                break;
+            }
+            let mut text = String::new();
+            File::open(text_path)
+                .unwrap()
+                .read_to_string(&mut text)
+                .unwrap();
+            let lines: Vec<&str> = text.lines().collect();
+
+            let f = File::open(dst.path().join(format!("fr_meta_part_{}.jsonl", part))).unwrap();
+            let metadata: Vec<Metadata> = std::io::BufReader::new(f)
+                .lines()
+                .map(|m| serde_json::from_str(&m.unwrap()).unwrap())
+                .collect();
+
+            // each document is complete and located by offsets relative to its part.
+            for meta in metadata {
+                let doc = lines[meta.offset..meta.offset + meta.nb_sentences].join("\n");
+                found.push(doc);
+            }
+        }
+
+        assert!(!dst.path().join("fr.txt").exists());
+        let expected: Vec<String> = merged_pieces.into_iter().map(|p| p.sentences).collect();
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    fn write_compressed() {
+        let dst = tempfile::tempdir().unwrap();
+        let mut wr = Writer::with_compression(dst.path(), "fr", None, Some(Compression::default()));
+
+        let merged_pieces: Vec<MergedPiece> = (1..4)
+            .map(|i| {
+                let headers: WarcHeaders = vec![(
+                    WarcHeader::Filename,
+                    Vec::from(format!("filenametest{}", i).as_bytes()),
+                )]
+                .into_iter()
+                .collect();
+                MergedPiece {
+                    sentences: vec!["lorem ipsum".to_string(); i].join("\n"),
+                    headers,
+                    nb_sentences: i,
+                    identification: "fr",
+                    confidence: 1.0,
+                    line_ranges: Vec::new(),
+                    source: None,
+                    shard_location: None,
+                }
+            })
+            .collect();
+
+        wr.write(merged_pieces.to_vec()).unwrap();
+        wr.close_meta().unwrap();
+
+        let mut text = String::new();
+        let f = File::open(dst.path().join("fr.txt.gz")).unwrap();
+        flate2::read::MultiGzDecoder::new(f)
+            .read_to_string(&mut text)
+            .unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+
+        let f = File::open(dst.path().join("fr_meta.jsonl.gz")).unwrap();
+        let metadata: Vec<Metadata> = std::io::BufReader::new(flate2::read::MultiGzDecoder::new(f))
+            .lines()
+            .map(|m| serde_json::from_str(&m.unwrap()).unwrap())
+            .collect();
+
+        // offsets point to uncompressed lines
+        for (meta, piece) in metadata.iter().zip(merged_pieces.iter()) {
+            let doc = &lines[meta.offset..meta.offset + meta.nb_sentences];
+            assert_eq!(doc.join("\n"), piece.sentences);
+        }
+        assert!(!dst.path().join("fr.txt").exists());
+    }
+
     #[test]
     fn write_multiple() {
         let dst = Path::new("dst_test_write_multiple");
@@ -217,6 +477,10 @@ Ecoutez ça va plutôt bien."
                 headers,
                 nb_sentences,
                 identification,
+                confidence: 1.0,
+                line_ranges: Vec::new(),
+                source: None,
+                shard_location: None,
             });
         }
 
@@ -241,4 +505,104 @@ Ecoutez ça va plutôt bien."
         assert_eq!(metadata[0].nb_sentences, merged_pieces[0].nb_sentences);
         std::fs::remove_dir_all(dst).unwrap();
     }
+
+    fn offsets(path: &Path, compression: Option<Compression>) -> Vec<usize> {
+        let reader = std::io::BufReader::new(OutputFile::reader(path, compression).unwrap());
+        reader
+            .lines()
+            .map(|m| {
+                serde_json::from_str::<Metadata>(&m.unwrap())
+                    .unwrap()
+                    .offset
+            })
+            .collect()
+    }
+
+    #[test]
+    fn resume() {
+        let dst = tempfile::tempdir().unwrap();
+        let piece = |sentences: &[&str]| {
+            let sentences = sentences.iter().map(|s| s.to_string()).collect();
+            MergedPiece::new(HashMap::new(), sentences, "fr")
+        };
+
+        // a first run, then a resumed one
+        let mut wr = Writer::new(dst.path(), "fr", None).unwrap();
+        wr.write(vec![piece(&["a", "b"]), piece(&["c"])]).unwrap();
+        wr.close_meta().unwrap();
+        let mut wr = Writer::new(dst.path(), "fr", None).unwrap();
+        wr.write_single(&piece(&["d"])).unwrap();
+        wr.write(vec![piece(&["e"])]).unwrap();
+        wr.close_meta().unwrap();
+
+        let text = std::fs::read_to_string(dst.path().join("fr.txt")).unwrap();
+        assert_eq!(text, "a\nb\n\nc\n\nd\n\ne\n\n");
+        assert_eq!(
+            offsets(&dst.path().join("fr_meta.jsonl"), None),
+            [0, 3, 5, 7]
+        );
+    }
+
+    #[test]
+    fn resume_parts() {
+        let dst = tempfile::tempdir().unwrap();
+        let compression = Some(flate2::Compression::fast());
+        let piece =
+            |sentence: &str| MergedPiece::new(HashMap::new(), vec![sentence.to_string()], "fr");
+        let writer = || Writer::with_compression(dst.path(), "fr", Some(20), compression);
+
+        // parts of 2 pieces at most
+        let mut wr = writer();
+        for sentence in ["aaaaaaaa", "bbbbbbbb", "cccccccc"] {
+            wr.write_single(&piece(sentence)).unwrap();
+        }
+        wr.close_meta().unwrap();
+
+        // the last part is completed before a new one is created
+        let mut wr = writer();
+        for sentence in ["dddddddd", "eeeeeeee"] {
+            wr.write_single(&piece(sentence)).unwrap();
+        }
+        wr.close_meta().unwrap();
+
+        let mut text = String::new();
+        OutputFile::reader(&dst.path().join("fr_part_2.txt.gz"), compression)
+            .unwrap()
+            .read_to_string(&mut text)
+            .unwrap();
+        assert_eq!(text, "cccccccc\n\ndddddddd\n\n");
+        assert!(!dst.path().join("fr.txt.gz").exists());
+        for (part, expected) in [(1, vec![0, 2]), (2, vec![0, 2]), (3, vec![0])] {
+            let path = dst.path().join(format!("fr_meta_part_{}.jsonl.gz", part));
+            assert_eq!(offsets(&path, compression), expected);
+        }
+    }
+
+    #[test]
+    fn write_prob_digits() {
+        let dst = tempfile::tempdir().unwrap();
+        let piece = |confidence| {
+            let mut piece = MergedPiece::new(HashMap::new(), vec!["a".to_string()], "fr");
+            piece.confidence = confidence;
+            piece
+        };
+
+        // both bulk and single writes are rounded
+        let mut wr = Writer::new(dst.path(), "fr", None).unwrap();
+        wr.write(vec![piece(0.8999999)]).unwrap();
+        wr.write_single(&piece(0.123456)).unwrap();
+        wr.set_prob_digits(2);
+        wr.write_single(&piece(0.123456)).unwrap();
+        wr.close_meta().unwrap();
+
+        let content = std::fs::read_to_string(dst.path().join("fr_meta.jsonl")).unwrap();
+        let confidences: Vec<_> = content
+            .lines()
+            .map(|l| {
+                let metadata: serde_json::Value = serde_json::from_str(l).unwrap();
+                metadata["confidence"].to_string()
+            })
+            .collect();
+        assert_eq!(confidences, vec!["0.9", "0.1235", "0.12"]);
+    }
 }
@@ -17,6 +17,14 @@ pub struct WriterDoc {
     handle: MetaWriter,
 }
 
+impl WriterDoc {
+    /// Append to existing files rather than overwriting them (see [MetaWriter::with_append]).
+    pub fn with_append(mut self, append: bool) -> Self {
+        self.handle = self.handle.with_append(append);
+        self
+    }
+}
+
 impl WriterTrait for WriterDoc {
     type Item = Document;
     /// Create a new Writer for provided language.
@@ -25,7 +33,7 @@ impl WriterTrait for WriterDoc {
     /// _See [TextWriter] to have an explanation about the *shouldn't*._
     fn new(dst: &Path, lang: &'static str, _size_limit: Option<u64>) -> Result<Self, error::Error> {
         Ok(Self {
-            handle: MetaWriter::new(dst, lang),
+            handle: MetaWriter::new(dst, lang, None),
         })
     }
     /// writes the provided [MergedPiece], checking language identification.
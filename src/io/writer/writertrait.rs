@@ -11,4 +11,14 @@ pub trait WriterTrait {
     fn write(&mut self, vals: Vec<Self::Item>) -> Result<(), Error>;
     fn write_single(&mut self, val: &Self::Item) -> Result<(), Error>;
     fn close_meta(&mut self) -> Result<(), Error>;
+
+    /// Write a UTF-8 byte order mark at the start of text files (see [super::TextWriter::with_bom]).
+    ///
+    /// Writers that don't write text files ignore it.
+    fn set_bom(&mut self, _bom: bool) {}
+
+    /// Set the number of significant digits of serialized probabilities (see [super::JsonlWriter::with_prob_digits]).
+    ///
+    /// Writers that don't serialize probabilities as text ignore it.
+    fn set_prob_digits(&mut self, _digits: usize) {}
 }
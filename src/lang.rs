@@ -7,6 +7,7 @@ use std::{
     collections::{HashMap, HashSet},
     fmt::Display,
     fs::{File, OpenOptions},
+    io::{BufRead, BufReader},
     path::{Path, PathBuf},
     str::FromStr,
 };
@@ -226,7 +227,7 @@ impl Lang {
             Self::Bs => "bs",
             Self::Bxr => "bxr",
             Self::Ca => "ca",
-            Self::Cbk => "cbr",
+            Self::Cbk => "cbk",
             Self::Ce => "ce",
             Self::Ceb => "ceb",
             Self::Ckb => "ckb",
@@ -374,7 +375,7 @@ impl Lang {
             Self::Wuu => "wuu",
             Self::Xal => "xal",
             Self::Xmf => "xmf",
-            Self::Yi => "vi",
+            Self::Yi => "yi",
             Self::Yo => "yo",
             Self::Yue => "yue",
             Self::Zh => "zh",
@@ -571,6 +572,47 @@ impl FromStr for Lang {
     }
 }
 
+impl Lang {
+    /// Get the ISO 639-3 code of the language.
+    ///
+    /// # Errors
+    /// Returns an error for labels that don't map to a single ISO 639-3 code (see [UNMAPPED]).
+    pub fn to_iso639_3(self) -> Result<&'static str, Error> {
+        let label = self.to_static();
+        ISO639_3
+            .get(label)
+            .copied()
+            .ok_or_else(|| Self::unmapped(label, "ISO 639-3"))
+    }
+
+    /// Get the BCP-47 language tag of the language.
+    ///
+    /// This is the ISO 639-1 code when there's one, the ISO 639-3 code otherwise.
+    /// Collective codes (`bh`, `nah`) are valid BCP-47 tags and are kept.
+    ///
+    /// # Errors
+    /// Returns an error for `eml` (deprecated, no replacement) and `multi`.
+    pub fn to_bcp47(self) -> Result<&'static str, Error> {
+        match self {
+            Self::Bh | Self::Nah => Ok(self.to_static()),
+            Self::Eml | Self::Multi => Err(Self::unmapped(self.to_static(), "BCP-47")),
+            _ => match self.to_static() {
+                label if label.len() == 2 => Ok(label),
+                _ => self.to_iso639_3(),
+            },
+        }
+    }
+
+    fn unmapped(label: &str, standard: &str) -> Error {
+        let reason = UNMAPPED
+            .iter()
+            .find(|(l, _)| *l == label)
+            .map(|(_, reason)| *reason)
+            .unwrap_or("unknown mapping");
+        Error::Custom(format!("no {} code for {}: {}", standard, label, reason))
+    }
+}
+
 impl Display for Lang {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let lang_str = self.to_static();
@@ -766,6 +808,305 @@ lazy_static! {
 
         m
     };
+
+    /// ISO 639-3 codes of [LANG] labels.
+    ///
+    /// Labels that have no single ISO 639-3 code (see [UNMAPPED]) are not present.
+    pub static ref ISO639_3: HashMap<&'static str, &'static str> = {
+        let mut m = HashMap::new();
+        m.insert("af", "afr");
+        m.insert("als", "gsw");
+        m.insert("am", "amh");
+        m.insert("an", "arg");
+        m.insert("ar", "ara");
+        m.insert("arz", "arz");
+        m.insert("as", "asm");
+        m.insert("ast", "ast");
+        m.insert("av", "ava");
+        m.insert("az", "aze");
+        m.insert("azb", "azb");
+        m.insert("ba", "bak");
+        m.insert("bar", "bar");
+        m.insert("bcl", "bcl");
+        m.insert("be", "bel");
+        m.insert("bg", "bul");
+        m.insert("bn", "ben");
+        m.insert("bo", "bod");
+        m.insert("bpy", "bpy");
+        m.insert("br", "bre");
+        m.insert("bs", "bos");
+        m.insert("bxr", "bxr");
+        m.insert("ca", "cat");
+        m.insert("cbk", "cbk");
+        m.insert("ce", "che");
+        m.insert("ceb", "ceb");
+        m.insert("ckb", "ckb");
+        m.insert("co", "cos");
+        m.insert("cs", "ces");
+        m.insert("cv", "chv");
+        m.insert("cy", "cym");
+        m.insert("da", "dan");
+        m.insert("de", "deu");
+        m.insert("diq", "diq");
+        m.insert("dsb", "dsb");
+        m.insert("dty", "dty");
+        m.insert("dv", "div");
+        m.insert("el", "ell");
+        m.insert("en", "eng");
+        m.insert("eo", "epo");
+        m.insert("es", "spa");
+        m.insert("et", "est");
+        m.insert("eu", "eus");
+        m.insert("fa", "fas");
+        m.insert("fi", "fin");
+        m.insert("fr", "fra");
+        m.insert("frr", "frr");
+        m.insert("fy", "fry");
+        m.insert("ga", "gle");
+        m.insert("gd", "gla");
+        m.insert("gl", "glg");
+        m.insert("gn", "grn");
+        m.insert("gom", "gom");
+        m.insert("gu", "guj");
+        m.insert("gv", "glv");
+        m.insert("he", "heb");
+        m.insert("hi", "hin");
+        m.insert("hif", "hif");
+        m.insert("hr", "hrv");
+        m.insert("hsb", "hsb");
+        m.insert("ht", "hat");
+        m.insert("hu", "hun");
+        m.insert("hy", "hye");
+        m.insert("ia", "ina");
+        m.insert("id", "ind");
+        m.insert("ie", "ile");
+        m.insert("ilo", "ilo");
+        m.insert("io", "ido");
+        m.insert("is", "isl");
+        m.insert("it", "ita");
+        m.insert("ja", "jpn");
+        m.insert("jbo", "jbo");
+        m.insert("jv", "jav");
+        m.insert("ka", "kat");
+        m.insert("kk", "kaz");
+        m.insert("km", "khm");
+        m.insert("kn", "kan");
+        m.insert("ko", "kor");
+        m.insert("krc", "krc");
+        m.insert("ku", "kur");
+        m.insert("kv", "kom");
+        m.insert("kw", "cor");
+        m.insert("ky", "kir");
+        m.insert("la", "lat");
+        m.insert("lb", "ltz");
+        m.insert("lez", "lez");
+        m.insert("li", "lim");
+        m.insert("lmo", "lmo");
+        m.insert("lo", "lao");
+        m.insert("lrc", "lrc");
+        m.insert("lt", "lit");
+        m.insert("lv", "lav");
+        m.insert("mai", "mai");
+        m.insert("mg", "mlg");
+        m.insert("mhr", "mhr");
+        m.insert("min", "min");
+        m.insert("mk", "mkd");
+        m.insert("ml", "mal");
+        m.insert("mn", "mon");
+        m.insert("mr", "mar");
+        m.insert("mrj", "mrj");
+        m.insert("ms", "msa");
+        m.insert("mt", "mlt");
+        m.insert("mwl", "mwl");
+        m.insert("my", "mya");
+        m.insert("myv", "myv");
+        m.insert("mzn", "mzn");
+        m.insert("nap", "nap");
+        m.insert("nds", "nds");
+        m.insert("ne", "nep");
+        m.insert("new", "new");
+        m.insert("nl", "nld");
+        m.insert("nn", "nno");
+        m.insert("no", "nor");
+        m.insert("oc", "oci");
+        m.insert("or", "ori");
+        m.insert("os", "oss");
+        m.insert("pa", "pan");
+        m.insert("pam", "pam");
+        m.insert("pfl", "pfl");
+        m.insert("pl", "pol");
+        m.insert("pms", "pms");
+        m.insert("pnb", "pnb");
+        m.insert("ps", "pus");
+        m.insert("pt", "por");
+        m.insert("qu", "que");
+        m.insert("rm", "roh");
+        m.insert("ro", "ron");
+        m.insert("ru", "rus");
+        m.insert("rue", "rue");
+        m.insert("sa", "san");
+        m.insert("sah", "sah");
+        m.insert("sc", "srd");
+        m.insert("scn", "scn");
+        m.insert("sco", "sco");
+        m.insert("sd", "snd");
+        m.insert("sh", "hbs");
+        m.insert("si", "sin");
+        m.insert("sk", "slk");
+        m.insert("sl", "slv");
+        m.insert("so", "som");
+        m.insert("sq", "sqi");
+        m.insert("sr", "srp");
+        m.insert("su", "sun");
+        m.insert("sv", "swe");
+        m.insert("sw", "swa");
+        m.insert("ta", "tam");
+        m.insert("te", "tel");
+        m.insert("tg", "tgk");
+        m.insert("th", "tha");
+        m.insert("tk", "tuk");
+        m.insert("tl", "tgl");
+        m.insert("tr", "tur");
+        m.insert("tt", "tat");
+        m.insert("tyv", "tyv");
+        m.insert("ug", "uig");
+        m.insert("uk", "ukr");
+        m.insert("ur", "urd");
+        m.insert("uz", "uzb");
+        m.insert("vec", "vec");
+        m.insert("vep", "vep");
+        m.insert("vi", "vie");
+        m.insert("vls", "vls");
+        m.insert("vo", "vol");
+        m.insert("wa", "wln");
+        m.insert("war", "war");
+        m.insert("wuu", "wuu");
+        m.insert("xal", "xal");
+        m.insert("xmf", "xmf");
+        m.insert("yi", "yid");
+        m.insert("yo", "yor");
+        m.insert("yue", "yue");
+        m.insert("zh", "zho");
+
+        m
+    };
+}
+
+/// Check that every provided label is in [LANG].
+///
+/// # Errors
+/// Returns an [Error::UnknownLang] holding the first unknown label.
+pub fn check_langs(langs: &HashSet<&'static str>) -> Result<(), Error> {
+    match langs.iter().find(|label| !LANG.contains(*label)) {
+        Some(label) => Err(Error::UnknownLang(label.to_string())),
+        None => Ok(()),
+    }
+}
+
+/// Get the label of [LANG] of an ISO 639-3 code (see [Lang::to_iso639_3]).
+pub fn label_from_iso639_3(code: &str) -> Option<&'static str> {
+    ISO639_3
+        .iter()
+        .find(|(_, c)| **c == code)
+        .map(|(label, _)| *label)
+}
+
+/// Get the label of [LANG] of a label emitted by a model.
+///
+/// Labels are trimmed, stripped of a leftover `__label__` prefix and lowercased before the lookup,
+/// so that cosmetic differences between models (ex. `" FR\n"`) don't make a label unknown.
+pub fn label_from_model(label: &str) -> Option<&'static str> {
+    if let Some(label) = LANG.get(label) {
+        return Some(label);
+    }
+    let label = label.trim();
+    let label = label.strip_prefix("__label__").unwrap_or(label).trim();
+    LANG.get(label.to_lowercase().as_str()).copied()
+}
+
+/// Read a mapping of extra model labels to labels of [LANG], from a tab-separated file at `path`.
+///
+/// Each line holds a model label and the [LANG] label it is routed to (ex. `fr_classic\tfr`).
+/// Blank lines and lines starting with `#` are ignored.
+///
+/// # Errors
+/// Returns an [Error::UnknownLang] if a target label is not in [LANG],
+/// and an [Error::Custom] on malformed lines.
+pub fn read_label_mapping(path: &Path) -> Result<HashMap<String, &'static str>, Error> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut mapping = HashMap::new();
+    for (idx, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (label, target) = match line.split_once('\t') {
+            Some((label, target)) if !label.trim().is_empty() => (label.trim(), target.trim()),
+            _ => {
+                return Err(Error::Custom(format!(
+                    "malformed label mapping at line {} of {:?}: {:?}",
+                    idx + 1,
+                    path,
+                    line
+                )))
+            }
+        };
+        let target = LANG
+            .get(target)
+            .ok_or_else(|| Error::UnknownLang(target.to_string()))?;
+        mapping.insert(label.to_string(), *target);
+    }
+    Ok(mapping)
+}
+
+/// Labels of [LANG] that have no single ISO 639-3 code, along with the reason.
+pub const UNMAPPED: &[(&str, &str)] = &[
+    (
+        "bh",
+        "Bihari is a collective code (bih) covering several languages",
+    ),
+    (
+        "eml",
+        "Emilian-Romagnol has been split into Emilian (egl) and Romagnol (rgn)",
+    ),
+    (
+        "nah",
+        "Nahuatl is a collective code covering several languages",
+    ),
+    ("multi", "multilingual documents have no language"),
+];
+
+/// Naming of output files and folders.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LangNaming {
+    /// fastText labels (`en.avro`).
+    #[default]
+    Label,
+    /// ISO 639-3 codes (`eng.avro`), see [Lang::to_iso639_3].
+    Iso639_3,
+    /// BCP-47 tags (`en.avro`, `gsw.avro`), see [Lang::to_bcp47].
+    Bcp47,
+}
+
+impl LangNaming {
+    /// Get the name of the files of `lang`.
+    ///
+    /// `multi` keeps its label, since it is not a language.
+    /// Languages that have no code in the chosen naming (`bh`, `eml` and `nah` for ISO 639-3, `eml` for BCP-47,
+    /// see [UNMAPPED]) keep their label too, with a warning.
+    /// Labels can't clash with codes: the ones that are kept are not codes of other languages.
+    pub fn name(&self, lang: Lang) -> &'static str {
+        let code = match (self, lang) {
+            (Self::Label, _) | (_, Lang::Multi) => return lang.to_static(),
+            (Self::Iso639_3, _) => lang.to_iso639_3(),
+            (Self::Bcp47, _) => lang.to_bcp47(),
+        };
+        code.unwrap_or_else(|e| {
+            warn!("{}, using the {} label instead", e, lang);
+            lang.to_static()
+        })
+    }
 }
 
 /// Holds language files handlers
@@ -809,3 +1150,107 @@ impl LangFiles {
         self.handles.get(key)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn to_static_roundtrip() {
+        for label in LANG.iter() {
+            assert_eq!(Lang::from_str(label).unwrap().to_static(), *label);
+        }
+    }
+
+    #[test]
+    fn iso639_3() {
+        assert_eq!(Lang::En.to_iso639_3().unwrap(), "eng");
+        assert_eq!(Lang::Als.to_iso639_3().unwrap(), "gsw");
+        assert_eq!(Lang::Arz.to_iso639_3().unwrap(), "arz");
+        assert!(Lang::Bh.to_iso639_3().is_err());
+        assert!(Lang::Multi.to_iso639_3().is_err());
+
+        // every label is either mapped or explained
+        for label in LANG.iter() {
+            assert_ne!(
+                ISO639_3.contains_key(label),
+                UNMAPPED.iter().any(|(l, _)| l == label),
+                "{}",
+                label
+            );
+        }
+        assert!(ISO639_3.keys().all(|label| LANG.contains(label)));
+        assert!(ISO639_3.values().all(|code| code.len() == 3));
+    }
+
+    #[test]
+    fn bcp47() {
+        assert_eq!(Lang::En.to_bcp47().unwrap(), "en");
+        assert_eq!(Lang::Als.to_bcp47().unwrap(), "gsw");
+        assert_eq!(Lang::Yue.to_bcp47().unwrap(), "yue");
+        assert_eq!(Lang::Nah.to_bcp47().unwrap(), "nah");
+        assert!(Lang::Eml.to_bcp47().is_err());
+        assert!(Lang::Multi.to_bcp47().is_err());
+    }
+
+    #[test]
+    fn naming() {
+        assert_eq!(LangNaming::Label.name(Lang::En), "en");
+        assert_eq!(LangNaming::Iso639_3.name(Lang::En), "eng");
+        assert_eq!(LangNaming::Iso639_3.name(Lang::Multi), "multi");
+        assert_eq!(LangNaming::Bcp47.name(Lang::Als), "gsw");
+
+        // unmapped languages keep their label
+        assert_eq!(LangNaming::Iso639_3.name(Lang::Bh), "bh");
+        assert_eq!(LangNaming::Iso639_3.name(Lang::Eml), "eml");
+        assert_eq!(LangNaming::Iso639_3.name(Lang::Nah), "nah");
+        assert_eq!(LangNaming::Bcp47.name(Lang::Eml), "eml");
+        assert_eq!(LangNaming::Bcp47.name(Lang::Nah), "nah");
+
+        // every language of the default set gets a distinct name
+        for naming in [LangNaming::Label, LangNaming::Iso639_3, LangNaming::Bcp47] {
+            let names: HashSet<_> = LANG
+                .iter()
+                .map(|label| naming.name(Lang::from_str(label).unwrap()))
+                .collect();
+            assert_eq!(names.len(), LANG.len(), "{:?}", naming);
+        }
+    }
+
+    #[test]
+    fn from_iso639_3() {
+        assert_eq!(label_from_iso639_3("eng"), Some("en"));
+        assert_eq!(label_from_iso639_3("gsw"), Some("als"));
+        assert_eq!(label_from_iso639_3("en"), None);
+    }
+
+    #[test]
+    fn from_model() {
+        assert_eq!(label_from_model("fr"), Some("fr"));
+        assert_eq!(label_from_model(" fr\n"), Some("fr"));
+        assert_eq!(label_from_model("EN"), Some("en"));
+        assert_eq!(label_from_model(" __label__Als "), Some("als"));
+        assert_eq!(label_from_model("not-a-lang"), None);
+        assert_eq!(label_from_model(""), None);
+    }
+
+    #[test]
+    fn label_mapping() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("labels.tsv");
+        std::fs::write(&path, "# custom labels\nfr_classic\tfr\n\nbre \t br\n").unwrap();
+        let mapping = read_label_mapping(&path).unwrap();
+        assert_eq!(mapping.len(), 2);
+        assert_eq!(mapping["fr_classic"], "fr");
+        assert_eq!(mapping["bre"], "br");
+
+        std::fs::write(&path, "custom\tnot-a-lang\n").unwrap();
+        assert!(matches!(
+            read_label_mapping(&path),
+            Err(Error::UnknownLang(label)) if label == "not-a-lang"
+        ));
+
+        std::fs::write(&path, "custom fr\n").unwrap();
+        assert!(matches!(read_label_mapping(&path), Err(Error::Custom(_))));
+    }
+}
@@ -7,4 +7,6 @@ pub mod lang;
 pub mod pipelines;
 pub mod processing;
 pub mod sources;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 pub mod transformers;
@@ -1,6 +1,7 @@
 #![doc = include_str!("../README.md")]
 use download::Downloader;
 use log::LevelFilter;
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::Write;
 use structopt::StructOpt;
@@ -22,6 +23,8 @@ mod lang;
 mod pipelines;
 mod processing;
 mod sources;
+#[cfg(any(test, feature = "testing"))]
+mod testing;
 mod transformers;
 
 #[tokio::main]
@@ -62,7 +65,30 @@ async fn main() -> Result<(), error::Error> {
         cli::Ungoliant::Pipeline(p) => {
             let mut schema_filepath = p.dst.clone();
             // let p = pipeline::OscarMetadata::new(p.src, p.dst, p.lid_path);
-            let p = pipelines::OscarDoc::new(p.src, p.dst, p.lid_path, p.blocklist);
+            let mut lang_thresholds = HashMap::new();
+            if let Some(path) = p.lang_thresholds {
+                let overrides: HashMap<String, f32> = serde_json::from_reader(File::open(path)?)?;
+                for (label, threshold) in overrides {
+                    let lang = lang::LANG
+                        .get(label.as_str())
+                        .ok_or(error::Error::UnknownLang(label))?;
+                    lang_thresholds.insert(*lang, threshold);
+                }
+            }
+            let log_format = if p.json_logs {
+                pipelines::events::LogFormat::Json
+            } else {
+                pipelines::events::LogFormat::Human
+            };
+            let min_doc_confidence = p.min_doc_confidence;
+            let src = std::iter::once(p.src).chain(p.extra_src).collect();
+            let mut p = pipelines::OscarDoc::new(src, p.dst, p.lid_path, p.blocklist)
+                .with_lang_thresholds(lang_thresholds)?
+                .with_log_format(log_format)
+                .with_deterministic(p.deterministic);
+            if let Some(min_doc_confidence) = min_doc_confidence {
+                p = p.with_min_doc_confidence(min_doc_confidence);
+            }
             p.run()?;
 
             schema_filepath.push("metadata_schema.json");
@@ -89,6 +115,7 @@ async fn main() -> Result<(), error::Error> {
             rb.run()?;
         }
         cli::Ungoliant::Check(c) => processing::check::check(c.src, c.dst)?,
+        cli::Ungoliant::Merge(m) => processing::merge::merge_outputs(&m.inputs, &m.dst)?,
     };
     Ok(())
 }
@@ -0,0 +1,161 @@
+//! Pipeline events.
+//!
+//! Key steps of a run (shard start/end, shard failure, per-language writes) are reported as [Event]s,
+//! so that runs can be monitored by other programs.
+//!
+//! With [LogFormat::Json], events are logged as one JSON object per line on the [EVENTS_TARGET] target,
+//! with an `event` field holding the kind of event (`shard_start`, `shard_end`, `shard_failure` or `lang_write`).
+//! Field names are stable, so that they can be relied upon.
+//! Use `RUST_LOG` to filter events from other logs (ex. `RUST_LOG=ungoliant::events=info`).
+use std::fmt;
+use std::path::Path;
+
+use log::{debug, error, info, warn};
+use serde::Serialize;
+
+/// Log target of events.
+pub const EVENTS_TARGET: &str = "ungoliant::events";
+
+/// Format of events.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Events are logged as text, at debug level (failures are logged at error level).
+    #[default]
+    Human,
+    /// Events are logged as JSON lines, at info level (failures are logged at error level).
+    Json,
+}
+
+/// Pipeline event.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// A shard is about to be processed.
+    ShardStart { shard_idx: usize, path: String },
+    /// A shard has been processed and written.
+    ShardEnd {
+        shard_idx: usize,
+        path: String,
+        records_processed: usize,
+        duration_ms: u64,
+    },
+    /// A shard could not be processed or written.
+    ShardFailure {
+        shard_idx: usize,
+        path: String,
+        error: String,
+        duration_ms: u64,
+    },
+    /// Documents of a shard have been written for a language.
+    LangWrite {
+        shard_idx: usize,
+        lang: &'static str,
+        documents: usize,
+    },
+}
+
+impl Event {
+    /// Get the path of a shard as an event field.
+    pub fn path(path: &Path) -> String {
+        path.to_string_lossy().into_owned()
+    }
+
+    /// Log event using `format`.
+    pub fn emit(&self, format: LogFormat) {
+        let failure = matches!(self, Self::ShardFailure { .. });
+        match format {
+            LogFormat::Human if failure => error!(target: EVENTS_TARGET, "{}", self),
+            LogFormat::Human => debug!(target: EVENTS_TARGET, "{}", self),
+            LogFormat::Json => match serde_json::to_string(self) {
+                Ok(json) if failure => error!(target: EVENTS_TARGET, "{}", json),
+                Ok(json) => info!(target: EVENTS_TARGET, "{}", json),
+                Err(e) => warn!("could not serialize event {:?}: {}", self, e),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ShardStart { shard_idx, path } => {
+                write!(f, "shard {} ({}) started", shard_idx, path)
+            }
+            Self::ShardEnd {
+                shard_idx,
+                path,
+                records_processed,
+                duration_ms,
+            } => write!(
+                f,
+                "shard {} ({}) done: {} records in {}ms",
+                shard_idx, path, records_processed, duration_ms
+            ),
+            Self::ShardFailure {
+                shard_idx,
+                path,
+                error,
+                duration_ms,
+            } => write!(
+                f,
+                "shard {} ({}) failed after {}ms: {}",
+                shard_idx, path, duration_ms, error
+            ),
+            Self::LangWrite {
+                shard_idx,
+                lang,
+                documents,
+            } => write!(
+                f,
+                "shard {}: {} documents written for {}",
+                shard_idx, documents, lang
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    #[test]
+    fn json_fields() {
+        let event = Event::ShardEnd {
+            shard_idx: 1,
+            path: Event::path(Path::new("src/1.txt.gz")),
+            records_processed: 10,
+            duration_ms: 42,
+        };
+        let json: serde_json::Value = serde_json::to_value(&event).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "event": "shard_end",
+                "shard_idx": 1,
+                "path": "src/1.txt.gz",
+                "records_processed": 10,
+                "duration_ms": 42,
+            })
+        );
+
+        let event = Event::LangWrite {
+            shard_idx: 1,
+            lang: "fr",
+            documents: 3,
+        };
+        let json: serde_json::Value = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["event"], "lang_write");
+        assert_eq!(json["lang"], "fr");
+    }
+
+    #[test]
+    fn display() {
+        let event = Event::ShardStart {
+            shard_idx: 0,
+            path: "0.txt.gz".to_string(),
+        };
+        assert_eq!(event.to_string(), "shard 0 (0.txt.gz) started");
+    }
+}
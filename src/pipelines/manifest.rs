@@ -0,0 +1,228 @@
+//! Run manifests.
+//!
+//! A [Manifest] is a machine-readable record of a run, written as [MANIFEST_FILE] in the destination folder.
+//! It holds the pipeline version, the language identification model (and its hash), the source and destination folders,
+//! the input shards and every configured parameter, so that a corpus build can be audited or reproduced.
+//!
+//! The manifest is written when the run starts, without counts, and written again with counts once the run is over.
+//! A manifest without counts thus belongs to a run that is still going on or that has been interrupted.
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::error::Error;
+
+/// Name of the manifest file, in the destination folder.
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// Language identification model used by a run.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LidManifest {
+    pub path: PathBuf,
+    /// sha256 of the model file, if it could be read
+    pub sha256: Option<String>,
+    /// number of predicted languages on each line
+    pub k: i32,
+    /// prediction threshold
+    pub threshold: f32,
+    /// per-language thresholds, overriding `threshold`
+    pub lang_thresholds: BTreeMap<String, f32>,
+}
+
+/// Counts of a finished run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestCounts {
+    /// number of shards that have been written
+    pub shards: usize,
+    /// number of shards that could not be processed
+    pub failed_shards: usize,
+    /// number of valid records of written shards
+    pub records: usize,
+    /// number of written documents
+    pub documents: usize,
+}
+
+/// Manifest of a run.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// pipeline version (see [crate::pipelines::Pipeline::version])
+    pub version: String,
+    /// source folders (or shard files), written as a single path if there's only one
+    #[serde(with = "one_or_many")]
+    pub src: Vec<PathBuf>,
+    pub dst: PathBuf,
+    /// input shards, sorted
+    pub inputs: Vec<PathBuf>,
+    /// fastText model, if one is used (custom identifiers are not described)
+    pub lid: Option<LidManifest>,
+    /// other parameters, by name
+    pub params: BTreeMap<String, serde_json::Value>,
+    /// counts, once the run is over
+    pub counts: Option<ManifestCounts>,
+}
+
+impl Manifest {
+    /// Create a new manifest, without counts.
+    ///
+    /// `inputs` are sorted, so that manifests of runs on the same shards are identical.
+    pub fn new(version: &str, src: Vec<PathBuf>, dst: PathBuf, mut inputs: Vec<PathBuf>) -> Self {
+        inputs.sort();
+        Self {
+            version: version.to_string(),
+            src,
+            dst,
+            inputs,
+            lid: None,
+            params: BTreeMap::new(),
+            counts: None,
+        }
+    }
+
+    /// Set the language identification model.
+    pub fn with_lid(mut self, lid: LidManifest) -> Self {
+        self.lid = Some(lid);
+        self
+    }
+
+    /// Add a parameter, replacing the previous value if any.
+    pub fn with_param(mut self, name: &str, value: serde_json::Value) -> Self {
+        self.params.insert(name.to_string(), value);
+        self
+    }
+
+    /// Get the path of the manifest in `dst`.
+    pub fn path(dst: &Path) -> PathBuf {
+        dst.join(MANIFEST_FILE)
+    }
+
+    /// Write the manifest in `dst` (see [MANIFEST_FILE]), replacing the existing one.
+    pub fn write(&self, dst: &Path) -> Result<(), Error> {
+        let mut writer = BufWriter::new(File::create(Self::path(dst))?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writeln!(writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Read the manifest of `dst`.
+    pub fn read(dst: &Path) -> Result<Self, Error> {
+        let reader = BufReader::new(File::open(Self::path(dst))?);
+        Ok(serde_json::from_reader(reader)?)
+    }
+}
+
+/// (De)serialization of source paths, as a single path if there's only one,
+/// so that manifests of single-source runs keep their format.
+mod one_or_many {
+    use std::path::PathBuf;
+
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(PathBuf),
+        Many(Vec<PathBuf>),
+    }
+
+    pub fn serialize<S: Serializer>(src: &[PathBuf], serializer: S) -> Result<S::Ok, S::Error> {
+        match src {
+            [src] => src.serialize(serializer),
+            src => src.serialize(serializer),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<PathBuf>, D::Error> {
+        Ok(match OneOrMany::deserialize(deserializer)? {
+            OneOrMany::One(src) => vec![src],
+            OneOrMany::Many(src) => src,
+        })
+    }
+}
+
+/// Compute the sha256 of a file, as an hexadecimal string.
+pub fn sha256(path: &Path) -> Result<String, Error> {
+    let mut f = File::open(path)?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut f, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn write_read() {
+        let dst = tempfile::tempdir().unwrap();
+        let mut manifest = Manifest::new(
+            "2.0.0",
+            vec![PathBuf::from("src")],
+            dst.path().to_path_buf(),
+            vec![PathBuf::from("src/1.txt.gz"), PathBuf::from("src/0.txt.gz")],
+        )
+        .with_lid(LidManifest {
+            path: PathBuf::from("lid.176.bin"),
+            sha256: None,
+            k: 1,
+            threshold: 0.8,
+            lang_thresholds: BTreeMap::new(),
+        })
+        .with_param("deterministic", json!(true));
+        assert_eq!(
+            manifest.inputs,
+            vec![PathBuf::from("src/0.txt.gz"), PathBuf::from("src/1.txt.gz")]
+        );
+
+        manifest.write(dst.path()).unwrap();
+        assert_eq!(Manifest::read(dst.path()).unwrap(), manifest);
+
+        // finalizing replaces the manifest
+        manifest.counts = Some(ManifestCounts {
+            shards: 2,
+            ..Default::default()
+        });
+        manifest.write(dst.path()).unwrap();
+        assert_eq!(Manifest::read(dst.path()).unwrap(), manifest);
+    }
+
+    #[test]
+    fn sources() {
+        let written = |src: Vec<PathBuf>| {
+            let manifest = Manifest::new("2.0.0", src, PathBuf::from("dst"), Vec::new());
+            let value = serde_json::to_value(&manifest).unwrap();
+            assert_eq!(
+                serde_json::from_value::<Manifest>(value.clone()).unwrap(),
+                manifest
+            );
+            value["src"].clone()
+        };
+
+        // single sources are written as in manifests of previous versions
+        assert_eq!(written(vec![PathBuf::from("a")]), json!("a"));
+        assert_eq!(
+            written(vec![PathBuf::from("a"), PathBuf::from("b")]),
+            json!(["a", "b"])
+        );
+    }
+
+    #[test]
+    fn sha256_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("model.bin");
+        std::fs::write(&path, "abc").unwrap();
+        assert_eq!(
+            sha256(&path).unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert!(sha256(&dir.path().join("missing.bin")).is_err());
+    }
+}
@@ -2,15 +2,23 @@
 //!
 //! Various pipelines are implemented here, and the module
 //! provides a light [pipeline::Pipeline] trait that enables easy and flexible pipeline creation.
+pub mod events;
+pub mod manifest;
 pub mod oscardoc;
 pub mod oscarmeta;
 pub mod oscartext;
 #[allow(clippy::module_inception)]
 pub mod pipeline;
+pub mod progress;
+pub mod retry;
+pub mod shutdown;
+pub mod threads;
+pub mod url_policy;
 
 // pub use oscardoc::Document;
 // pub use oscardoc::Metadata;
 pub use oscardoc::OscarDoc;
+pub use oscarmeta::Identify;
 pub use oscarmeta::OscarMetadata;
 pub use pipeline::Pipeline;
 // pub use rayon_all::RayonAll;
@@ -2,6 +2,6 @@
 mod pipeline;
 pub mod types;
 
-pub use pipeline::OscarDoc;
+pub use pipeline::{InconsistentAction, OscarDoc};
 // pub use types::Document;
 // pub use types::Metadata;
@@ -12,47 +12,123 @@
 //!   short and long sentences, discarding records where the content is primarly in short sentences. (sentence = newline-separated string)
 //! 1. The remaining ones get identified both by line and as a whole (we keep the language that has the most information (=bytes)).
 //! 1. We pass the records in the adult content annotator
+//! 1. We pass the remaining records in user-provided annotators, if any (see [OscarDoc::with_annotator])
 //! 1. We remove remaining short sentences at start/end[^1]
+//! 1. We drop documents that are too short, if configured (see [OscarDoc::with_min_length])
+//! 1. We drop or annotate documents whose dominant language is not prevalent enough, if configured (see [OscarDoc::with_language_consistency])
 //! 1. We then write documents in files.
 //!
+//! A manifest describing the run is written in the destination folder (see [crate::pipelines::manifest]).
+//!
 //! [^1]: We should do this after step 1: better efficiency.
 use std::fs::File;
 use std::path::Path;
 use std::str::Lines;
-use std::{collections::HashMap, path::PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    path::PathBuf,
+};
 
-use super::types::{Document, Location, Metadata, RebuildWriters};
+use super::types::{
+    Codec, Document, Location, Metadata, ProbQuantization, RebuildLayout, RebuildWriters,
+};
 use crate::error::Error;
+use crate::filtering::content::ContentLength;
 use crate::filtering::{record, Filter};
-use crate::identifiers::{self, Identification, Identifier};
-use crate::identifiers::{FastText, StrictMultilingual};
-use crate::io::writer::WriterTrait;
-use crate::lang::Lang;
+use crate::identifiers::{FastText, FastTextModel, StrictMultilingual};
+use crate::identifiers::{Identification, Identifier, LanguageIdentifier};
+use crate::lang::{self, Lang, LangNaming, LANG};
+use crate::pipelines::events::{Event, LogFormat};
+use crate::pipelines::manifest::{self, LidManifest, Manifest, ManifestCounts};
 use crate::pipelines::oscardoc::types::{LocationBuilder, ShardResult};
 use crate::pipelines::pipeline::Pipeline;
-use crate::sources::commoncrawl::Wet;
+use crate::pipelines::retry::Retry;
+use crate::pipelines::url_policy::UrlPolicy;
+use crate::sources::commoncrawl::RecordOffsets;
 use crate::transformers::{
     self, Annotate, Annotator, ContentDetector, Header, Noisy, ShortSentences, TinyDocument,
     Transform,
 };
+use itertools::Either;
 use log::{debug, error, info, log_enabled, warn};
 use rayon::prelude::*;
+use serde_json::json;
 use ut1_blocklist::Blocklist;
 use warc::BufferedBody;
 use warc::{Record, WarcHeader};
 
-use crate::io::LangFilesDoc;
+use crate::io::{DocFormat, LangFilesDoc, LayoutStrategy};
 
 const DOC_THRESHOLD: f32 = 0.6f32;
+
+/// Number of predicted languages on each line, and prediction threshold of the fastText model.
+const LID_K: i32 = 1;
+const LID_THRESHOLD: f32 = 0.8;
+
+/// Annotation of documents failing the language consistency check (see [OscarDoc::with_language_consistency]).
+const MULTILINGUAL_ANNOTATION: &str = "multilingual";
+
+/// What to do with documents whose dominant language is not prevalent enough
+/// (see [OscarDoc::with_language_consistency]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InconsistentAction {
+    /// Drop the document.
+    Drop,
+    /// Keep the document in its language, annotated as `multilingual`.
+    Annotate,
+}
+
+/// Documents of a processed shard, sorted by language and waiting to be written.
+struct ProcessedShard {
+    idx: usize,
+    shard_id: u64,
+    path: String,
+    documents: HashMap<Lang, Vec<(Document, Location)>>,
+    nb_records: usize,
+    start: Instant,
+}
+
 pub struct OscarDoc {
-    src: PathBuf,
+    src: Vec<PathBuf>,
     dst: PathBuf,
     lid_path: PathBuf,
     blocklist: Option<PathBuf>,
+    lang_thresholds: HashMap<&'static str, f32>,
+    languages: Option<HashSet<&'static str>>,
+    min_length: Option<ContentLength>,
+    min_doc_confidence: Option<f32>,
+    language_consistency: Option<(f32, InconsistentAction)>,
+    open_retry: Retry,
+    model: Option<FastTextModel>,
+    identifier: Option<Arc<dyn LanguageIdentifier>>,
+    layout: LayoutStrategy,
+    lang_naming: LangNaming,
+    doc_format: DocFormat,
+    rebuild_codec: Codec,
+    rebuild_layout: RebuildLayout,
+    rebuild_quantization: ProbQuantization,
+    log_format: LogFormat,
+    deterministic: bool,
+    append: bool,
+    extra_header: Option<WarcHeader>,
+    url_policy: Option<Arc<dyn UrlPolicy>>,
+    annotators: Annotator,
 }
 
 impl OscarDoc {
-    pub fn new(src: PathBuf, dst: PathBuf, lid_path: PathBuf, blocklist: Option<PathBuf>) -> Self {
+    /// Create a new pipeline, reading shards from every folder (or shard file) of `src` into a single output in `dst`.
+    ///
+    /// Shard ids of rebuild files are shard numbers, so they have to be unique across folders
+    /// (see [OscarDoc::get_paths_iter]).
+    pub fn new(
+        src: Vec<PathBuf>,
+        dst: PathBuf,
+        lid_path: PathBuf,
+        blocklist: Option<PathBuf>,
+    ) -> Self {
         if blocklist.is_none() {
             warn!("No blocklist folder specified! No adult content tagging will be done.");
         }
@@ -63,35 +139,448 @@ impl OscarDoc {
             dst,
             lid_path,
             blocklist,
+            lang_thresholds: HashMap::new(),
+            languages: None,
+            min_length: None,
+            min_doc_confidence: None,
+            language_consistency: None,
+            open_retry: Retry::default(),
+            model: None,
+            identifier: None,
+            layout: LayoutStrategy::default(),
+            lang_naming: LangNaming::default(),
+            doc_format: DocFormat::default(),
+            rebuild_codec: Codec::Snappy,
+            rebuild_layout: RebuildLayout::default(),
+            rebuild_quantization: ProbQuantization::default(),
+            log_format: LogFormat::default(),
+            deterministic: false,
+            append: false,
+            extra_header: None,
+            url_policy: None,
+            annotators: Annotator::default(),
+        }
+    }
+
+    /// Register an additional annotator (such as [transformers::AdultKeywords] or [transformers::Duplicates]).
+    ///
+    /// Additional annotators are run after the default ones, in registration order.
+    pub fn with_annotator(mut self, annotator: Box<dyn Annotate + Sync>) -> Self {
+        self.annotators.add(annotator);
+        self
+    }
+
+    /// Override the sentence identification threshold for some languages.
+    ///
+    /// Languages that are not present keep the default threshold.
+    ///
+    /// # Errors
+    /// Returns an error if a label is not in [crate::lang::LANG].
+    pub fn with_lang_thresholds(
+        mut self,
+        lang_thresholds: HashMap<&'static str, f32>,
+    ) -> Result<Self, Error> {
+        FastText::check_lang_thresholds(&lang_thresholds)?;
+        self.lang_thresholds = lang_thresholds;
+        Ok(self)
+    }
+
+    /// Set the layout of output files in `dst`.
+    ///
+    /// With [LayoutStrategy::Flat] (the default), rebuild files are put in `<dst>/rebuild/`.
+    /// With [LayoutStrategy::PerLangDir], they're put along text files, in `<dst>/<lang>/`.
+    pub fn with_layout(mut self, layout: LayoutStrategy) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    /// Set the naming of output files and language folders.
+    ///
+    /// Defaults to [LangNaming::Label] (`en.jsonl`). With [LangNaming::Iso639_3], files are named `eng.jsonl`, `eng.avro`.
+    /// Documents keep the fastText labels in their metadata.
+    ///
+    /// Languages without a code in `naming` (see [lang::UNMAPPED]) keep their label, with a warning
+    /// (see [LangNaming::name]).
+    pub fn with_lang_naming(mut self, naming: LangNaming) -> Self {
+        self.lang_naming = naming;
+        self
+    }
+
+    /// Set the format of document files.
+    ///
+    /// Defaults to [DocFormat::Jsonl]. With [DocFormat::Parquet], each document is written
+    /// along with its location (see [crate::io::writer::ParquetWriter]). Parquet files can't be appended to
+    /// (see [OscarDoc::with_append]).
+    pub fn with_doc_format(mut self, format: DocFormat) -> Self {
+        self.doc_format = format;
+        self
+    }
+
+    /// Set the codec used to compress rebuild files.
+    ///
+    /// Defaults to [Codec::Snappy]. [Codec::Deflate] compresses better, and [Codec::Null] is faster.
+    pub fn with_rebuild_codec(mut self, codec: Codec) -> Self {
+        self.rebuild_codec = codec;
+        self
+    }
+
+    /// Set how rebuild information is routed into rebuild files.
+    ///
+    /// Defaults to [RebuildLayout::PerLang]. With [RebuildLayout::PerShard], each shard gets its own
+    /// `shard_<id>.avro` file holding all of its languages, which is put in `<dst>/rebuild/` whatever the layout.
+    pub fn with_rebuild_layout(mut self, rebuild_layout: RebuildLayout) -> Self {
+        self.rebuild_layout = rebuild_layout;
+        self
+    }
+
+    /// Set how identification probabilities are stored in rebuild files.
+    ///
+    /// Defaults to [ProbQuantization::None], storing them as is. Quantized probabilities are
+    /// decoded transparently when reading rebuild files, with the precision of the quantization.
+    /// Documents (and their metadata files) keep exact probabilities.
+    /// An invalid quantization makes the run fail when it starts.
+    pub fn with_rebuild_quantization(mut self, quantization: ProbQuantization) -> Self {
+        self.rebuild_quantization = quantization;
+        self
+    }
+
+    /// Use an already loaded language identification model, in place of loading `lid_path`.
+    ///
+    /// This avoids loading the model again on each run (see [FastText::load_model]).
+    pub fn with_model(mut self, model: FastTextModel) -> Self {
+        self.model = Some(model);
+        self
+    }
+
+    /// Use another language identification backend, in place of [FastText].
+    ///
+    /// `lid_path`, [OscarDoc::with_model] and [OscarDoc::with_lang_thresholds] are then ignored:
+    /// the backend handles its own thresholds.
+    pub fn with_identifier(mut self, identifier: Arc<dyn LanguageIdentifier>) -> Self {
+        self.identifier = Some(identifier);
+        self
+    }
+
+    /// Only write documents identified in the provided languages (`multi` included).
+    ///
+    /// Files are only created for the provided languages.
+    /// Defaults to every language of [LANG].
+    ///
+    /// # Errors
+    /// Returns an error if a label is not in [LANG].
+    pub fn with_languages(mut self, languages: HashSet<&'static str>) -> Result<Self, Error> {
+        lang::check_langs(&languages)?;
+        self.languages = Some(languages);
+        Ok(self)
+    }
+
+    /// Drop documents that are shorter than `min_length` once short sentences are removed.
+    ///
+    /// Dropped documents are not written in rebuild files either.
+    /// By default, every document is kept.
+    pub fn with_min_length(mut self, min_length: ContentLength) -> Self {
+        self.min_length = Some(min_length);
+        self
+    }
+
+    /// Drop documents whose identification probability is below `min_doc_confidence`.
+    ///
+    /// The probability of a document is the byte-weighted mean probability of its lines identified in its language,
+    /// over every line (see [LanguageIdentifier::get_weighted_ids]), so that it combines the share of the language
+    /// and the confidence of its identifications.
+    /// Multilingual documents have no meaningful probability and are always kept.
+    /// Dropped documents are not written in rebuild files either, and their per-shard count is logged.
+    /// Documents below `0.6` are always dropped during identification, so lower values have no effect.
+    /// By default, there is no further filtering.
+    pub fn with_min_doc_confidence(mut self, min_doc_confidence: f32) -> Self {
+        self.min_doc_confidence = Some(min_doc_confidence);
+        self
+    }
+
+    /// Check if `doc` has a high enough confidence (see [OscarDoc::with_min_doc_confidence]).
+    fn keep_confident(&self, doc: &Document) -> bool {
+        let identification = doc.identification();
+        *identification.label() == Lang::Multi
+            || self
+                .min_doc_confidence
+                .is_none_or(|min| identification.prob() >= &min)
+    }
+
+    /// Require that at least `min_share` of the characters of a document are identified in its dominant language
+    /// (see [Document::dominant_language]), applying `action` to documents that don't.
+    ///
+    /// Unidentified lines count against the share, and multilingual documents are not checked.
+    /// Dropped documents are not written in rebuild files either, and per-shard counts are logged.
+    /// By default, documents are not checked.
+    ///
+    /// # Errors
+    /// Returns an error if `min_share` is not in `(0, 1]`.
+    pub fn with_language_consistency(
+        mut self,
+        min_share: f32,
+        action: InconsistentAction,
+    ) -> Result<Self, Error> {
+        if !(min_share > 0.0 && min_share <= 1.0) {
+            return Err(Error::Custom(format!(
+                "invalid language share: {} (should be in (0, 1])",
+                min_share
+            )));
         }
+        self.language_consistency = Some((min_share, action));
+        Ok(self)
     }
 
-    /// list files in source folder,
+    /// Check if at least `min_share` of the characters of `doc` are in its dominant language
+    /// (see [OscarDoc::with_language_consistency]).
+    fn is_consistent(doc: &Document, min_share: f32) -> bool {
+        *doc.identification().label() == Lang::Multi
+            || doc
+                .dominant_language()
+                .is_some_and(|(_, share)| share >= min_share)
+    }
+
+    /// Apply `action` to the documents of `documents` that are not consistent (see [OscarDoc::is_consistent]),
+    /// returning their number.
+    fn check_consistency(
+        documents: &mut Vec<(Document, Location)>,
+        min_share: f32,
+        action: InconsistentAction,
+    ) -> usize {
+        let mut nb_inconsistent = 0;
+        documents.retain_mut(|(doc, _)| {
+            if Self::is_consistent(doc, min_share) {
+                return true;
+            }
+            nb_inconsistent += 1;
+            match action {
+                InconsistentAction::Drop => false,
+                InconsistentAction::Annotate => {
+                    doc.metadata_mut()
+                        .set_annotation(MULTILINGUAL_ANNOTATION.to_string());
+                    true
+                }
+            }
+        });
+        nb_inconsistent
+    }
+
+    /// Retry opening shards that fail with a transient I/O error, making at most `attempts` attempts.
+    ///
+    /// Retries wait `base_delay`, then twice as long after each failed attempt.
+    /// Permanent errors (such as a missing file) are not retried (see [Retry::is_transient]).
+    /// Shards that still can't be opened fail as usual.
+    /// By default, shards are opened once.
+    pub fn with_open_retries(mut self, attempts: usize, base_delay: Duration) -> Self {
+        self.open_retry = Retry::new(attempts, base_delay);
+        self
+    }
+
+    /// Set the format of run events (see [crate::pipelines::events]).
+    ///
+    /// Defaults to [LogFormat::Human]. Other logs are not affected.
+    pub fn with_log_format(mut self, log_format: LogFormat) -> Self {
+        self.log_format = log_format;
+        self
+    }
+
+    /// Enable or disable deterministic output.
+    ///
+    /// Shards and records are processed concurrently, so documents are written in a different order on each run.
+    /// When enabled, documents are written ordered by `(shard_id, loc_in_shard, line_start)`
+    /// (see [Location]), both in text/metadata files and in rebuild files,
+    /// so that two runs on the same input produce the same files
+    /// (apart from Avro sync markers, that are random).
+    ///
+    /// # Performance
+    /// Shards are still processed concurrently, but they are written one at a time in shard order:
+    /// a processed shard is kept in memory until every shard before it is written.
+    /// A slow shard thus holds back the writing of the following ones,
+    /// and memory usage can grow up to the size of several shards.
+    ///
+    /// Defaults to `false`.
+    pub fn with_deterministic(mut self, deterministic: bool) -> Self {
+        self.deterministic = deterministic;
+        self
+    }
+
+    /// Enable or disable appending to an existing corpus in `dst`.
+    ///
+    /// Documents are then appended to existing text/metadata and rebuild files rather than overwriting them,
+    /// so that new shards can be added to a corpus built by a previous run with the same layout and naming.
+    /// Rebuild files keep their codec (see [super::types::RebuildWriter::append_path]).
+    /// The manifest (see [Manifest]) is replaced, and only describes the last run.
+    ///
+    /// This is riskier than writing a new corpus: shards are not checked against the ones already processed,
+    /// and an interrupted run leaves incomplete files that can't be appended to anymore.
+    /// Only JSON Lines document files can be appended to (see [OscarDoc::with_doc_format]).
+    /// Defaults to `false`.
+    pub fn with_append(mut self, append: bool) -> Self {
+        self.append = append;
+        self
+    }
+
+    /// Carry the JSON text of the `header` WARC header (such as `x-source-metadata`) verbatim as extra metadata
+    /// of documents (see [super::types::Metadata::with_extra]).
+    ///
+    /// Documents without the header have no extra metadata.
+    /// Values that are not valid JSON are logged and left out.
+    /// Defaults to `None`.
+    pub fn with_extra_header(mut self, header: Option<&str>) -> Self {
+        self.extra_header = header.map(WarcHeader::from);
+        self
+    }
+
+    /// Rewrite the URL of documents (such as stripping query strings) before it is written
+    /// in their metadata (see [UrlPolicy::rewrite_headers]).
+    ///
+    /// URLs are rewritten before extra metadata is extracted (see [OscarDoc::with_extra_header]),
+    /// so rebuild files built from the `warc-target-uri` header hold rewritten URLs too.
+    /// Defaults to `None`, keeping URLs as is.
+    pub fn with_url_policy(mut self, url_policy: Option<Arc<dyn UrlPolicy>>) -> Self {
+        self.url_policy = url_policy;
+        self
+    }
+
+    /// Describe a run on `inputs` (see [Manifest]).
+    ///
+    /// The fastText model is described unless another backend is used (see [OscarDoc::with_identifier]),
+    /// and is hashed from `lid_path` (even if it has been provided with [OscarDoc::with_model]).
+    fn manifest(&self, inputs: Vec<PathBuf>) -> Manifest {
+        let mut manifest = Manifest::new(
+            <Self as Pipeline<()>>::version(),
+            self.src.clone(),
+            self.dst.clone(),
+            inputs,
+        );
+        if self.identifier.is_none() {
+            let sha256 = manifest::sha256(&self.lid_path)
+                .map_err(|e| warn!("could not hash model {:?}: {:?}", self.lid_path, e))
+                .ok();
+            manifest = manifest.with_lid(LidManifest {
+                path: self.lid_path.clone(),
+                sha256,
+                k: LID_K,
+                threshold: LID_THRESHOLD,
+                lang_thresholds: self
+                    .lang_thresholds
+                    .iter()
+                    .map(|(lang, threshold)| (lang.to_string(), *threshold))
+                    .collect(),
+            });
+        }
+
+        let mut languages: Option<Vec<&'static str>> =
+            self.languages.as_ref().map(|l| l.iter().copied().collect());
+        if let Some(languages) = &mut languages {
+            languages.sort_unstable();
+        }
+        let min_length = self.min_length.map(|min_length| match min_length {
+            ContentLength::Chars(chars) => json!({ "chars": chars }),
+            ContentLength::Lines(lines) => json!({ "lines": lines }),
+        });
+
+        manifest
+            .with_param("identifier", json!(self.identifier.is_some()))
+            .with_param("doc_threshold", json!(DOC_THRESHOLD))
+            .with_param("blocklist", json!(self.blocklist))
+            .with_param("languages", json!(languages))
+            .with_param("min_length", json!(min_length))
+            .with_param("min_doc_confidence", json!(self.min_doc_confidence))
+            .with_param(
+                "language_consistency",
+                json!(self.language_consistency.map(|(min_share, action)| json!({
+                    "min_share": min_share,
+                    "action": format!("{:?}", action),
+                }))),
+            )
+            .with_param(
+                "open_retry",
+                json!({
+                    "attempts": self.open_retry.attempts(),
+                    "base_delay_ms": self.open_retry.base_delay().as_millis() as u64,
+                }),
+            )
+            .with_param("layout", json!(format!("{:?}", self.layout)))
+            .with_param("lang_naming", json!(format!("{:?}", self.lang_naming)))
+            .with_param("doc_format", json!(format!("{:?}", self.doc_format)))
+            .with_param("rebuild_codec", json!(format!("{:?}", self.rebuild_codec)))
+            .with_param(
+                "rebuild_layout",
+                json!(format!("{:?}", self.rebuild_layout)),
+            )
+            .with_param(
+                "rebuild_quantization",
+                json!(format!("{:?}", self.rebuild_quantization)),
+            )
+            .with_param("log_format", json!(format!("{:?}", self.log_format)))
+            .with_param("deterministic", json!(self.deterministic))
+            .with_param("append", json!(self.append))
+            .with_param(
+                "extra_header",
+                json!(self.extra_header.as_ref().map(WarcHeader::to_string)),
+            )
+            .with_param("url_policy", json!(self.url_policy.is_some()))
+    }
+
+    /// list files in source folders, in `src` order,
     /// filter out errors from fs and from gzip/wet.
     ///
+    /// If a source is a file, it is a shard itself,
+    /// so that the output is the same as with a directory holding this file only.
+    ///
     /// This means that invalid gz files and invalid
     /// wet files are discarded silently
     fn get_paths_iter(&self) -> Result<impl Iterator<Item = PathBuf>, Error> {
-        let results = std::fs::read_dir(&self.src)?
-            .filter_map(|shard| {
-                shard.map_or_else(
-                    |e| {
-                        error!("error reading shard directory: {}", e);
-                        None
-                    },
-                    Some,
-                )
+        let sources = self
+            .src
+            .iter()
+            .map(|src| {
+                if src.is_file() {
+                    return Ok(Either::Left(std::iter::once(src.clone())));
+                }
+
+                let results = std::fs::read_dir(src)?
+                    .filter_map(|shard| {
+                        shard.map_or_else(
+                            |e| {
+                                error!("error reading shard directory: {}", e);
+                                None
+                            },
+                            Some,
+                        )
+                    })
+                    .map(|shard| shard.path());
+                Ok(Either::Right(results))
             })
-            .map(|shard| shard.path());
-        Ok(results)
+            .collect::<Result<Vec<_>, Error>>()?;
+        Ok(sources.into_iter().flatten())
     }
 
-    fn get_shard_number(shard_path: &Path) -> Result<usize, Error> {
+    /// Check that shard numbers, that are used as shard ids in rebuild files, are unique across `shards`
+    /// (such as shards of different source folders).
+    ///
+    /// Shards without a number are not checked, since they can't be processed.
+    fn check_shard_numbers(shards: &[PathBuf]) -> Result<(), Error> {
+        let mut numbers: HashMap<u64, &PathBuf> = HashMap::with_capacity(shards.len());
+        for shard in shards {
+            if let Ok(number) = Self::get_shard_number(shard) {
+                if let Some(other) = numbers.insert(number, shard) {
+                    return Err(Error::Custom(format!(
+                        "shards {:?} and {:?} have the same number ({}): their shard ids would collide",
+                        other, shard, number
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn get_shard_number(shard_path: &Path) -> Result<u64, Error> {
         let shard_number = shard_path.file_stem();
         let shard_number = shard_number
             .and_then(|s| s.to_str())
             .and_then(|s| s.split('.').next())
-            .map(|s| s.parse::<usize>());
+            .map(|s| s.parse::<u64>());
 
         match shard_number {
             Some(Ok(sn)) => Ok(sn),
@@ -104,23 +593,34 @@ impl OscarDoc {
     }
 
     /// Process a shard, returning a [Vec] of [Document].
+    ///
+    /// `nb_records` is incremented for each valid record of the shard.
+    /// The shard is opened using `open_retry`.
     fn process_shard(
         shard_path: &Path,
-        identifier: &identifiers::FastText,
+        open_retry: &Retry,
+        identifier: &dyn LanguageIdentifier,
         filter: Option<record::FilterKind>,
         blocklist: &Option<PathBuf>,
-    ) -> Result<(usize, Vec<(Document, Location)>), Error> {
+        annotators: &Annotator,
+        nb_records: &AtomicUsize,
+    ) -> Result<(u64, Vec<(Document, Location)>), Error> {
         info!("working on shard: {:?}", shard_path);
 
         // get shard number
         let shard_id = Self::get_shard_number(shard_path)?;
 
-        let shard = Wet::from_path_gzip(&shard_path)?;
-        let record_iter = shard.iter.enumerate().par_bridge();
+        let shard = open_retry.run(&format!("opening shard {:?}", shard_path), || {
+            RecordOffsets::from_path(shard_path)
+        })?;
+        let record_iter = shard.enumerate().par_bridge();
 
         // only get valid records, print errors
         let record_iter = record_iter.filter_map(|(idx, record)| match record {
-            Ok(r) => Some((idx, r)),
+            Ok(r) => {
+                nb_records.fetch_add(1, Ordering::Relaxed);
+                Some((idx, r))
+            }
             Err(e) => {
                 error!("{:?}", e);
                 None
@@ -129,39 +629,43 @@ impl OscarDoc {
 
         // begin creation of location
         // We fill what we can fill now: shard_id, location_in_shard and record_id.
-        let record_iter = record_iter.map(|(idx, record)| {
+        let record_iter = record_iter.map(|(idx, (record, body_range))| {
             let mut loc = LocationBuilder::default();
             loc.set_shard_id(shard_id);
             loc.set_loc_in_shard(idx);
             loc.set_record_id(record.warc_id().to_string());
 
-            (loc, record)
+            (loc, record, body_range.start)
         });
 
         // remove short sentences, discarding documents that only have short sentences
         let length_filter = transformers::RemoveShortSentences::default();
-        let record_iter = record_iter.filter_map(|(mut loc, mut record)| {
+        let record_iter = record_iter.filter_map(|(mut loc, mut record, body_start)| {
+            // the body is replaced by kept lines, so we have to compute byte offsets beforehand
+            let body = record.body().to_vec();
             let bounds = length_filter.transform(&mut record);
-            match bounds.len() {
+            let bounds = match bounds.len() {
                 0 => {
                     debug!("record {} has no sentences kept", record.warc_id());
-                    None
-                }
-                1 => {
-                    loc.set_line_start(*bounds[0].start());
-                    loc.set_line_end(*bounds[0].end());
-                    Some((loc, record))
+                    return None;
                 }
+                1 => &bounds[0],
                 _ => {
                     warn!(
                         "record {} has more than one chunk of sentences kept",
                         record.warc_id()
                     );
-                    loc.set_line_start(*bounds[0].start());
-                    loc.set_line_end(*bounds[0].end());
-                    Some((loc, record))
+                    &bounds[0]
                 }
+            };
+            loc.set_line_start(*bounds.start());
+            loc.set_line_end(*bounds.end());
+            if let Some((start, end)) =
+                Location::lines_byte_range(&body, *bounds.start(), *bounds.end())
+            {
+                loc.set_byte_range(body_start + start, body_start + end);
             }
+            Some((loc, record))
         });
 
         // get specified filter or resort to default filter kind
@@ -216,6 +720,12 @@ impl OscarDoc {
             }
         });
 
+        // run additional annotators on kept documents
+        let record_iter = record_iter.map(|(mut r, loc)| {
+            annotators.annotate(&mut r);
+            (r, loc)
+        });
+
         let records: Vec<(_, _)> = record_iter.collect();
         info!("Shard {}: Got {} documents", shard_id, records.len());
 
@@ -227,7 +737,7 @@ impl OscarDoc {
     /// then compute the most present identification
     fn process_record(
         record: Record<BufferedBody>,
-        identifier: &identifiers::FastText,
+        identifier: &dyn LanguageIdentifier,
     ) -> Result<Option<Document>, Error> {
         // get lines
         let (headers, body) = record.into_raw_parts();
@@ -306,34 +816,110 @@ impl OscarDoc {
         ret
     }
 
+    /// Write the documents of a processed shard, emitting the corresponding events and updating `counts`.
+    ///
+    /// Shards that can't be written are reported as failed (some of their documents may have been written).
+    fn write_shard<'a>(
+        &self,
+        langfiles: &LangFilesDoc,
+        avrowriters: &'a RebuildWriters<'a, File>,
+        shard: ProcessedShard,
+        counts: &Mutex<ManifestCounts>,
+    ) {
+        let lang_counts: Vec<(&'static str, usize)> = shard
+            .documents
+            .iter()
+            .map(|(lang, docs)| (lang.to_static(), docs.len()))
+            .collect();
+        if let Err(e) =
+            Self::write_documents(langfiles, avrowriters, shard.shard_id, shard.documents)
+        {
+            counts.lock().unwrap().failed_shards += 1;
+            Event::ShardFailure {
+                shard_idx: shard.idx,
+                path: shard.path,
+                error: format!("{:?}", e),
+                duration_ms: shard.start.elapsed().as_millis() as u64,
+            }
+            .emit(self.log_format);
+            return;
+        }
+
+        let mut counts = counts.lock().unwrap();
+        counts.shards += 1;
+        counts.records += shard.nb_records;
+        counts.documents += lang_counts.iter().map(|(_, nb)| nb).sum::<usize>();
+        drop(counts);
+
+        for (lang, documents) in lang_counts {
+            Event::LangWrite {
+                shard_idx: shard.idx,
+                lang,
+                documents,
+            }
+            .emit(self.log_format);
+        }
+        Event::ShardEnd {
+            shard_idx: shard.idx,
+            path: shard.path,
+            records_processed: shard.nb_records,
+            duration_ms: shard.start.elapsed().as_millis() as u64,
+        }
+        .emit(self.log_format);
+    }
+
     /// concurrently write documets
+    ///
+    /// With [RebuildLayout::PerShard], rebuild information of all languages is then written at once,
+    /// sorted by language.
+    ///
+    /// # Errors
+    /// Languages are written independently: if some of them fail, the others are still written,
+    /// and the errors are returned together.
     fn write_documents<'a>(
         langfiles: &LangFilesDoc,
         avrowriters: &'a RebuildWriters<'a, File>,
-        shard_id: usize,
+        shard_id: u64,
         documents: HashMap<Lang, Vec<(Document, Location)>>,
     ) -> Result<(), Error> {
-        let errors: Vec<Error> = documents
+        let per_shard = avrowriters.rebuild_layout() == RebuildLayout::PerShard;
+        let shard_rebuild = Mutex::new(Vec::new());
+        let mut errors: Vec<Error> = documents
             .into_par_iter()
             .map(|(lang, docs)| {
                 debug!("[{}]: {} documents", lang, docs.len());
 
                 // get mutexes on writers
                 let writer = langfiles.writers().get(&lang).unwrap();
-                let avrowriter = avrowriters.get(&lang).unwrap();
                 let mut writer_lock = writer.lock().unwrap();
-                let mut avrowriter_lock = avrowriter.lock().unwrap();
 
-                // divide the documents iterator into two iterators
-                let (docs, locations): (Vec<_>, Vec<_>) =
-                    docs.into_iter().map(|(doc, loc)| (doc, loc)).unzip();
+                // divide the documents iterator into two iterators,
+                // computing counts on the final content
+                let (docs, locations): (Vec<_>, Vec<_>) = docs
+                    .into_iter()
+                    .map(|(mut doc, loc)| {
+                        doc.update_counts();
+                        (doc, loc)
+                    })
+                    .unzip();
 
                 // clone metadata
-                let metadata_cloned = docs.iter().map(|doc| doc.metadata().clone()).collect();
-                let sr = ShardResult::new(shard_id as i64, locations, metadata_cloned);
+                let metadata_cloned: Vec<_> =
+                    docs.iter().map(|doc| doc.metadata().clone()).collect();
 
                 // write docs and rebuild files
-                writer_lock.write(docs)?;
+                writer_lock.write(docs, &locations)?;
+                if per_shard {
+                    shard_rebuild
+                        .lock()
+                        .unwrap()
+                        .push((lang, locations, metadata_cloned));
+                    return Ok(());
+                }
+
+                let sr = ShardResult::new(shard_id, locations, metadata_cloned);
+                let avrowriter = avrowriters.get(&lang).unwrap();
+                let mut avrowriter_lock = avrowriter.lock().unwrap();
                 avrowriter_lock.append_ser(sr)?;
 
                 //TODO: not sure that we need the flush
@@ -348,11 +934,34 @@ impl OscarDoc {
             })
             .collect();
 
-        for error in errors {
-            error!("{:?}", error);
+        if per_shard {
+            let mut shard_rebuild = shard_rebuild.into_inner().unwrap();
+            shard_rebuild.sort_unstable_by_key(|(lang, _, _)| lang.to_static());
+            let (mut locations, mut metadata) = (Vec::new(), Vec::new());
+            for (_, lang_locations, lang_metadata) in shard_rebuild {
+                locations.extend(lang_locations);
+                metadata.extend(lang_metadata);
+            }
+            let sr = ShardResult::new(shard_id, locations, metadata);
+            if let Err(e) = avrowriters.write_shard(&sr) {
+                errors.push(e);
+            }
         }
 
-        Ok(())
+        match errors.len() {
+            0 => Ok(()),
+            1 => Err(errors.remove(0)),
+            _ => Err(Error::Custom(format!(
+                "{} errors while writing shard {}: {}",
+                errors.len(),
+                shard_id,
+                errors
+                    .iter()
+                    .map(|e| format!("{:?}", e))
+                    .collect::<Vec<_>>()
+                    .join("; ")
+            ))),
+        }
     }
 }
 
@@ -364,10 +973,20 @@ impl Pipeline<()> for OscarDoc {
     fn run(&self) -> Result<(), Error> {
         // let errors;
 
-        let cls = FastText::new(&self.lid_path, 1, 0.8).expect(&format!(
-            "Could not load language identifier at {:?}",
-            self.lid_path
-        ));
+        let cls: Arc<dyn LanguageIdentifier> = match &self.identifier {
+            Some(identifier) => identifier.clone(),
+            None => {
+                let mut cls = match &self.model {
+                    Some(model) => FastText::from_model(model.clone(), LID_K, LID_THRESHOLD),
+                    None => FastText::new(&self.lid_path, LID_K, LID_THRESHOLD).expect(&format!(
+                        "Could not load language identifier at {:?}",
+                        self.lid_path
+                    )),
+                };
+                cls.set_lang_thresholds(self.lang_thresholds.clone())?;
+                Arc::new(cls)
+            }
+        };
 
         if !self.dst.exists() {
             warn!("Destination file does not exist. Creating");
@@ -377,38 +996,434 @@ impl Pipeline<()> for OscarDoc {
         if !self.dst.is_dir() {
             panic!("Destination has to be a directory: {:?}", self.dst);
         }
-        let results = self.get_paths_iter()?;
+        let mut results: Vec<PathBuf> = self.get_paths_iter()?.collect();
+        Self::check_shard_numbers(&results)?;
+        if self.deterministic {
+            // shard indices follow shard numbers
+            results.sort_by_cached_key(|path| (Self::get_shard_number(path).ok(), path.clone()));
+        }
+
+        let mut manifest = self.manifest(results.clone());
+        manifest.write(&self.dst)?;
+        let counts = Mutex::new(ManifestCounts::default());
 
         // convert to parallel iterator
         // /!\: We use par_bridge, that is suboptimal
         //      compared to implementing IntoParallelIterator
         //      ourselves.
-        let results = results.enumerate().par_bridge();
-
-        let langfiles = LangFilesDoc::new(&self.dst, None)?;
-        let mut dst_rebuild = self.dst.clone();
-        dst_rebuild.push("rebuild");
-
-        let rebuild_files = RebuildWriters::with_dst(&dst_rebuild)?;
+        let results = results.into_iter().enumerate().par_bridge();
 
-        //iterate over shards
-        let shards_results = results.map(|(idx, shard)| {
+        let languages = self.languages.as_ref().unwrap_or(&LANG);
+        let dst_rebuild = match (self.layout, self.rebuild_layout) {
+            (LayoutStrategy::PerLangDir, RebuildLayout::PerLang) => self.dst.clone(),
+            _ => self.dst.join("rebuild"),
+        };
+        // before any file is created
+        self.rebuild_quantization.check()?;
+        if self.append && self.doc_format != DocFormat::Jsonl {
+            return Err(Error::Custom(
+                "Parquet files can't be appended to".to_string(),
+            ));
+        }
+        let (langfiles, rebuild_files) = if self.append {
             (
-                idx,
-                Self::process_shard(&shard, &cls, None, &self.blocklist),
+                LangFilesDoc::open_append(
+                    &self.dst,
+                    languages,
+                    None,
+                    self.layout,
+                    self.lang_naming,
+                )?,
+                RebuildWriters::with_rebuild_layout(
+                    &dst_rebuild,
+                    languages,
+                    self.layout,
+                    self.rebuild_codec,
+                    self.lang_naming,
+                    self.rebuild_layout,
+                    true,
+                )?,
             )
-        });
+        } else {
+            (
+                LangFilesDoc::with_format(
+                    &self.dst,
+                    languages,
+                    None,
+                    self.layout,
+                    self.lang_naming,
+                    self.doc_format,
+                )?,
+                RebuildWriters::with_rebuild_layout(
+                    &dst_rebuild,
+                    languages,
+                    self.layout,
+                    self.rebuild_codec,
+                    self.lang_naming,
+                    self.rebuild_layout,
+                    false,
+                )?,
+            )
+        };
+        let rebuild_files = rebuild_files.with_quantization(self.rebuild_quantization)?;
 
+        // next shard to write and processed shards waiting for it, in deterministic mode.
+        // failed shards are None.
+        let pending: Mutex<(usize, BTreeMap<usize, Option<ProcessedShard>>)> =
+            Mutex::new((0, BTreeMap::new()));
+
+        //iterate over shards
         // for each shard result, sort by lang and write concurrently.
-        shards_results.for_each(|(idx, shard_result)| {
-            if let Ok((shard_id, shard_result)) = shard_result {
-                let hm = Self::sort_by_lang(shard_result);
-                Self::write_documents(&langfiles, &rebuild_files, shard_id, hm).unwrap();
-            } else {
-                error!("Error with shard idx {}:{:?}", idx, shard_result);
+        results.for_each(|(idx, shard)| {
+            let path = Event::path(&shard);
+            Event::ShardStart {
+                shard_idx: idx,
+                path: path.clone(),
+            }
+            .emit(self.log_format);
+            let start = Instant::now();
+            let nb_records = AtomicUsize::new(0);
+
+            let shard_result = Self::process_shard(
+                &shard,
+                &self.open_retry,
+                cls.as_ref(),
+                None,
+                &self.blocklist,
+                &self.annotators,
+                &nb_records,
+            );
+            let processed = match shard_result {
+                Ok((shard_id, mut shard_result)) => {
+                    // drop documents that are not confident enough,
+                    // documents of languages that are not processed
+                    // and documents that are too short, along with their locations
+                    let nb_documents = shard_result.len();
+                    shard_result.retain(|(doc, _)| self.keep_confident(doc));
+                    if let Some(min) = self.min_doc_confidence {
+                        info!(
+                            "shard {}: dropped {} documents with a confidence below {}",
+                            idx,
+                            nb_documents - shard_result.len(),
+                            min
+                        );
+                    }
+                    if let Some((min_share, action)) = self.language_consistency {
+                        let nb_inconsistent =
+                            Self::check_consistency(&mut shard_result, min_share, action);
+                        info!(
+                            "shard {}: {} documents with a dominant language share below {} ({:?})",
+                            idx, nb_inconsistent, min_share, action
+                        );
+                    }
+                    shard_result.retain(|(doc, _)| {
+                        languages.contains(doc.identification().label().to_static())
+                            && self
+                                .min_length
+                                .is_none_or(|min_length| min_length.detect(doc.content()))
+                    });
+                    if let Some(url_policy) = &self.url_policy {
+                        for (doc, _) in shard_result.iter_mut() {
+                            url_policy.rewrite_headers(doc.warc_headers_mut());
+                        }
+                    }
+                    if let Some(header) = &self.extra_header {
+                        for (doc, _) in shard_result.iter_mut() {
+                            if let Err(e) = doc.set_extra_from_header(header) {
+                                warn!(
+                                    "record {}: invalid extra metadata in {} header: {:?}",
+                                    doc.warc_id(),
+                                    header,
+                                    e
+                                );
+                            }
+                        }
+                    }
+                    if self.deterministic {
+                        // restore record order, lost by par_bridge
+                        shard_result.sort_unstable_by_key(|(_, loc)| {
+                            (loc.shard_id(), loc.loc_in_shard(), loc.line_start())
+                        });
+                    }
+                    Some(ProcessedShard {
+                        idx,
+                        shard_id,
+                        path,
+                        documents: Self::sort_by_lang(shard_result),
+                        nb_records: nb_records.into_inner(),
+                        start,
+                    })
+                }
+                Err(e) => {
+                    counts.lock().unwrap().failed_shards += 1;
+                    Event::ShardFailure {
+                        shard_idx: idx,
+                        path,
+                        error: format!("{:?}", e),
+                        duration_ms: start.elapsed().as_millis() as u64,
+                    }
+                    .emit(self.log_format);
+                    None
+                }
+            };
+
+            if !self.deterministic {
+                if let Some(shard) = processed {
+                    self.write_shard(&langfiles, &rebuild_files, shard, &counts);
+                }
+                return;
+            }
+
+            // write every shard that is ready, in shard order
+            let mut pending = pending.lock().unwrap();
+            let (next, shards) = &mut *pending;
+            shards.insert(idx, processed);
+            while let Some(shard) = shards.remove(next) {
+                *next += 1;
+                if let Some(shard) = shard {
+                    self.write_shard(&langfiles, &rebuild_files, shard, &counts);
+                }
             }
         });
 
+        manifest.counts = Some(counts.into_inner().unwrap());
+        manifest.write(&self.dst)?;
+
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+    use std::sync::{Arc, Mutex};
+    use std::time::Instant;
+
+    use fasttext::Prediction;
+
+    use crate::error::Error;
+    use crate::identifiers::{Identification, LanguageIdentifier};
+    use crate::io::{LangFilesDoc, LayoutStrategy};
+    use crate::lang::{Lang, LangNaming};
+    use crate::pipelines::manifest::ManifestCounts;
+    use crate::pipelines::oscardoc::types::{
+        Codec, Document, Location, Metadata, RebuildLayout, RebuildReader, RebuildWriters,
+    };
+    use crate::pipelines::Pipeline;
+    use crate::testing::WetBuilder;
+
+    use super::{InconsistentAction, OscarDoc, ProcessedShard};
+
+    fn pipeline() -> OscarDoc {
+        OscarDoc::new(vec![PathBuf::new()], PathBuf::new(), PathBuf::new(), None)
+    }
+
+    /// create a document identified as `label` with probability `prob`.
+    fn document(label: Lang, prob: f32) -> Document {
+        let metadata = Metadata::new(&Identification::new(label, prob), &[]);
+        Document::new("foo".to_string(), HashMap::new(), metadata)
+    }
+
+    #[test]
+    fn test_keep_confident() {
+        // no minimum by default
+        let p = pipeline();
+        assert!(p.keep_confident(&document(Lang::Fr, 0.61)));
+
+        let p = p.with_min_doc_confidence(0.8);
+        assert!(p.keep_confident(&document(Lang::Fr, 0.8)));
+        assert!(p.keep_confident(&document(Lang::Fr, 0.9)));
+        assert!(!p.keep_confident(&document(Lang::Fr, 0.79)));
+
+        // multilingual documents are always kept
+        assert!(p.keep_confident(&document(Lang::Multi, 0.1)));
+    }
+
+    /// create a document of `lines`, each identified as its language (unidentified if [None]).
+    fn mixed_document(lines: &[(&str, Option<Lang>)]) -> (Document, Location) {
+        let content = lines
+            .iter()
+            .map(|(line, _)| *line)
+            .collect::<Vec<_>>()
+            .join("\n");
+        let ids: Vec<_> = lines
+            .iter()
+            .map(|(_, lang)| lang.map(|lang| Identification::new(lang, 0.9)))
+            .collect();
+        let metadata = Metadata::new(&Identification::new(Lang::Fr, 0.9), &ids);
+        let location = Location::new(0, "record_id".to_string(), 0, lines.len() - 1, 0);
+        (Document::new(content, HashMap::new(), metadata), location)
+    }
+
+    #[test]
+    fn test_is_consistent() {
+        // 7 of 10 characters are french
+        let (doc, _) = mixed_document(&[
+            ("bonjour", Some(Lang::Fr)),
+            ("hi", Some(Lang::En)),
+            ("a", None),
+        ]);
+        assert!(OscarDoc::is_consistent(&doc, 0.7));
+        assert!(!OscarDoc::is_consistent(&doc, 0.75));
+
+        // multilingual documents are always consistent
+        assert!(OscarDoc::is_consistent(&document(Lang::Multi, 0.9), 1.0));
+    }
+
+    #[test]
+    fn test_check_consistency() {
+        let documents = || {
+            vec![
+                mixed_document(&[("bonjour", Some(Lang::Fr)), ("bonsoir", Some(Lang::Fr))]),
+                mixed_document(&[("bonjour", Some(Lang::Fr)), ("hello there", Some(Lang::En))]),
+            ]
+        };
+        let p = pipeline();
+        assert!(p
+            .with_language_consistency(0.8, InconsistentAction::Drop)
+            .is_ok());
+        assert!(pipeline()
+            .with_language_consistency(0.0, InconsistentAction::Drop)
+            .is_err());
+
+        // the mixed-language document is dropped
+        let mut dropped = documents();
+        assert_eq!(
+            OscarDoc::check_consistency(&mut dropped, 0.8, InconsistentAction::Drop),
+            1
+        );
+        assert_eq!(dropped.len(), 1);
+        assert_eq!(dropped[0].0.content(), "bonjour\nbonsoir");
+        assert_eq!(dropped[0].0.metadata().annotation(), None);
+
+        // or kept, annotated as multilingual
+        let mut annotated = documents();
+        assert_eq!(
+            OscarDoc::check_consistency(&mut annotated, 0.8, InconsistentAction::Annotate),
+            1
+        );
+        assert_eq!(annotated.len(), 2);
+        assert_eq!(annotated[0].0.metadata().annotation(), None);
+        assert_eq!(
+            annotated[1].0.metadata().annotation(),
+            Some(&vec!["multilingual".to_string()])
+        );
+        assert_eq!(annotated[1].0.identification().label(), &Lang::Fr);
+    }
+
+    #[test]
+    fn test_write_shard_failure() {
+        let dst = tempfile::tempdir().unwrap();
+        let languages = vec!["fr"].into_iter().collect();
+        let langfiles =
+            LangFilesDoc::with_languages(dst.path(), &languages, None, LayoutStrategy::Flat)
+                .unwrap();
+        let rebuild_dst = dst.path().join("rebuild");
+        let rebuild_files = RebuildWriters::with_rebuild_layout(
+            &rebuild_dst,
+            &languages,
+            LayoutStrategy::Flat,
+            Codec::Null,
+            LangNaming::default(),
+            RebuildLayout::PerShard,
+            false,
+        )
+        .unwrap();
+        let shard = |idx| ProcessedShard {
+            idx,
+            shard_id: idx as u64,
+            path: format!("{}.txt.gz", idx),
+            documents: vec![(
+                Lang::Fr,
+                vec![mixed_document(&[("bonjour", Some(Lang::Fr))])],
+            )]
+            .into_iter()
+            .collect(),
+            nb_records: 1,
+            start: Instant::now(),
+        };
+        let counts = Mutex::new(ManifestCounts::default());
+        let p = pipeline();
+
+        p.write_shard(&langfiles, &rebuild_files, shard(0), &counts);
+
+        // rebuild files can't be written anymore
+        std::fs::remove_dir_all(&rebuild_dst).unwrap();
+        p.write_shard(&langfiles, &rebuild_files, shard(1), &counts);
+
+        let counts = counts.into_inner().unwrap();
+        assert_eq!(counts.shards, 1);
+        assert_eq!(counts.failed_shards, 1);
+        assert_eq!(counts.records, 1);
+        assert_eq!(counts.documents, 1);
+    }
+
+    /// identifies everything as french.
+    struct French;
+
+    impl LanguageIdentifier for French {
+        fn predict(&self, _: &str) -> Result<Option<Vec<Prediction>>, Error> {
+            Ok(Some(vec![Prediction {
+                label: "fr".to_string(),
+                prob: 0.9,
+            }]))
+        }
+    }
+
+    #[test]
+    fn test_deterministic() {
+        let src = tempfile::tempdir().unwrap();
+        for shard in 0..4 {
+            // lines have to be long enough to be kept
+            let bodies: Vec<_> = (0..50)
+                .map(|record| {
+                    let line = format!("shard {} record {} {}", shard, record, "a".repeat(100));
+                    [line.as_str(); 3].join("\n")
+                })
+                .collect();
+            bodies
+                .iter()
+                .fold(WetBuilder::new(), |builder, body| builder.text(body))
+                .write(&src.path().join(format!("{}.txt.gz", shard)))
+                .unwrap();
+        }
+
+        let run = || {
+            let dst = tempfile::tempdir().unwrap();
+            OscarDoc::new(
+                vec![src.path().to_path_buf()],
+                dst.path().to_path_buf(),
+                PathBuf::new(),
+                None,
+            )
+            .with_identifier(Arc::new(French))
+            .with_languages(["fr"].into_iter().collect())
+            .unwrap()
+            .with_deterministic(true)
+            .run()
+            .unwrap();
+            dst
+        };
+        let (first, second) = (run(), run());
+
+        let documents = std::fs::read(first.path().join("fr_meta.jsonl")).unwrap();
+        assert_eq!(String::from_utf8_lossy(&documents).lines().count(), 200);
+        assert_eq!(
+            documents,
+            std::fs::read(second.path().join("fr_meta.jsonl")).unwrap()
+        );
+
+        // rebuild files only differ by their (random) sync markers
+        let rebuild_info = |dst: &tempfile::TempDir| {
+            RebuildReader::from_path(&dst.path().join("rebuild").join("fr.avro"))
+                .unwrap()
+                .rebuild_info()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap()
+        };
+        let rebuild = rebuild_info(&first);
+        assert_eq!(rebuild.len(), 200);
+        assert_eq!(rebuild, rebuild_info(&second));
+    }
+}
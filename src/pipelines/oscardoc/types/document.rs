@@ -1,9 +1,12 @@
 use std::borrow::Cow;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 use schemars::JsonSchema;
 use serde::Deserialize;
 use serde::Serialize;
+use serde::Serializer;
+use serde_json::value::RawValue;
+use serde_json::Value;
 use warc::BufferedBody;
 use warc::Record;
 use warc::WarcHeader;
@@ -14,14 +17,32 @@ use crate::lang::Lang;
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, JsonSchema)]
 
 /// OSCAR-specific metadata
+///
+/// Holds the identification of a document, its annotations and the identifications of each of its lines
+/// (`None` for lines that couldn't be identified).
+/// A metadata can be built with [Metadata::new], [Metadata::with_annotation] and [Metadata::with_counts].
+///
+/// Sentence and character counts are computed from the final content (see [Document::update_counts]),
+/// and are `None` for documents written before they were tracked.
+///
+/// Extra metadata (see [Metadata::with_extra]) is opaque JSON text, kept verbatim.
+/// It is serialized as a string, so that it fits in a nullable string field of rebuild files,
+/// but documents embed it as a JSON value (see [Document]).
 /// TODO: make it a HashMap
 pub struct Metadata {
     identification: Identification,
     annotation: Option<Vec<String>>,
     sentence_identifications: Vec<Option<Identification>>,
+    #[serde(default)]
+    nb_sentences: Option<usize>,
+    #[serde(default)]
+    nb_chars: Option<usize>,
+    #[serde(default)]
+    extra: Option<String>,
 }
 
 impl Metadata {
+    /// Create a new metadata without annotation.
     pub fn new(
         identification: &Identification,
         sentence_identifications: &[Option<Identification>],
@@ -30,10 +51,46 @@ impl Metadata {
             identification: identification.clone(),
             annotation: None,
             sentence_identifications: sentence_identifications.to_owned(),
+            nb_sentences: None,
+            nb_chars: None,
+            extra: None,
         }
     }
 
-    /// Set the metadata's annotation.
+    /// Set the metadata's annotations, replacing existing ones.
+    ///
+    /// An empty `annotation` removes annotations, as for documents that have never been annotated.
+    pub fn with_annotation(mut self, annotation: Vec<String>) -> Self {
+        self.annotation = if annotation.is_empty() {
+            None
+        } else {
+            Some(annotation)
+        };
+        self
+    }
+
+    /// Set the metadata's sentence and character counts (see [Metadata::nb_sentences] and [Metadata::nb_chars]).
+    ///
+    /// Counts are not checked against any content: prefer [Document::update_counts] for documents.
+    pub fn with_counts(mut self, nb_sentences: usize, nb_chars: usize) -> Self {
+        self.nb_sentences = Some(nb_sentences);
+        self.nb_chars = Some(nb_chars);
+        self
+    }
+
+    /// Set the metadata's extra metadata, opaque JSON text carried along standard metadata.
+    ///
+    /// # Errors
+    /// Returns an error if `extra` is not valid JSON.
+    pub fn with_extra(mut self, extra: Option<String>) -> Result<Self, Error> {
+        if let Some(extra) = &extra {
+            serde_json::from_str::<serde::de::IgnoredAny>(extra)?;
+        }
+        self.extra = extra;
+        Ok(self)
+    }
+
+    /// Add an annotation to the metadata.
     pub fn set_annotation(&mut self, annotation: String) {
         match &mut self.annotation {
             Some(anno) => anno.push(annotation),
@@ -45,6 +102,31 @@ impl Metadata {
     pub fn annotation(&self) -> Option<&Vec<String>> {
         self.annotation.as_ref()
     }
+
+    /// Get a reference to the metadata's (document) identification.
+    pub fn identification(&self) -> &Identification {
+        &self.identification
+    }
+
+    /// Get a reference to the metadata's sentence identifications, in line order.
+    pub fn sentence_identifications(&self) -> &[Option<Identification>] {
+        &self.sentence_identifications
+    }
+
+    /// Get the number of sentences (lines) of the document, if known.
+    pub fn nb_sentences(&self) -> Option<usize> {
+        self.nb_sentences
+    }
+
+    /// Get the number of characters (see [str::chars]) of the document, excluding newlines, if known.
+    pub fn nb_chars(&self) -> Option<usize> {
+        self.nb_chars
+    }
+
+    /// Get the metadata's extra metadata (JSON text), if any.
+    pub fn extra(&self) -> Option<&str> {
+        self.extra.as_deref()
+    }
 }
 
 impl Default for Metadata {
@@ -55,6 +137,9 @@ impl Default for Metadata {
             identification: Identification::new(Lang::En, 1.0),
             annotation: None,
             sentence_identifications: vec![Some(Identification::new(Lang::En, 1.0))],
+            nb_sentences: None,
+            nb_chars: None,
+            extra: None,
         }
     }
 }
@@ -76,8 +161,67 @@ pub struct Document {
 /// Serializable version of [Document].
 struct DocumentSer {
     content: String,
+    #[serde(serialize_with = "serialize_sorted")]
     warc_headers: WarchHeadersSer,
-    metadata: Metadata,
+    metadata: MetadataSer,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+/// Serializable version of [Metadata] in documents, embedding extra metadata verbatim as a JSON value
+/// and leaving it out when there's none.
+struct MetadataSer {
+    identification: Identification,
+    annotation: Option<Vec<String>>,
+    sentence_identifications: Vec<Option<Identification>>,
+    #[serde(default)]
+    nb_sentences: Option<usize>,
+    #[serde(default)]
+    nb_chars: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Option<Value>")]
+    extra: Option<Box<RawValue>>,
+}
+
+impl From<Metadata> for MetadataSer {
+    fn from(m: Metadata) -> Self {
+        // extra metadata is valid JSON (see Metadata::with_extra), but is kept as a string otherwise
+        let extra = m
+            .extra
+            .map(|text| match serde_json::from_str::<Box<RawValue>>(&text) {
+                Ok(raw) => raw,
+                Err(_) => serde_json::value::to_raw_value(&text).unwrap(),
+            });
+        Self {
+            identification: m.identification,
+            annotation: m.annotation,
+            sentence_identifications: m.sentence_identifications,
+            nb_sentences: m.nb_sentences,
+            nb_chars: m.nb_chars,
+            extra,
+        }
+    }
+}
+
+impl From<MetadataSer> for Metadata {
+    fn from(m: MetadataSer) -> Self {
+        Self {
+            identification: m.identification,
+            annotation: m.annotation,
+            sentence_identifications: m.sentence_identifications,
+            nb_sentences: m.nb_sentences,
+            nb_chars: m.nb_chars,
+            extra: m.extra.map(|raw| raw.get().to_string()),
+        }
+    }
+}
+
+/// Serialize headers sorted by name, so that identical documents are serialized identically.
+fn serialize_sorted<S: Serializer>(headers: &WarchHeadersSer, s: S) -> Result<S::Ok, S::Error> {
+    let sorted: BTreeMap<String, &String> = headers
+        .iter()
+        .map(|(k, v)| (String::from(k.clone()), v))
+        .collect();
+    sorted.serialize(s)
 }
 
 impl DocumentSer {
@@ -96,7 +240,7 @@ impl From<Document> for DocumentSer {
         Self {
             content: d.content,
             warc_headers,
-            metadata: d.metadata,
+            metadata: d.metadata.into(),
         }
     }
 }
@@ -112,7 +256,7 @@ impl From<DocumentSer> for Document {
         Self {
             content: d.content,
             warc_headers,
-            metadata: d.metadata,
+            metadata: d.metadata.into(),
         }
     }
 }
@@ -162,20 +306,90 @@ impl Document {
         &self.warc_headers
     }
 
+    /// Get a mutable reference to the document's WARC headers.
+    pub(crate) fn warc_headers_mut(&mut self) -> &mut WarcHeaders {
+        &mut self.warc_headers
+    }
+
     /// Get a mutable reference to the document's metadata.
     pub(crate) fn metadata_mut(&mut self) -> &mut Metadata {
         &mut self.metadata
     }
 
     /// Get a reference to the document's metadata.
-    pub(crate) fn metadata(&self) -> &Metadata {
+    pub fn metadata(&self) -> &Metadata {
         &self.metadata
     }
 
+    /// Set the document's extra metadata (see [Metadata::with_extra]) from the JSON text of its `header` header,
+    /// kept verbatim.
+    ///
+    /// Extra metadata is unset if the document doesn't have the header.
+    ///
+    /// # Errors
+    /// Returns an error if the header value is not valid JSON. Extra metadata is unset then.
+    pub fn set_extra_from_header(&mut self, header: &WarcHeader) -> Result<(), Error> {
+        self.metadata.extra = None;
+        if let Some(value) = self.warc_headers.get(header) {
+            serde_json::from_slice::<serde::de::IgnoredAny>(value)?;
+            // valid JSON is valid UTF-8
+            self.metadata.extra = Some(String::from_utf8_lossy(value).into_owned());
+        }
+        Ok(())
+    }
+
     /// Set the document's content.
     pub fn set_content(&mut self, content: String) {
         self.content = content;
     }
+
+    /// Get the dominant language of the document, along with its share of the document's characters.
+    ///
+    /// Lines are weighted by their length (in unicode scalar values), and lines that couldn't be identified
+    /// count in the total without belonging to any language.
+    /// Ties are broken by taking the lowest label in lexicographic order.
+    ///
+    /// Returns [None] if no (non-empty) line has been identified.
+    pub fn dominant_language(&self) -> Option<(Lang, f32)> {
+        let mut chars_per_lang: HashMap<Lang, usize> = HashMap::new();
+        let mut total_chars = 0;
+        for (line, id) in self
+            .content
+            .lines()
+            .zip(self.metadata.sentence_identifications.iter())
+        {
+            let nb_chars = line.chars().count();
+            total_chars += nb_chars;
+            if let Some(id) = id {
+                *chars_per_lang.entry(*id.label()).or_default() += nb_chars;
+            }
+        }
+
+        chars_per_lang
+            .into_iter()
+            .filter(|(_, count)| *count > 0)
+            // max by count, then min by label
+            .max_by(|(lang_a, count_a), (lang_b, count_b)| {
+                count_a
+                    .cmp(count_b)
+                    .then_with(|| lang_b.to_static().cmp(lang_a.to_static()))
+            })
+            .map(|(lang, count)| (lang, count as f32 / total_chars as f32))
+    }
+
+    /// Set the metadata's sentence and character counts from the document's content.
+    ///
+    /// Should be called once the content won't change anymore.
+    pub fn update_counts(&mut self) {
+        let (nb_sentences, nb_chars) = self
+            .content
+            .lines()
+            .fold((0, 0), |(nb_sentences, nb_chars), line| {
+                (nb_sentences + 1, nb_chars + line.chars().count())
+            });
+        self.metadata.nb_sentences = Some(nb_sentences);
+        self.metadata.nb_chars = Some(nb_chars);
+    }
 }
 
 /// custom debug implementation that converts:
@@ -200,9 +414,13 @@ impl std::fmt::Debug for Document {
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
+
     use warc::{Record, WarcHeader};
 
     use super::{Document, Metadata};
+    use crate::identifiers::Identification;
+    use crate::lang::Lang;
 
     #[test]
     fn test_from_record() {
@@ -225,6 +443,138 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_serialize_sorted_headers() {
+        let headers = vec![
+            (WarcHeader::TargetURI, b"http://example.com".to_vec()),
+            (WarcHeader::ContentType, b"text/plain".to_vec()),
+            (WarcHeader::RecordID, b"<urn:uuid:0>".to_vec()),
+        ]
+        .into_iter()
+        .collect();
+        let doc = Document::new("foo".to_string(), headers, Metadata::default());
+
+        let serialized = serde_json::to_string(&doc).unwrap();
+        let content_type = serialized.find("content-type").unwrap();
+        let record_id = serialized.find("warc-record-id").unwrap();
+        let target_uri = serialized.find("warc-target-uri").unwrap();
+        assert!(content_type < record_id && record_id < target_uri);
+
+        let doc2: Document = serde_json::from_str(&serialized).unwrap();
+        assert!(doc == doc2);
+    }
+
+    #[test]
+    fn test_metadata_builder() {
+        let id = Identification::new(Lang::Fr, 0.8);
+        let sentence_ids = vec![Some(id.clone()), None];
+        let m = Metadata::new(&id, &sentence_ids)
+            .with_annotation(vec!["tiny".to_string(), "adult".to_string()]);
+
+        assert_eq!(m.identification(), &id);
+        assert_eq!(m.sentence_identifications(), &sentence_ids[..]);
+        assert_eq!(
+            m.annotation(),
+            Some(&vec!["tiny".to_string(), "adult".to_string()])
+        );
+
+        let m = m.with_annotation(Vec::new());
+        assert_eq!(m.annotation(), None);
+        assert_eq!(m, Metadata::new(&id, &sentence_ids));
+    }
+
+    #[test]
+    fn test_update_counts() {
+        let mut doc = Document::new(
+            "foo bar\nbaz\n\u{e9}t\u{e9}".to_string(),
+            HashMap::new(),
+            Metadata::default(),
+        );
+        assert_eq!(doc.metadata().nb_sentences(), None);
+        assert_eq!(doc.metadata().nb_chars(), None);
+
+        doc.update_counts();
+        assert_eq!(doc.metadata().nb_sentences(), Some(3));
+        assert_eq!(doc.metadata().nb_chars(), Some(13));
+
+        // counts survive serialization, and are absent from older documents
+        let serialized = serde_json::to_string(&doc).unwrap();
+        let doc2: Document = serde_json::from_str(&serialized).unwrap();
+        assert!(doc == doc2);
+
+        let old = r#"{"identification":{"label":"en","prob":1.0},"annotation":null,"sentence_identifications":[]}"#;
+        let m: Metadata = serde_json::from_str(old).unwrap();
+        assert_eq!(m.nb_sentences(), None);
+    }
+
+    #[test]
+    fn test_dominant_language() {
+        let en = Some(Identification::new(Lang::En, 0.9));
+        let fr = Some(Identification::new(Lang::Fr, 0.9));
+        let doc = |content: &str, ids: &[Option<Identification>]| {
+            let metadata = Metadata::new(&Identification::new(Lang::En, 0.9), ids);
+            Document::new(content.to_string(), HashMap::new(), metadata)
+        };
+
+        let d = doc(
+            "english
+français
+unknown",
+            &[en.clone(), fr.clone(), None],
+        );
+        let (lang, share) = d.dominant_language().unwrap();
+        assert_eq!(lang, Lang::Fr);
+        assert!((share - 8.0 / 22.0).abs() < 1e-6);
+
+        // ties are broken by label
+        let d = doc(
+            "abc
+def",
+            &[fr, en],
+        );
+        assert_eq!(d.dominant_language(), Some((Lang::En, 0.5)));
+
+        assert_eq!(doc("unknown", &[None]).dominant_language(), None);
+    }
+
+    #[test]
+    fn test_set_extra_from_header() {
+        let header = WarcHeader::Unknown("x-source-metadata".to_string());
+        let mut headers = HashMap::new();
+        headers.insert(header.clone(), br#"{"source": "forum"}"#.to_vec());
+        let mut doc = Document::new(String::new(), headers, Metadata::default());
+
+        doc.set_extra_from_header(&header).unwrap();
+        let extra = r#"{"source": "forum"}"#;
+        assert_eq!(doc.metadata().extra(), Some(extra));
+
+        // documents embed extra metadata verbatim, and leave it out when absent
+        let serialized = serde_json::to_string(&doc).unwrap();
+        assert!(serialized.contains(r#""extra":{"source": "forum"}"#));
+        let deserialized: Document = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(deserialized.metadata().extra(), Some(extra));
+        let empty = Document::new(String::new(), HashMap::new(), Metadata::default());
+        let serialized = serde_json::to_string(&empty).unwrap();
+        assert!(!serialized.contains("extra"));
+        let deserialized: Document = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(deserialized.metadata().extra(), None);
+
+        // metadata holds it as text (as in rebuild files)
+        let serialized = serde_json::to_value(doc.metadata()).unwrap();
+        assert_eq!(serialized["extra"], extra);
+        assert!(Metadata::default()
+            .with_extra(Some("{not json".to_string()))
+            .is_err());
+
+        // missing headers unset extra metadata, invalid ones are errors
+        doc.set_extra_from_header(&WarcHeader::TargetURI).unwrap();
+        assert_eq!(doc.metadata().extra(), None);
+        doc.warc_headers
+            .insert(header.clone(), b"{not json".to_vec());
+        assert!(doc.set_extra_from_header(&header).is_err());
+        assert_eq!(doc.metadata().extra(), None);
+    }
+
     #[test]
     fn test_serialize() {
         let m = Metadata::default();
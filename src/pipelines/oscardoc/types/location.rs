@@ -2,6 +2,8 @@ use std::convert::{TryFrom, TryInto};
 
 use serde::{Deserialize, Serialize};
 
+use crate::error::Error;
+
 /// Incomplete location error type.
 ///
 /// uses [LocationKind] to inform which field is missing.
@@ -28,16 +30,17 @@ pub enum LocationKind {
 // TODO: Add methods to ensure that we add only once?
 #[derive(Clone, Debug, PartialEq)]
 pub struct LocationBuilder {
-    shard_id: Option<usize>,
+    shard_id: Option<u64>,
     record_id: Option<String>,
     line_start: Option<usize>,
     line_end: Option<usize>,
     loc_in_shard: Option<usize>,
+    byte_range: Option<(usize, usize)>,
 }
 
 impl<'a> LocationBuilder {
     /// Set the partial location's shard id.
-    pub fn set_shard_id(&mut self, shard_id: usize) {
+    pub fn set_shard_id(&mut self, shard_id: u64) {
         self.shard_id = Some(shard_id);
     }
 
@@ -61,6 +64,13 @@ impl<'a> LocationBuilder {
         self.loc_in_shard = Some(loc_in_shard);
     }
 
+    /// Set the partial location's byte range (see [Location::byte_start]).
+    ///
+    /// The byte range is optional.
+    pub fn set_byte_range(&mut self, byte_start: usize, byte_end: usize) {
+        self.byte_range = Some((byte_start, byte_end));
+    }
+
     /// Builds the location.
     ///
     /// Errors if a field is missing
@@ -77,6 +87,7 @@ impl<'a> Default for LocationBuilder {
             line_start: None,
             line_end: None,
             loc_in_shard: None,
+            byte_range: None,
         }
     }
 }
@@ -109,6 +120,8 @@ impl<'a> TryFrom<LocationBuilder> for Location {
             line_start,
             line_end,
             loc_in_shard,
+            byte_start: value.byte_range.map(|(start, _)| start),
+            byte_end: value.byte_range.map(|(_, end)| end),
         })
     }
 }
@@ -117,6 +130,8 @@ impl<'a> TryFrom<LocationBuilder> for Location {
 /// - record_id is the record id :)
 /// - line_start/line_end are the boundaries of kept text (inclusive)
 /// - loc_in_shard is the record index _in_ shard.
+/// - byte_start/byte_end are the boundaries of kept text in the decompressed shard
+///   (see [Location::byte_start]), if known.
 ///
 /// # Example
 /// If we're working on the 10th record of a shard that is shard 100,
@@ -124,19 +139,24 @@ impl<'a> TryFrom<LocationBuilder> for Location {
 /// We'd get `line_start=0, line_end=4, loc_in_shard=99`.
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct Location {
-    shard_id: usize,
+    shard_id: u64,
     record_id: String,
     line_start: usize,
     line_end: usize,
     loc_in_shard: usize,
+    #[serde(default)]
+    byte_start: Option<usize>,
+    #[serde(default)]
+    byte_end: Option<usize>,
 }
 
 impl Location {
     /// Create a new [Location].
     ///
     /// Depending on usage, [LocationBuilder] can be more convinient.
+    /// Lines are not checked, see [Location::try_new].
     pub fn new(
-        shard_id: usize,
+        shard_id: u64,
         record_id: String,
         line_start: usize,
         line_end: usize,
@@ -148,11 +168,49 @@ impl Location {
             line_start,
             line_end,
             loc_in_shard,
+            byte_start: None,
+            byte_end: None,
+        }
+    }
+
+    /// Create a new [Location], checking that `line_start..=line_end` is not empty.
+    ///
+    /// This is meant to create locations from external data (e.g. to write rebuild files
+    /// without running a pipeline, see [super::ShardResult::new]).
+    ///
+    /// # Errors
+    /// Returns an error if `line_end` is before `line_start`.
+    pub fn try_new(
+        shard_id: u64,
+        record_id: String,
+        line_start: usize,
+        line_end: usize,
+        loc_in_shard: usize,
+    ) -> Result<Self, Error> {
+        if line_end < line_start {
+            return Err(Error::Custom(format!(
+                "location of record {}: line end {} is before line start {}",
+                record_id, line_end, line_start
+            )));
         }
+        Ok(Self::new(
+            shard_id,
+            record_id,
+            line_start,
+            line_end,
+            loc_in_shard,
+        ))
+    }
+
+    /// Set the location's byte range (see [Location::byte_start]).
+    pub fn with_byte_range(mut self, byte_start: usize, byte_end: usize) -> Self {
+        self.byte_start = Some(byte_start);
+        self.byte_end = Some(byte_end);
+        self
     }
 
     /// Get a reference to the location's shard id.
-    pub fn shard_id(&self) -> usize {
+    pub fn shard_id(&self) -> u64 {
         self.shard_id
     }
 
@@ -175,6 +233,56 @@ impl Location {
     pub fn loc_in_shard(&self) -> usize {
         self.loc_in_shard
     }
+
+    /// Get the offset of the first byte of kept text, from the start of the decompressed shard.
+    ///
+    /// Kept text spans `[byte_start, byte_end)`, from the start of line `line_start`
+    /// to the end of line `line_end` of the record body, line break excluded.
+    pub fn byte_start(&self) -> Option<usize> {
+        self.byte_start
+    }
+
+    /// Get the offset of the byte following kept text, from the start of the decompressed shard
+    /// (see [Location::byte_start]).
+    pub fn byte_end(&self) -> Option<usize> {
+        self.byte_end
+    }
+
+    /// Get the `[start, end)` byte range of lines `line_start..=line_end` in `body`,
+    /// line break excluded, as split by [str::lines].
+    ///
+    /// Returns [None] if `body` has less than `line_end + 1` lines.
+    pub fn lines_byte_range(
+        body: &[u8],
+        line_start: usize,
+        line_end: usize,
+    ) -> Option<(usize, usize)> {
+        // start offset of each line, a trailing line break not starting a new line
+        let mut starts: Vec<usize> = std::iter::once(0)
+            .chain(
+                body.iter()
+                    .enumerate()
+                    .filter(|(_, b)| **b == b'\n')
+                    .map(|(idx, _)| idx + 1),
+            )
+            .collect();
+        if starts.last() == Some(&body.len()) {
+            starts.pop();
+        }
+        if line_start > line_end || line_end >= starts.len() {
+            return None;
+        }
+
+        let start = starts[line_start];
+        let mut end = match starts.get(line_end + 1) {
+            Some(next) => next - 1,
+            None => body.len() - usize::from(body.ends_with(b"\n")),
+        };
+        if end > start && body[end - 1] == b'\r' {
+            end -= 1;
+        }
+        Some((start, end))
+    }
 }
 
 impl Default for Location {
@@ -185,6 +293,8 @@ impl Default for Location {
             line_start: Default::default(),
             line_end: Default::default(),
             loc_in_shard: Default::default(),
+            byte_start: None,
+            byte_end: None,
         }
     }
 }
@@ -217,4 +327,43 @@ mod tests {
 
         assert_eq!(location, loc_built);
     }
+
+    #[test]
+    fn location_try_new() {
+        let location = Location::try_new(4, "record_id".to_string(), 2, 2, 1).unwrap();
+        assert_eq!(location, Location::new(4, "record_id".to_string(), 2, 2, 1));
+        assert!(Location::try_new(4, "record_id".to_string(), 3, 2, 1).is_err());
+    }
+
+    #[test]
+    fn location_build_byte_range() {
+        let mut lb = LocationBuilder::default();
+        lb.set_record_id("record_id".to_string());
+        lb.set_line_start(0);
+        lb.set_line_end(1);
+        lb.set_loc_in_shard(0);
+        lb.set_shard_id(0);
+        lb.set_byte_range(10, 20);
+        let loc = lb.build().unwrap();
+        assert_eq!((loc.byte_start(), loc.byte_end()), (Some(10), Some(20)));
+        assert_eq!(
+            loc,
+            Location::new(0, "record_id".to_string(), 0, 1, 0).with_byte_range(10, 20)
+        );
+    }
+
+    #[test]
+    fn lines_byte_range() {
+        let body = "zero\r\none\ntwo\n";
+        let range = |start, end| {
+            Location::lines_byte_range(body.as_bytes(), start, end).map(|(s, e)| &body[s..e])
+        };
+        assert_eq!(range(0, 0), Some("zero"));
+        assert_eq!(range(1, 2), Some("one\ntwo"));
+        assert_eq!(range(0, 1), Some("zero\r\none"));
+        assert_eq!(range(2, 3), None);
+        assert_eq!(range(2, 1), None);
+        assert_eq!(Location::lines_byte_range(b"a\nb", 1, 1), Some((2, 3)));
+        assert_eq!(Location::lines_byte_range(b"", 0, 0), None);
+    }
 }
@@ -5,9 +5,18 @@ mod document;
 mod location;
 mod rebuild;
 
+/// Codecs of rebuild files (see [RebuildWriters::with_dst]).
+pub use avro_rs::Codec;
 pub use document::Document;
 pub use document::Metadata;
 pub use location::{IncompleteLocation, Location, LocationBuilder};
+pub(crate) use rebuild::split_lines;
+pub use rebuild::Duplicates;
+pub use rebuild::ProbQuantization;
+pub use rebuild::RebuildInfoIter;
 pub use rebuild::RebuildInformation;
+pub use rebuild::RebuildLayout;
+pub use rebuild::RebuildReader;
+pub use rebuild::RebuildWriter;
 pub use rebuild::RebuildWriters;
 pub use rebuild::ShardResult;
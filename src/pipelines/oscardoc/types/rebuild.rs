@@ -3,36 +3,63 @@ Each (avro) record is an  `(shard_id, array of (shard) records)`.
 !*/
 
 use std::{
-    collections::HashMap,
-    fs::File,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    convert::Infallible,
+    fs::{File, OpenOptions},
+    io::{BufReader, Read, Seek},
     path::{Path, PathBuf},
     str::FromStr,
     sync::{Arc, Mutex},
 };
 
-use avro_rs::{AvroResult, Codec, Schema, Writer};
-use log::error;
+use avro_rs::{types::Value, AvroResult, Codec, Reader, Schema, Writer};
+use log::{debug, error, warn};
+use rayon::prelude::*;
 use serde::Deserialize;
 use serde::Serialize;
 use structopt::lazy_static::lazy_static;
 
-use crate::lang::LANG;
+use crate::io::LayoutStrategy;
+use crate::lang::{LangNaming, LANG};
 use crate::{error::Error, lang::Lang};
 
 use super::{Location, Metadata};
 
 lazy_static! {
-    static ref SCHEMA: Schema = {
-
-      // schema of Identification struct
-        let identification_schema = r#"
-      {"name":"identification", "type":"record", "fields": [
-        {"name": "label", "type":"string"},
-        {"name": "prob", "type":"float"}
-      ]}
-"#;
-      // schema of Metadata struct
-        let metadata_schema = r#"
+    static ref SCHEMA: Schema = build_schema(r#""float""#);
+    /// Rebuild schema of [ProbQuantization::Bucket].
+    static ref BUCKET_SCHEMA: Schema =
+        build_schema(r#"{"type": "fixed", "name": "prob_bucket", "size": 1}"#);
+    /// Rebuild schemas of [ProbQuantization::Decimals], indexed by number of decimals.
+    static ref DECIMALS_SCHEMAS: Vec<Schema> = (0..=MAX_DECIMALS)
+        .map(|decimals| {
+            build_schema(&format!(
+                r#"{{"type": "record", "name": "{}{}", "fields": [{{"name": "value", "type": "long"}}]}}"#,
+                DECIMALS_PREFIX, decimals
+            ))
+        })
+        .collect();
+}
+
+/// Build the rebuild schema, storing identification probabilities as `prob_type`.
+///
+/// Quantized probabilities (see [ProbQuantization]) have their own type,
+/// so that the quantization of a file is found in its schema.
+fn build_schema(prob_type: &str) -> Schema {
+    // schema of Identification struct
+    let identification_schema = format!(
+        r#"
+      {{"name":"identification", "type":"record", "fields": [
+        {{"name": "label", "type":"string"}},
+        {{"name": "prob", "type":{}}}
+      ]}}
+"#,
+        prob_type
+    );
+    // schema of Metadata struct
+    // nb_sentences/nb_chars/extra have been added afterwards, and are nullable for the same reason as byte_start/byte_end.
+    // extra holds JSON text.
+    let metadata_schema = r#"
 {
   "type":"record",
   "name":"metadata_record",
@@ -42,12 +69,17 @@ lazy_static! {
     {"name": "sentence_identifications", "type":"array", "items":[
       "null",
       "identification"
-    ]}
+    ]},
+    {"name": "nb_sentences", "type":["null", "long"], "default": null},
+    {"name": "nb_chars", "type":["null", "long"], "default": null},
+    {"name": "extra", "type":["null", "string"], "default": null}
   ]
 }
 "#;
-  // schema of RebuildInformation struct
-        let rebuild_schema = r#"
+    // schema of RebuildInformation struct
+    // byte_start/byte_end have been added afterwards:
+    // they're nullable with a null default, so that older files (that lack them) can still be read.
+    let rebuild_schema = r#"
 {
   "type":"record",
   "name":"rebuild_information",
@@ -57,12 +89,14 @@ lazy_static! {
     {"name": "line_start", "type":"long"},
     {"name": "line_end", "type":"long"},
     {"name": "loc_in_shard", "type":"long"},
-    {"name":"metadata", "type":"metadata_record"}
+    {"name":"metadata", "type":"metadata_record"},
+    {"name": "byte_start", "type":["null", "long"], "default": null},
+    {"name": "byte_end", "type":["null", "long"], "default": null}
   ]
 }
 "#;
-  // schema of ShardResult struct
-        let schema = r#"
+    // schema of ShardResult struct
+    let schema = r#"
 {
   "type":"record",
   "name":"shard_result",
@@ -73,15 +107,174 @@ lazy_static! {
 }
 "#;
 
-        Schema::parse_list(&[
-            identification_schema,
-            metadata_schema,
-            rebuild_schema,
-            schema,
-        ])
-        .unwrap()[3]
-            .clone()
-    };
+    Schema::parse_list(&[
+        &identification_schema,
+        metadata_schema,
+        rebuild_schema,
+        schema,
+    ])
+    .unwrap()[3]
+        .clone()
+}
+
+/// Maximum number of decimals of [ProbQuantization::Decimals].
+const MAX_DECIMALS: u32 = 9;
+
+/// Prefix of the name of the probability type of [ProbQuantization::Decimals], followed by the number of decimals.
+const DECIMALS_PREFIX: &str = "prob_decimals_";
+
+/// Storage of identification probabilities in rebuild files.
+///
+/// Probabilities are stored as Avro `float`s by default (4 bytes each).
+/// Quantized probabilities are smaller, at the cost of precision.
+/// The quantization is recorded once, in the schema of the file (see [ProbQuantization::from_schema]),
+/// and readers decode probabilities transparently (as [f32], see [RebuildReader]).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ProbQuantization {
+    /// Probabilities are stored as is.
+    #[default]
+    None,
+    /// Probabilities are rounded to one of 256 evenly spaced values of `[0, 1]`, stored in a single byte.
+    Bucket,
+    /// Probabilities are rounded to this number of decimals (at most 9), stored as a variable-length integer.
+    Decimals(u32),
+}
+
+impl ProbQuantization {
+    /// Check that the number of decimals is supported.
+    pub fn check(&self) -> Result<(), Error> {
+        match self {
+            Self::Decimals(decimals) if *decimals > MAX_DECIMALS => Err(Error::Custom(format!(
+                "can't quantize probabilities to {} decimals (at most {})",
+                decimals, MAX_DECIMALS
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Get the rebuild schema of the quantization.
+    ///
+    /// # Errors
+    /// Returns an error if the quantization uses too many decimals.
+    fn schema(&self) -> Result<&'static Schema, Error> {
+        self.check()?;
+        Ok(match self {
+            Self::None => &SCHEMA,
+            Self::Bucket => &BUCKET_SCHEMA,
+            Self::Decimals(decimals) => &DECIMALS_SCHEMAS[*decimals as usize],
+        })
+    }
+
+    /// Get the quantization of a file written using `schema`, from the type of its probabilities.
+    ///
+    /// Files written before quantization have (non-quantized) `float` probabilities.
+    ///
+    /// # Errors
+    /// Returns an error if `schema` has no known probability type.
+    pub fn from_schema(schema: &Schema) -> Result<Self, Error> {
+        let quantization = match Self::find_prob(schema) {
+            Some(Schema::Float) => Some(Self::None),
+            Some(Schema::Fixed { name, size: 1 }) if name.name == "prob_bucket" => {
+                Some(Self::Bucket)
+            }
+            Some(Schema::Record { name, .. }) => name
+                .name
+                .strip_prefix(DECIMALS_PREFIX)
+                .and_then(|decimals| decimals.parse().ok())
+                .map(Self::Decimals),
+            _ => None,
+        };
+        let quantization = quantization.ok_or_else(|| {
+            Error::Custom("schema has no known identification probability type".to_string())
+        })?;
+        quantization.check()?;
+        Ok(quantization)
+    }
+
+    /// Find the type of the first `prob` field of `schema`.
+    fn find_prob(schema: &Schema) -> Option<&Schema> {
+        match schema {
+            Schema::Record { fields, .. } => fields.iter().find_map(|field| {
+                if field.name == "prob" {
+                    Some(&field.schema)
+                } else {
+                    Self::find_prob(&field.schema)
+                }
+            }),
+            Schema::Array(items) => Self::find_prob(items),
+            Schema::Union(union) => union.variants().iter().find_map(Self::find_prob),
+            _ => None,
+        }
+    }
+
+    /// Quantize `prob` into a value of the `prob` type of [Self::schema].
+    fn encode(&self, prob: f32) -> Value {
+        match self {
+            Self::None => Value::Float(prob),
+            Self::Bucket => Value::Fixed(1, vec![(prob.clamp(0.0, 1.0) * 255.0).round() as u8]),
+            Self::Decimals(decimals) => Value::Record(vec![(
+                "value".to_string(),
+                Value::Long((prob as f64 * 10f64.powi(*decimals as i32)).round() as i64),
+            )]),
+        }
+    }
+
+    /// Decode a probability quantized following `self`.
+    fn decode(&self, value: &Value) -> Result<f32, Error> {
+        match (self, value) {
+            (Self::None, Value::Float(prob)) => Ok(*prob),
+            (Self::Bucket, Value::Fixed(1, bucket)) => Ok(bucket[0] as f32 / 255.0),
+            (Self::Decimals(decimals), Value::Record(fields)) => match fields.as_slice() {
+                [(_, Value::Long(value))] => {
+                    Ok((*value as f64 / 10f64.powi(*decimals as i32)) as f32)
+                }
+                _ => Err(Error::Custom(format!(
+                    "invalid quantized probability: {:?}",
+                    fields
+                ))),
+            },
+            (_, value) => Err(Error::Custom(format!(
+                "invalid probability for {:?}: {:?}",
+                self, value
+            ))),
+        }
+    }
+
+    /// Apply `f` on every probability (`prob` field) of `value`.
+    fn map_probs<F, E>(value: &mut Value, f: &F) -> Result<(), E>
+    where
+        F: Fn(&Value) -> Result<Value, E>,
+    {
+        match value {
+            Value::Record(fields) => {
+                for (name, field) in fields {
+                    if name == "prob" {
+                        *field = f(field)?;
+                    } else {
+                        Self::map_probs(field, f)?;
+                    }
+                }
+                Ok(())
+            }
+            Value::Array(items) => items
+                .iter_mut()
+                .try_for_each(|item| Self::map_probs(item, f)),
+            Value::Union(inner) => Self::map_probs(inner, f),
+            _ => Ok(()),
+        }
+    }
+
+    /// Quantize every probability of `value`.
+    fn quantize(&self, mut value: Value) -> Value {
+        let Ok(()) = Self::map_probs(&mut value, &|prob| {
+            Ok::<_, Infallible>(match prob {
+                Value::Float(prob) => self.encode(*prob),
+                // not a probability of an identification
+                other => other.clone(),
+            })
+        });
+        value
+    }
 }
 
 /// Holds the same fields as [Location], adding [Metadata].
@@ -89,12 +282,39 @@ lazy_static! {
 /// Should be transformed into a struct that holds two attributes rather than copying some.
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct RebuildInformation {
-    shard_id: usize,
+    shard_id: u64,
     record_id: String,
     line_start: usize,
     line_end: usize,
     loc_in_shard: usize,
     metadata: Metadata,
+    #[serde(default)]
+    byte_start: Option<usize>,
+    #[serde(default)]
+    byte_end: Option<usize>,
+}
+
+/// Split a record body into lines, following [str::lines]: lines end with `\n` or `\r\n`,
+/// and the line ending of the last line is optional.
+///
+/// Line numbers of [RebuildInformation] refer to these lines (see [RebuildInformation::extract_lines]).
+pub(crate) fn split_lines(body: &[u8]) -> Vec<&[u8]> {
+    let mut lines = Vec::new();
+    let mut rest = body;
+    while !rest.is_empty() {
+        match rest.iter().position(|b| *b == b'\n') {
+            Some(end) => {
+                let line = &rest[..end];
+                lines.push(line.strip_suffix(b"\r").unwrap_or(line));
+                rest = &rest[end + 1..];
+            }
+            None => {
+                lines.push(rest);
+                break;
+            }
+        }
+    }
+    lines
 }
 
 impl RebuildInformation {
@@ -107,21 +327,25 @@ impl RebuildInformation {
             line_end: location.line_end(),
             loc_in_shard: location.loc_in_shard(),
             metadata,
+            byte_start: location.byte_start(),
+            byte_end: location.byte_end(),
         }
     }
 
     /// Convert into a ([Location], [Metadata]) tuple.
     pub fn into_raw_parts(self) -> (Location, Metadata) {
-        (
-            Location::new(
-                self.shard_id,
-                self.record_id,
-                self.line_start,
-                self.line_end,
-                self.loc_in_shard,
-            ),
-            self.metadata,
-        )
+        let location = Location::new(
+            self.shard_id,
+            self.record_id,
+            self.line_start,
+            self.line_end,
+            self.loc_in_shard,
+        );
+        let location = match (self.byte_start, self.byte_end) {
+            (Some(start), Some(end)) => location.with_byte_range(start, end),
+            _ => location,
+        };
+        (location, self.metadata)
     }
     /// Get a reference to the rebuild information's loc in shard.
     pub fn loc_in_shard(&self) -> usize {
@@ -138,7 +362,7 @@ impl RebuildInformation {
         self.line_start
     }
 
-    /// Get a reference to the rebuild information's line end.
+    /// Get a reference to the rebuild information's line end (inclusive).
     pub fn line_end(&self) -> usize {
         self.line_end
     }
@@ -149,21 +373,61 @@ impl RebuildInformation {
     }
 
     /// Get a reference to the rebuild information's shard id.
-    pub fn shard_id(&self) -> usize {
+    pub fn shard_id(&self) -> u64 {
         self.shard_id
     }
+
+    /// Get the rebuild information's byte start (see [Location::byte_start]).
+    ///
+    /// [None] for files written before byte offsets were tracked.
+    pub fn byte_start(&self) -> Option<usize> {
+        self.byte_start
+    }
+
+    /// Get the rebuild information's byte end (see [Location::byte_end]).
+    pub fn byte_end(&self) -> Option<usize> {
+        self.byte_end
+    }
+
+    /// Get a key identifying the record globally, combining shard id and record id.
+    ///
+    /// Record ids alone are not unique across shards, and shouldn't be used to index rebuild information.
+    pub fn global_key(&self) -> (u64, String) {
+        (self.shard_id, self.record_id.clone())
+    }
+
+    /// Extract lines `[line_start, line_end]` (both inclusive, see [Location]) from the content
+    /// of the record located at `loc_in_shard`.
+    ///
+    /// Lines are split following [split_lines]. Lines past the end of the content are ignored.
+    pub fn extract_lines(&self, content: &str) -> String {
+        split_lines(content.as_bytes())
+            .into_iter()
+            .skip(self.line_start)
+            .take((self.line_end + 1).saturating_sub(self.line_start))
+            // lines of valid UTF-8 are split on ASCII characters, so they're valid UTF-8 too
+            .map(String::from_utf8_lossy)
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
 }
 
 /// Holds multiple [RebuildInformation] for a single shard.
+///
+/// Shard ids are stored as Avro `long`s, so that files written with signed ids are still readable.
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct ShardResult {
-    shard_id: i64,
+    shard_id: u64,
     rebuild_info: Vec<RebuildInformation>,
 }
 
 impl ShardResult {
     /// Merges `locations` and `metadata` into [RebuildInformation].
-    pub fn new(shard_id: i64, locations: Vec<Location>, metadata: Vec<Metadata>) -> Self {
+    ///
+    /// `locations` and `metadata` should have the same length, since extra items are ignored.
+    /// Along with [Location::try_new] and [Metadata::new], this can be used to write rebuild files
+    /// from external data (see [RebuildWriter]).
+    pub fn new(shard_id: u64, locations: Vec<Location>, metadata: Vec<Metadata>) -> Self {
         let rebuild_info = locations
             .into_iter()
             .zip(metadata.into_iter())
@@ -175,12 +439,21 @@ impl ShardResult {
         }
     }
 
+    /// Deserialize a shard result from an Avro value read using the rebuild schema of `quantization`
+    /// (see [ProbQuantization::from_schema]), decoding quantized probabilities.
+    pub fn from_value(mut value: Value, quantization: ProbQuantization) -> Result<Self, Error> {
+        ProbQuantization::map_probs(&mut value, &|prob| {
+            quantization.decode(prob).map(Value::Float)
+        })?;
+        Ok(avro_rs::from_value(&value)?)
+    }
+
     /// extract owned parts of struct: (`shard_id`, `Vec<RebuildInformation>`)
-    pub fn into_raw_parts(self) -> (i64, Vec<RebuildInformation>) {
+    pub fn into_raw_parts(self) -> (u64, Vec<RebuildInformation>) {
         (self.shard_id, self.rebuild_info)
     }
     /// Get a reference to the shard result's shard id.
-    pub fn shard_id(&self) -> i64 {
+    pub fn shard_id(&self) -> u64 {
         self.shard_id
     }
 
@@ -189,27 +462,240 @@ impl ShardResult {
         self.rebuild_info.as_ref()
     }
 }
+/// Magic bytes starting Avro object container files.
+const AVRO_MAGIC: &[u8; 4] = b"Obj\x01";
+
+/// Size (in bytes) of the uncompressed data blocks written by [BlockWriter], as [avro_rs::Writer] does.
+const BLOCK_SIZE: usize = 16000;
+
+/// Header of an existing Avro object container file.
+struct ContainerHeader {
+    schema: Schema,
+    codec: Codec,
+    marker: [u8; 16],
+}
+
+impl ContainerHeader {
+    /// Read the header of an object container file, checking that its data blocks are complete.
+    ///
+    /// Blocks are skipped rather than decoded, but an interrupted write (such as a truncated block)
+    /// or a foreign sync marker is detected.
+    fn read(path: &Path) -> Result<Self, Error> {
+        let len = std::fs::metadata(path)?.len();
+        let mut reader = BufReader::new(File::open(path)?);
+
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != AVRO_MAGIC {
+            return Err(avro_rs::Error::HeaderMagic.into());
+        }
+
+        let meta = match avro_rs::from_avro_datum(
+            &Schema::Map(Box::new(Schema::Bytes)),
+            &mut reader,
+            None,
+        )? {
+            Value::Map(meta) => meta,
+            _ => return Err(avro_rs::Error::GetHeaderMetadata.into()),
+        };
+        let meta_str = |key: &str| match meta.get(key) {
+            Some(Value::Bytes(bytes)) => std::str::from_utf8(bytes).ok(),
+            _ => None,
+        };
+        let schema = meta_str("avro.schema")
+            .ok_or(avro_rs::Error::GetAvroSchemaFromMap)
+            .and_then(Schema::parse_str)?;
+        // the codec is optional and defaults to null
+        let codec = match meta_str("avro.codec") {
+            Some(codec) => Codec::from_str(codec)
+                .map_err(|_| Error::Custom(format!("{:?}: unknown codec {}", path, codec)))?,
+            None => Codec::Null,
+        };
+
+        let mut marker = [0u8; 16];
+        reader.read_exact(&mut marker)?;
+
+        // skip blocks, checking their markers
+        while reader.stream_position()? < len {
+            let read_long = |reader: &mut BufReader<File>| match avro_rs::from_avro_datum(
+                &Schema::Long,
+                reader,
+                None,
+            )? {
+                Value::Long(n) => Ok(n),
+                _ => Err(Error::Custom(format!("{:?}: invalid block header", path))),
+            };
+            let _nb_values = read_long(&mut reader)?;
+            let size = read_long(&mut reader)?;
+            if size < 0 {
+                return Err(Error::Custom(format!(
+                    "{:?}: invalid block size {}",
+                    path, size
+                )));
+            }
+            reader.seek_relative(size)?;
+
+            let mut block_marker = [0u8; 16];
+            reader
+                .read_exact(&mut block_marker)
+                .map_err(|e| Error::Custom(format!("{:?}: truncated block ({:?})", path, e)))?;
+            if block_marker != marker {
+                return Err(avro_rs::Error::GetBlockMarker.into());
+            }
+        }
+
+        Ok(Self {
+            schema,
+            codec,
+            marker,
+        })
+    }
+}
+
+/// Writes data blocks at the end of an existing object container file,
+/// using its codec and sync marker.
+///
+/// [avro_rs::Writer] always writes a new header with a random sync marker,
+/// which would make appended blocks unreadable.
+struct BlockWriter<'a, T> {
+    schema: &'a Schema,
+    writer: T,
+    codec: Codec,
+    marker: [u8; 16],
+    buffer: Vec<u8>,
+    nb_values: usize,
+}
+
+impl<'a, T: std::io::Write> BlockWriter<'a, T> {
+    /// Encode `value` in the current block, writing the block if it's large enough.
+    fn append(&mut self, value: Value) -> AvroResult<usize> {
+        self.buffer
+            .extend(avro_rs::to_avro_datum(self.schema, value)?);
+        self.nb_values += 1;
+
+        if self.buffer.len() >= BLOCK_SIZE {
+            return self.flush();
+        }
+        Ok(0)
+    }
+
+    /// Write the current block, if it's not empty.
+    fn flush(&mut self) -> AvroResult<usize> {
+        if self.nb_values == 0 {
+            return Ok(0);
+        }
+
+        self.codec.compress(&mut self.buffer)?;
+        let mut block = avro_rs::to_avro_datum(&Schema::Long, self.nb_values as i64)?;
+        block.extend(avro_rs::to_avro_datum(
+            &Schema::Long,
+            self.buffer.len() as i64,
+        )?);
+        block.extend_from_slice(&self.buffer);
+        block.extend_from_slice(&self.marker);
+
+        self.writer
+            .write_all(&block)
+            .map_err(avro_rs::Error::WriteBytes)?;
+        self.buffer.clear();
+        self.nb_values = 0;
+
+        Ok(block.len())
+    }
+}
+
+/// Writer of a new file, or of an existing one (see [RebuildWriter::append_path]).
+enum ContainerWriter<'a, T> {
+    New(Writer<'a, T>),
+    Append(BlockWriter<'a, T>),
+}
+
 /// Holds an Avro writer.
 pub struct RebuildWriter<'a, T> {
     schema: &'a Schema,
-    writer: Writer<'a, T>,
+    writer: ContainerWriter<'a, T>,
+    codec: Codec,
+    /// Quantization of probabilities, if `schema` is the rebuild schema.
+    quantization: Option<ProbQuantization>,
+    /// Whether values have been appended (the header of new files is written along with the first one).
+    appended: bool,
 }
 
 impl<'a, T: std::io::Write> RebuildWriter<'a, T> {
-    /// Create a new rebuilder.
-    pub fn new(schema: &'a Schema, writer: T) -> Self {
+    /// Create a new rebuilder, compressing blocks using `codec`.
+    ///
+    /// [Codec::Snappy] was the only codec used before, and is the default for pipelines.
+    pub fn new(schema: &'a Schema, writer: T, codec: Codec) -> Self {
         Self {
             schema,
-            writer: Writer::with_codec(schema, writer, Codec::Snappy),
+            writer: ContainerWriter::New(Writer::with_codec(schema, writer, codec)),
+            codec,
+            quantization: Self::default_quantization(schema),
+            appended: false,
         }
     }
 
+    /// Probabilities are only quantized with the rebuild schema,
+    /// so that values of other schemas are written as is.
+    fn default_quantization(schema: &Schema) -> Option<ProbQuantization> {
+        (*schema == *SCHEMA).then(ProbQuantization::default)
+    }
+
+    /// Quantize identification probabilities (see [ProbQuantization]).
+    ///
+    /// New files are written using the rebuild schema of the quantization.
+    /// Files that are appended to (see [RebuildWriter::append_path]) keep their own quantization.
+    ///
+    /// # Errors
+    /// Returns an error if the writer doesn't use the rebuild schema, if values have already been written,
+    /// or if the quantization uses too many decimals.
+    pub fn with_quantization(mut self, quantization: ProbQuantization) -> Result<Self, Error> {
+        let schema = quantization.schema()?;
+        let current = self.quantization.ok_or_else(|| {
+            Error::Custom("probabilities can only be quantized with the rebuild schema".to_string())
+        })?;
+        if self.appended {
+            return Err(Error::Custom(
+                "probabilities can't be quantized once values have been written".to_string(),
+            ));
+        }
+
+        match self.writer {
+            ContainerWriter::New(writer) => {
+                // nothing has been written yet, not even the header
+                let writer = writer.into_inner()?;
+                self.schema = schema;
+                self.writer = ContainerWriter::New(Writer::with_codec(schema, writer, self.codec));
+                self.quantization = Some(quantization);
+            }
+            ContainerWriter::Append(_) if current != quantization => {
+                warn!(
+                    "rebuild file uses {:?} rather than {:?}: appending using the former",
+                    current, quantization
+                );
+            }
+            ContainerWriter::Append(_) => (),
+        }
+        Ok(self)
+    }
+
     /// Append a single serializable value (`value` must implement [Serialize]).
     ///
+    /// Identification probabilities are quantized beforehand (see [Self::with_quantization]).
+    ///
     /// This function is not guaranteed to perform a write operation
     /// See documentation of [avro_rs::Writer] for more information.
     pub fn append_ser<S: Serialize>(&mut self, value: S) -> AvroResult<usize> {
-        self.writer.append_ser(value)
+        let value = avro_rs::to_value(value)?;
+        let value = match &self.quantization {
+            Some(quantization) => quantization.quantize(value),
+            None => value,
+        };
+        self.appended = true;
+        match &mut self.writer {
+            ContainerWriter::New(writer) => writer.append(value),
+            ContainerWriter::Append(writer) => writer.append(value),
+        }
     }
 
     /// Append from an interator of values, each implementing [Serialize].
@@ -220,89 +706,1334 @@ impl<'a, T: std::io::Write> RebuildWriter<'a, T> {
     where
         I: IntoIterator<Item = U>,
     {
-        self.writer.extend_ser(values)
+        let mut nb_bytes = 0;
+        for value in values {
+            nb_bytes += self.append_ser(value)?;
+        }
+        Ok(nb_bytes + self.flush()?)
     }
 
     /// Flush the underlying buffer.
     ///
     /// See [avro_rs::Writer] for more information.
     pub fn flush(&mut self) -> AvroResult<usize> {
-        self.writer.flush()
+        match &mut self.writer {
+            ContainerWriter::New(writer) => writer.flush(),
+            ContainerWriter::Append(writer) => writer.flush(),
+        }
     }
 }
 
 impl<'a> RebuildWriter<'a, File> {
     /// Create a writer on `dst` file.
     /// Errors if provided path already exists.
-    pub fn from_path(dst: &Path) -> Result<Self, Error> {
+    pub fn from_path(dst: &Path, codec: Codec) -> Result<Self, Error> {
         let schema = &SCHEMA;
         let dest_file = File::create(dst)?;
-        Ok(Self::new(schema, dest_file))
+        Ok(Self::new(schema, dest_file, codec))
+    }
+
+    /// Create a writer appending to `dst` file, so that it stays a single valid Avro file.
+    ///
+    /// Missing or empty files (such as files of languages without any document) are written as new ones.
+    /// Otherwise, blocks are written using the codec, sync marker and quantization (see [ProbQuantization])
+    /// of the file, and `codec` is ignored.
+    ///
+    /// # Errors
+    /// Returns an error if `dst` is not a complete Avro file (for example if a previous write has been interrupted),
+    /// or if it uses another schema than the current one (for example files written before
+    /// byte ranges were tracked, see [RebuildInformation::byte_start]).
+    pub fn append_path(dst: &Path, codec: Codec) -> Result<Self, Error> {
+        let schema = &SCHEMA;
+        let is_empty = match std::fs::metadata(dst) {
+            Ok(metadata) => metadata.len() == 0,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => true,
+            Err(e) => return Err(e.into()),
+        };
+        let dest_file = OpenOptions::new().append(true).create(true).open(dst)?;
+        if is_empty {
+            return Ok(Self::new(schema, dest_file, codec));
+        }
+
+        let header = ContainerHeader::read(dst)?;
+        let quantization = ProbQuantization::from_schema(&header.schema)
+            .and_then(|quantization| Ok((quantization, quantization.schema()?)));
+        let (quantization, schema) = match quantization {
+            Ok((quantization, schema)) if header.schema == *schema => (quantization, schema),
+            _ => {
+                return Err(Error::Custom(format!(
+                    "{:?} has been written with another schema, and can't be appended to",
+                    dst
+                )))
+            }
+        };
+        if header.codec != codec {
+            warn!(
+                "{:?} uses {:?} rather than {:?}: appending using the former",
+                dst, header.codec, codec
+            );
+        }
+
+        Ok(Self {
+            schema,
+            writer: ContainerWriter::Append(BlockWriter {
+                schema,
+                writer: dest_file,
+                codec: header.codec,
+                marker: header.marker,
+                buffer: Vec::with_capacity(BLOCK_SIZE),
+                nb_values: 0,
+            }),
+            codec: header.codec,
+            quantization: Some(quantization),
+            appended: false,
+        })
+    }
+
+    /// Get the codec of the rebuild file at `path`.
+    ///
+    /// # Errors
+    /// Returns an error if `path` is not a complete Avro file.
+    pub fn path_codec(path: &Path) -> Result<Codec, Error> {
+        Ok(ContainerHeader::read(path)?.codec)
+    }
+}
+
+/// Holds an Avro reader, yielding [ShardResult] from a rebuild file.
+pub struct RebuildReader<'a, R> {
+    reader: Reader<'a, R>,
+    quantization: ProbQuantization,
+    /// Rebuild schema of `quantization`, that values are resolved against.
+    schema: &'static Schema,
+}
+
+impl<'a, R: Read> Iterator for RebuildReader<'a, R> {
+    type Item = Result<ShardResult, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let value = match self
+            .reader
+            .next()?
+            .and_then(|value| value.resolve(self.schema))
+        {
+            Ok(value) => value,
+            Err(e) => return Some(Err(e.into())),
+        };
+        Some(ShardResult::from_value(value, self.quantization))
+    }
+}
+
+impl<R: Read> RebuildReader<'static, R> {
+    /// Create a new reader from an avro stream.
+    ///
+    /// Values are resolved against the rebuild schema of the quantization of the file
+    /// (see [ProbQuantization::from_schema]), so that older files can still be read.
+    pub fn new(reader: R) -> Result<Self, Error> {
+        let reader = Reader::new(reader)?;
+        let quantization = ProbQuantization::from_schema(reader.writer_schema())?;
+        Ok(Self {
+            reader,
+            quantization,
+            schema: quantization.schema()?,
+        })
+    }
+
+    /// Convert into an iterator over every [RebuildInformation] of every [ShardResult].
+    pub fn rebuild_info(self) -> RebuildInfoIter<'static, R> {
+        RebuildInfoIter {
+            shard_results: self,
+            current: Vec::new().into_iter(),
+        }
+    }
+}
+
+impl RebuildReader<'static, BufReader<File>> {
+    /// Open a rebuild (`<lang>.avro`) file.
+    pub fn from_path(src: &Path) -> Result<Self, Error> {
+        let f = File::open(src)?;
+        Self::new(BufReader::new(f))
+    }
+
+    /// Find duplicates in the rebuild files (`*.avro`) of `src`, including those in subfolders
+    /// (so that both [LayoutStrategy] are supported).
+    ///
+    /// Duplicates are reported in [Duplicates] and summarized in logs.
+    /// Every key is held in memory while files are read.
+    ///
+    /// # Errors
+    /// Returns an error if a file can't be listed or read.
+    pub fn find_duplicates(src: &Path) -> Result<Duplicates, Error> {
+        let pattern = src.join("**").join("*.avro");
+        let pattern = pattern
+            .to_str()
+            .ok_or_else(|| Error::Custom(format!("invalid rebuild files directory: {:?}", src)))?;
+        let mut paths = glob::glob(pattern)?.collect::<Result<Vec<PathBuf>, _>>()?;
+        paths.sort();
+
+        let mut shards_by_record: HashMap<String, BTreeSet<u64>> = HashMap::new();
+        let mut paths_by_key: HashMap<(u64, String), Vec<PathBuf>> = HashMap::new();
+        for path in paths {
+            debug!("checking {:?} for duplicates", path);
+            for rb_info in Self::from_path(&path)?.rebuild_info() {
+                let rb_info = rb_info?;
+                shards_by_record
+                    .entry(rb_info.record_id().to_string())
+                    .or_default()
+                    .insert(rb_info.shard_id());
+                paths_by_key
+                    .entry(rb_info.global_key())
+                    .or_default()
+                    .push(path.clone());
+            }
+        }
+
+        let duplicates = Duplicates {
+            record_ids: shards_by_record
+                .into_iter()
+                .filter(|(_, shards)| shards.len() > 1)
+                .map(|(record_id, shards)| (record_id, shards.into_iter().collect()))
+                .collect(),
+            keys: paths_by_key
+                .into_iter()
+                .filter(|(_, paths)| paths.len() > 1)
+                .collect(),
+        };
+
+        if !duplicates.record_ids.is_empty() {
+            warn!(
+                "{} record ids are found in several shards of {:?}",
+                duplicates.record_ids.len(),
+                src
+            );
+        }
+        if !duplicates.keys.is_empty() {
+            warn!(
+                "{} (shard id, record id) keys are found several times in {:?}",
+                duplicates.keys.len(),
+                src
+            );
+        }
+
+        Ok(duplicates)
     }
 }
 
-/// Holds mutex-protected [RebuildWriter] for each [Lang].
-pub struct RebuildWriters<'a, T>(HashMap<Lang, Arc<Mutex<RebuildWriter<'a, T>>>>);
+/// Duplicates found in rebuild files (see [RebuildReader::find_duplicates]).
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Duplicates {
+    /// Record ids found in several shards, along with these shards (sorted).
+    ///
+    /// These collide when rebuild information is keyed by record id only,
+    /// and are distinguished by [RebuildInformation::global_key].
+    pub record_ids: BTreeMap<String, Vec<u64>>,
+    /// Global keys (see [RebuildInformation::global_key]) found several times,
+    /// along with the file of each occurrence.
+    pub keys: BTreeMap<(u64, String), Vec<PathBuf>>,
+}
+
+impl Duplicates {
+    /// Check if no duplicate has been found.
+    pub fn is_empty(&self) -> bool {
+        self.record_ids.is_empty() && self.keys.is_empty()
+    }
+}
+
+/// Iterator over [RebuildInformation] of a rebuild file, see [RebuildReader::rebuild_info].
+pub struct RebuildInfoIter<'a, R> {
+    shard_results: RebuildReader<'a, R>,
+    current: std::vec::IntoIter<RebuildInformation>,
+}
+
+impl<'a, R: Read> Iterator for RebuildInfoIter<'a, R> {
+    type Item = Result<RebuildInformation, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(rb_info) = self.current.next() {
+                return Some(Ok(rb_info));
+            }
+
+            match self.shard_results.next()? {
+                Ok(shard_result) => self.current = shard_result.into_raw_parts().1.into_iter(),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// Routing of rebuild information into files.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum RebuildLayout {
+    /// One file per language, holding the documents of every shard (`<lang>.avro`).
+    #[default]
+    PerLang,
+    /// One file per shard, holding the documents of every language (`shard_<id>.avro`).
+    ///
+    /// Languages can be told apart from [RebuildInformation::metadata].
+    PerShard,
+}
+
+/// Holds mutex-protected [RebuildWriter] for each [Lang],
+/// or a destination folder for per-shard files (see [RebuildLayout::PerShard]).
+pub struct RebuildWriters<'a, T> {
+    writers: HashMap<Lang, Arc<Mutex<RebuildWriter<'a, T>>>>,
+    per_shard: Option<PerShard>,
+}
+
+/// Destination of per-shard files.
+struct PerShard {
+    dst: PathBuf,
+    codec: Codec,
+    append: bool,
+    quantization: ProbQuantization,
+}
 
 impl<'a, T> RebuildWriters<'a, T> {
     /// Maps to [HashMap::get].
+    ///
+    /// There are no language writers with [RebuildLayout::PerShard].
     pub fn get(&'a self, k: &Lang) -> Option<&Arc<Mutex<RebuildWriter<T>>>> {
-        self.0.get(k)
+        self.writers.get(k)
+    }
+
+    /// Get the layout of rebuild files.
+    pub fn rebuild_layout(&self) -> RebuildLayout {
+        match self.per_shard {
+            Some(_) => RebuildLayout::PerShard,
+            None => RebuildLayout::PerLang,
+        }
+    }
+}
+
+impl<'a, T: std::io::Write> RebuildWriters<'a, T> {
+    /// Quantize identification probabilities of every file (see [RebuildWriter::with_quantization]).
+    ///
+    /// # Errors
+    /// Returns an error if the quantization uses too many decimals,
+    /// or if writers are already in use.
+    pub fn with_quantization(mut self, quantization: ProbQuantization) -> Result<Self, Error> {
+        quantization.check()?;
+        self.writers = self
+            .writers
+            .into_iter()
+            .map(|(lang, writer)| {
+                let writer = Arc::try_unwrap(writer)
+                    .map_err(|_| {
+                        Error::Custom(format!("rebuild writer of {} is already in use", lang))
+                    })?
+                    .into_inner()
+                    .unwrap();
+                Ok((
+                    lang,
+                    Arc::new(Mutex::new(writer.with_quantization(quantization)?)),
+                ))
+            })
+            .collect::<Result<_, Error>>()?;
+        if let Some(per_shard) = &mut self.per_shard {
+            per_shard.quantization = quantization;
+        }
+        Ok(self)
     }
 }
 
 impl<'a> RebuildWriters<'a, File> {
     #[inline]
-    fn forge_dst(dst: &Path, lang: &Lang) -> PathBuf {
-        let mut p = PathBuf::from(dst);
-        p.push(format!("{}.avro", lang));
+    fn forge_dst(dst: &Path, name: &str, layout: LayoutStrategy) -> Result<PathBuf, Error> {
+        let mut p = layout.lang_dir(dst, name)?;
+        p.push(format!("{}.avro", name));
 
-        p
+        Ok(p)
     }
 
     #[inline]
-    /// Convinience function that creates a new ([Lang], `Arc<Mutex<RebuildWriter>>`]) pair.
+    /// Convinience function that creates a new ([Lang], `Arc<Mutex<RebuildWriter>>`]) pair,
+    /// appending to existing files if `append` is set (see [RebuildWriter::append_path]).
     fn new_writer_mutex(
         dst: &Path,
-        lang: &str,
+        lang: Lang,
+        name: &str,
+        layout: LayoutStrategy,
+        codec: Codec,
+        append: bool,
     ) -> Result<(Lang, Arc<Mutex<RebuildWriter<'a, File>>>), Error> {
-        let lang = Lang::from_str(lang).unwrap();
-        let path = Self::forge_dst(dst, &lang);
-        let rw = RebuildWriter::from_path(&path)?;
+        let path = Self::forge_dst(dst, name, layout)?;
+        let rw = if append {
+            RebuildWriter::append_path(&path, codec)?
+        } else {
+            RebuildWriter::from_path(&path, codec)?
+        };
         let rw_mutex = Arc::new(Mutex::new(rw));
         Ok((lang, rw_mutex))
     }
 
     /// Use `dst` as a root path for avro files storage.
     ///
-    /// Each language will have a possibly empty avro file, at `<dst>/<lang>.avro`.
-    pub fn with_dst(dst: &Path) -> Result<Self, Error> {
+    /// Each language will have a possibly empty avro file, at `<dst>/<lang>.avro`
+    /// or at `<dst>/<lang>/<lang>.avro`, depending on `layout`.
+    ///
+    /// With [LayoutStrategy::Flat], `dst` should be empty.
+    /// With [LayoutStrategy::PerLangDir], `dst` can hold other files (such as text outputs),
+    /// but avro files shouldn't exist yet.
+    ///
+    /// Blocks are compressed using `codec`.
+    /// Readers ([RebuildReader]) get the codec from the file header, so any codec can be read back.
+    ///
+    /// Files are created in parallel.
+    /// If some of them can't be created, the ones that have been are removed
+    /// and the error names the failing languages.
+    pub fn with_dst(dst: &Path, layout: LayoutStrategy, codec: Codec) -> Result<Self, Error> {
+        Self::with_dst_languages(dst, &LANG, layout, codec)
+    }
+
+    /// Use `dst` as a root path for avro files storage, only creating files for `languages`.
+    ///
+    /// See [Self::with_dst].
+    pub fn with_dst_languages(
+        dst: &Path,
+        languages: &HashSet<&'static str>,
+        layout: LayoutStrategy,
+        codec: Codec,
+    ) -> Result<Self, Error> {
+        Self::with_dst_naming(dst, languages, layout, codec, LangNaming::default())
+    }
+
+    /// Use `dst` as a root path for avro files storage, only creating files for `languages`,
+    /// named following `naming` (`<dst>/eng.avro` with [LangNaming::Iso639_3]).
+    ///
+    /// See [Self::with_dst].
+    ///
+    /// # Errors
+    /// Returns an error if a label is not a [Lang]. No file is created then.
+    pub fn with_dst_naming(
+        dst: &Path,
+        languages: &HashSet<&'static str>,
+        layout: LayoutStrategy,
+        codec: Codec,
+        naming: LangNaming,
+    ) -> Result<Self, Error> {
+        let names = Self::names(languages, naming)?;
+
         if !dst.exists() {
-            std::fs::create_dir(dst)?;
+            std::fs::create_dir_all(dst)?;
         }
         if dst.is_file() {
             error!("rebuild destination must be an empty folder!");
         };
 
-        if !dst.read_dir()?.next().is_none() {
-            error!("rebuild destination folder must be empty!");
+        match layout {
+            LayoutStrategy::Flat => {
+                if !dst.read_dir()?.next().is_none() {
+                    error!("rebuild destination folder must be empty!");
+                }
+            }
+            // language folders may already hold other outputs
+            LayoutStrategy::PerLangDir => {
+                for (_, name) in &names {
+                    let path = dst.join(name).join(format!("{}.avro", name));
+                    if path.exists() {
+                        error!("rebuild file {:?} already exists!", path);
+                    }
+                }
+            }
         }
 
-        let ret: Result<HashMap<Lang, Arc<Mutex<RebuildWriter<'_, File>>>>, Error> = LANG
+        Self::create(dst, names, layout, codec, false)
+    }
+
+    /// Use `dst` as a root path for avro files storage, routing rebuild information following `rebuild_layout`.
+    ///
+    /// With [RebuildLayout::PerLang], this is [Self::with_dst_naming] (or [Self::open_append] if `append` is set).
+    /// With [RebuildLayout::PerShard], files are written by [Self::write_shard] at `<dst>/shard_<id>.avro`,
+    /// and `layout`, `languages` and `naming` are not used.
+    ///
+    /// # Errors
+    /// See [Self::with_dst_naming] and [Self::open_append].
+    pub fn with_rebuild_layout(
+        dst: &Path,
+        languages: &HashSet<&'static str>,
+        layout: LayoutStrategy,
+        codec: Codec,
+        naming: LangNaming,
+        rebuild_layout: RebuildLayout,
+        append: bool,
+    ) -> Result<Self, Error> {
+        match (rebuild_layout, append) {
+            (RebuildLayout::PerLang, false) => {
+                Self::with_dst_naming(dst, languages, layout, codec, naming)
+            }
+            (RebuildLayout::PerLang, true) => {
+                Self::open_append(dst, languages, layout, codec, naming)
+            }
+            (RebuildLayout::PerShard, _) => {
+                std::fs::create_dir_all(dst)?;
+                Ok(RebuildWriters {
+                    writers: HashMap::new(),
+                    per_shard: Some(PerShard {
+                        dst: dst.to_path_buf(),
+                        codec,
+                        append,
+                        quantization: ProbQuantization::default(),
+                    }),
+                })
+            }
+        }
+    }
+
+    /// Write the rebuild information of a whole shard in its own `shard_<id>.avro` file.
+    ///
+    /// Files of shards that are written again are appended to if [Self::with_rebuild_layout] has been
+    /// called with `append`, and overwritten otherwise.
+    ///
+    /// # Errors
+    /// Returns an error if the layout is not [RebuildLayout::PerShard], or if the file can't be written.
+    pub fn write_shard(&self, shard_result: &ShardResult) -> Result<(), Error> {
+        let per_shard = self
+            .per_shard
+            .as_ref()
+            .ok_or_else(|| Error::Custom("rebuild files are not written per shard".to_string()))?;
+        let path = per_shard
+            .dst
+            .join(format!("shard_{}.avro", shard_result.shard_id()));
+        let writer = if per_shard.append {
+            RebuildWriter::append_path(&path, per_shard.codec)?
+        } else {
+            RebuildWriter::from_path(&path, per_shard.codec)?
+        };
+        let mut writer = writer.with_quantization(per_shard.quantization)?;
+        writer.append_ser(shard_result)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Open rebuild files of `languages` in `dst` for append, in order to add documents to an existing corpus.
+    ///
+    /// `dst`, `layout` and `naming` have to be the same as when files were created (see [Self::with_dst_naming]),
+    /// but files that don't exist yet are created.
+    /// Existing files keep their codec (see [RebuildWriter::append_path]).
+    ///
+    /// This is riskier than writing new files, since an interrupted write leaves an invalid existing file.
+    ///
+    /// # Errors
+    /// Returns an error if a label is not a [Lang], or if some files can't be appended to.
+    /// Contrary to [Self::with_dst_naming], existing files are never removed.
+    pub fn open_append(
+        dst: &Path,
+        languages: &HashSet<&'static str>,
+        layout: LayoutStrategy,
+        codec: Codec,
+        naming: LangNaming,
+    ) -> Result<Self, Error> {
+        let names = Self::names(languages, naming)?;
+        std::fs::create_dir_all(dst)?;
+        Self::create(dst, names, layout, codec, true)
+    }
+
+    /// Get the name of each language of `languages` in `naming`.
+    fn names(
+        languages: &HashSet<&'static str>,
+        naming: LangNaming,
+    ) -> Result<Vec<(Lang, &'static str)>, Error> {
+        languages
             .iter()
-            .map(|lang| Self::new_writer_mutex(dst, lang))
+            .map(|lang| {
+                let lang = Lang::from_str(lang)?;
+                Ok((lang, naming.name(lang)))
+            })
+            .collect()
+    }
+
+    /// Create (or open for append) the files of `names`.
+    ///
+    /// On failure, created files are removed unless `append` is set.
+    fn create(
+        dst: &Path,
+        names: Vec<(Lang, &'static str)>,
+        layout: LayoutStrategy,
+        codec: Codec,
+        append: bool,
+    ) -> Result<Self, Error> {
+        // file creation can be slow on networked storage, so files are created concurrently.
+        let results: Vec<(&'static str, Result<_, Error>)> = names
+            .into_par_iter()
+            .map(|(lang, name)| {
+                let writer = Self::new_writer_mutex(dst, lang, name, layout, codec, append);
+                (name, writer)
+            })
             .collect();
 
-        Ok(RebuildWriters(ret?))
+        let (created, mut failed): (Vec<_>, Vec<_>) =
+            results.into_iter().partition(|(_, result)| result.is_ok());
+        if failed.is_empty() {
+            let writers = created
+                .into_iter()
+                .filter_map(|(_, result)| result.ok())
+                .collect();
+            return Ok(RebuildWriters {
+                writers,
+                per_shard: None,
+            });
+        }
+
+        // close and remove created files
+        for (name, writer) in created {
+            drop(writer);
+            if append {
+                continue;
+            }
+            let path = Self::forge_dst(dst, name, layout)?;
+            if let Err(e) = std::fs::remove_file(&path) {
+                warn!("could not remove rebuild file {:?}: {:?}", path, e);
+            }
+        }
+
+        failed.sort_unstable_by_key(|(name, _)| *name);
+        for (name, result) in &failed {
+            if let Err(e) = result {
+                error!("could not open rebuild file of {}: {:?}", name, e);
+            }
+        }
+        let names: Vec<&str> = failed.iter().map(|(name, _)| *name).collect();
+        Err(Error::Custom(format!(
+            "could not {} rebuild files of {}",
+            if append { "open" } else { "create" },
+            names.join(", ")
+        )))
     }
 }
 
 #[cfg(test)]
 mod tests {
 
-    use crate::pipelines::oscardoc::types::{Location, Metadata};
+    use std::{collections::HashMap, fs::File};
+
+    use avro_rs::{Codec, Schema};
+    use serde::Serialize;
+
+    use crate::{
+        error::Error,
+        identifiers::Identification,
+        io::LayoutStrategy,
+        lang::{Lang, LangNaming},
+        pipelines::oscardoc::types::{Document, Location, Metadata},
+    };
+
+    use super::{
+        Duplicates, ProbQuantization, RebuildInformation, RebuildLayout, RebuildReader,
+        RebuildWriter, RebuildWriters, ShardResult,
+    };
+
+    fn shard_results() -> Vec<ShardResult> {
+        let id = Identification::new(Lang::Fr, 0.9);
+        (0..3u64)
+            .map(|shard_id| {
+                let locs = (0..2)
+                    .map(|i| Location::new(shard_id, format!("record-{}", i), i, i + 2, i * 3))
+                    .collect();
+                let meta = vec![Metadata::new(&id, &[Some(id.clone()), None]); 2];
+                ShardResult::new(shard_id, locs, meta)
+            })
+            .collect()
+    }
+
+    fn write(shard_results: &[ShardResult], codec: Codec) -> Vec<u8> {
+        let mut buf = Vec::new();
+        let mut rw = RebuildWriter::new(&super::SCHEMA, &mut buf, codec);
+        rw.extend_ser(shard_results).unwrap();
+        rw.flush().unwrap();
+        drop(rw);
+        buf
+    }
+
+    #[test]
+    fn rebuild_reader_roundtrip() {
+        let srs = shard_results();
+        for codec in [Codec::Null, Codec::Deflate, Codec::Snappy] {
+            let buf = write(&srs, codec);
+
+            let reader = RebuildReader::new(&buf[..]).unwrap();
+            let result: Vec<ShardResult> = reader.map(|sr| sr.unwrap()).collect();
+            assert_eq!(result, srs, "{:?}", codec);
+        }
+    }
+
+    #[test]
+    fn shard_result_from_scratch() {
+        let id = Identification::new(Lang::De, 0.7);
+        let sentence_ids = vec![
+            Some(id.clone()),
+            None,
+            Some(Identification::new(Lang::En, 0.6)),
+        ];
+        let meta = Metadata::new(&id, &sentence_ids)
+            .with_annotation(vec!["tiny".to_string()])
+            .with_counts(3, 120);
+        let loc = Location::try_new(42, "<urn:uuid:0>".to_string(), 1, 3, 5)
+            .unwrap()
+            .with_byte_range(100, 220);
+        let srs = vec![ShardResult::new(42, vec![loc], vec![meta.clone()])];
+
+        let buf = write(&srs, Codec::Deflate);
+        let result: Vec<RebuildInformation> = RebuildReader::new(&buf[..])
+            .unwrap()
+            .rebuild_info()
+            .map(|r| r.unwrap())
+            .collect();
+        assert_eq!(result.len(), 1);
+        let rb_info = &result[0];
+        assert_eq!(rb_info.global_key(), (42, "<urn:uuid:0>".to_string()));
+        assert_eq!((rb_info.line_start(), rb_info.line_end()), (1, 3));
+        assert_eq!(rb_info.loc_in_shard(), 5);
+        assert_eq!(
+            (rb_info.byte_start(), rb_info.byte_end()),
+            (Some(100), Some(220))
+        );
+        assert_eq!(rb_info.metadata(), &meta);
+        assert_eq!(rb_info.metadata().nb_chars(), Some(120));
+    }
+
+    #[test]
+    fn rebuild_reader_annotations() {
+        let id = Identification::new(Lang::Fr, 0.9);
+        let meta = Metadata::new(&id, &[Some(id.clone()), None])
+            .with_annotation(vec!["adult".to_string()]);
+        let locs = vec![Location::new(0, "record-0".to_string(), 0, 2, 0)];
+        let srs = vec![ShardResult::new(0, locs, vec![meta.clone()])];
+
+        let buf = write(&srs, Codec::Null);
+        let reader = RebuildReader::new(&buf[..]).unwrap();
+        let result: Vec<RebuildInformation> = reader.rebuild_info().map(|r| r.unwrap()).collect();
+        assert_eq!(result[0].metadata(), &meta);
+    }
+
+    #[test]
+    fn rebuild_reader_byte_range() {
+        let id = Identification::new(Lang::Fr, 0.9);
+        let loc = Location::new(0, "record-0".to_string(), 0, 2, 0).with_byte_range(120, 180);
+        let srs = vec![ShardResult::new(
+            0,
+            vec![loc.clone()],
+            vec![Metadata::new(&id, &[Some(id.clone())])],
+        )];
+
+        let buf = write(&srs, Codec::Null);
+        let reader = RebuildReader::new(&buf[..]).unwrap();
+        let result: Vec<RebuildInformation> = reader.rebuild_info().map(|r| r.unwrap()).collect();
+        assert_eq!(result[0].byte_start(), Some(120));
+        assert_eq!(result[0].byte_end(), Some(180));
+        assert_eq!(result.into_iter().next().unwrap().into_raw_parts().0, loc);
+    }
+
+    #[test]
+    fn rebuild_reader_old_schema() {
+        // schema of files written before byte offsets were tracked
+        let old_schema = Schema::parse_list(&[
+            r#"{"name":"identification", "type":"record", "fields": [
+                {"name": "label", "type":"string"},
+                {"name": "prob", "type":"float"}
+            ]}"#,
+            r#"{"type":"record", "name":"metadata_record", "fields":[
+                {"name":"identification", "type":"identification"},
+                {"name":"annotation", "type":["null", {"type": "array", "items":"string"}]},
+                {"name": "sentence_identifications", "type":"array", "items":["null", "identification"]}
+            ]}"#,
+            r#"{"type":"record", "name":"rebuild_information", "fields":[
+                {"name": "shard_id", "type":"long"},
+                {"name": "record_id", "type":"string"},
+                {"name": "line_start", "type":"long"},
+                {"name": "line_end", "type":"long"},
+                {"name": "loc_in_shard", "type":"long"},
+                {"name":"metadata", "type":"metadata_record"}
+            ]}"#,
+            r#"{"type":"record", "name":"shard_result", "fields":[
+                {"name": "shard_id", "type":"long"},
+                {"name": "rebuild_info", "type":"array", "items":"rebuild_information"}
+            ]}"#,
+        ])
+        .unwrap()[3]
+            .clone();
+
+        #[derive(Serialize)]
+        struct OldMetadata {
+            identification: Identification,
+            annotation: Option<Vec<String>>,
+            sentence_identifications: Vec<Option<Identification>>,
+        }
+        #[derive(Serialize)]
+        struct OldRebuildInformation {
+            shard_id: usize,
+            record_id: String,
+            line_start: usize,
+            line_end: usize,
+            loc_in_shard: usize,
+            metadata: OldMetadata,
+        }
+        #[derive(Serialize)]
+        struct OldShardResult {
+            shard_id: i64,
+            rebuild_info: Vec<OldRebuildInformation>,
+        }
+
+        let id = Identification::new(Lang::Fr, 0.9);
+        let metadata = Metadata::new(&id, &[Some(id.clone())]);
+        let old = OldShardResult {
+            shard_id: 0,
+            rebuild_info: vec![OldRebuildInformation {
+                shard_id: 0,
+                record_id: "record-0".to_string(),
+                line_start: 0,
+                line_end: 2,
+                loc_in_shard: 0,
+                metadata: OldMetadata {
+                    identification: id.clone(),
+                    annotation: None,
+                    sentence_identifications: vec![Some(id.clone())],
+                },
+            }],
+        };
+        let mut buf = Vec::new();
+        let mut rw = RebuildWriter::new(&old_schema, &mut buf, Codec::Null);
+        rw.append_ser(old).unwrap();
+        rw.flush().unwrap();
+        drop(rw);
+
+        let reader = RebuildReader::new(&buf[..]).unwrap();
+        let result: Vec<RebuildInformation> = reader.rebuild_info().map(|r| r.unwrap()).collect();
+        let loc = Location::new(0, "record-0".to_string(), 0, 2, 0);
+        assert_eq!(result, vec![RebuildInformation::new(loc, metadata)]);
+        assert_eq!(result[0].byte_start(), None);
+        assert_eq!(result[0].metadata().nb_sentences(), None);
+    }
+
+    #[test]
+    fn rebuild_reader_counts() {
+        let id = Identification::new(Lang::Fr, 0.9);
+        let mut doc = Document::new(
+            "premi\u{e8}re phrase\nseconde phrase".to_string(),
+            HashMap::new(),
+            Metadata::new(&id, &[Some(id.clone()), Some(id.clone())]),
+        );
+        doc.update_counts();
+        let locs = vec![Location::new(0, "record-0".to_string(), 0, 2, 0)];
+        let srs = vec![ShardResult::new(0, locs, vec![doc.metadata().clone()])];
+
+        let buf = write(&srs, Codec::Null);
+        let reader = RebuildReader::new(&buf[..]).unwrap();
+        let result: Vec<RebuildInformation> = reader.rebuild_info().map(|r| r.unwrap()).collect();
+        assert_eq!(result[0].metadata().nb_sentences(), Some(2));
+        assert_eq!(result[0].metadata().nb_chars(), Some(29));
+    }
+
+    #[test]
+    fn rebuild_quantization() {
+        let id = Identification::new(Lang::Fr, 0.8765);
+        let meta = Metadata::new(&id, &[Some(id.clone()), None]);
+        // enough documents for quantized probabilities to outweigh the longer schema
+        let locs = (0..100)
+            .map(|i| Location::new(0, format!("record-{}", i), 0, 2, i))
+            .collect();
+        let srs = vec![ShardResult::new(0, locs, vec![meta; 100])];
+
+        let write_quantized = |quantization| {
+            let mut buf = Vec::new();
+            let mut rw = RebuildWriter::new(&super::SCHEMA, &mut buf, Codec::Null)
+                .with_quantization(quantization)
+                .unwrap();
+            rw.extend_ser(&srs).unwrap();
+            drop(rw);
+            buf
+        };
+        let read_probs = |buf: &[u8]| -> Vec<f32> {
+            let reader = RebuildReader::new(buf).unwrap();
+            let rb_info: Vec<RebuildInformation> =
+                reader.rebuild_info().map(|r| r.unwrap()).collect();
+            let metadata = rb_info[0].metadata();
+            assert_eq!(metadata.sentence_identifications()[1], None);
+            vec![
+                *metadata.identification().prob(),
+                *metadata.sentence_identifications()[0]
+                    .as_ref()
+                    .unwrap()
+                    .prob(),
+            ]
+        };
+
+        let writer_quantization = |buf: &[u8]| {
+            let reader = avro_rs::Reader::new(buf).unwrap();
+            ProbQuantization::from_schema(reader.writer_schema()).unwrap()
+        };
+
+        // probabilities are plain floats when they're not quantized
+        let exact = write_quantized(ProbQuantization::None);
+        assert_eq!(read_probs(&exact), vec![0.8765, 0.8765]);
+        assert_eq!(
+            avro_rs::Reader::new(&exact[..]).unwrap().writer_schema(),
+            &*super::SCHEMA
+        );
+        assert_eq!(writer_quantization(&exact), ProbQuantization::None);
+
+        let bucket = write_quantized(ProbQuantization::Bucket);
+        assert!(bucket.len() < exact.len());
+        assert_eq!(read_probs(&bucket), vec![224.0 / 255.0; 2]);
+        assert_eq!(writer_quantization(&bucket), ProbQuantization::Bucket);
+
+        let decimals = write_quantized(ProbQuantization::Decimals(2));
+        assert!(decimals.len() < exact.len());
+        for prob in read_probs(&decimals) {
+            assert!((prob - 0.88).abs() < 1e-6);
+        }
+        assert_eq!(
+            writer_quantization(&decimals),
+            ProbQuantization::Decimals(2)
+        );
+
+        // the schema can't change once values are written
+        let mut rw = RebuildWriter::new(&super::SCHEMA, Vec::new(), Codec::Null);
+        rw.append_ser(&srs[0]).unwrap();
+        assert!(rw.with_quantization(ProbQuantization::Bucket).is_err());
+
+        assert!(RebuildWriter::new(&super::SCHEMA, Vec::new(), Codec::Null)
+            .with_quantization(ProbQuantization::Decimals(10))
+            .is_err());
+        let other_schema = Schema::parse_str(
+            r#"{"type":"record", "name":"other", "fields":[{"name": "a", "type":"long"}]}"#,
+        )
+        .unwrap();
+        assert!(RebuildWriter::new(&other_schema, Vec::new(), Codec::Null)
+            .with_quantization(ProbQuantization::Bucket)
+            .is_err());
+    }
+
+    #[test]
+    fn rebuild_reader_extra() {
+        let extra = r#"{"source": "forum", "tags": [1, 2]}"#;
+        let meta = Metadata::default()
+            .with_extra(Some(extra.to_string()))
+            .unwrap();
+        let locs = vec![
+            Location::new(0, "record-0".to_string(), 0, 2, 0),
+            Location::new(0, "record-1".to_string(), 0, 2, 1),
+        ];
+        let srs = vec![ShardResult::new(0, locs, vec![meta, Metadata::default()])];
+
+        let buf = write(&srs, Codec::Null);
+        let reader = RebuildReader::new(&buf[..]).unwrap();
+        let result: Vec<RebuildInformation> = reader.rebuild_info().map(|r| r.unwrap()).collect();
+        assert_eq!(result[0].metadata().extra(), Some(extra));
+        assert_eq!(result[1].metadata().extra(), None);
+    }
+
+    #[test]
+    fn rebuild_reader_from_path() {
+        let srs = shard_results();
+        let buf = write(&srs, Codec::Snappy);
+        let dst = tempfile::tempdir().unwrap();
+        let path = dst.path().join("fr.avro");
+        std::fs::write(&path, buf).unwrap();
+
+        let reader = RebuildReader::from_path(&path).unwrap();
+        let result: Vec<RebuildInformation> = reader.rebuild_info().map(|r| r.unwrap()).collect();
+        let expected: Vec<RebuildInformation> = srs
+            .into_iter()
+            .flat_map(|sr| sr.into_raw_parts().1)
+            .collect();
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn append_path() {
+        let srs = shard_results();
+        let dst = tempfile::tempdir().unwrap();
+        let path = dst.path().join("fr.avro");
+
+        let mut rw = RebuildWriter::from_path(&path, Codec::Snappy).unwrap();
+        rw.extend_ser(&srs[..2]).unwrap();
+        drop(rw);
+
+        // the codec of the existing file is kept
+        for sr in &srs[2..] {
+            let mut rw = RebuildWriter::append_path(&path, Codec::Null).unwrap();
+            rw.append_ser(sr).unwrap();
+            rw.flush().unwrap();
+        }
+
+        let reader = RebuildReader::from_path(&path).unwrap();
+        let result: Vec<ShardResult> = reader.map(|sr| sr.unwrap()).collect();
+        assert_eq!(result, srs);
+    }
+
+    #[test]
+    fn append_path_quantization() {
+        let srs = shard_results();
+        let dst = tempfile::tempdir().unwrap();
+        let path = dst.path().join("fr.avro");
+
+        let mut rw = RebuildWriter::from_path(&path, Codec::Null)
+            .unwrap()
+            .with_quantization(ProbQuantization::Bucket)
+            .unwrap();
+        rw.extend_ser(&srs[..2]).unwrap();
+        drop(rw);
+
+        // the quantization of the existing file is kept
+        let mut rw = RebuildWriter::append_path(&path, Codec::Null)
+            .unwrap()
+            .with_quantization(ProbQuantization::None)
+            .unwrap();
+        rw.extend_ser(&srs[2..]).unwrap();
+        drop(rw);
+
+        let reader = RebuildReader::from_path(&path).unwrap();
+        let probs: Vec<f32> = reader
+            .rebuild_info()
+            .map(|rb_info| *rb_info.unwrap().metadata().identification().prob())
+            .collect();
+        assert_eq!(probs, vec![(0.9f32 * 255.0).round() / 255.0; 6]);
+    }
+
+    #[test]
+    fn append_path_new() {
+        let srs = shard_results();
+        let dst = tempfile::tempdir().unwrap();
+
+        // missing and empty files are written as new files
+        let missing = dst.path().join("fr.avro");
+        let empty = dst.path().join("en.avro");
+        std::fs::write(&empty, "").unwrap();
+        for path in [&missing, &empty] {
+            let mut rw = RebuildWriter::append_path(path, Codec::Deflate).unwrap();
+            rw.extend_ser(&srs).unwrap();
+            drop(rw);
+
+            let reader = RebuildReader::from_path(path).unwrap();
+            let result: Vec<ShardResult> = reader.map(|sr| sr.unwrap()).collect();
+            assert_eq!(result, srs);
+        }
+    }
+
+    #[test]
+    fn append_path_invalid() {
+        let srs = shard_results();
+        let dst = tempfile::tempdir().unwrap();
+
+        // interrupted write
+        let truncated = dst.path().join("fr.avro");
+        let buf = write(&srs, Codec::Null);
+        std::fs::write(&truncated, &buf[..buf.len() - 4]).unwrap();
+        assert!(RebuildWriter::append_path(&truncated, Codec::Null).is_err());
+
+        // negative block size
+        let marker = &buf[buf.len() - 16..];
+        let header_len = buf.windows(16).position(|window| window == marker).unwrap() + 16;
+        let mut corrupt = buf[..header_len].to_vec();
+        // 1 value, -100 bytes (zigzag encoded)
+        corrupt.extend_from_slice(&[0x02, 0xc7, 0x01]);
+        corrupt.extend_from_slice(marker);
+        let negative = dst.path().join("de.avro");
+        std::fs::write(&negative, &corrupt).unwrap();
+        let err = RebuildWriter::append_path(&negative, Codec::Null)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("invalid block size"), "{}", err);
+
+        // not an avro file
+        let text = dst.path().join("fr.jsonl");
+        std::fs::write(&text, "{}\n").unwrap();
+        assert!(RebuildWriter::append_path(&text, Codec::Null).is_err());
+
+        // another schema
+        let other_schema = Schema::parse_str(
+            r#"{"type":"record", "name":"other", "fields":[{"name": "a", "type":"long"}]}"#,
+        )
+        .unwrap();
+        let other = dst.path().join("en.avro");
+        let mut rw = RebuildWriter::new(&other_schema, File::create(&other).unwrap(), Codec::Null);
+        rw.append_ser(Other { a: 1 }).unwrap();
+        rw.flush().unwrap();
+        drop(rw);
+        assert!(RebuildWriter::append_path(&other, Codec::Null).is_err());
+
+        // files are left untouched
+        assert_eq!(std::fs::read(&truncated).unwrap(), &buf[..buf.len() - 4]);
+    }
+
+    #[derive(Serialize)]
+    struct Other {
+        a: i64,
+    }
+
+    #[test]
+    fn open_append() {
+        let srs = shard_results();
+        let dst = tempfile::tempdir().unwrap();
+        let languages = vec!["fr", "en"].into_iter().collect();
+
+        {
+            let writers = RebuildWriters::with_dst_languages(
+                dst.path(),
+                &languages,
+                LayoutStrategy::Flat,
+                Codec::Snappy,
+            )
+            .unwrap();
+            let mut fr = writers.get(&Lang::Fr).unwrap().lock().unwrap();
+            fr.extend_ser(&srs[..1]).unwrap();
+        }
+
+        // a language is added
+        let languages = vec!["fr", "en", "de"].into_iter().collect();
+        {
+            let writers = RebuildWriters::open_append(
+                dst.path(),
+                &languages,
+                LayoutStrategy::Flat,
+                Codec::Snappy,
+                LangNaming::default(),
+            )
+            .unwrap();
+            for lang in [Lang::Fr, Lang::De] {
+                let mut writer = writers.get(&lang).unwrap().lock().unwrap();
+                writer.extend_ser(&srs[1..]).unwrap();
+            }
+        }
+
+        let read = |name: &str| -> Vec<ShardResult> {
+            let reader = RebuildReader::from_path(&dst.path().join(name)).unwrap();
+            reader.map(|sr| sr.unwrap()).collect()
+        };
+        assert_eq!(read("fr.avro"), srs);
+        assert_eq!(read("de.avro"), &srs[1..]);
+        assert_eq!(
+            std::fs::metadata(dst.path().join("en.avro")).unwrap().len(),
+            0
+        );
+    }
+
+    #[test]
+    fn with_dst_per_lang_dir() {
+        let dst = tempfile::tempdir().unwrap();
+        // other outputs are allowed in the destination
+        std::fs::create_dir(dst.path().join("fr")).unwrap();
+        std::fs::write(dst.path().join("fr").join("fr.txt"), "").unwrap();
+
+        let languages = vec!["fr", "en"].into_iter().collect();
+        let writers = RebuildWriters::with_dst_languages(
+            dst.path(),
+            &languages,
+            LayoutStrategy::PerLangDir,
+            Codec::Snappy,
+        )
+        .unwrap();
+
+        assert!(writers.get(&Lang::Fr).is_some());
+        assert!(dst.path().join("fr").join("fr.avro").is_file());
+        assert!(dst.path().join("en").join("en.avro").is_file());
+        assert!(!dst.path().join("fr.avro").exists());
+    }
+
+    #[test]
+    fn with_dst_partial_failure() {
+        let dst = tempfile::tempdir().unwrap();
+        // a folder prevents the creation of the French file
+        std::fs::create_dir(dst.path().join("fr.avro")).unwrap();
+
+        let languages = vec!["fr", "en", "de"].into_iter().collect();
+        let err = match RebuildWriters::with_dst_languages(
+            dst.path(),
+            &languages,
+            LayoutStrategy::Flat,
+            Codec::Null,
+        ) {
+            Err(Error::Custom(err)) => err,
+            Err(e) => panic!("unexpected error: {:?}", e),
+            Ok(_) => panic!("French file should not have been created"),
+        };
+        assert_eq!(err, "could not create rebuild files of fr");
+
+        // other files have been removed
+        let entries: Vec<_> = dst
+            .path()
+            .read_dir()
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        assert_eq!(entries, vec!["fr.avro"]);
+    }
+
+    #[test]
+    fn with_dst_naming() {
+        let dst = tempfile::tempdir().unwrap();
+        let languages = vec!["fr", "als"].into_iter().collect();
+        let writers = RebuildWriters::with_dst_naming(
+            dst.path(),
+            &languages,
+            LayoutStrategy::PerLangDir,
+            Codec::Snappy,
+            LangNaming::Iso639_3,
+        )
+        .unwrap();
+
+        assert!(writers.get(&Lang::Als).is_some());
+        assert!(dst.path().join("fra").join("fra.avro").is_file());
+        assert!(dst.path().join("gsw").join("gsw.avro").is_file());
+
+        // unmapped languages keep their label
+        let dst = tempfile::tempdir().unwrap();
+        let languages = vec!["fr", "eml"].into_iter().collect();
+        RebuildWriters::with_dst_naming(
+            dst.path(),
+            &languages,
+            LayoutStrategy::Flat,
+            Codec::Snappy,
+            LangNaming::Iso639_3,
+        )
+        .unwrap();
+        assert!(dst.path().join("fra.avro").is_file());
+        assert!(dst.path().join("eml.avro").is_file());
+
+        // invalid labels are reported before creating any file
+        let dst = tempfile::tempdir().unwrap();
+        let languages = vec!["fr", "not-a-lang"].into_iter().collect();
+        assert!(RebuildWriters::with_dst_naming(
+            dst.path(),
+            &languages,
+            LayoutStrategy::Flat,
+            Codec::Snappy,
+            LangNaming::Iso639_3,
+        )
+        .is_err());
+        assert!(dst.path().read_dir().unwrap().next().is_none());
+    }
+
+    #[test]
+    fn with_rebuild_layout_per_shard() {
+        let srs = shard_results();
+        let dst = tempfile::tempdir().unwrap();
+        let rebuild_dst = dst.path().join("rebuild");
+        let languages = vec!["fr", "en"].into_iter().collect();
+
+        let writers = RebuildWriters::with_rebuild_layout(
+            &rebuild_dst,
+            &languages,
+            LayoutStrategy::Flat,
+            Codec::Deflate,
+            LangNaming::default(),
+            RebuildLayout::PerShard,
+            false,
+        )
+        .unwrap();
+        assert_eq!(writers.rebuild_layout(), RebuildLayout::PerShard);
+        assert!(writers.get(&Lang::Fr).is_none());
+        for sr in &srs {
+            writers.write_shard(sr).unwrap();
+        }
+
+        for sr in &srs {
+            let path = rebuild_dst.join(format!("shard_{}.avro", sr.shard_id()));
+            let read: Vec<ShardResult> = RebuildReader::from_path(&path)
+                .unwrap()
+                .map(|sr| sr.unwrap())
+                .collect();
+            assert_eq!(read.as_slice(), std::slice::from_ref(sr));
+        }
+
+        // language writers can't write whole shards
+        let writers = RebuildWriters::with_rebuild_layout(
+            &dst.path().join("per_lang"),
+            &languages,
+            LayoutStrategy::Flat,
+            Codec::Deflate,
+            LangNaming::default(),
+            RebuildLayout::PerLang,
+            false,
+        )
+        .unwrap();
+        assert_eq!(writers.rebuild_layout(), RebuildLayout::PerLang);
+        assert!(writers.write_shard(&srs[0]).is_err());
+    }
+
+    #[test]
+    fn global_key() {
+        let loc = Location::new(3, "record-0".to_string(), 1, 3, 0);
+        let ri = RebuildInformation::new(loc, Metadata::default());
+        assert_eq!(ri.global_key(), (3, "record-0".to_string()));
+    }
 
-    use super::{RebuildInformation, RebuildWriter, ShardResult};
+    #[test]
+    fn find_duplicates() {
+        let dst = tempfile::tempdir().unwrap();
+        // record-0 and record-1 are in shards 0, 1 and 2
+        std::fs::write(
+            dst.path().join("fr.avro"),
+            write(&shard_results(), Codec::Null),
+        )
+        .unwrap();
+
+        let duplicates = RebuildReader::find_duplicates(dst.path()).unwrap();
+        assert_eq!(duplicates.record_ids.len(), 2);
+        assert_eq!(duplicates.record_ids["record-0"], vec![0, 1, 2]);
+        assert!(duplicates.keys.is_empty());
+
+        // the same shard is found again in another language folder
+        let id = Identification::new(Lang::En, 0.9);
+        let loc = Location::new(1, "record-1".to_string(), 0, 1, 3);
+        let sr = ShardResult::new(1, vec![loc], vec![Metadata::new(&id, &[Some(id.clone())])]);
+        std::fs::create_dir(dst.path().join("en")).unwrap();
+        let en_path = dst.path().join("en").join("en.avro");
+        std::fs::write(&en_path, write(&[sr], Codec::Null)).unwrap();
+
+        let duplicates = RebuildReader::find_duplicates(dst.path()).unwrap();
+        assert_eq!(duplicates.keys.len(), 1);
+        assert_eq!(
+            duplicates.keys[&(1, "record-1".to_string())],
+            vec![en_path, dst.path().join("fr.avro")]
+        );
+    }
+
+    #[test]
+    fn find_duplicates_none() {
+        let dst = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dst.path().join("fr.avro"),
+            write(&shard_results()[..1], Codec::Null),
+        )
+        .unwrap();
+        let duplicates = RebuildReader::find_duplicates(dst.path()).unwrap();
+        assert_eq!(duplicates, Duplicates::default());
+        assert!(duplicates.is_empty());
+    }
+
+    #[test]
+    fn extract_lines() {
+        let loc = Location::new(0, "record-0".to_string(), 1, 3, 0);
+        let ri = RebuildInformation::new(loc, Metadata::default());
+        assert_eq!(ri.extract_lines("zero\none\ntwo\nthree"), "one\ntwo\nthree");
+        assert_eq!(ri.extract_lines("zero\none"), "one");
+
+        // the last line is kept, whatever the line endings
+        assert_eq!(
+            ri.extract_lines("zero\r\none\r\ntwo\r\nthree\r\n"),
+            "one\ntwo\nthree"
+        );
+    }
+
+    #[test]
+    fn split_lines() {
+        for body in [
+            "",
+            "\n",
+            "a",
+            "a\n",
+            "a\n\n",
+            "a\r\nb",
+            "a\r\nb\r\n",
+            "a\rb\n",
+            "a\r",
+            "\r\n\r\n",
+        ] {
+            let expected: Vec<_> = body.lines().map(str::as_bytes).collect();
+            assert_eq!(super::split_lines(body.as_bytes()), expected, "{:?}", body);
+        }
+    }
 
     #[test]
     fn rebuild_information_into_raw_parts() {
@@ -319,7 +2050,7 @@ mod tests {
         let sr = ShardResult::new(0, Vec::new(), Vec::new());
         println!("{:#?}", sr);
         let buf = Vec::new();
-        let mut rw = RebuildWriter::new(&super::SCHEMA, buf);
+        let mut rw = RebuildWriter::new(&super::SCHEMA, buf, Codec::Snappy);
 
         rw.append_ser(sr).unwrap();
     }
@@ -332,7 +2063,7 @@ mod tests {
         println!("{:#?}", sr);
         println!("{:#?}", *super::SCHEMA);
         let mut buf = Vec::new();
-        let mut rw = RebuildWriter::new(&super::SCHEMA, &mut buf);
+        let mut rw = RebuildWriter::new(&super::SCHEMA, &mut buf, Codec::Snappy);
 
         rw.append_ser(&sr).unwrap();
         rw.flush().unwrap();
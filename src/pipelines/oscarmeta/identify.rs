@@ -0,0 +1,250 @@
+//! Identification-only pipeline.
+//!
+//! [Identify] runs language identification over shards the way [OscarMetadata] does,
+//! but writes a flat table of predictions instead of a corpus, for quality audits.
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use log::{error, info};
+use rayon::prelude::*;
+use warc::WarcHeader;
+
+use super::types::WarcHeaders;
+use super::OscarMetadata;
+use crate::error::Error;
+use crate::identifiers::LanguageIdentifier;
+use crate::pipelines::pipeline::Pipeline;
+
+/// Name of the file (in `dst`) holding predictions.
+pub const PREDICTIONS_FILE: &str = "predictions.tsv";
+
+/// Header line of [PREDICTIONS_FILE].
+const HEADER: &str = "shard\trecord\tline\tlabel\tprob\n";
+
+/// Writes the predictions of each identified sentence to `<dst>/predictions.tsv`,
+/// one tab-separated `shard`, `record`, `line`, `label`, `prob` row per sentence
+/// (or per segment, see [OscarMetadata::with_windowed_identification]).
+///
+/// Records and sentences go through the same filters as in an [OscarMetadata] run
+/// (length bounds, normalization, sentence splitting, processed languages…),
+/// and each sentence gets its most probable prediction.
+/// Nothing is merged, deduplicated or written to the corpus.
+///
+/// Rows of a shard are written together, in record and line order.
+/// Shards are named by their path and records by their WARC record id.
+pub struct Identify {
+    pipeline: OscarMetadata,
+    dst: PathBuf,
+    threshold: f32,
+}
+
+impl Identify {
+    /// Identify the shards of `pipeline`, writing predictions in `dst`.
+    ///
+    /// The threshold defaults to the one of [OscarMetadata] runs (`0.8`).
+    pub fn new(pipeline: OscarMetadata, dst: PathBuf) -> Self {
+        Self {
+            pipeline,
+            dst,
+            threshold: 0.8,
+        }
+    }
+
+    /// Only keep predictions whose probability is at least `threshold`.
+    ///
+    /// Use `0.0` to get a prediction for every sentence.
+    /// Per-language thresholds (see [OscarMetadata::with_lang_thresholds]) still apply.
+    ///
+    /// # Errors
+    /// Returns an error if `threshold` is not in `[0, 1]`.
+    pub fn with_threshold(mut self, threshold: f32) -> Result<Self, Error> {
+        if !(0.0..=1.0).contains(&threshold) {
+            return Err(Error::Custom(format!(
+                "invalid identification threshold: {} (should be in [0, 1])",
+                threshold
+            )));
+        }
+        self.threshold = threshold;
+        Ok(self)
+    }
+
+    /// Get the (lossily decoded) WARC record id of provided headers, or an empty string.
+    fn record_id(headers: &WarcHeaders) -> String {
+        headers
+            .get(&WarcHeader::RecordID)
+            .map(|id| String::from_utf8_lossy(id).into_owned())
+            .unwrap_or_default()
+    }
+
+    /// Get the prediction rows of the shard at `shard` (of index `idx`).
+    fn shard_rows(
+        &self,
+        idx: usize,
+        shard: &Path,
+        cls: &dyn LanguageIdentifier,
+    ) -> Result<String, Error> {
+        let mut rows = String::new();
+        for (_, (sentences, headers, _)) in self.pipeline.identify_shard(idx, shard, cls)? {
+            let record_id = Self::record_id(&headers);
+            for (_, lang, prob, line) in sentences {
+                if prob < self.threshold {
+                    continue;
+                }
+                rows.push_str(&format!(
+                    "{}\t{}\t{}\t{}\t{}\n",
+                    shard.display(),
+                    record_id,
+                    line,
+                    lang,
+                    prob
+                ));
+            }
+        }
+        Ok(rows)
+    }
+}
+
+impl Pipeline<()> for Identify {
+    fn version() -> &'static str {
+        "1.1.0"
+    }
+
+    /// Run the identification, writing [PREDICTIONS_FILE] in `dst`.
+    ///
+    /// Shards that can't be listed or read are logged and skipped.
+    fn run(&self) -> Result<(), Error> {
+        let cls = self.pipeline.classifier_with_threshold(self.threshold)?;
+        let cls = cls.as_ref();
+
+        std::fs::create_dir_all(&self.dst)?;
+        let mut writer = BufWriter::new(File::create(self.dst.join(PREDICTIONS_FILE))?);
+        writer.write_all(HEADER.as_bytes())?;
+        let writer = Mutex::new(writer);
+
+        let failed: Vec<_> = self
+            .pipeline
+            .shard_paths()?
+            .enumerate()
+            .par_bridge()
+            .filter_map(|(idx, shard)| {
+                let rows = shard
+                    .map_err(Error::from)
+                    .and_then(|shard| self.shard_rows(idx, &shard, cls))
+                    .and_then(|rows| Ok(writer.lock().unwrap().write_all(rows.as_bytes())?));
+                rows.err().map(|e| (idx, e))
+            })
+            .collect();
+
+        for (idx, e) in &failed {
+            error!("could not identify shard {}: {:?}", idx, e);
+        }
+        writer.into_inner().unwrap().flush()?;
+        info!(
+            "predictions written to {:?} ({} failed shards)",
+            self.dst.join(PREDICTIONS_FILE),
+            failed.len()
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use fasttext::Prediction;
+
+    use super::{Identify, PREDICTIONS_FILE};
+    use crate::error::Error;
+    use crate::identifiers::LanguageIdentifier;
+    use crate::pipelines::oscarmeta::OscarMetadata;
+    use crate::pipelines::Pipeline;
+    use crate::testing::WetBuilder;
+
+    /// identifies sentences holding "bonjour" as confidently french, and others as unreliably english.
+    struct Bonjour;
+
+    impl LanguageIdentifier for Bonjour {
+        fn predict(&self, text: &str) -> Result<Option<Vec<Prediction>>, Error> {
+            let prediction = if text.contains("bonjour") {
+                ("fr", 0.9)
+            } else {
+                ("en", 0.3)
+            };
+            Ok(Some(vec![Prediction {
+                prob: prediction.1,
+                label: prediction.0.to_string(),
+            }]))
+        }
+    }
+
+    #[test]
+    fn with_threshold() {
+        let p = || {
+            Identify::new(
+                OscarMetadata::new(
+                    vec![PathBuf::new()],
+                    PathBuf::new(),
+                    PathBuf::new(),
+                    1,
+                    None,
+                ),
+                PathBuf::new(),
+            )
+        };
+        assert!(p().with_threshold(0.0).is_ok());
+        assert!(p().with_threshold(1.5).is_err());
+        assert!(p().with_threshold(-0.1).is_err());
+    }
+
+    #[test]
+    fn run() {
+        let src = tempfile::tempdir().unwrap();
+        let dst = tempfile::tempdir().unwrap();
+        let line = |word: &str| format!("{} {}", word, "lorem ipsum dolor sit amet ".repeat(5));
+        let shard_path = src.path().join("0.txt.gz");
+        WetBuilder::new()
+            .text(&format!("{}\n{}\nshort", line("bonjour"), line("hello")))
+            .write(&shard_path)
+            .unwrap();
+
+        let read = |threshold: f32| {
+            let pipeline = OscarMetadata::new(
+                vec![src.path().to_path_buf()],
+                PathBuf::new(),
+                PathBuf::new(),
+                1,
+                None,
+            )
+            .with_identifier(Arc::new(Bonjour));
+            Identify::new(pipeline, dst.path().to_path_buf())
+                .with_threshold(threshold)
+                .unwrap()
+                .run()
+                .unwrap();
+            let predictions = std::fs::read_to_string(dst.path().join(PREDICTIONS_FILE)).unwrap();
+            predictions
+                .lines()
+                .map(|row| row.split('\t').map(String::from).collect::<Vec<_>>())
+                .collect::<Vec<_>>()
+        };
+
+        let rows = read(0.0);
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0], vec!["shard", "record", "line", "label", "prob"]);
+        assert_eq!(rows[1][0], shard_path.display().to_string());
+        assert!(rows[1][1].starts_with("<urn:uuid:"));
+        assert_eq!(rows[1][2..], ["0", "fr", "0.9"]);
+        assert_eq!(rows[2][2..], ["1", "en", "0.3"]);
+
+        // unreliable predictions are left out, short sentences are never identified
+        let rows = read(0.5);
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[1][3], "fr");
+    }
+}
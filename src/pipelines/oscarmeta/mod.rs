@@ -1,6 +1,12 @@
 //! OSCAR Schema v1.1 pipeline
 mod chunks;
+mod identify;
 mod pipeline;
+mod rejects;
+mod stats;
 pub mod types;
+mod windows;
 
-pub use pipeline::OscarMetadata;
+pub use identify::{Identify, PREDICTIONS_FILE};
+pub use pipeline::{OscarMetadata, RecordFilter, ShardStatus, SUMMARY_FILE};
+pub use stats::{DiscardReason, LangStats, LangSummary, RunStats, ShardTiming};
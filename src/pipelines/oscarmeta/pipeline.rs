@@ -1,23 +1,179 @@
-use std::{collections::HashMap, path::PathBuf};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap, HashSet},
+    fs::{File, OpenOptions},
+    hash::{Hash, Hasher},
+    io::{BufRead, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
 
 use super::types::Document;
 use super::types::MergedPiece;
+use super::types::Source;
+use super::windows::{self, Segment, SlidingWindows};
 use crate::error::Error;
-use crate::identifiers::FastText;
-use crate::io::writer::WriterTrait;
-use crate::lang::LANG;
+use crate::filtering::content::ContentLength;
+use crate::filtering::minhash::NearDuplicates;
+use crate::filtering::normalizer::{Normalizer, TextTransform};
+use crate::filtering::splitter::{NoSplit, SentenceSplitter};
+use crate::filtering::{Filter, FilterMut};
+use crate::identifiers::{self, FastText, FastTextModel, LanguageIdentifier};
+use crate::lang::{self, LANG};
 use crate::sources::commoncrawl::Wet;
+use itertools::Either;
 use log::Level::Debug;
 use log::{debug, error, info, log_enabled, warn};
 use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use twox_hash::XxHash64;
 use warc::BufferedBody;
 use warc::Record;
+use warc::WarcHeader;
 
-use crate::io::LangFiles;
+use crate::io::writer::DEFAULT_PROB_DIGITS;
+use crate::io::{
+    FileNaming, LangCaps, LangChannels, LangFiles, LayoutStrategy, OutputFormat, Staging,
+};
 
 use crate::pipelines::pipeline::Pipeline;
+use crate::pipelines::progress::ProgressObserver;
+use crate::pipelines::retry::Retry;
+use crate::pipelines::shutdown::Shutdown;
+use crate::pipelines::threads::{self, ThreadPinning};
+use crate::pipelines::url_policy::UrlPolicy;
 
+use super::rejects::{RejectPrediction, RejectReason, RejectSink};
+use super::stats::{DiscardReason, LangSummary, RunStats, ShardTiming};
 use super::types::WarcHeaders;
+
+/// Identified (sentence, language, probability, line number) tuples of a record, in line order,
+/// along with its headers and the (sorted) numbers of the lines dropped for being too short,
+/// that gaps can be bridged over (see [OscarMetadata::with_gap_bridging]).
+type ProcessedRecord = (
+    Vec<(String, &'static str, f32, usize)>,
+    WarcHeaders,
+    Vec<usize>,
+);
+
+/// Identified (sentence, language, probability) candidate of a sentence (see [OscarMetadata::identify_sentence]).
+type Candidate = (String, &'static str, f32);
+
+/// Predicate on record headers, returning `true` for records that should be processed
+/// (see [OscarMetadata::with_record_filter]).
+pub type RecordFilter = Box<dyn Fn(&WarcHeaders) -> bool + Send + Sync>;
+
+/// Name of the manifest (in `dst`) recording the status of processed shards, one JSON [ManifestEntry] per line.
+///
+/// Entries are appended as shards complete or fail, so a shard may have several entries:
+/// the last one holds its current status.
+const COMPLETED_SHARDS_FILE: &str = "done.jsonl";
+
+/// Status of a shard in the manifest (see [OscarMetadata::completed_shards] and [OscarMetadata::failed_shards]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ShardStatus {
+    /// Every language of the shard has been written.
+    Done,
+    /// The shard could not be processed or written.
+    Failed,
+}
+
+/// Entry of the manifest, such as `{"shard": "shards/0.txt.gz", "status": "failed", "error": "..."}`.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum ManifestEntry {
+    Status {
+        shard: PathBuf,
+        status: ShardStatus,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+    /// Entry of manifests written by previous versions, where every listed shard is completed.
+    Completed(PathBuf),
+}
+
+/// Name of the file (in `dst`) holding per-shard language distributions.
+const SHARD_LANGS_FILE: &str = "shard_langs.tsv";
+
+/// Name of the file (in `dst`) holding the per-language summary of the corpus (see [RunStats::summary]).
+pub const SUMMARY_FILE: &str = "corpus_summary.json";
+
+/// Default number of records processed by each rayon task (see [OscarMetadata::with_record_chunk_size]).
+const DEFAULT_RECORD_CHUNK_SIZE: usize = 8;
+
+/// State of a shard, shared by the threads processing its records.
+#[derive(Debug, Default)]
+struct ShardState {
+    /// records that have been processed
+    records: AtomicUsize,
+    /// sentences discarded by the length filter
+    discarded: AtomicUsize,
+    /// sentences skipped because they're blank (see [OscarMetadata::with_skip_blank])
+    blank: AtomicUsize,
+    /// records skipped by the record filter (see [OscarMetadata::with_record_filter])
+    filtered: AtomicUsize,
+    /// records skipped for being too large (see [OscarMetadata::with_max_record_bytes])
+    oversized: AtomicUsize,
+    /// sentences whose identification failed
+    predict_errors: AtomicUsize,
+    /// discarded sentences, by reason (indexed by [DiscardReason] discriminant)
+    discards: [AtomicUsize; DiscardReason::ALL.len()],
+    /// records that could not be fully processed (see [OscarMetadata::with_max_error_rate])
+    failed: AtomicUsize,
+    /// short sentences of each record, grouped by language (see [OscarMetadata::with_keep_short])
+    short: Mutex<HashMap<&'static str, Vec<String>>>,
+    /// sink of rejected sentences, if enabled (see [OscarMetadata::with_rejects])
+    rejects: Option<Arc<RejectSink>>,
+}
+
+impl ShardState {
+    /// Count a sentence discarded because of `reason`.
+    fn discard(&self, reason: DiscardReason) {
+        self.discards[reason as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Account for discarded sentences in `stats`.
+    fn add_discards(&self, stats: &mut RunStats) {
+        for reason in DiscardReason::ALL {
+            stats.add_discard_reason(
+                reason,
+                self.discards[reason as usize].load(Ordering::Relaxed),
+            );
+        }
+    }
+}
+
+/// Maximum rate of failed records (see [OscarMetadata::with_max_error_rate]).
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct ErrorBudget {
+    max_rate: f32,
+    min_records: usize,
+}
+
+impl ErrorBudget {
+    /// Check `failed` records out of `records`, returning a description of the exceeded budget, if any.
+    fn exceeded(&self, failed: usize, records: usize) -> Option<String> {
+        if records == 0 || records < self.min_records {
+            return None;
+        }
+        let rate = failed as f32 / records as f32;
+        (rate > self.max_rate).then(|| {
+            format!(
+                "{} of {} records failed ({:.1}%, max {:.1}%)",
+                failed,
+                records,
+                rate * 100.0,
+                self.max_rate * 100.0
+            )
+        })
+    }
+}
+
 /// OSCAR v1.5 generation pipeline
 ///
 /// OSCAR v1.5 is a retrocompatible corpus
@@ -34,250 +190,4790 @@ use super::types::WarcHeaders;
 ///   transform out list of sentence-language pairs into chunks of contiguous same-language sentences
 ///   and we store shard-level line offsets on metadata.
 ///   Then we group same-language chunks for each language (on shard-level) and we write on disk.
+/// - Once every language of a shard is written, the shard is recorded in a manifest in `dst`,
+///   so that a subsequent run on the same `dst` skips it (see [OscarMetadata::completed_shards]).
+///   Failed shards are recorded too, and can be processed again (see [OscarMetadata::run_failed_only]).
 /// - We also keep track of disk-level line offsets to sync shard-level offsets between writes.
+/// - Each piece also records the ranges of lines it comes from in its record (see [MergedPiece::line_ranges]),
+///   so that the output can be rebuilt from the shards.
 ///
 /// TODO: Better document this step.
 pub struct OscarMetadata {
-    src: PathBuf,
+    src: Vec<PathBuf>,
     dst: PathBuf,
     lid_path: PathBuf,
+    k: usize,
+    part_size: Option<usize>,
+    min_sentence_chars: usize,
+    max_sentence_chars: Option<usize>,
+    keep_short: bool,
+    skip_blank: bool,
+    windows: Option<SlidingWindows>,
+    normalizer: Option<Box<dyn Normalizer>>,
+    splitter: Box<dyn SentenceSplitter>,
+    record_filter: Option<RecordFilter>,
+    url_policy: Option<Arc<dyn UrlPolicy>>,
+    text_transform: Option<TextTransform>,
+    dedup: bool,
+    near_dedup: Option<(usize, f32)>,
+    lossy_utf8: bool,
+    lenient_headers: bool,
+    dry_run: bool,
+    max_shard_concurrency: Option<usize>,
+    threads: Option<usize>,
+    thread_pinning: ThreadPinning,
+    channel_bound: Option<usize>,
+    record_chunk_size: usize,
+    min_confidence: Option<f32>,
+    min_piece_length: Option<ContentLength>,
+    max_gap: usize,
+    max_predict_errors: Option<usize>,
+    error_budget: Option<ErrorBudget>,
+    max_record_bytes: Option<usize>,
+    open_retry: Retry,
+    layout: LayoutStrategy,
+    output_format: OutputFormat,
+    file_naming: FileNaming,
+    log_shard_langs: bool,
+    write_shard_langs: bool,
+    write_rejects: bool,
+    include_source: bool,
+    staging: Option<Option<PathBuf>>,
+    write_bom: bool,
+    prob_digits: usize,
+    predict_timeout: Option<Duration>,
+    lang_thresholds: HashMap<&'static str, f32>,
+    languages: Option<HashSet<&'static str>>,
+    extra_labels: HashMap<String, &'static str>,
+    header_language_bias: Option<f32>,
+    lang_caps: Option<LangCaps>,
+    model: Option<FastTextModel>,
+    identifier: Option<Arc<dyn LanguageIdentifier>>,
+    progress: Option<Arc<dyn ProgressObserver>>,
+    shutdown: Option<Shutdown>,
 }
 
 impl OscarMetadata {
-    pub fn new(src: PathBuf, dst: PathBuf, lid_path: PathBuf) -> Self {
-        Self { src, dst, lid_path }
+    /// Create a new pipeline.
+    ///
+    /// Shards are read from every folder (or shard file) of `src`, into a single output in `dst`
+    /// (see [OscarMetadata::shard_paths]).
+    ///
+    /// `k` is the maximum number of language candidates kept for each sentence.
+    ///
+    /// `part_size` is the approximate maximum size (in MBytes) of each language output part.
+    /// Parts are only split between records, so a part can be larger than `part_size`.
+    pub fn new(
+        src: Vec<PathBuf>,
+        dst: PathBuf,
+        lid_path: PathBuf,
+        k: usize,
+        part_size: Option<usize>,
+    ) -> Self {
+        Self {
+            src,
+            dst,
+            lid_path,
+            k,
+            part_size,
+            min_sentence_chars: 100,
+            max_sentence_chars: None,
+            keep_short: false,
+            skip_blank: true,
+            windows: None,
+            normalizer: None,
+            splitter: Box::new(NoSplit),
+            record_filter: None,
+            url_policy: None,
+            text_transform: None,
+            dedup: false,
+            near_dedup: None,
+            lossy_utf8: false,
+            lenient_headers: false,
+            dry_run: false,
+            max_shard_concurrency: None,
+            threads: None,
+            thread_pinning: ThreadPinning::default(),
+            channel_bound: None,
+            record_chunk_size: DEFAULT_RECORD_CHUNK_SIZE,
+            min_confidence: None,
+            min_piece_length: None,
+            max_gap: 0,
+            max_predict_errors: None,
+            error_budget: None,
+            max_record_bytes: None,
+            open_retry: Retry::default(),
+            layout: LayoutStrategy::default(),
+            output_format: OutputFormat::default(),
+            file_naming: FileNaming::default(),
+            log_shard_langs: false,
+            write_shard_langs: false,
+            write_rejects: false,
+            include_source: false,
+            staging: None,
+            write_bom: false,
+            prob_digits: DEFAULT_PROB_DIGITS,
+            predict_timeout: None,
+            lang_thresholds: HashMap::new(),
+            languages: None,
+            extra_labels: HashMap::new(),
+            header_language_bias: None,
+            lang_caps: None,
+            model: None,
+            identifier: None,
+            progress: None,
+            shutdown: None,
+        }
     }
 
-    /// attempt to predict language on provided sentence.
+    /// Get the current status of the shards recorded in the manifest by previous runs.
     ///
-    /// Returns [None] if no language is detected.
-    // why return the sentence itself?
-    // TODO: change return type to Option<&'static str>.
-    fn identify_sentence(sentence: &str, cls: &FastText) -> Option<(String, &'static str)> {
-        let prediction = cls.predict(sentence).ok();
+    /// Returns an empty map if there's no manifest yet.
+    /// Invalid entries (such as a line truncated by a crash) are ignored.
+    fn shard_statuses(&self) -> HashMap<PathBuf, ShardStatus> {
+        let manifest = match File::open(self.dst.join(COMPLETED_SHARDS_FILE)) {
+            Ok(f) => f,
+            Err(_) => return HashMap::new(),
+        };
 
-        if let Some(Some(lang)) = prediction {
-            //TODO: rewrite these two lines more elegantly
-            //      we can unwrap since predict returns None if no predictions are
-            //      found
-            let lang = lang.get(0).unwrap();
+        BufReader::new(manifest)
+            .lines()
+            .filter_map(
+                |line| match line.map(|l| serde_json::from_str::<ManifestEntry>(&l)) {
+                    Ok(Ok(ManifestEntry::Status { shard, status, .. })) => Some((shard, status)),
+                    Ok(Ok(ManifestEntry::Completed(shard))) => Some((shard, ShardStatus::Done)),
+                    Ok(Err(e)) => {
+                        warn!("ignoring invalid shard manifest entry: {:?}", e);
+                        None
+                    }
+                    Err(e) => {
+                        warn!("error reading shard manifest: {:?}", e);
+                        None
+                    }
+                },
+            )
+            .collect()
+    }
 
-            // check if fasttext provided lang exists
-            // return None if not
-            match LANG.get(lang.label.as_str()) {
-                Some(lang) => Some((sentence.to_string(), *lang)),
-                None => {
-                    warn!("lang {} does not exist!", lang.label);
-                    None
-                }
+    /// Get the shards of `dst` whose current status is `status`.
+    fn shards_with_status(&self, status: ShardStatus) -> HashSet<PathBuf> {
+        self.shard_statuses()
+            .into_iter()
+            .filter_map(|(shard, s)| (s == status).then_some(shard))
+            .collect()
+    }
+
+    /// Get the shards that have been completely processed and written by a previous run.
+    ///
+    /// Returns an empty set if there's no manifest yet.
+    /// Invalid entries (such as a line truncated by a crash) are ignored.
+    pub fn completed_shards(&self) -> HashSet<PathBuf> {
+        self.shards_with_status(ShardStatus::Done)
+    }
+
+    /// Get the shards that failed in the last run that processed them, and that have not been completed since.
+    ///
+    /// Shards that could not be listed (and have no path) or that were left unprocessed
+    /// after a shutdown or an exceeded error budget are not recorded, and are processed by the next run.
+    /// As for [OscarMetadata::completed_shards], invalid entries are ignored.
+    pub fn failed_shards(&self) -> HashSet<PathBuf> {
+        self.shards_with_status(ShardStatus::Failed)
+    }
+
+    /// Record `shard` as completed in the manifest.
+    fn mark_completed(manifest: &Mutex<File>, shard: &Path) -> Result<(), Error> {
+        Self::record_status(manifest, shard, ShardStatus::Done, None)
+    }
+
+    /// Record `shard` as failed with `error` in the manifest.
+    fn mark_failed(manifest: &Mutex<File>, shard: &Path, error: &Error) -> Result<(), Error> {
+        Self::record_status(
+            manifest,
+            shard,
+            ShardStatus::Failed,
+            Some(error.to_string()),
+        )
+    }
+
+    /// Append an entry recording the status of `shard` to the manifest.
+    fn record_status(
+        manifest: &Mutex<File>,
+        shard: &Path,
+        status: ShardStatus,
+        error: Option<String>,
+    ) -> Result<(), Error> {
+        let entry = ManifestEntry::Status {
+            shard: shard.to_path_buf(),
+            status,
+            error,
+        };
+        let mut entry = serde_json::to_string(&entry)?;
+        entry.push('\n');
+
+        let mut manifest = manifest.lock().unwrap();
+        manifest.write_all(entry.as_bytes())?;
+        manifest.flush()?;
+        Ok(())
+    }
+
+    /// Override the sentence identification threshold for some languages.
+    ///
+    /// Languages that are not present keep the default threshold.
+    ///
+    /// # Errors
+    /// Returns an error if a label is not in [LANG].
+    pub fn with_lang_thresholds(
+        mut self,
+        lang_thresholds: HashMap<&'static str, f32>,
+    ) -> Result<Self, Error> {
+        FastText::check_lang_thresholds(&lang_thresholds)?;
+        self.lang_thresholds = lang_thresholds;
+        Ok(self)
+    }
+
+    /// Use an already loaded language identification model, in place of loading `lid_path`.
+    ///
+    /// This avoids loading the model again on each run (see [FastText::load_model]).
+    pub fn with_model(mut self, model: FastTextModel) -> Self {
+        self.model = Some(model);
+        self
+    }
+
+    /// Use another language identification backend, in place of [FastText].
+    ///
+    /// `lid_path`, `k`, [OscarMetadata::with_model] and [OscarMetadata::with_lang_thresholds] are then ignored:
+    /// the backend handles its own number of candidates and thresholds.
+    pub fn with_identifier(mut self, identifier: Arc<dyn LanguageIdentifier>) -> Self {
+        self.identifier = Some(identifier);
+        self
+    }
+
+    /// Only process the provided languages.
+    ///
+    /// Predictions of other languages are dropped, and files are only created for the provided languages.
+    /// Defaults to every language of [LANG].
+    ///
+    /// # Errors
+    /// Returns an error if a label is not in [LANG].
+    pub fn with_languages(mut self, languages: HashSet<&'static str>) -> Result<Self, Error> {
+        lang::check_langs(&languages)?;
+        self.languages = Some(languages);
+        Ok(self)
+    }
+
+    /// Route predictions of extra model labels to languages of [LANG].
+    ///
+    /// This is meant for custom models emitting labels that are not in [LANG]:
+    /// sentences of mapped labels are kept as sentences of the target language,
+    /// while labels that are neither in [LANG] nor mapped are still dropped with a warning.
+    /// Labels of [LANG] take precedence over the mapping.
+    ///
+    /// # Errors
+    /// Returns an error if a target label is not in [LANG].
+    pub fn with_extra_labels(
+        mut self,
+        extra_labels: HashMap<String, &'static str>,
+    ) -> Result<Self, Error> {
+        if let Some(target) = extra_labels.values().find(|target| !LANG.contains(*target)) {
+            return Err(Error::UnknownLang(target.to_string()));
+        }
+        self.extra_labels = extra_labels;
+        Ok(self)
+    }
+
+    /// Route predictions of extra model labels to languages of [LANG], reading the mapping from a file
+    /// (see [lang::read_label_mapping] and [OscarMetadata::with_extra_labels]).
+    ///
+    /// # Errors
+    /// Returns an error if the file can't be read, is malformed or maps to an unknown language.
+    pub fn with_extra_labels_file(self, path: &Path) -> Result<Self, Error> {
+        let extra_labels = lang::read_label_mapping(path)?;
+        self.with_extra_labels(extra_labels)
+    }
+
+    /// Bias identification towards the language hinted by record headers, by up to `bias`.
+    ///
+    /// The hint is the first known language of `WARC-Identified-Content-Language` (ISO 639-3 codes),
+    /// or else of the HTTP `Content-Language` (language tags, such as `en-US`), if the record has one.
+    /// When the hinted language is a candidate of a sentence (see [OscarMetadata::new] for the number of candidates)
+    /// whose probability is within `bias` of the most probable candidate, the hinted language is kept instead.
+    /// This is only a tie-break: sentences for which no candidate is reliable enough are still not identified,
+    /// and probabilities are left untouched.
+    /// Records without a known hinted language are processed as usual.
+    /// Defaults to `None`.
+    ///
+    /// # Errors
+    /// Returns an error if `bias` is not within `[0, 1]`.
+    pub fn with_header_language_bias(mut self, bias: Option<f32>) -> Result<Self, Error> {
+        if let Some(bias) = bias {
+            if !(0.0..=1.0).contains(&bias) {
+                return Err(Error::Custom(format!(
+                    "header language bias has to be within [0, 1], got {}",
+                    bias
+                )));
             }
-        } else {
-            None
         }
+        self.header_language_bias = bias;
+        Ok(self)
     }
 
-    /// Process a provided record.
+    /// Set the sentence length bounds, in characters (see [str::chars]).
     ///
-    /// Here, sentences that are >100 chars are processed,
-    /// and the others are discarded.
-    /// See [String::chars::count].
+    /// Sentences are kept if they are strictly longer than `min_sentence_chars`
+    /// and, if `max_sentence_chars` is set, not longer than it.
+    /// Defaults to `100` and `None`.
+    pub fn with_sentence_chars(
+        mut self,
+        min_sentence_chars: usize,
+        max_sentence_chars: Option<usize>,
+    ) -> Self {
+        self.min_sentence_chars = min_sentence_chars;
+        self.max_sentence_chars = max_sentence_chars;
+        self
+    }
+
+    /// Keep sentences that are too short (see [OscarMetadata::with_sentence_chars])
+    /// in a separate `<lang>_short.txt` file per language, rather than discarding them.
     ///
-    /// Then, we identify language for each sentence
-    /// and return (sentence, language) along with headers
-    /// extracted from the WARC.
-    fn process_record(
-        record: Record<BufferedBody>,
-        cls: &FastText,
-    ) -> Option<(Vec<(String, &'static str)>, WarcHeaders)> {
-        if log_enabled!(Debug) {
-            debug!("processing record {}", record.warc_id());
-        };
-        let body = String::from_utf8(record.body().to_vec()).ok();
+    /// Short sentences are identified like the others (and are restricted to [OscarMetadata::with_languages]),
+    /// but they aren't part of pieces, metadata or statistics, so the corpus itself is unchanged.
+    /// In particular, their identification errors are logged but don't count towards
+    /// [OscarMetadata::with_max_predict_errors] or [OscarMetadata::with_max_error_rate].
+    /// They're written once their shard is processed, one block per record.
+    /// Defaults to `false`.
+    pub fn with_keep_short(mut self, keep_short: bool) -> Self {
+        self.keep_short = keep_short;
+        self
+    }
 
-        // process record if body is utf8-valid
-        if let Some(sentences) = body {
-            // filter out lines that does not contain 100 characters.
-            // then convert into a parallel iterator
-            let sentences = sentences
-                .lines()
-                .filter(|line| line.chars().count() > 100)
-                .par_bridge();
+    /// Skip sentences that are blank once normalized (see [OscarMetadata::is_blank]), before the length filter.
+    ///
+    /// Blank sentences are counted apart from sentences discarded by the length filter
+    /// (see [RunStats::blank_sentences]), and are never kept as short sentences.
+    /// When disabled, blank sentences only go through the length filter:
+    /// long runs of padding characters are then identified.
+    /// Defaults to `true`.
+    pub fn with_skip_blank(mut self, skip_blank: bool) -> Self {
+        self.skip_blank = skip_blank;
+        self
+    }
 
-            let results: Vec<(String, &'static str)> = sentences
-                // predict for each sentence, discarding
-                // predictions that does not meet threshold
-                .filter_map(|sentence| Self::identify_sentence(sentence, cls))
-                .collect();
+    /// Identify sentences that are longer than `threshold` characters over sliding windows
+    /// of `window_chars` characters, overlapping by `overlap_chars` characters.
+    ///
+    /// Each window is identified separately, and adjacent windows of the same language are stitched back,
+    /// so that a long line mixing languages yields one segment per language run (see [OscarMetadata::identify_segments]).
+    /// Segments are then merged into pieces like ordinary sentences, keeping the line number of their sentence.
+    /// Shorter sentences are identified as a whole.
+    /// Disabled by default.
+    ///
+    /// # Errors
+    /// Returns an error if `window_chars` is 0 or if `overlap_chars` is not smaller than `window_chars`.
+    pub fn with_windowed_identification(
+        mut self,
+        threshold: usize,
+        window_chars: usize,
+        overlap_chars: usize,
+    ) -> Result<Self, Error> {
+        self.windows = Some(SlidingWindows::new(threshold, window_chars, overlap_chars)?);
+        Ok(self)
+    }
 
-            Some((results, record.into_raw_parts().0.headers))
-        } else {
-            error!("body not UTF-8 valid: {:?}", record.warc_id());
-            None
+    /// Drop merged pieces that are shorter than `min_length`.
+    ///
+    /// Since sentences are merged into same-language pieces, a piece can consist of a single sentence
+    /// that barely passes [OscarMetadata::with_sentence_chars], which is mostly noise.
+    /// By default, every piece is kept.
+    pub fn with_min_piece_length(mut self, min_length: ContentLength) -> Self {
+        self.min_piece_length = Some(min_length);
+        self
+    }
+
+    /// Bridge gaps of at most `max_gap` dropped lines between contiguous same-language sentences of a record
+    /// (see [super::types::Document::with_gap_bridging]).
+    ///
+    /// A paragraph split by a short line in its middle then has a single line range spanning that line
+    /// (see [MergedPiece::line_ranges]), so that it is rebuilt as a whole, short line included.
+    /// Only lines whose sentences have all been discarded for being too short (see [OscarMetadata::with_sentence_chars])
+    /// are bridged: lines that are blank (see [OscarMetadata::with_skip_blank]), too long or unreliably identified
+    /// (as those of another language) break ranges, as do sentences removed by deduplication.
+    /// Defaults to `0`, not bridging any gap.
+    pub fn with_gap_bridging(mut self, max_gap: usize) -> Self {
+        self.max_gap = max_gap;
+        self
+    }
+
+    /// Only process records whose headers match `record_filter` (such as records of a domain or of a date range).
+    ///
+    /// The filter is applied before the record body is decoded and identified.
+    /// Filtered records are counted in [RunStats::filtered_records].
+    /// Defaults to `None`, processing every record.
+    pub fn with_record_filter(mut self, record_filter: Option<RecordFilter>) -> Self {
+        self.record_filter = record_filter;
+        self
+    }
+
+    /// Rewrite the URL of records (such as stripping query strings) before it is written in metadata
+    /// (see [UrlPolicy::rewrite_headers]).
+    ///
+    /// URLs are rewritten after the record filter is applied (see [OscarMetadata::with_record_filter]),
+    /// so filters see original URLs. Both the `warc-target-uri` header and the source of paragraphs
+    /// (see [OscarMetadata::with_source]) hold the rewritten URL.
+    /// Defaults to `None`, keeping URLs as is.
+    pub fn with_url_policy(mut self, url_policy: Option<Arc<dyn UrlPolicy>>) -> Self {
+        self.url_policy = url_policy;
+        self
+    }
+
+    /// Set the normalizer applied to each sentence before length filtering and identification.
+    ///
+    /// Sentences are written normalized, so that lengths and offsets are consistent with the output.
+    /// Defaults to `None`, keeping sentences as is.
+    /// Use [Whitespace](crate::filtering::normalizer::Whitespace) to collapse whitespace and remove invisible characters.
+    pub fn with_normalizer(mut self, normalizer: Option<Box<dyn Normalizer>>) -> Self {
+        self.normalizer = normalizer;
+        self
+    }
+
+    /// Set the splitter of (normalized) lines into sentences, applied before length filtering and identification.
+    ///
+    /// Each sentence is then processed on its own, but keeps the line number of its line:
+    /// [MergedPiece::line_ranges] of a split line expand to its line number once per kept sentence
+    /// (see [super::types::line_numbers]), as for segments of windowed identification
+    /// (see [OscarMetadata::with_windowed_identification]).
+    /// Rejected and short sentences are reported with the line number of their line too.
+    /// Defaults to [NoSplit], processing each line as a single sentence.
+    pub fn with_sentence_splitter(mut self, splitter: Box<dyn SentenceSplitter>) -> Self {
+        self.splitter = splitter;
+        self
+    }
+
+    /// Set the transform applied to each sentence before writing (see [TextTransform]).
+    ///
+    /// Unlike normalization (see [OscarMetadata::with_normalizer]), the transform doesn't affect identification
+    /// nor filtering: it's applied on identified sentences, right before they're merged into pieces.
+    /// Line ranges of pieces (see [MergedPiece::line_ranges]) and offsets of metadata refer to lines,
+    /// so transforms have to keep line structure (see [TextTransform::preserves_line_structure]).
+    /// Defaults to `None`.
+    ///
+    /// # Errors
+    /// Returns an error if `text_transform` may add or remove line breaks.
+    pub fn with_text_transform(
+        mut self,
+        text_transform: Option<TextTransform>,
+    ) -> Result<Self, Error> {
+        if let Some(transform) = text_transform {
+            if !transform.preserves_line_structure() {
+                return Err(Error::Custom(format!(
+                    "text transform {:?} doesn't keep line structure, which would desync line ranges and offsets",
+                    transform
+                )));
+            }
         }
+        self.text_transform = text_transform;
+        Ok(self)
     }
-}
 
-impl Pipeline<()> for OscarMetadata {
-    fn version() -> &'static str {
-        "1.1.0"
+    /// Drop merged pieces whose confidence is below `min_confidence`.
+    ///
+    /// A piece's confidence is the length-weighted mean of its sentence probabilities (see [MergedPiece::confidence]).
+    /// By default, every piece is kept.
+    pub fn with_min_confidence(mut self, min_confidence: f32) -> Self {
+        self.min_confidence = Some(min_confidence);
+        self
     }
 
-    /// Run the whole pipeline
-    fn run(&self) -> Result<(), Error> {
-        // let errors;
-
-        let cls = FastText::new(&self.lid_path, 1, 0.8)?;
-
-        // list files in source folder,
-        // filter out errors from fs and from gzip/wet.
-        // This means that invalid gz files and invalid
-        // wet files are discarded silently
-        let results = std::fs::read_dir(&self.src)?
-            .filter_map(|shard| {
-                shard.map_or_else(
-                    |e| {
-                        error!("error reading shard directory: {}", e);
-                        None
-                    },
-                    Some,
-                )
-            })
-            .map(|shard| shard.path());
+    /// Enable or disable shard-level exact deduplication of sentences.
+    ///
+    /// When enabled, sentences that already appeared in the same shard are removed, keeping the first occurrence.
+    /// Defaults to `false`.
+    pub fn with_dedup(mut self, dedup: bool) -> Self {
+        self.dedup = dedup;
+        self
+    }
 
-        // convert to parallel iterator
-        // /!\: We use par_bridge, that is suboptimal
-        //      compared to implementing IntoParallelIterator
-        //      ourselves.
-        let results = results.enumerate().par_bridge();
+    /// Enable shard-level near-duplicate filtering of sentences (see [NearDuplicates]).
+    ///
+    /// Sentences are compared using shingles of `shingle_size` words, and sentences whose similarity
+    /// to an earlier kept sentence of the same shard is at least `threshold` are removed.
+    /// This is applied after exact deduplication (see [OscarMetadata::with_dedup]), and is disabled by default.
+    ///
+    /// # Errors
+    /// Returns an error if `shingle_size` is 0 or if `threshold` is not in `(0, 1]`.
+    pub fn with_near_dedup(mut self, shingle_size: usize, threshold: f32) -> Result<Self, Error> {
+        NearDuplicates::new(shingle_size, threshold)?;
+        self.near_dedup = Some((shingle_size, threshold));
+        Ok(self)
+    }
 
-        // holds file handles
-        // let langfiles = match self.part_size {
-        //     Some(ps) => LangFiles::new(&self.dst, Some(ps * 1_000_000))?,
-        //     None => LangFiles::new(&self.dst, None)?,
-        // };
+    /// Enable or disable lossy UTF-8 decoding of record bodies.
+    ///
+    /// When enabled, invalid sequences are replaced by `U+FFFD` (see [String::from_utf8_lossy])
+    /// instead of discarding the whole record.
+    /// Defaults to `false`.
+    pub fn with_lossy_utf8(mut self, lossy_utf8: bool) -> Self {
+        self.lossy_utf8 = lossy_utf8;
+        self
+    }
 
-        let langfiles = LangFiles::new(&self.dst, None)?;
+    /// Enable or disable lenient parsing of record headers (see [Wet::with_lenient_headers]).
+    ///
+    /// When enabled, records with minor header malformations are repaired and processed
+    /// instead of failing their shard.
+    /// Defaults to `false`.
+    pub fn with_lenient_headers(mut self, lenient_headers: bool) -> Self {
+        self.lenient_headers = lenient_headers;
+        self
+    }
 
-        // iterate over shards
-        let r: Vec<Error> = results
-            .filter_map(|(idx, shard)| {
-                // holds merged pieces by lang
-                let mut lang_pieces: HashMap<&'static str, Vec<MergedPiece>> = HashMap::new();
+    /// Set the layout of output files in `dst`.
+    ///
+    /// Defaults to [LayoutStrategy::Flat].
+    pub fn with_layout(mut self, layout: LayoutStrategy) -> Self {
+        self.layout = layout;
+        self
+    }
 
-                // get an atomic reference to global offsets
-                // let offsets_global_arc = offsets_global.clone();
-                info!("processing shard {}: {:?}", idx, &shard);
+    /// Set the naming of text and metadata files (such as `oscar_<lang>.raw`).
+    ///
+    /// Defaults to `<lang>.txt` and `<lang>_meta.jsonl` (see [FileNaming]).
+    pub fn with_file_naming(mut self, file_naming: FileNaming) -> Self {
+        self.file_naming = file_naming;
+        self
+    }
 
-                let shard = Wet::from_path_gzip(&shard);
+    /// Set the format of output files.
+    ///
+    /// With [OutputFormat::Combined], every language is written in a single file,
+    /// shards being written one language at a time.
+    /// Defaults to [OutputFormat::TextMeta].
+    pub fn with_output_format(mut self, output_format: OutputFormat) -> Self {
+        self.output_format = output_format;
+        self
+    }
 
-                if shard.is_err() {
-                    error!("Could not read/open shard {}", idx);
-                    return shard.err();
-                }
+    /// Give up on predictions that take longer than `timeout` (see [identifiers::Timeout]),
+    /// so that a pathological sentence can't block a thread indefinitely.
+    ///
+    /// Sentences whose prediction times out are handled like other identification errors:
+    /// they're skipped and logged with the id of their record, counted in [RunStats::predict_errors],
+    /// and their record counts as failed (see [OscarMetadata::with_max_error_rate]).
+    /// Each timed out prediction leaks a thread: once [identifiers::Timeout::DEFAULT_MAX_ABANDONED] predictions
+    /// have timed out, every prediction fails.
+    /// Timeouts apply to each prediction, that is to each sentence (or window, see [OscarMetadata::with_windowed_identification]).
+    /// Defaults to `None`.
+    pub fn with_predict_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.predict_timeout = timeout;
+        self
+    }
 
-                let shard = shard.unwrap();
-                // convert into a parallel iterator
-                let wetfile = shard.iter.enumerate().par_bridge();
+    /// Write a UTF-8 byte order mark at the start of each text file (see [LangFiles::with_bom]).
+    ///
+    /// Files that a resumed run appends to keep their first line as is.
+    /// Defaults to `false`.
+    pub fn with_bom(mut self, write_bom: bool) -> Self {
+        self.write_bom = write_bom;
+        self
+    }
 
-                let shard_results: Vec<(Vec<(String, &'static str)>, WarcHeaders)> = wetfile
-                    .filter_map(|(idx_record, record)| match record {
-                        Ok(record) => OscarMetadata::process_record(record, &cls),
-                        Err(e) => {
-                            warn!("Error on record {} of shard {}: {:?}", idx_record, idx, e);
-                            None
-                        }
-                    })
-                    // collect here is blocking
-                    // because we can't write concurrently into a HashMap
-                    // and using Mutexes might ruin performance.
-                    .collect(); //TODO: test with a for_each and a channel to send?
-
-                // Iterate over (record, header) tuples
-                let shard_results = shard_results.into_iter().filter_map(|(record, header)| {
-                    // split between langs and sentences
-                    let langs: Vec<&str> = record.iter().map(|(_, lang)| *lang).collect();
-                    let sentences: Vec<String> =
-                        record.into_iter().map(|(sentences, _)| sentences).collect();
-
-                    // create new document for current record
-                    let doc = Document::new(header, sentences, langs);
-
-                    match doc {
-                        Ok(doc) => Some(doc),
-                        Err(e) => {
-                            warn!("{:?}", e);
-                            None
-                        }
-                    }
-                });
-
-                // merge all documents together
-                // get a vector of merged pieces of difference languages
-                let docs_merged = shard_results
-                    .map(|doc| doc.into_merged_pieces_lang())
-                    .flatten()
-                    .collect::<Vec<MergedPiece>>();
-
-                // sort merged pieces into different langs
-                // now there's a hashmap that points each lang
-                // to a vector of merged pieces
-                for piece in docs_merged {
-                    let e = lang_pieces
-                        .entry(piece.identification())
-                        .or_insert_with(Vec::new);
-                    e.push(piece);
-                }
+    /// Set the number of significant digits of probabilities written in JSON Lines and metadata files
+    /// (see [LangFiles::with_prob_digits]).
+    ///
+    /// Defaults to [DEFAULT_PROB_DIGITS].
+    pub fn with_prob_digits(mut self, digits: usize) -> Self {
+        self.prob_digits = digits;
+        self
+    }
 
-                // write concurrently
-                lang_pieces.into_par_iter().for_each(|(lang, pieces)| {
-                    let writer = langfiles.writers().get(lang).unwrap();
-                    let mut writer_lock = writer.lock().unwrap();
-                    writer_lock.write(pieces).unwrap();
-                });
+    /// Report progress to `progress` (see [crate::pipelines::progress]).
+    ///
+    /// Shards are reported once they start and once they're done (including failed ones),
+    /// and records are reported as they're read.
+    /// Shards that were completed by a previous run are not reported.
+    /// Defaults to `None`.
+    pub fn with_progress(mut self, progress: Option<Arc<dyn ProgressObserver>>) -> Self {
+        self.progress = progress;
+        self
+    }
 
-                None
-            })
-            .collect();
+    /// Stop writing a language once it reaches its cap (see [LangFiles::admit]).
+    ///
+    /// Further pieces of the language are dropped, and counted in [RunStats::capped_pieces]
+    /// rather than in its language statistics. Caps are not enforced in dry runs,
+    /// and they are counted from the start of each run.
+    /// Defaults to `None`.
+    pub fn with_lang_caps(mut self, caps: Option<LangCaps>) -> Self {
+        self.lang_caps = caps;
+        self
+    }
+
+    /// Stop the run once `shutdown` is requested (see [crate::pipelines::shutdown]).
+    ///
+    /// No new shard is picked up, but shards that are being processed are finished,
+    /// and writers are closed as usual, so that an interrupted run yields a valid partial corpus.
+    /// Unprocessed shards are not marked as completed, and are processed by the next run.
+    /// Use [Shutdown::on_signals] to stop on `SIGINT`/`SIGTERM`
+    /// (the CLI doesn't, since it runs [crate::pipelines::OscarDoc]).
+    /// Defaults to `None`.
+    pub fn with_shutdown(mut self, shutdown: Option<Shutdown>) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
+
+    /// Enable or disable dry run mode.
+    ///
+    /// In dry run mode, records are identified and merged as usual,
+    /// but nothing is written: no file nor folder is created in `dst`
+    /// and a projected output summary is logged once every shard is processed.
+    /// Completed shards (see [OscarMetadata::completed_shards]) are still skipped.
+    /// Defaults to `false`.
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Process at most `max_shard_concurrency` shards at once.
+    ///
+    /// Shards are then iterated in a dedicated thread pool of that size,
+    /// while records of each shard are still processed in parallel using a separate, default-sized pool.
+    ///
+    /// Every shard being processed holds its whole content in memory before writing,
+    /// so memory and file handle usage grow with the number of concurrent shards.
+    /// A lower concurrency bounds them at the cost of idle threads when a shard's records are scarce.
+    /// By default, shards are iterated in the global rayon pool (one shard per available thread).
+    pub fn with_shard_concurrency(mut self, max_shard_concurrency: usize) -> Self {
+        self.max_shard_concurrency = Some(max_shard_concurrency);
+        self
+    }
+
+    /// Process shards and records in a dedicated pool of `nb_threads` threads, rather than in the global rayon pool.
+    ///
+    /// With [OscarMetadata::with_shard_concurrency], shards are still iterated in their own pool,
+    /// and records are processed in this one.
+    /// Writer threads (see [OscarMetadata::with_channel_writers]) are not part of the pool.
+    /// The run fails to start if `nb_threads` is 0.
+    pub fn with_threads(mut self, nb_threads: usize) -> Self {
+        self.threads = Some(nb_threads);
+        self
+    }
+
+    /// Pin the threads of dedicated pools (see [OscarMetadata::with_threads] and [OscarMetadata::with_shard_concurrency])
+    /// to CPU cores, following `pinning`.
+    ///
+    /// The global rayon pool is never pinned.
+    /// Defaults to [ThreadPinning::None].
+    pub fn with_thread_pinning(mut self, pinning: ThreadPinning) -> Self {
+        self.thread_pinning = pinning;
+        self
+    }
 
-        // fix trailing comma
-        // langfiles.close_meta()?;
+    /// Write using a dedicated thread per language, fed by channels of `bound` pending records.
+    ///
+    /// Records are then written as soon as they're processed rather than once their whole shard is,
+    /// which overlaps identification with writing and bounds memory usage.
+    /// Pieces are written in processing order rather than in shard order.
+    /// Offsets in metadata stay consistent as they're computed at write time.
+    ///
+    /// Not compatible with deduplication (see [OscarMetadata::with_dedup] and [OscarMetadata::with_near_dedup]),
+    /// that needs whole shards.
+    /// Ignored in dry run mode.
+    pub fn with_channel_writers(mut self, bound: usize) -> Self {
+        self.channel_bound = Some(bound);
+        self
+    }
 
-        for err in r {
-            error!("{:?}", err);
+    /// Process records of each shard in chunks of `chunk_size` records.
+    ///
+    /// Records are read sequentially, and each chunk is processed by a single rayon task,
+    /// which amortizes per-task overhead on shards with many small records.
+    /// Sentences of each record are still identified in parallel, so large records are split further.
+    /// Larger chunks lower overhead but may leave threads idle at the end of a shard.
+    /// Defaults to 8 records.
+    ///
+    /// # Errors
+    /// Returns an error if `chunk_size` is 0.
+    pub fn with_record_chunk_size(mut self, chunk_size: usize) -> Result<Self, Error> {
+        if chunk_size == 0 {
+            return Err(Error::Custom(
+                "record chunk size must be positive".to_string(),
+            ));
         }
+        self.record_chunk_size = chunk_size;
+        Ok(self)
+    }
 
-        Ok(())
+    /// Fail a shard once more than `max` of its sentences could not be identified.
+    ///
+    /// Identification errors (e.g. a corrupt model) are always logged and counted (see [RunStats::predict_errors]),
+    /// and the failing sentences are dropped.
+    /// Errors on short sentences kept with [OscarMetadata::with_keep_short] are not counted.
+    /// A failed shard is not marked as completed, but with [OscarMetadata::with_channel_writers]
+    /// some of its pieces may already have been written.
+    /// By default, there is no limit.
+    pub fn with_max_predict_errors(mut self, max: usize) -> Self {
+        self.max_predict_errors = Some(max);
+        self
     }
-}
 
-#[cfg(test)]
-mod tests {
+    /// Abort the run once more than `max_rate` of the records of a shard, or of the whole run, failed.
+    ///
+    /// A record fails if its body is not UTF-8 valid (see [OscarMetadata::with_lossy_utf8])
+    /// or if the identification of one of its sentences failed (see [OscarMetadata::with_max_predict_errors]).
+    /// Rates are only checked once at least `min_records` records have been processed, so that a few
+    /// failing records at the start of a shard don't abort the run.
+    ///
+    /// Rates are checked after each record: a shard that exceeds its budget fails right away,
+    /// no new shard is picked up, and the run returns an error once writers are closed.
+    /// This stops runs that would otherwise produce an empty corpus (e.g. with a wrong model) early.
+    /// By default, there is no budget.
+    ///
+    /// # Errors
+    /// Returns an error if `max_rate` is not within `[0, 1]`.
+    pub fn with_max_error_rate(mut self, max_rate: f32, min_records: usize) -> Result<Self, Error> {
+        if !(0.0..=1.0).contains(&max_rate) {
+            return Err(Error::Custom(format!(
+                "max error rate has to be within [0, 1], got {}",
+                max_rate
+            )));
+        }
+        self.error_budget = Some(ErrorBudget {
+            max_rate,
+            min_records,
+        });
+        Ok(self)
+    }
 
-    use warc::{EmptyBody, Record};
+    /// Skip records whose body is larger than `max` bytes.
+    ///
+    /// Bodies are decoded into owned strings and split into sentences, so a pathological record can use a lot of memory.
+    /// The body of a record has already been read by the WARC reader when it's checked:
+    /// the check only avoids decoding and identifying it. Skipped records are logged
+    /// and counted in [RunStats::oversized_records].
+    /// By default, there is no limit.
+    pub fn with_max_record_bytes(mut self, max: usize) -> Self {
+        self.max_record_bytes = Some(max);
+        self
+    }
 
-    use crate::identifiers::FastText;
+    /// Retry opening shards that fail with a transient I/O error, making at most `attempts` attempts.
+    ///
+    /// Retries wait `base_delay`, then twice as long after each failed attempt.
+    /// Permanent errors (such as a missing file) are not retried (see [Retry::is_transient]).
+    /// Shards that still can't be opened fail as usual.
+    /// By default, shards are opened once.
+    pub fn with_open_retries(mut self, attempts: usize, base_delay: Duration) -> Self {
+        self.open_retry = Retry::new(attempts, base_delay);
+        self
+    }
 
-    use super::OscarMetadata;
-    #[test]
-    fn test_process_record() {
-        let cls = FastText::new_lid().unwrap();
+    /// Enable per-shard language distribution reporting (number of merged pieces per language).
+    ///
+    /// - `log`: log distributions at info level,
+    /// - `write`: append distributions to `shard_langs.tsv` in `dst`,
+    ///   as `shard index\tshard path\tlanguage\tcount` lines.
+    ///   Shards without any piece get a single line with an empty language and a zero count.
+    ///
+    /// Both default to `false`.
+    pub fn with_shard_langs(mut self, log: bool, write: bool) -> Self {
+        self.log_shard_langs = log;
+        self.write_shard_langs = write;
+        self
+    }
 
-        // let oscar_metadata =
-        //     OscarMetadata::new(temp_dir(), temp_dir(), PathBuf::from("lid.176.bin"));
+    /// Enable or disable writing rejected sentences to `rejects.jsonl` in `dst`, for threshold tuning.
+    ///
+    /// Sentences discarded by the length filter and sentences whose predictions are all below
+    /// the identification thresholds are appended as JSON objects holding their record id,
+    /// line number, sentence, rejection reason (`too_short`, `too_long` or `below_threshold`)
+    /// and, for threshold rejects, their best prediction (see [LanguageIdentifier::predict_best]).
+    /// Blank sentences (see [OscarMetadata::with_skip_blank]) and sentences of languages
+    /// that are not processed (see [OscarMetadata::with_languages]) are not considered rejected.
+    ///
+    /// Rejects are only written by runs (and not in dry run mode).
+    /// Threshold rejects cost an additional prediction, so this should not be enabled in production.
+    /// Defaults to `false`.
+    pub fn with_rejects(mut self, write_rejects: bool) -> Self {
+        self.write_rejects = write_rejects;
+        self
+    }
 
-        let record: Record<EmptyBody> = Record::default();
-        let body = "english test that is longer than one hundred characters. english test that is longer than one hundred characters.
-phrase française de plus de cent caractères. Ceci est une phrase française de plus de cent caractères.";
-        println!("{}", body.len());
-        let record = record.add_body(body);
-        let (identifications, _) = OscarMetadata::process_record(record, &cls).unwrap();
+    /// Enable or disable adding the source of each piece to its metadata (see [Source]).
+    ///
+    /// The source holds the `WARC-Target-URI` and `WARC-Date` headers of the originating record,
+    /// as `url` and `date`, that are `null` if the record lacks them.
+    /// Defaults to `false`, leaving the `source` field out of metadata.
+    pub fn with_source(mut self, include_source: bool) -> Self {
+        self.include_source = include_source;
+        self
+    }
 
-        for (sentence, id) in identifications {
-            if id == "en" {
-                assert_eq!(sentence, "english test that is longer than one hundred characters. english test that is longer than one hundred characters.");
-            } else if id == "fr" {
-                assert_eq!(sentence, "phrase française de plus de cent caractères. Ceci est une phrase française de plus de cent caractères.");
-            }
+    /// Enable or disable staging of language files (see [LangFiles::with_staging]).
+    ///
+    /// Language files are written in `dir` (defaulting to [crate::io::STAGING_DIR] in `dst`),
+    /// and are moved into `dst` once the run completes, so that readers never see partial files.
+    /// The manifest and other reports are written in `dst` directly.
+    ///
+    /// Since staged files can't be appended to existing ones, runs fail if some shards
+    /// have already been completed (see [OscarMetadata::completed_shards]).
+    /// Defaults to `false`.
+    pub fn with_staging(mut self, staging: bool, dir: Option<PathBuf>) -> Self {
+        self.staging = staging.then_some(dir);
+        self
+    }
+
+    /// Sort per-language merged piece counts by language.
+    fn shard_langs(counts: &HashMap<&'static str, usize>) -> Vec<(&'static str, usize)> {
+        let mut counts: Vec<(&'static str, usize)> =
+            counts.iter().map(|(lang, count)| (*lang, *count)).collect();
+        counts.sort_unstable();
+        counts
+    }
+
+    /// Format a shard language distribution as tsv lines.
+    fn shard_langs_tsv(idx: usize, shard: &Path, counts: &[(&'static str, usize)]) -> String {
+        if counts.is_empty() {
+            return format!("{}\t{}\t\t0\n", idx, shard.display());
         }
+
+        counts
+            .iter()
+            .map(|(lang, count)| format!("{}\t{}\t{}\t{}\n", idx, shard.display(), lang, count))
+            .collect()
+    }
+
+    /// List shards of each source, in `src` order: files of a source if it's a directory, or the source itself if it's a file.
+    ///
+    /// Shards are enumerated across sources, so that shard indices (and thus shard ids of pieces) are unique
+    /// in the output.
+    /// Directory entries that can't be read are kept as errors
+    /// so that they're accounted for as failed shards.
+    ///
+    /// # Errors
+    /// Returns an error if a source directory can't be read.
+    pub(super) fn shard_paths(
+        &self,
+    ) -> Result<impl Iterator<Item = std::io::Result<PathBuf>>, Error> {
+        let sources = self
+            .src
+            .iter()
+            .map(|src| {
+                if src.is_file() {
+                    return Ok(Either::Left(std::iter::once(Ok(src.clone()))));
+                }
+                let shards = std::fs::read_dir(src)?.map(|shard| shard.map(|shard| shard.path()));
+                Ok(Either::Right(shards))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        Ok(sources.into_iter().flatten())
+    }
+
+    /// Remove sentences that were already seen in the provided records (ordered by their position in the shard),
+    /// keeping the first occurrence. Records left without sentences are removed.
+    fn dedup_sentences(records: &mut Vec<(usize, ProcessedRecord)>) {
+        let mut seen: HashSet<u64> = HashSet::new();
+        for (_, (sentences, _, _)) in records.iter_mut() {
+            sentences.retain(|(sentence, _, _, _)| {
+                let mut hasher = XxHash64::default();
+                sentence.hash(&mut hasher);
+                seen.insert(hasher.finish())
+            });
+        }
+        records.retain(|(_, (sentences, _, _))| !sentences.is_empty());
+    }
+
+    /// Remove sentences that are near-duplicates of earlier sentences of the provided records
+    /// (ordered by their position in the shard), as detected by `filter`. Records left without sentences are removed.
+    fn near_dedup_sentences(
+        records: &mut Vec<(usize, ProcessedRecord)>,
+        filter: &mut NearDuplicates,
+    ) {
+        for (_, (sentences, _, _)) in records.iter_mut() {
+            sentences.retain(|(sentence, _, _, _)| filter.detect_mut(sentence));
+        }
+        records.retain(|(_, (sentences, _, _))| !sentences.is_empty());
+    }
+
+    /// Decode a record body, replacing invalid sequences if `lossy` is set.
+    ///
+    /// Returns [None] if the body is not valid UTF-8 and `lossy` is not set.
+    fn decode_body(body: &[u8], lossy: bool) -> Option<String> {
+        match std::str::from_utf8(body) {
+            Ok(body) => Some(body.to_string()),
+            Err(_) if lossy => {
+                let nb_replaced = body
+                    .utf8_chunks()
+                    .filter(|chunk| !chunk.invalid().is_empty())
+                    .count();
+                debug!("replaced {} invalid UTF-8 sequences", nb_replaced);
+                Some(String::from_utf8_lossy(body).into_owned())
+            }
+            Err(_) => None,
+        }
+    }
+
+    /// Check if a sentence is blank: empty once trimmed (see [str::trim]),
+    /// zero-width spaces, word joiners and byte order marks being treated as whitespace.
+    fn is_blank(sentence: &str) -> bool {
+        sentence
+            .chars()
+            .all(|c| c.is_whitespace() || matches!(c, '\u{200B}' | '\u{2060}' | '\u{FEFF}'))
+    }
+
+    /// Check if a sentence length is within the configured bounds.
+    fn keep_sentence(&self, sentence: &str) -> bool {
+        let nb_chars = sentence.chars().count();
+        nb_chars > self.min_sentence_chars
+            && self.max_sentence_chars.is_none_or(|max| nb_chars <= max)
+    }
+
+    /// Get the language of a predicted label, looking into [LANG] (see [lang::label_from_model])
+    /// then into extra labels (see [OscarMetadata::with_extra_labels]).
+    ///
+    /// Labels are trimmed before looking into extra labels too.
+    fn resolve_label(&self, label: &str) -> Option<&'static str> {
+        lang::label_from_model(label).or_else(|| {
+            self.extra_labels
+                .get(label)
+                .or_else(|| self.extra_labels.get(label.trim()))
+                .copied()
+        })
+    }
+
+    /// attempt to predict language on provided sentence.
+    ///
+    /// Returns up to [FastText::k] `(sentence, language, probability)` candidates,
+    /// ordered by decreasing probability.
+    /// Candidates are already filtered by the identifier (e.g. [FastText] uses per-language thresholds),
+    /// and candidates of languages that are not processed (see [OscarMetadata::with_languages]) are dropped.
+    /// The returned vector is empty if no language is detected.
+    ///
+    /// # Errors
+    /// Returns the identifier error if the prediction failed.
+    // why return the sentence itself?
+    fn identify_sentence(
+        &self,
+        sentence: &str,
+        cls: &dyn LanguageIdentifier,
+    ) -> Result<Vec<(String, &'static str, f32)>, Error> {
+        self.identify_sentence_with_reason(sentence, cls)
+            .map(|(candidates, _)| candidates)
+    }
+
+    /// attempt to predict language on provided sentence, as [OscarMetadata::identify_sentence] does.
+    ///
+    /// If no candidate is left, also returns why (see [DiscardReason]): no prediction met the thresholds,
+    /// predicted labels are unknown, or predicted languages are not processed.
+    ///
+    /// # Errors
+    /// Returns the identifier error if the prediction failed.
+    fn identify_sentence_with_reason(
+        &self,
+        sentence: &str,
+        cls: &dyn LanguageIdentifier,
+    ) -> Result<(Vec<Candidate>, Option<DiscardReason>), Error> {
+        let predictions = match cls.predict(sentence)? {
+            Some(predictions) => predictions,
+            None => return Ok((Vec::new(), Some(DiscardReason::BelowThreshold))),
+        };
+        let nb_predictions = predictions.len();
+
+        let known: Vec<_> = predictions
+            .into_iter()
+            // check if fasttext provided lang exists
+            // discard it if not
+            .filter_map(|prediction| match self.resolve_label(&prediction.label) {
+                Some(lang) => Some((sentence.to_string(), lang, prediction.prob)),
+                None => {
+                    warn!("lang {} does not exist!", prediction.label);
+                    None
+                }
+            })
+            .collect();
+        let nb_known = known.len();
+
+        let candidates: Vec<_> = known
+            .into_iter()
+            .filter(|(_, lang, _)| {
+                self.languages
+                    .as_ref()
+                    .is_none_or(|languages| languages.contains(lang))
+            })
+            .collect();
+
+        let reason = match (nb_predictions, nb_known, candidates.len()) {
+            (_, _, 1..) => None,
+            (0, _, _) => Some(DiscardReason::BelowThreshold),
+            (_, 0, _) => Some(DiscardReason::UnknownLabel),
+            _ => Some(DiscardReason::UnprocessedLanguage),
+        };
+        Ok((candidates, reason))
+    }
+
+    /// Get the language hinted by record headers (see [OscarMetadata::with_header_language_bias]),
+    /// if the bias is enabled.
+    fn language_hint(&self, headers: &WarcHeaders) -> Option<&'static str> {
+        self.header_language_bias?;
+
+        let header = |name: &str| {
+            headers
+                .get(&WarcHeader::Unknown(name.to_string()))
+                .map(|value| String::from_utf8_lossy(value).into_owned())
+        };
+        let identified = header("warc-identified-content-language").and_then(|codes| {
+            codes
+                .split(',')
+                .find_map(|code| lang::label_from_iso639_3(code.trim()))
+        });
+        identified.or_else(|| {
+            header("content-language").and_then(|tags| {
+                tags.split(',').find_map(|tag| {
+                    let primary = tag.trim().split('-').next()?.to_lowercase();
+                    LANG.get(primary.as_str())
+                        .copied()
+                        .or_else(|| lang::label_from_iso639_3(&primary))
+                })
+            })
+        })
+    }
+
+    /// Keep the `hint` candidate first, if its probability is within the header language bias of the first one
+    /// (see [OscarMetadata::with_header_language_bias]).
+    fn bias_candidates(
+        &self,
+        mut candidates: Vec<(String, &'static str, f32)>,
+        hint: Option<&'static str>,
+    ) -> Vec<(String, &'static str, f32)> {
+        let (bias, hint) = match (self.header_language_bias, hint) {
+            (Some(bias), Some(hint)) => (bias, hint),
+            _ => return candidates,
+        };
+        let best = match candidates.first() {
+            Some((_, lang, prob)) if *lang != hint => *prob,
+            _ => return candidates,
+        };
+        if let Some(pos) = candidates.iter().position(|(_, lang, _)| *lang == hint) {
+            if candidates[pos].2 + bias >= best {
+                let hinted = candidates.remove(pos);
+                candidates.insert(0, hinted);
+            }
+        }
+        candidates
+    }
+
+    /// Identify a short `sentence`, only keeping the most probable candidate (see [OscarMetadata::identify_sentence]),
+    /// biased towards `hint` (see [OscarMetadata::with_header_language_bias]).
+    ///
+    /// Failed identifications are logged, but not counted in [RunStats::predict_errors]:
+    /// short sentences are not part of the corpus, so they can't fail a shard or a record.
+    fn identify_short(
+        &self,
+        sentence: &str,
+        cls: &dyn LanguageIdentifier,
+        hint: Option<&'static str>,
+    ) -> Option<(String, &'static str, f32)> {
+        match self.identify_sentence(sentence, cls) {
+            Ok(candidates) => self.bias_candidates(candidates, hint).into_iter().next(),
+            Err(e) => {
+                warn!(
+                    "could not identify short sentence ({} chars): {}",
+                    sentence.chars().count(),
+                    e
+                );
+                None
+            }
+        }
+    }
+
+    /// Log and count in `state` a failed identification of `sentence`, of the record of id `record_id`.
+    fn predict_error(record_id: Option<&str>, sentence: &str, e: &Error, state: &ShardState) {
+        warn!(
+            "could not identify sentence of record {} ({} chars): {}",
+            record_id.unwrap_or("<unknown>"),
+            sentence.chars().count(),
+            e
+        );
+        state.predict_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// attempt to predict the languages of the segments of provided sentence.
+    ///
+    /// Returns `(byte range, language, probability)` segments, in sentence order.
+    /// If [OscarMetadata::with_windowed_identification] is enabled and `sentence` is long enough,
+    /// it is identified over sliding windows that are stitched into same-language segments,
+    /// trimmed of surrounding whitespace.
+    /// Otherwise, the whole sentence is a single segment, of its most probable candidate.
+    /// Candidates (of the sentence or of each window) are biased towards `hint`
+    /// (see [OscarMetadata::with_header_language_bias]).
+    /// The returned vector is empty if no language is detected, and is then returned along with why
+    /// (see [OscarMetadata::identify_sentence_with_reason]). Windowed sentences get the reason of their first window.
+    ///
+    /// # Errors
+    /// Returns the identifier error if a prediction failed.
+    fn identify_segments(
+        &self,
+        sentence: &str,
+        cls: &dyn LanguageIdentifier,
+        hint: Option<&'static str>,
+    ) -> Result<(Vec<Segment>, Option<DiscardReason>), Error> {
+        let sliding = match &self.windows {
+            Some(sliding) if sliding.applies(sentence) => sliding,
+            _ => {
+                let (candidates, reason) = self.identify_sentence_with_reason(sentence, cls)?;
+                let segments = self
+                    .bias_candidates(candidates, hint)
+                    .into_iter()
+                    .next()
+                    .map(|(_, lang, prob)| (0..sentence.len(), lang, prob))
+                    .into_iter()
+                    .collect();
+                return Ok((segments, reason));
+            }
+        };
+
+        let mut reason = None;
+        let cores = sliding
+            .split(sentence)
+            .into_iter()
+            .map(|(window, core)| {
+                let (candidates, window_reason) =
+                    self.identify_sentence_with_reason(&sentence[window], cls)?;
+                reason = reason.or(window_reason);
+                let best = self.bias_candidates(candidates, hint).into_iter().next();
+                Ok((core, best.map(|(_, lang, prob)| (lang, prob))))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        let segments: Vec<_> = windows::stitch(cores)
+            .into_iter()
+            .filter_map(|(range, lang, prob)| {
+                let segment = &sentence[range.clone()];
+                let start = range.start + (segment.len() - segment.trim_start().len());
+                let end = range.start + segment.trim_end().len();
+                (start < end).then_some((start..end, lang, prob))
+            })
+            .collect();
+        let reason = segments
+            .is_empty()
+            .then(|| reason.unwrap_or(DiscardReason::BelowThreshold));
+        Ok((segments, reason))
+    }
+
+    /// Write a sentence that hasn't been identified to the reject sink,
+    /// along with its best prediction.
+    ///
+    /// Sentences whose best prediction is a language that is not processed are ignored.
+    /// Failures are logged, since rejects are only diagnostics.
+    fn reject_unidentified(
+        &self,
+        rejects: &RejectSink,
+        headers: &WarcHeaders,
+        line_number: usize,
+        sentence: &str,
+        cls: &dyn LanguageIdentifier,
+    ) {
+        let prediction = match cls.predict_best(sentence) {
+            Ok(prediction) => prediction,
+            Err(e) => {
+                warn!(
+                    "could not get best prediction of rejected sentence: {:?}",
+                    e
+                );
+                None
+            }
+        };
+        let not_processed = prediction
+            .as_ref()
+            .and_then(|p| self.resolve_label(&p.label))
+            .is_some_and(|lang| {
+                self.languages
+                    .as_ref()
+                    .is_some_and(|languages| !languages.contains(lang))
+            });
+        if not_processed {
+            return;
+        }
+
+        let prediction = prediction.map(|p| RejectPrediction {
+            label: p.label,
+            prob: p.prob,
+        });
+        if let Err(e) = rejects.write(
+            headers,
+            line_number,
+            RejectReason::BelowThreshold,
+            sentence,
+            prediction.as_ref(),
+        ) {
+            error!("could not write rejected sentence: {:?}", e);
+        }
+    }
+
+    /// Identify the `short` (line number, sentence) pairs of a record
+    /// and store them in `state`, one block per language in line order.
+    fn store_short(
+        &self,
+        short: Vec<(usize, String)>,
+        cls: &dyn LanguageIdentifier,
+        hint: Option<&'static str>,
+        state: &ShardState,
+    ) {
+        let mut identified: Vec<(usize, &'static str, String)> = short
+            .into_par_iter()
+            .filter_map(|(line_number, sentence)| {
+                self.identify_short(&sentence, cls, hint)
+                    .map(|(sentence, lang, _)| (line_number, lang, sentence))
+            })
+            .collect();
+        identified.sort_unstable_by_key(|(line_number, _, _)| *line_number);
+
+        let mut blocks: HashMap<&'static str, Vec<String>> = HashMap::new();
+        for (_, lang, sentence) in identified {
+            blocks.entry(lang).or_default().push(sentence);
+        }
+
+        let mut short = state.short.lock().unwrap();
+        for (lang, sentences) in blocks {
+            short.entry(lang).or_default().push(sentences.join("\n"));
+        }
+    }
+
+    /// Write the short sentences of a shard, one record block at a time.
+    ///
+    /// Languages without a short writer are ignored.
+    fn write_short(
+        short: HashMap<&'static str, Vec<String>>,
+        langfiles: &LangFiles,
+    ) -> Result<(), Error> {
+        short.into_par_iter().try_for_each(|(lang, blocks)| {
+            if let Some(writer) = langfiles.short_writers().get(lang) {
+                let mut writer_lock = writer.lock().unwrap();
+                for block in blocks {
+                    writer_lock.write_all(block.as_bytes())?;
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Check a record of shard `idx`.
+    ///
+    /// A truncated record at the end of the shard is logged and skipped (returning `None`),
+    /// while other errors mean that the shard is corrupt and are returned.
+    /// Valid records are reported to [OscarMetadata::with_progress].
+    fn check_record(
+        &self,
+        idx: usize,
+        idx_record: usize,
+        record: Result<Record<BufferedBody>, Error>,
+    ) -> Result<Option<Record<BufferedBody>>, Error> {
+        match record {
+            Ok(record) => {
+                if let Some(progress) = &self.progress {
+                    progress.on_records(1);
+                }
+                Ok(Some(record))
+            }
+            Err(Error::TruncatedRecord(e)) => {
+                warn!(
+                    "Truncated record {} at the end of shard {}: {:?}",
+                    idx_record, idx, e
+                );
+                Ok(None)
+            }
+            Err(e) => {
+                let e = e.in_record(Some(idx_record), None);
+                error!("Corrupt record of shard {}: {}", idx, e);
+                Err(e)
+            }
+        }
+    }
+
+    /// Check that the body of `record` is within [OscarMetadata::with_max_record_bytes], logging it if not.
+    fn check_record_size(&self, record: &Record<BufferedBody>) -> bool {
+        match self.max_record_bytes {
+            Some(max) if record.body().len() > max => {
+                warn!(
+                    "skipping record {}: body of {} bytes is larger than {} bytes",
+                    record.warc_id(),
+                    record.body().len(),
+                    max
+                );
+                false
+            }
+            _ => true,
+        }
+    }
+
+    /// Check that the identification errors of shard `idx` are within [OscarMetadata::with_max_predict_errors].
+    fn check_predict_errors(&self, idx: usize, state: &ShardState) -> Result<(), Error> {
+        let predict_errors = state.predict_errors.load(Ordering::Relaxed);
+        match self.max_predict_errors {
+            Some(max) if predict_errors > max => Err(Error::Custom(format!(
+                "shard {}: {} sentences could not be identified (max {})",
+                idx, predict_errors, max
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Check that the failed records of shard `idx` are within [OscarMetadata::with_max_error_rate].
+    fn check_error_rate(&self, idx: usize, state: &ShardState) -> Result<(), Error> {
+        let exceeded = self.error_budget.and_then(|budget| {
+            budget.exceeded(
+                state.failed.load(Ordering::Relaxed),
+                state.records.load(Ordering::Relaxed),
+            )
+        });
+        match exceeded {
+            Some(exceeded) => Err(Error::Custom(format!(
+                "shard {}: error budget exceeded: {}",
+                idx, exceeded
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Process a provided record.
+    ///
+    /// Records that are too large (see [OscarMetadata::with_max_record_bytes])
+    /// or that don't match the record filter (see [OscarMetadata::with_record_filter]) are skipped.
+    /// Here, lines are normalized if a normalizer is set (see [OscarMetadata::with_normalizer])
+    /// and split into sentences (see [OscarMetadata::with_sentence_splitter]),
+    /// then sentences that are within the configured length bounds are processed
+    /// (by default, sentences that are >100 chars),
+    /// and the others are discarded.
+    /// See [OscarMetadata::with_sentence_chars].
+    ///
+    /// Then, we identify language for each sentence
+    /// and return (sentence, language, probability, line number) in line order, along with headers
+    /// extracted from the WARC and the lines whose sentences have all been discarded for being too short.
+    ///
+    /// `state` counters are incremented for each filtered or oversized record, each discarded sentence, each failed identification
+    /// and each failed record (see [OscarMetadata::with_max_error_rate]).
+    /// If [OscarMetadata::with_keep_short] is enabled, short sentences are identified too
+    /// and stored in `state`, one newline-separated block per record and language.
+    /// Discarded and unidentified sentences are written to the reject sink of `state`, if any
+    /// (see [OscarMetadata::with_rejects]).
+    fn process_record(
+        &self,
+        record: Record<BufferedBody>,
+        cls: &dyn LanguageIdentifier,
+        state: &ShardState,
+    ) -> Option<ProcessedRecord> {
+        if log_enabled!(Debug) {
+            debug!("processing record {}", record.warc_id());
+        };
+        if !self.check_record_size(&record) {
+            state.oversized.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+        state.records.fetch_add(1, Ordering::Relaxed);
+        let (mut header, body) = record.into_raw_parts();
+        if let Some(record_filter) = &self.record_filter {
+            if !record_filter(&header.headers) {
+                state.filtered.fetch_add(1, Ordering::Relaxed);
+                return None;
+            }
+        }
+        if let Some(url_policy) = &self.url_policy {
+            url_policy.rewrite_headers(&mut header.headers);
+        }
+        let body = Self::decode_body(&body, self.lossy_utf8);
+        let hint = self.language_hint(&header.headers);
+
+        // process record if body is utf8-valid
+        if let Some(sentences) = body {
+            // lines that are too short, if kept
+            let mut short = Vec::new();
+            // lines with sentences discarded for being too short, and lines with other sentences
+            let mut short_lines = Vec::new();
+            let mut other_lines = HashSet::new();
+
+            // normalize lines, filter out lines that are too short or too long.
+            // then convert into a parallel iterator
+            let sentences = sentences
+                .lines()
+                .enumerate()
+                .map(|(line_number, line)| match &self.normalizer {
+                    Some(normalizer) => (line_number, normalizer.normalize(line)),
+                    None => (line_number, Cow::Borrowed(line)),
+                })
+                // split lines into sentences, numbered within their line
+                .flat_map(|(line_number, line)| {
+                    self.split_line(line)
+                        .into_iter()
+                        .enumerate()
+                        .map(move |(idx_sentence, sentence)| (line_number, idx_sentence, sentence))
+                })
+                .filter(|(line_number, _, line)| {
+                    if self.skip_blank && Self::is_blank(line) {
+                        state.blank.fetch_add(1, Ordering::Relaxed);
+                        state.discard(DiscardReason::Blank);
+                        other_lines.insert(*line_number);
+                        return false;
+                    }
+                    let keep = self.keep_sentence(line);
+                    if !keep {
+                        state.discarded.fetch_add(1, Ordering::Relaxed);
+                        let is_short = line.chars().count() <= self.min_sentence_chars;
+                        if is_short {
+                            short_lines.push(*line_number);
+                        } else {
+                            other_lines.insert(*line_number);
+                        }
+                        state.discard(if is_short {
+                            DiscardReason::TooShort
+                        } else {
+                            DiscardReason::TooLong
+                        });
+                        if self.keep_short && is_short {
+                            short.push((*line_number, line.to_string()));
+                        }
+                        if let Some(rejects) = &state.rejects {
+                            let reason = if is_short {
+                                RejectReason::TooShort
+                            } else {
+                                RejectReason::TooLong
+                            };
+                            if let Err(e) =
+                                rejects.write(&header.headers, *line_number, reason, line, None)
+                            {
+                                error!("could not write rejected sentence: {:?}", e);
+                            }
+                        }
+                    } else {
+                        // kept lines aren't gaps, and unidentified ones aren't bridged
+                        other_lines.insert(*line_number);
+                    }
+                    keep
+                })
+                .par_bridge();
+
+            let record_failed = AtomicBool::new(false);
+            let mut results: Vec<_> = sentences
+                // predict for each sentence (or each of its segments), discarding
+                // predictions that does not meet threshold
+                // only keep the most probable candidate
+                .flat_map_iter(|(line_number, idx_sentence, sentence)| {
+                    let segments = match self.identify_segments(&sentence, cls, hint) {
+                        Ok((segments, reason)) => {
+                            if let Some(reason) = reason {
+                                state.discard(reason);
+                            }
+                            segments
+                        }
+                        Err(e) => {
+                            let record_id = Self::record_id(&header.headers);
+                            Self::predict_error(record_id.as_deref(), &sentence, &e, state);
+                            record_failed.store(true, Ordering::Relaxed);
+                            return Vec::new();
+                        }
+                    };
+                    if segments.is_empty() {
+                        if let Some(rejects) = &state.rejects {
+                            self.reject_unidentified(
+                                rejects,
+                                &header.headers,
+                                line_number,
+                                &sentence,
+                                cls,
+                            );
+                        }
+                    }
+                    segments
+                        .into_iter()
+                        .map(move |(range, lang, prob)| {
+                            (
+                                idx_sentence,
+                                (sentence[range].to_string(), lang, prob, line_number),
+                            )
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .collect();
+
+            // par_bridge doesn't preserve order.
+            // segments of a sentence are contiguous and in order, so the sort has to be stable
+            results.sort_by_key(|(idx_sentence, (_, _, _, line_number))| {
+                (*line_number, *idx_sentence)
+            });
+            let results = results.into_iter().map(|(_, result)| result).collect();
+            if record_failed.into_inner() {
+                state.failed.fetch_add(1, Ordering::Relaxed);
+            }
+
+            if !short.is_empty() {
+                self.store_short(short, cls, hint, state);
+            }
+
+            // lines are in order
+            short_lines.dedup();
+            short_lines.retain(|line_number| !other_lines.contains(line_number));
+
+            Some((results, header.headers, short_lines))
+        } else {
+            let warc_id = Self::record_id(&header.headers);
+            error!("body not UTF-8 valid: {:?}", warc_id);
+            state.failed.fetch_add(1, Ordering::Relaxed);
+            state.discard(DiscardReason::InvalidUtf8);
+            None
+        }
+    }
+
+    /// Split a line into sentences (see [OscarMetadata::with_sentence_splitter]).
+    ///
+    /// Lines that are kept as a whole are not copied.
+    fn split_line<'a>(&self, line: Cow<'a, str>) -> Vec<Cow<'a, str>> {
+        let ranges = self.splitter.split(&line);
+        if let [range] = ranges.as_slice() {
+            if *range == (0..line.len()) {
+                return vec![line];
+            }
+        }
+        ranges
+            .into_iter()
+            .map(|range| Cow::Owned(line[range].to_string()))
+            .collect()
+    }
+
+    /// Get the (lossily decoded) WARC record id of provided headers, if any.
+    fn record_id(headers: &WarcHeaders) -> Option<String> {
+        headers
+            .get(&WarcHeader::RecordID)
+            .map(|id| String::from_utf8_lossy(id).into_owned())
+    }
+
+    /// Merge the identified sentences of a record into pieces of same-language sentences.
+    ///
+    /// Sentences are transformed beforehand, if enabled (see [OscarMetadata::with_text_transform]).
+    /// Pieces with a confidence below [OscarMetadata::with_min_confidence]
+    /// or shorter than [OscarMetadata::with_min_piece_length] are dropped.
+    fn merge_record(&self, (record, header, short_lines): ProcessedRecord) -> Vec<MergedPiece> {
+        // split between langs, probabilities, line numbers and sentences
+        let langs: Vec<&str> = record.iter().map(|(_, lang, _, _)| *lang).collect();
+        let probabilities: Vec<f32> = record.iter().map(|(_, _, prob, _)| *prob).collect();
+        let line_numbers: Vec<usize> = record.iter().map(|(_, _, _, line)| *line).collect();
+        let sentences: Vec<String> = record
+            .into_iter()
+            .map(|(sentences, _, _, _)| sentences)
+            .collect();
+        let sentences = match self.text_transform {
+            Some(transform) => sentences
+                .iter()
+                .map(|sentence| transform.apply(sentence).into_owned())
+                .collect(),
+            None => sentences,
+        };
+
+        // create new document for current record
+        let doc = Document::with_probabilities(header, sentences, langs, probabilities)
+            .and_then(|doc| doc.with_line_numbers(line_numbers))
+            .map(|doc| doc.with_gap_bridging(self.max_gap, short_lines));
+        let mut pieces = match doc {
+            Ok(doc) => doc.into_merged_pieces_lang(),
+            Err(e) => {
+                warn!("{:?}", e);
+                return Vec::new();
+            }
+        };
+        if self.include_source {
+            for piece in &mut pieces {
+                piece.source = Some(Source::from_headers(&piece.headers));
+            }
+        }
+
+        let pieces = match self.min_confidence {
+            Some(min_confidence) => pieces
+                .into_iter()
+                .filter(|piece| {
+                    let keep = piece.confidence >= min_confidence;
+                    if !keep {
+                        debug!(
+                            "dropping {} piece with confidence {}",
+                            piece.identification(),
+                            piece.confidence
+                        );
+                    }
+                    keep
+                })
+                .collect(),
+            None => pieces,
+        };
+
+        match &self.min_piece_length {
+            Some(min_length) => pieces
+                .into_iter()
+                .filter(|piece| {
+                    let keep = min_length.detect(&piece.sentences);
+                    if !keep {
+                        debug!(
+                            "dropping {} piece of {} sentences for length",
+                            piece.identification(),
+                            piece.nb_sentences
+                        );
+                    }
+                    keep
+                })
+                .collect(),
+            None => pieces,
+        }
+    }
+
+    /// Merge the processed records of shard `idx`, grouping pieces by language.
+    ///
+    /// Records are restored in shard order (and deduplicated if enabled) beforehand.
+    /// Pieces get their shard location (see [MergedPiece::shard_location]).
+    fn merge_records(
+        &self,
+        idx: usize,
+        mut shard_results: Vec<(usize, ProcessedRecord)>,
+    ) -> Result<HashMap<&'static str, Vec<MergedPiece>>, Error> {
+        // restore shard order, lost by par_bridge
+        shard_results.sort_unstable_by_key(|(idx_record, _)| *idx_record);
+
+        if self.dedup {
+            Self::dedup_sentences(&mut shard_results);
+        }
+        if let Some((shingle_size, threshold)) = self.near_dedup {
+            let mut filter = NearDuplicates::new(shingle_size, threshold)?;
+            Self::near_dedup_sentences(&mut shard_results, &mut filter);
+        }
+
+        // sort merged pieces into different langs
+        // now there's a hashmap that points each lang
+        // to a vector of merged pieces
+        let mut lang_pieces: HashMap<&'static str, Vec<MergedPiece>> = HashMap::new();
+        for (idx_record, mut piece) in shard_results.into_iter().flat_map(|(idx_record, record)| {
+            self.merge_record(record)
+                .into_iter()
+                .map(move |piece| (idx_record, piece))
+        }) {
+            piece.shard_location = Some((idx, idx_record));
+            lang_pieces
+                .entry(piece.identification())
+                .or_default()
+                .push(piece);
+        }
+
+        Ok(lang_pieces)
+    }
+
+    /// Drop the pieces of languages that reached their cap (see [OscarMetadata::with_lang_caps]).
+    ///
+    /// Returns the kept pieces, along with the number of dropped ones.
+    fn admit_pieces(
+        lang_pieces: HashMap<&'static str, Vec<MergedPiece>>,
+        langfiles: Option<&LangFiles>,
+    ) -> (HashMap<&'static str, Vec<MergedPiece>>, usize) {
+        let langfiles = match langfiles {
+            Some(langfiles) => langfiles,
+            None => return (lang_pieces, 0),
+        };
+
+        let mut nb_capped = 0;
+        let lang_pieces = lang_pieces
+            .into_iter()
+            .filter_map(|(lang, pieces)| {
+                let nb_pieces = pieces.len();
+                let pieces = langfiles.admit(pieces);
+                nb_capped += nb_pieces - pieces.len();
+                (!pieces.is_empty()).then_some((lang, pieces))
+            })
+            .collect();
+        (lang_pieces, nb_capped)
+    }
+
+    /// Write the merged pieces of a shard, once capped ones are dropped (see [OscarMetadata::admit_pieces]).
+    ///
+    /// Nothing is written if `langfiles` is [None] (dry run).
+    ///
+    /// Returns the number of pieces per language, along with statistics of the shard.
+    fn write_pieces(
+        lang_pieces: HashMap<&'static str, Vec<MergedPiece>>,
+        langfiles: Option<&LangFiles>,
+    ) -> Result<(HashMap<&'static str, usize>, RunStats), Error> {
+        let (lang_pieces, nb_capped) = Self::admit_pieces(lang_pieces, langfiles);
+
+        // compute statistics before pieces are consumed by writers
+        let mut shard_stats = RunStats::default();
+        shard_stats.add_capped(nb_capped);
+        let mut counts = HashMap::with_capacity(lang_pieces.len());
+        for (lang, pieces) in &lang_pieces {
+            shard_stats.add_pieces(lang, pieces);
+            counts.insert(*lang, pieces.len());
+        }
+
+        // write concurrently (languages may share a writer, see OutputFormat::Combined)
+        if let Some(langfiles) = langfiles {
+            lang_pieces.into_par_iter().try_for_each(|(lang, pieces)| {
+                let writer = langfiles.writers().get(lang).unwrap();
+                let mut writer_lock = writer.lock().unwrap();
+                writer_lock.write(pieces)
+            })?;
+        }
+
+        Ok((counts, shard_stats))
+    }
+
+    /// Process the records of shard `idx`, sending the pieces of each record to `channels`
+    /// as soon as the record is processed.
+    ///
+    /// Pieces are not sent in shard order, and [LangChannels::sync] has to be called
+    /// to ensure that they're written. Capped pieces are dropped beforehand (see [OscarMetadata::admit_pieces]).
+    ///
+    /// Returns the number of pieces per language, along with statistics of the shard.
+    fn stream_records<I>(
+        &self,
+        idx: usize,
+        records: I,
+        cls: &dyn LanguageIdentifier,
+        state: &ShardState,
+        channels: &LangChannels,
+        langfiles: &LangFiles,
+    ) -> Result<(HashMap<&'static str, usize>, RunStats), Error>
+    where
+        I: ParallelIterator<Item = (usize, Result<Record<BufferedBody>, Error>)>,
+    {
+        let shard_stats = Mutex::new((HashMap::new(), RunStats::default()));
+
+        records.try_for_each(|(idx_record, record)| {
+            let record = match self.check_record(idx, idx_record, record)? {
+                Some(record) => record,
+                None => return Ok(()),
+            };
+
+            let processed = self.process_record(record, cls, state);
+            // give up on the shard as soon as there are too many errors
+            self.check_predict_errors(idx, state)?;
+            self.check_error_rate(idx, state)?;
+            let pieces = match processed {
+                Some(processed) => self.merge_record(processed),
+                None => return Ok(()),
+            };
+
+            let mut lang_pieces: HashMap<&'static str, Vec<MergedPiece>> = HashMap::new();
+            for piece in pieces {
+                lang_pieces
+                    .entry(piece.identification())
+                    .or_default()
+                    .push(piece);
+            }
+            let (lang_pieces, nb_capped) = Self::admit_pieces(lang_pieces, Some(langfiles));
+
+            {
+                let mut shard_stats = shard_stats.lock().unwrap();
+                let (counts, stats) = &mut *shard_stats;
+                stats.add_capped(nb_capped);
+                for (lang, pieces) in &lang_pieces {
+                    stats.add_pieces(lang, pieces);
+                    *counts.entry(*lang).or_insert(0) += pieces.len();
+                }
+            }
+
+            lang_pieces
+                .into_iter()
+                .try_for_each(|(lang, pieces)| channels.send(idx, lang, pieces))
+        })?;
+
+        Ok(shard_stats.into_inner().unwrap())
+    }
+
+    /// Log the projected output of a dry run, one line per language.
+    fn log_projection(stats: &RunStats) {
+        let mut langs: Vec<_> = stats.langs().iter().collect();
+        langs.sort_unstable_by_key(|(lang, _)| **lang);
+
+        for (lang, lang_stats) in &langs {
+            info!(
+                "[dry run] {}: {} documents, {} lines, {} bytes",
+                lang, lang_stats.nb_documents, lang_stats.nb_sentences, lang_stats.nb_bytes
+            );
+        }
+
+        let nb_bytes: usize = langs
+            .iter()
+            .map(|(_, lang_stats)| lang_stats.nb_bytes)
+            .sum();
+        info!(
+            "[dry run] {} languages, {} bytes would be written",
+            langs.len(),
+            nb_bytes
+        );
+    }
+
+    /// Load the language identifier, using the provided backend or the shared model if there's one.
+    fn classifier(&self) -> Result<Arc<dyn LanguageIdentifier>, Error> {
+        self.classifier_with_threshold(0.8)
+    }
+
+    /// Load the language identifier as [OscarMetadata::classifier] does,
+    /// keeping fastText predictions whose probability is at least `threshold`.
+    ///
+    /// Per-language thresholds (see [OscarMetadata::with_lang_thresholds]) still apply,
+    /// and provided backends (see [OscarMetadata::with_identifier]) keep their own thresholds.
+    ///
+    /// The identifier is bounded by the prediction timeout, if any (see [OscarMetadata::with_predict_timeout]).
+    pub(super) fn classifier_with_threshold(
+        &self,
+        threshold: f32,
+    ) -> Result<Arc<dyn LanguageIdentifier>, Error> {
+        let cls: Arc<dyn LanguageIdentifier> = match &self.identifier {
+            Some(identifier) => identifier.clone(),
+            None => {
+                let k = i32::try_from(self.k).map_err(|_| {
+                    Error::Custom(format!("invalid number of candidates: {}", self.k))
+                })?;
+                let mut cls = match &self.model {
+                    Some(model) => FastText::from_model(model.clone(), k, threshold),
+                    None => FastText::new(&self.lid_path, k, threshold)?,
+                };
+                cls.set_lang_thresholds(self.lang_thresholds.clone())?;
+                Arc::new(cls)
+            }
+        };
+        match self.predict_timeout {
+            Some(timeout) => Ok(Arc::new(identifiers::Timeout::new(cls, timeout))),
+            None => Ok(cls),
+        }
+    }
+
+    /// Open shard `idx`, retrying on failure (see [OscarMetadata::with_open_retries]).
+    fn open_shard(
+        &self,
+        idx: usize,
+        shard: &Path,
+    ) -> Result<Wet<BufReader<Box<dyn Read + Send>>>, Error> {
+        self.open_retry
+            .run(&format!("opening shard {}", idx), || Wet::from_path(shard))
+            .map(|shard| shard.with_lenient_headers(self.lenient_headers))
+    }
+
+    /// Convert `records` into a parallel iterator processing chunks of records
+    /// (see [OscarMetadata::with_record_chunk_size]).
+    ///
+    /// Items of a chunk are yielded sequentially in the task processing the chunk.
+    fn record_chunks<I, T>(&self, mut records: I) -> impl ParallelIterator<Item = T>
+    where
+        I: Iterator<Item = T> + Send,
+        T: Send,
+    {
+        let chunk_size = self.record_chunk_size;
+        std::iter::from_fn(move || {
+            let chunk: Vec<T> = records.by_ref().take(chunk_size).collect();
+            (!chunk.is_empty()).then_some(chunk)
+        })
+        .par_bridge()
+        .flat_map_iter(|chunk| chunk)
+    }
+
+    /// Process the records of shard `idx` and merge them, grouping pieces by language.
+    ///
+    /// Records are processed in parallel chunks (see [OscarMetadata::with_record_chunk_size]), then restored in shard order (and deduplicated if enabled) before being merged.
+    /// `state` counters are incremented as in [OscarMetadata::process_record].
+    ///
+    /// # Errors
+    /// Returns an error on the first corrupt record, or if there are too many identification errors.
+    fn merge_shard<T>(
+        &self,
+        idx: usize,
+        shard: Wet<T>,
+        cls: &dyn LanguageIdentifier,
+        state: &ShardState,
+    ) -> Result<HashMap<&'static str, Vec<MergedPiece>>, Error>
+    where
+        T: BufRead + Send,
+    {
+        let shard_results: Vec<(usize, ProcessedRecord)> = self
+            .record_chunks(shard.records().enumerate())
+            .filter_map(
+                |(idx_record, record)| match self.check_record(idx, idx_record, record) {
+                    Ok(Some(record)) => {
+                        let processed = self.process_record(record, cls, state);
+                        // give up on the shard as soon as too many records failed
+                        if let Err(e) = self.check_error_rate(idx, state) {
+                            return Some(Err(e));
+                        }
+                        processed.map(|result| Ok((idx_record, result)))
+                    }
+                    Ok(None) => None,
+                    Err(e) => Some(Err(e)),
+                },
+            )
+            // collect here is blocking
+            // because we can't write concurrently into a HashMap
+            // and using Mutexes might ruin performance.
+            // See OscarMetadata::with_channel_writers for a streaming alternative.
+            .collect::<Result<_, Error>>()?;
+
+        // don't merge anything if there are too many errors
+        self.check_predict_errors(idx, state)?;
+        self.merge_records(idx, shard_results)
+    }
+
+    /// Process the records of the shard at `shard` (of index `idx`), without merging them.
+    ///
+    /// Returns the identified sentences of each record, along with the record index, in shard order.
+    /// Records are not deduplicated, and short sentences (see [OscarMetadata::with_keep_short]) are not kept.
+    ///
+    /// # Errors
+    /// Returns an error if the shard can't be opened, or on the first corrupt record.
+    pub(super) fn identify_shard(
+        &self,
+        idx: usize,
+        shard: &Path,
+        cls: &dyn LanguageIdentifier,
+    ) -> Result<Vec<(usize, ProcessedRecord)>, Error> {
+        let wet = self.open_shard(idx, shard)?;
+        let state = ShardState::default();
+        let mut records: Vec<(usize, ProcessedRecord)> = self
+            .record_chunks(wet.records().enumerate())
+            .filter_map(
+                |(idx_record, record)| match self.check_record(idx, idx_record, record) {
+                    Ok(Some(record)) => self
+                        .process_record(record, cls, &state)
+                        .map(|result| Ok((idx_record, result))),
+                    Ok(None) => None,
+                    Err(e) => Some(Err(e)),
+                },
+            )
+            .collect::<Result<_, Error>>()?;
+        records.sort_unstable_by_key(|(idx_record, _)| *idx_record);
+        Ok(records)
+    }
+
+    /// Process the shard at `shard` (of index `idx`), returning the merged pieces of each language.
+    ///
+    /// Records are processed, deduplicated and merged as they would be in a run, but nothing is written,
+    /// and short sentences (see [OscarMetadata::with_keep_short]) are not kept.
+    /// This is meant to benchmark record throughput and to test merging end to end.
+    ///
+    /// # Errors
+    /// Returns an error if the shard can't be opened, on the first corrupt record,
+    /// or if there are too many identification errors.
+    pub fn process_shard(
+        &self,
+        idx: usize,
+        shard: &Path,
+        cls: &dyn LanguageIdentifier,
+    ) -> Result<HashMap<&'static str, Vec<MergedPiece>>, Error> {
+        let wet = self.open_shard(idx, shard)?;
+        self.merge_shard(idx, wet, cls, &ShardState::default())
+    }
+
+    /// Process records of index `start..end` of the shard at `shard_path`,
+    /// returning the merged pieces of each record along with the record index.
+    ///
+    /// Records are processed as they would be in a run, but nothing is written.
+    /// This is meant to reproduce issues on specific records without processing whole shards.
+    /// Records whose body is not valid UTF-8 are skipped.
+    ///
+    /// # Errors
+    /// Returns an error if the shard can't be read, or on the first invalid record.
+    pub fn process_range<P: AsRef<Path>>(
+        &self,
+        shard_path: P,
+        start: usize,
+        end: usize,
+    ) -> Result<Vec<(usize, Vec<MergedPiece>)>, Error> {
+        let cls = self.classifier()?;
+        let cls = cls.as_ref();
+        let shard = Wet::from_path(shard_path)?.with_lenient_headers(self.lenient_headers);
+        let state = ShardState::default();
+
+        let mut pieces = Vec::new();
+        for (idx, record) in shard.range(start, end) {
+            if let Some(processed) = self.process_record(record?, cls, &state) {
+                pieces.push((idx, self.merge_record(processed)));
+            }
+        }
+
+        Ok(pieces)
+    }
+
+    /// Run the whole pipeline, returning statistics about the written corpus.
+    ///
+    /// Shards skipped because they were completed by a previous run are not accounted for.
+    ///
+    /// Shards that could not be listed, read or written are logged (ordered by shard index)
+    /// and counted in [RunStats::failed_shards], rather than failing the whole run.
+    /// This includes shards holding corrupt records, but not shards whose last record is truncated:
+    /// the truncated record is skipped and the shard is written (see [crate::sources::commoncrawl::Records]).
+    ///
+    /// If a shutdown is requested (see [OscarMetadata::with_shutdown]), remaining shards are skipped
+    /// and counted in [RunStats::interrupted_shards].
+    ///
+    /// Each processed shard, failed or not, is timed (see [RunStats::shard_timings]) to help find slow shards.
+    /// Failed shards are recorded in the manifest, along with their error (see [OscarMetadata::failed_shards]),
+    /// so that they can be processed again with [OscarMetadata::run_failed_only].
+    ///
+    /// Once shards are processed, the per-language summary of the written corpus
+    /// (document, sentence and character counts, mean identification probability) is written to [SUMMARY_FILE] in `dst`,
+    /// from the statistics gathered while writing. Contrary to the returned statistics, shards completed by a previous run
+    /// are accounted for, by merging the summary of previous runs (see [LangSummary::merge]).
+    /// Nothing is written on dry runs.
+    pub fn run_with_stats(&self) -> Result<RunStats, Error> {
+        self.run_shards(None)
+    }
+
+    /// Process again the shards that failed in previous runs (see [OscarMetadata::failed_shards]),
+    /// appending their pieces to the existing corpus.
+    ///
+    /// Other shards of `src` are skipped, and failed shards keep the index they have in `src`,
+    /// so `src` should hold the same shards as in the failed run.
+    /// Shards are recorded in the manifest as in [OscarMetadata::run_with_stats],
+    /// so shards that fail again can be processed by a subsequent call.
+    /// Nothing is done (and nothing is written) if there's no failed shard.
+    ///
+    /// The returned statistics, as well as the written summary, only account for the processed shards.
+    ///
+    /// # Errors
+    /// Same as [OscarMetadata::run_with_stats]. Since staged files can't be appended to existing ones,
+    /// this also fails if staging is enabled and some shards have been completed.
+    pub fn run_failed_only(&self) -> Result<RunStats, Error> {
+        let failed = self.failed_shards();
+        if failed.is_empty() {
+            info!("no failed shard to process in {:?}", self.dst);
+            return Ok(RunStats::default());
+        }
+        info!("processing {} failed shards", failed.len());
+        self.run_shards(Some(&failed))
+    }
+
+    /// Run the pipeline on the shards of `src`, or only on those of `only` if provided.
+    fn run_shards(&self, only: Option<&HashSet<PathBuf>>) -> Result<RunStats, Error> {
+        if (self.dedup || self.near_dedup.is_some()) && self.channel_bound.is_some() {
+            return Err(Error::Custom(
+                "deduplication can't be used with channel writers".to_string(),
+            ));
+        }
+
+        let cls = self.classifier()?;
+        let cls = cls.as_ref();
+
+        let results = self.shard_paths()?;
+
+        // convert to parallel iterator
+        // /!\: We use par_bridge, that is suboptimal
+        //      compared to implementing IntoParallelIterator
+        //      ourselves.
+        let results = results.enumerate().par_bridge();
+
+        // holds file handles
+        let part_size_bytes = self.part_size.map(|ps| ps as u64 * 1_000_000);
+        let languages = self.languages.as_ref().unwrap_or(&LANG);
+        if matches!(self.output_format, OutputFormat::Parquet { .. })
+            && !self.dry_run
+            && !self.completed_shards().is_empty()
+        {
+            return Err(Error::Custom(
+                "Parquet files can't be appended to, so a Parquet run can't be resumed".to_string(),
+            ));
+        }
+        let staging = match &self.staging {
+            Some(dir) if !self.dry_run => {
+                if !self.completed_shards().is_empty() {
+                    return Err(Error::Custom(
+                        "staging can't be used to resume a run, whose files would be replaced"
+                            .to_string(),
+                    ));
+                }
+                Some(Staging::new(&self.dst, dir.as_deref())?)
+            }
+            _ => None,
+        };
+        let langfiles_dst = staging
+            .as_ref()
+            .map_or_else(|| self.dst.clone(), |staging| staging.dir().to_path_buf());
+        let langfiles = if self.dry_run {
+            None
+        } else {
+            let langfiles = LangFiles::with_languages(
+                &langfiles_dst,
+                languages,
+                part_size_bytes,
+                self.output_format,
+                None,
+                self.layout,
+                &self.file_naming,
+            )?
+            .with_caps(self.lang_caps.clone())
+            .with_staging(staging)
+            .with_bom(self.write_bom)
+            .with_prob_digits(self.prob_digits);
+            if self.keep_short {
+                Some(langfiles.with_short_writers(
+                    &langfiles_dst,
+                    None,
+                    self.layout,
+                    &self.file_naming,
+                )?)
+            } else {
+                Some(langfiles)
+            }
+        };
+
+        // writer threads, if enabled
+        let channels = match (self.channel_bound, &langfiles) {
+            (Some(bound), Some(langfiles)) => Some(LangChannels::new(langfiles, bound)),
+            _ => None,
+        };
+
+        // shards written by a previous run, and manifest to record newly written ones
+        let completed = self.completed_shards();
+        let manifest = if self.dry_run {
+            None
+        } else {
+            let f = OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(self.dst.join(COMPLETED_SHARDS_FILE))?;
+            Some(Mutex::new(f))
+        };
+
+        // rejected sentences, if enabled
+        let rejects = if self.write_rejects && !self.dry_run {
+            Some(Arc::new(RejectSink::open(&self.dst)?))
+        } else {
+            None
+        };
+
+        // per-shard language distributions
+        let shard_langs_file = if self.write_shard_langs && !self.dry_run {
+            let f = OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(self.dst.join(SHARD_LANGS_FILE))?;
+            Some(Mutex::new(f))
+        } else {
+            None
+        };
+
+        // aggregated statistics of written shards
+        let stats = Mutex::new(RunStats::default());
+
+        // number of shards that have been processed (or attempted to)
+        let nb_processed = AtomicUsize::new(0);
+
+        // number of shards left unprocessed because of a shutdown request
+        let nb_interrupted = AtomicUsize::new(0);
+
+        // processed and failed records of the run, and description of the exceeded error budget
+        // (see OscarMetadata::with_max_error_rate)
+        let run_records = AtomicUsize::new(0);
+        let run_failed = AtomicUsize::new(0);
+        let budget_exceeded: Mutex<Option<String>> = Mutex::new(None);
+
+        // bounded pool for shards, and pool for records if shard concurrency is limited.
+        // with a dedicated pool and no shard concurrency, shards are iterated in the record pool.
+        let pinning = &self.thread_pinning;
+        let (shard_pool, record_pool) = match (self.max_shard_concurrency, self.threads) {
+            (Some(nb_threads), threads) => (
+                Some(threads::pool(Some(nb_threads), pinning)?),
+                Some(threads::pool(threads, pinning)?),
+            ),
+            (None, Some(threads)) => (None, Some(threads::pool(Some(threads), pinning)?)),
+            (None, None) => (None, None),
+        };
+
+        // iterate over shards
+        let process_shards = || -> Vec<(usize, Error)> {
+            results
+                .filter_map(|(idx, shard_path)| {
+                    if self.shutdown.as_ref().is_some_and(Shutdown::is_requested) {
+                        nb_interrupted.fetch_add(1, Ordering::Relaxed);
+                        return None;
+                    }
+                    if budget_exceeded.lock().unwrap().is_some() {
+                        return None;
+                    }
+
+                    let shard_path = match shard_path {
+                        Ok(shard_path) => shard_path,
+                        Err(e) => {
+                            nb_processed.fetch_add(1, Ordering::Relaxed);
+                            error!("error reading shard directory entry {}: {}", idx, e);
+                            return Some((idx, Error::from(e).in_shard(idx, None)));
+                        }
+                    };
+
+                    if completed.contains(&shard_path) {
+                        info!("skipping completed shard {}: {:?}", idx, &shard_path);
+                        return None;
+                    }
+                    if only.is_some_and(|only| !only.contains(&shard_path)) {
+                        return None;
+                    }
+
+                    // get an atomic reference to global offsets
+                    // let offsets_global_arc = offsets_global.clone();
+                    info!("processing shard {}: {:?}", idx, &shard_path);
+                    nb_processed.fetch_add(1, Ordering::Relaxed);
+
+                    if let Some(progress) = &self.progress {
+                        progress.on_shard_start(idx, &shard_path);
+                    }
+
+                    // shared with the closure, so that records can be counted even if the shard fails
+                    let state = ShardState {
+                        rejects: rejects.clone(),
+                        ..Default::default()
+                    };
+
+                    let process_shard = || -> Option<(usize, Error)> {
+                        let shard = match self.open_shard(idx, &shard_path) {
+                            Ok(shard) => shard,
+                            Err(e) => {
+                                error!("Could not read/open shard {}", idx);
+                                return Some((idx, e));
+                            }
+                        };
+
+                        let processed = match (&channels, &langfiles) {
+                            // stream pieces to writer threads
+                            (Some(channels), Some(langfiles)) => {
+                                // convert into a parallel iterator
+                                let wetfile = self.record_chunks(shard.records().enumerate());
+                                let process_records = || {
+                                    self.stream_records(
+                                        idx, wetfile, cls, &state, channels, langfiles,
+                                    )
+                                };
+                                let streamed = match &record_pool {
+                                    Some(pool) => pool.install(process_records),
+                                    None => process_records(),
+                                };
+                                streamed.and_then(|counts| channels.sync(idx).map(|_| counts))
+                            }
+                            _ => {
+                                let process_records = || self.merge_shard(idx, shard, cls, &state);
+                                let lang_pieces = match &record_pool {
+                                    Some(pool) => pool.install(process_records),
+                                    None => process_records(),
+                                };
+                                // don't write anything if the shard is corrupt or if there are too many errors
+                                lang_pieces.and_then(|lang_pieces| {
+                                    Self::write_pieces(lang_pieces, langfiles.as_ref())
+                                })
+                            }
+                        };
+
+                        let (counts, mut shard_stats) = match processed {
+                            Ok(processed) => processed,
+                            Err(e) => {
+                                error!("Could not process shard {}", idx);
+                                return Some((idx, e));
+                            }
+                        };
+                        if let Some(langfiles) = &langfiles {
+                            let short = std::mem::take(&mut *state.short.lock().unwrap());
+                            if let Err(e) = Self::write_short(short, langfiles) {
+                                error!("Could not write short sentences of shard {}", idx);
+                                return Some((idx, e));
+                            }
+                        }
+                        shard_stats.add_discarded(state.discarded.load(Ordering::Relaxed));
+                        shard_stats.add_blank(state.blank.load(Ordering::Relaxed));
+                        state.add_discards(&mut shard_stats);
+                        shard_stats.add_filtered(state.filtered.load(Ordering::Relaxed));
+                        shard_stats.add_oversized(state.oversized.load(Ordering::Relaxed));
+                        shard_stats
+                            .add_predict_errors(state.predict_errors.load(Ordering::Relaxed));
+
+                        // report language distribution of the shard
+                        if self.log_shard_langs || self.write_shard_langs {
+                            let counts = Self::shard_langs(&counts);
+                            if self.log_shard_langs {
+                                let counts_str: Vec<String> = counts
+                                    .iter()
+                                    .map(|(lang, count)| format!("{}:{}", lang, count))
+                                    .collect();
+                                info!("shard {} languages: [{}]", idx, counts_str.join(", "));
+                            }
+                            if let Some(f) = &shard_langs_file {
+                                let tsv = Self::shard_langs_tsv(idx, &shard_path, &counts);
+                                if let Err(e) = f.lock().unwrap().write_all(tsv.as_bytes()) {
+                                    error!(
+                                        "Could not write language distribution of shard {}",
+                                        idx
+                                    );
+                                    return Some((idx, e.into()));
+                                }
+                            }
+                        }
+
+                        // dry run: only account for pieces
+                        let manifest = match &manifest {
+                            Some(manifest) => manifest,
+                            None => {
+                                stats.lock().unwrap().merge(&shard_stats);
+                                return None;
+                            }
+                        };
+
+                        stats.lock().unwrap().merge(&shard_stats);
+                        Self::mark_completed(manifest, &shard_path)
+                            .err()
+                            .map(|e| (idx, e))
+                    };
+                    let start = Instant::now();
+                    let failure =
+                        process_shard().map(|(idx, e)| (idx, e.in_shard(idx, Some(&shard_path))));
+                    if let (Some((_, e)), Some(manifest)) = (&failure, &manifest) {
+                        if let Err(manifest_err) = Self::mark_failed(manifest, &shard_path, e) {
+                            error!(
+                                "Could not record failure of shard {}: {:?}",
+                                idx, manifest_err
+                            );
+                        }
+                    }
+                    let timing = ShardTiming {
+                        idx,
+                        path: shard_path.clone(),
+                        duration: start.elapsed(),
+                        nb_records: state.records.load(Ordering::Relaxed),
+                        failed: failure.is_some(),
+                    };
+                    debug!(
+                        "shard {} took {:?} ({} records)",
+                        idx, timing.duration, timing.nb_records
+                    );
+                    stats.lock().unwrap().add_shard_timing(timing);
+
+                    // account for the records of the shard in the run's error budget
+                    if let Some(budget) = self.error_budget {
+                        let shard_failed = state.failed.load(Ordering::Relaxed);
+                        let shard_records = state.records.load(Ordering::Relaxed);
+                        let failed = run_failed.fetch_add(shard_failed, Ordering::Relaxed);
+                        let records = run_records.fetch_add(shard_records, Ordering::Relaxed);
+                        let exceeded = budget
+                            .exceeded(shard_failed, shard_records)
+                            .map(|exceeded| format!("shard {}: {}", idx, exceeded))
+                            .or_else(|| {
+                                budget
+                                    .exceeded(failed + shard_failed, records + shard_records)
+                                    .map(|exceeded| format!("run: {}", exceeded))
+                            });
+                        if let Some(exceeded) = exceeded {
+                            let mut budget_exceeded = budget_exceeded.lock().unwrap();
+                            if budget_exceeded.is_none() {
+                                error!("error budget exceeded, aborting run: {}", exceeded);
+                                *budget_exceeded = Some(exceeded);
+                            }
+                        }
+                    }
+                    if let Some(progress) = &self.progress {
+                        progress.on_shard_done(idx, failure.is_none());
+                    }
+                    failure
+                })
+                .collect()
+        };
+
+        let mut r = match shard_pool.as_ref().or(record_pool.as_ref()) {
+            Some(pool) => pool.install(process_shards),
+            None => process_shards(),
+        };
+
+        // wait for remaining writes
+        if let Some(channels) = channels {
+            channels.finish()?;
+        }
+
+        // par_bridge doesn't preserve order
+        r.sort_unstable_by_key(|(idx, _)| *idx);
+
+        // finalize metadata and compressed files
+        if let Some(langfiles) = langfiles {
+            let written = langfiles.nonempty_languages();
+            info!(
+                "{} languages written: [{}]",
+                written.len(),
+                written.join(", ")
+            );
+            let mut capped: Vec<_> = langfiles.capped_pieces().into_iter().collect();
+            capped.sort_unstable();
+            for (lang, nb) in capped {
+                info!("{}: {} pieces dropped after reaching its cap", lang, nb);
+            }
+            langfiles.close()?;
+        }
+        if let Some(rejects) = rejects {
+            rejects.flush()?;
+        }
+
+        for (_, err) in &r {
+            error!("failed: {}", err);
+        }
+
+        info!(
+            "{} shards processed, {} failed",
+            nb_processed.into_inner(),
+            r.len()
+        );
+
+        let nb_interrupted = nb_interrupted.into_inner();
+        if nb_interrupted > 0 {
+            warn!(
+                "run interrupted: {} shards left unprocessed, run again to complete the corpus",
+                nb_interrupted
+            );
+        }
+
+        if let Some(exceeded) = budget_exceeded.into_inner().unwrap() {
+            return Err(Error::Custom(format!(
+                "run aborted, error budget exceeded: {}",
+                exceeded
+            )));
+        }
+
+        let mut stats = stats.into_inner().unwrap();
+        stats.add_failed_shards(r.len());
+        stats.add_interrupted_shards(nb_interrupted);
+
+        if self.dry_run {
+            Self::log_projection(&stats);
+        } else {
+            Self::write_summary(&self.dst, &stats, !completed.is_empty())?;
+        }
+
+        Ok(stats)
+    }
+
+    /// Write the per-language summary of `stats` (see [RunStats::summary]) to [SUMMARY_FILE], in `dst`.
+    ///
+    /// If `resumed` is set, `dst` holds shards of previous runs, and the existing summary is merged into this one.
+    /// A missing summary (such as after a crash) is warned about, since the written one then only covers this run.
+    fn write_summary(dst: &Path, stats: &RunStats, resumed: bool) -> Result<(), Error> {
+        let path = dst.join(SUMMARY_FILE);
+        let mut summary: BTreeMap<String, LangSummary> = if resumed && path.exists() {
+            serde_json::from_reader(BufReader::new(File::open(&path)?))?
+        } else {
+            if resumed {
+                warn!(
+                    "{:?} is missing: the summary only covers shards processed by this run",
+                    path
+                );
+            }
+            BTreeMap::new()
+        };
+        for (lang, lang_summary) in stats.summary() {
+            summary
+                .entry(lang.to_string())
+                .and_modify(|previous| previous.merge(&lang_summary))
+                .or_insert(lang_summary);
+        }
+
+        let f = File::create(path)?;
+        let mut writer = BufWriter::new(f);
+        serde_json::to_writer_pretty(&mut writer, &summary)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Estimate the number of sentences and characters (see [MergedPiece::nb_chars]) of each language
+    /// that a run would yield, without writing anything.
+    ///
+    /// Records are identified and merged as in a run, but pieces are counted and dropped as soon as
+    /// their record is merged, rather than being kept until their shard is written.
+    /// This is lighter than a dry run (see [OscarMetadata::with_dry_run]), at the cost of not deduplicating:
+    /// deduplication (see [OscarMetadata::with_dedup] and [OscarMetadata::with_near_dedup]) needs whole shards,
+    /// so estimates are upper bounds when it's enabled.
+    /// Completed shards (see [OscarMetadata::completed_shards]) are estimated too, and short sentences
+    /// (see [OscarMetadata::with_keep_short]) are not counted.
+    ///
+    /// Shards that fail (as they would in a run) are logged and left out of the estimate.
+    ///
+    /// # Errors
+    /// Returns an error if the identifier can't be loaded or if `src` can't be read.
+    pub fn estimate(&self) -> Result<HashMap<&'static str, (usize, usize)>, Error> {
+        let cls = self.classifier()?;
+        let cls = cls.as_ref();
+        let counts = Mutex::new(HashMap::new());
+
+        self.shard_paths()?
+            .enumerate()
+            .par_bridge()
+            .for_each(|(idx, shard_path)| {
+                let shard_counts = match shard_path {
+                    Ok(shard_path) => self
+                        .estimate_shard(idx, &shard_path, cls)
+                        .map_err(|e| e.in_shard(idx, Some(&shard_path))),
+                    Err(e) => Err(Error::from(e).in_shard(idx, None)),
+                };
+                match shard_counts {
+                    Ok(shard_counts) => {
+                        let mut counts = counts.lock().unwrap();
+                        for (lang, (nb_sentences, nb_chars)) in shard_counts {
+                            let count = counts.entry(lang).or_insert((0, 0));
+                            count.0 += nb_sentences;
+                            count.1 += nb_chars;
+                        }
+                    }
+                    Err(e) => error!("could not estimate: {}", e),
+                }
+            });
+
+        Ok(counts.into_inner().unwrap())
+    }
+
+    /// Count the sentences and characters of each language of shard `idx` (see [OscarMetadata::estimate]).
+    fn estimate_shard(
+        &self,
+        idx: usize,
+        shard: &Path,
+        cls: &dyn LanguageIdentifier,
+    ) -> Result<HashMap<&'static str, (usize, usize)>, Error> {
+        let wet = self.open_shard(idx, shard)?;
+        let state = ShardState::default();
+        let counts = Mutex::new(HashMap::new());
+
+        self.record_chunks(wet.records().enumerate())
+            .try_for_each(|(idx_record, record)| {
+                let record = match self.check_record(idx, idx_record, record)? {
+                    Some(record) => record,
+                    None => return Ok(()),
+                };
+
+                let processed = self.process_record(record, cls, &state);
+                self.check_predict_errors(idx, &state)?;
+                self.check_error_rate(idx, &state)?;
+                if let Some(processed) = processed {
+                    let pieces = self.merge_record(processed);
+                    let mut counts = counts.lock().unwrap();
+                    for piece in pieces {
+                        let count = counts.entry(piece.identification()).or_insert((0, 0));
+                        count.0 += piece.nb_sentences;
+                        count.1 += piece.nb_chars();
+                    }
+                }
+                Ok::<_, Error>(())
+            })?;
+
+        Ok(counts.into_inner().unwrap())
+    }
+}
+
+impl Pipeline<()> for OscarMetadata {
+    fn version() -> &'static str {
+        "1.1.0"
+    }
+
+    /// Run the whole pipeline
+    fn run(&self) -> Result<(), Error> {
+        self.run_with_stats().map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::{
+        collections::{HashMap, HashSet},
+        fs::OpenOptions,
+        io::Write,
+        path::Path,
+        path::PathBuf,
+        sync::atomic::{AtomicUsize, Ordering},
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use std::io::Cursor;
+
+    use fasttext::Prediction;
+    use warc::{BufferedBody, EmptyBody, Record, WarcHeader, WarcWriter};
+
+    use crate::error::Error;
+    use crate::identifiers::{FastText, LanguageIdentifier};
+    use crate::io::{CapUnit, FileNaming, LangCaps, LangFiles, LayoutStrategy, OutputFormat};
+    use crate::pipelines::progress::ProgressObserver;
+    use crate::pipelines::shutdown::Shutdown;
+    use crate::pipelines::threads::ThreadPinning;
+    use crate::pipelines::url_policy::StripQuery;
+    use crate::sources::commoncrawl::Wet;
+    use crate::testing::WetBuilder;
+
+    use super::{
+        DiscardReason, OscarMetadata, ShardState, WarcHeaders, COMPLETED_SHARDS_FILE,
+        SHARD_LANGS_FILE, SUMMARY_FILE,
+    };
+    use crate::filtering::content::ContentLength;
+    use crate::filtering::minhash::NearDuplicates;
+    use crate::filtering::normalizer::{TextTransform, Whitespace};
+    use crate::filtering::splitter::Punctuation;
+    use crate::pipelines::oscarmeta::rejects::{RejectSink, REJECTS_FILE};
+    use crate::pipelines::oscarmeta::types::{Metadata, Source};
+
+    #[test]
+    fn test_check_predict_errors() {
+        let state = ShardState::default();
+        state.predict_errors.fetch_add(2, Ordering::Relaxed);
+
+        let p = OscarMetadata::new(
+            vec![PathBuf::new()],
+            PathBuf::new(),
+            PathBuf::new(),
+            1,
+            None,
+        );
+        assert!(p.check_predict_errors(0, &state).is_ok());
+
+        let p = p.with_max_predict_errors(2);
+        assert!(p.check_predict_errors(0, &state).is_ok());
+
+        state.predict_errors.fetch_add(1, Ordering::Relaxed);
+        assert!(p.check_predict_errors(0, &state).is_err());
+    }
+
+    #[test]
+    fn test_check_error_rate() {
+        let p = OscarMetadata::new(
+            vec![PathBuf::new()],
+            PathBuf::new(),
+            PathBuf::new(),
+            1,
+            None,
+        );
+        let state = ShardState::default();
+        state.records.fetch_add(2, Ordering::Relaxed);
+        state.failed.fetch_add(2, Ordering::Relaxed);
+        // no budget by default
+        assert!(p.check_error_rate(0, &state).is_ok());
+        assert!(OscarMetadata::new(
+            vec![PathBuf::new()],
+            PathBuf::new(),
+            PathBuf::new(),
+            1,
+            None
+        )
+        .with_max_error_rate(1.5, 0)
+        .is_err());
+
+        // not enough records to check the rate
+        let p = p.with_max_error_rate(0.5, 4).unwrap();
+        assert!(p.check_error_rate(0, &state).is_ok());
+
+        state.records.fetch_add(2, Ordering::Relaxed);
+        assert!(p.check_error_rate(0, &state).is_ok());
+        state.failed.fetch_add(1, Ordering::Relaxed);
+        assert!(p.check_error_rate(0, &state).is_err());
+    }
+
+    #[test]
+    fn test_check_record() {
+        let p = OscarMetadata::new(
+            vec![PathBuf::new()],
+            PathBuf::new(),
+            PathBuf::new(),
+            1,
+            None,
+        );
+        let record = Ok(Record::default());
+        assert!(p.check_record(0, 0, record).unwrap().is_some());
+
+        let truncated = Err(Error::TruncatedRecord(warc::Error::UnexpectedEOB));
+        assert!(p.check_record(0, 0, truncated).unwrap().is_none());
+
+        let corrupt = Err(Error::Warc(warc::Error::ReadOverflow));
+        let err = p.check_record(0, 3, corrupt).unwrap_err();
+        assert!(matches!(
+            err,
+            Error::Record {
+                record_idx: Some(3),
+                ..
+            }
+        ));
+        assert!(matches!(err.root(), Error::Warc(_)));
+    }
+
+    #[derive(Default)]
+    struct CountRecords(AtomicUsize);
+
+    impl ProgressObserver for CountRecords {
+        fn on_records(&self, n: usize) {
+            self.0.fetch_add(n, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn test_check_record_size() {
+        let record: Record<EmptyBody> = Record::default();
+        let record = record.add_body("0123456789");
+
+        let p = OscarMetadata::new(
+            vec![PathBuf::new()],
+            PathBuf::new(),
+            PathBuf::new(),
+            1,
+            None,
+        );
+        assert!(p.check_record_size(&record));
+
+        let p = p.with_max_record_bytes(10);
+        assert!(p.check_record_size(&record));
+
+        let p = p.with_max_record_bytes(9);
+        assert!(!p.check_record_size(&record));
+
+        // skipped records are counted, but not as processed
+        let p = p.with_identifier(Arc::new(French));
+        let cls = p.classifier().unwrap();
+        let state = ShardState::default();
+        assert!(p.process_record(record, cls.as_ref(), &state).is_none());
+        assert_eq!(state.oversized.into_inner(), 1);
+        assert_eq!(state.records.into_inner(), 0);
+    }
+
+    #[test]
+    fn test_check_record_progress() {
+        let progress = Arc::new(CountRecords::default());
+        let p = OscarMetadata::new(
+            vec![PathBuf::new()],
+            PathBuf::new(),
+            PathBuf::new(),
+            1,
+            None,
+        )
+        .with_progress(Some(progress.clone()));
+
+        p.check_record(0, 0, Ok(Record::default())).unwrap();
+        let truncated = Err(Error::TruncatedRecord(warc::Error::UnexpectedEOB));
+        p.check_record(0, 1, truncated).unwrap();
+        assert_eq!(progress.0.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_completed_shards_empty() {
+        let dst = tempfile::tempdir().unwrap();
+        let p = OscarMetadata::new(
+            vec![PathBuf::new()],
+            dst.path().to_path_buf(),
+            PathBuf::new(),
+            1,
+            None,
+        );
+        assert!(p.completed_shards().is_empty());
+    }
+
+    #[test]
+    fn test_completed_shards() {
+        let dst = tempfile::tempdir().unwrap();
+        let p = OscarMetadata::new(
+            vec![PathBuf::new()],
+            dst.path().to_path_buf(),
+            PathBuf::new(),
+            1,
+            None,
+        );
+
+        let manifest_path = dst.path().join(COMPLETED_SHARDS_FILE);
+        let manifest = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&manifest_path)
+            .unwrap();
+        let manifest = Mutex::new(manifest);
+        OscarMetadata::mark_completed(&manifest, Path::new("shards/0.txt.gz")).unwrap();
+        OscarMetadata::mark_completed(&manifest, Path::new("shards/1.txt.gz")).unwrap();
+
+        // simulate a crash while writing an entry
+        manifest
+            .lock()
+            .unwrap()
+            .write_all(b"\"shards/2.tx")
+            .unwrap();
+
+        let completed = p.completed_shards();
+        assert_eq!(completed.len(), 2);
+        assert!(completed.contains(Path::new("shards/0.txt.gz")));
+        assert!(completed.contains(Path::new("shards/1.txt.gz")));
+    }
+
+    #[test]
+    fn test_shard_statuses() {
+        let dst = tempfile::tempdir().unwrap();
+        let p = OscarMetadata::new(
+            vec![PathBuf::new()],
+            dst.path().to_path_buf(),
+            PathBuf::new(),
+            1,
+            None,
+        );
+
+        // manifests of previous versions only list completed shards
+        std::fs::write(
+            dst.path().join(COMPLETED_SHARDS_FILE),
+            "\"shards/0.txt.gz\"\n\"shards/1.txt.gz\"\n",
+        )
+        .unwrap();
+        let manifest = OpenOptions::new()
+            .append(true)
+            .open(dst.path().join(COMPLETED_SHARDS_FILE))
+            .unwrap();
+        let manifest = Mutex::new(manifest);
+        let error = Error::Custom("corrupt".to_string());
+        OscarMetadata::mark_failed(&manifest, Path::new("shards/1.txt.gz"), &error).unwrap();
+        OscarMetadata::mark_failed(&manifest, Path::new("shards/2.txt.gz"), &error).unwrap();
+        OscarMetadata::mark_failed(&manifest, Path::new("shards/3.txt.gz"), &error).unwrap();
+        OscarMetadata::mark_completed(&manifest, Path::new("shards/3.txt.gz")).unwrap();
+
+        // the last entry of a shard wins
+        let completed = p.completed_shards();
+        assert_eq!(completed.len(), 2);
+        assert!(completed.contains(Path::new("shards/0.txt.gz")));
+        assert!(completed.contains(Path::new("shards/3.txt.gz")));
+        let failed = p.failed_shards();
+        assert_eq!(failed.len(), 2);
+        assert!(failed.contains(Path::new("shards/1.txt.gz")));
+        assert!(failed.contains(Path::new("shards/2.txt.gz")));
+
+        let content = std::fs::read_to_string(dst.path().join(COMPLETED_SHARDS_FILE)).unwrap();
+        let entry: serde_json::Value =
+            serde_json::from_str(content.lines().nth(2).unwrap()).unwrap();
+        assert_eq!(entry["shard"], "shards/1.txt.gz");
+        assert_eq!(entry["status"], "failed");
+        assert!(entry["error"].as_str().unwrap().contains("corrupt"));
+    }
+    #[test]
+    fn test_lang_thresholds_unknown_lang() {
+        let mut thresholds = HashMap::new();
+        thresholds.insert("fr", 0.5);
+        thresholds.insert("not_a_lang", 0.5);
+        let p = OscarMetadata::new(
+            vec![PathBuf::new()],
+            PathBuf::new(),
+            PathBuf::new(),
+            1,
+            None,
+        )
+        .with_lang_thresholds(thresholds);
+        assert!(p.is_err());
+    }
+
+    #[test]
+    fn test_identify_sentence_topk() {
+        let cls = FastText::new(Path::new("lid.176.bin"), 3, 0.0).unwrap();
+        let sentence = "english test that is longer than one hundred characters. english test that is longer than one hundred characters.";
+        let p = OscarMetadata::new(
+            vec![PathBuf::new()],
+            PathBuf::new(),
+            PathBuf::new(),
+            3,
+            None,
+        );
+        let ids = p.identify_sentence(sentence, &cls).unwrap();
+
+        assert!(!ids.is_empty() && ids.len() <= 3);
+        assert_eq!(ids[0].1, "en");
+        assert!(ids.windows(2).all(|w| w[0].2 >= w[1].2));
+    }
+
+    #[test]
+    fn test_windowed_identification_invalid() {
+        let p = OscarMetadata::new(
+            vec![PathBuf::new()],
+            PathBuf::new(),
+            PathBuf::new(),
+            1,
+            None,
+        );
+        assert!(p.with_windowed_identification(100, 50, 50).is_err());
+    }
+
+    #[test]
+    fn test_identify_segments() {
+        let cls = FastText::new(Path::new("lid.176.bin"), 1, 0.0).unwrap();
+        let en =
+            "this is an english sentence that talks about the weather, which is quite nice today.";
+        let fr = "ceci est une phrase en français qui parle du temps, qui est plutôt agréable aujourd'hui.";
+        let sentence = format!("{} {} {} {}", en, en, fr, fr);
+        let p = OscarMetadata::new(
+            vec![PathBuf::new()],
+            PathBuf::new(),
+            PathBuf::new(),
+            1,
+            None,
+        );
+
+        // simple path
+        let segments = p.identify_segments(&sentence, &cls, None).unwrap().0;
+        assert_eq!(segments.len(), 1);
+        assert_eq!(segments[0].0, 0..sentence.len());
+
+        let p = p.with_windowed_identification(100, 80, 20).unwrap();
+        let segments = p.identify_segments(&sentence, &cls, None).unwrap().0;
+        let langs: Vec<&str> = segments.iter().map(|(_, lang, _)| *lang).collect();
+        assert_eq!(langs, vec!["en", "fr"]);
+        assert!(segments.windows(2).all(|w| w[0].0.end <= w[1].0.start));
+
+        // short sentences are identified as a whole
+        let segments = p.identify_segments(en, &cls, None).unwrap().0;
+        assert_eq!(segments, vec![(0..en.len(), segments[0].1, segments[0].2)]);
+    }
+
+    #[test]
+    fn test_languages_unknown_lang() {
+        let p = OscarMetadata::new(
+            vec![PathBuf::new()],
+            PathBuf::new(),
+            PathBuf::new(),
+            1,
+            None,
+        );
+        let languages = vec!["fr", "not-a-lang"].into_iter().collect();
+        assert!(p.with_languages(languages).is_err());
+    }
+
+    #[test]
+    fn test_identify_sentence_languages() {
+        let cls = FastText::new(Path::new("lid.176.bin"), 3, 0.0).unwrap();
+        let sentence = "english test that is longer than one hundred characters. english test that is longer than one hundred characters.";
+        let p = OscarMetadata::new(
+            vec![PathBuf::new()],
+            PathBuf::new(),
+            PathBuf::new(),
+            3,
+            None,
+        )
+        .with_languages(vec!["fr"].into_iter().collect())
+        .unwrap();
+        let ids = p.identify_sentence(sentence, &cls).unwrap();
+
+        assert!(ids.iter().all(|(_, lang, _)| *lang == "fr"));
+    }
+
+    #[test]
+    fn test_shard_langs() {
+        let counts = vec![("fr", 1), ("en", 2), ("de", 1)].into_iter().collect();
+        let counts = OscarMetadata::shard_langs(&counts);
+        assert_eq!(counts, vec![("de", 1), ("en", 2), ("fr", 1)]);
+
+        let tsv = OscarMetadata::shard_langs_tsv(3, Path::new("shards/3.txt.gz"), &counts);
+        assert_eq!(
+            tsv,
+            "3\tshards/3.txt.gz\tde\t1\n3\tshards/3.txt.gz\ten\t2\n3\tshards/3.txt.gz\tfr\t1\n"
+        );
+
+        let tsv = OscarMetadata::shard_langs_tsv(4, Path::new("shards/4.txt.gz"), &[]);
+        assert_eq!(tsv, "4\tshards/4.txt.gz\t\t0\n");
+    }
+
+    #[test]
+    fn test_shard_paths() {
+        let src = tempfile::tempdir().unwrap();
+        let shard = src.path().join("0.txt.gz");
+        std::fs::write(&shard, "").unwrap();
+
+        // directory
+        let p = OscarMetadata::new(
+            vec![src.path().to_path_buf()],
+            PathBuf::new(),
+            PathBuf::new(),
+            1,
+            None,
+        );
+        let paths: Vec<PathBuf> = p.shard_paths().unwrap().map(Result::unwrap).collect();
+        assert_eq!(paths, vec![shard.clone()]);
+
+        // single file
+        let p = OscarMetadata::new(vec![shard.clone()], PathBuf::new(), PathBuf::new(), 1, None);
+        let paths: Vec<PathBuf> = p.shard_paths().unwrap().map(Result::unwrap).collect();
+        assert_eq!(paths, vec![shard]);
+
+        let p = OscarMetadata::new(
+            vec![src.path().join("missing")],
+            PathBuf::new(),
+            PathBuf::new(),
+            1,
+            None,
+        );
+        assert!(p.shard_paths().is_err());
+    }
+
+    #[test]
+    fn test_multiple_sources() {
+        let sentence = "a".repeat(101);
+        let sources: Vec<_> = (0..2).map(|_| tempfile::tempdir().unwrap()).collect();
+        for src in &sources {
+            WetBuilder::new()
+                .text(&sentence)
+                .write(&src.path().join("0.txt.gz"))
+                .unwrap();
+        }
+        let dst = tempfile::tempdir().unwrap();
+        let p = OscarMetadata::new(
+            sources.iter().map(|src| src.path().to_path_buf()).collect(),
+            dst.path().to_path_buf(),
+            PathBuf::new(),
+            1,
+            None,
+        )
+        .with_identifier(Arc::new(French))
+        .with_languages(["fr"].into_iter().collect())
+        .unwrap()
+        .with_shard_langs(false, true);
+
+        // shards are listed in source order
+        let paths: Vec<PathBuf> = p.shard_paths().unwrap().map(Result::unwrap).collect();
+        assert_eq!(
+            paths,
+            vec![
+                sources[0].path().join("0.txt.gz"),
+                sources[1].path().join("0.txt.gz")
+            ]
+        );
+
+        // shards of same name are distinct shards of a single output
+        let stats = p.run_with_stats().unwrap();
+        assert_eq!(stats.langs()["fr"].nb_documents, 2);
+        assert_eq!(p.completed_shards().len(), 2);
+        let shard_langs = std::fs::read_to_string(dst.path().join(SHARD_LANGS_FILE)).unwrap();
+        let mut indices: Vec<_> = shard_langs
+            .lines()
+            .map(|line| line.split('\t').next().unwrap().to_string())
+            .collect();
+        indices.sort_unstable();
+        assert_eq!(indices, ["0", "1"]);
+    }
+
+    #[test]
+    fn test_dedup_sentences() {
+        let sentences = |s: &[(&str, &'static str)]| -> Vec<(String, &'static str, f32, usize)> {
+            s.iter().map(|(s, l)| (s.to_string(), *l, 1.0, 0)).collect()
+        };
+
+        let records = vec![
+            (
+                sentences(&[("accept cookies", "en"), ("hello", "en"), ("hello", "en")]),
+                HashMap::new(),
+                Vec::new(),
+            ),
+            (
+                sentences(&[("accept cookies", "en")]),
+                HashMap::new(),
+                Vec::new(),
+            ),
+            (
+                sentences(&[("bonjour", "fr"), ("accept cookies", "en")]),
+                HashMap::new(),
+                Vec::new(),
+            ),
+        ];
+
+        let mut records = records.into_iter().enumerate().collect();
+        OscarMetadata::dedup_sentences(&mut records);
+
+        let result: Vec<Vec<(String, &'static str, f32, usize)>> =
+            records.into_iter().map(|(_, (s, _, _))| s).collect();
+        assert_eq!(
+            result,
+            vec![
+                sentences(&[("accept cookies", "en"), ("hello", "en")]),
+                sentences(&[("bonjour", "fr")]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_near_dedup_sentences() {
+        let sentences = |s: &[&str]| -> Vec<(String, &'static str, f32, usize)> {
+            s.iter().map(|s| (s.to_string(), "en", 1.0, 0)).collect()
+        };
+
+        let records = vec![
+            (
+                sentences(&["share this article on social media", "hello there"]),
+                HashMap::new(),
+                Vec::new(),
+            ),
+            (
+                sentences(&["share  this article on social media"]),
+                HashMap::new(),
+                Vec::new(),
+            ),
+        ];
+
+        let mut filter = NearDuplicates::new(2, 0.9).unwrap();
+        let mut records = records.into_iter().enumerate().collect();
+        OscarMetadata::near_dedup_sentences(&mut records, &mut filter);
+
+        let result: Vec<Vec<(String, &'static str, f32, usize)>> =
+            records.into_iter().map(|(_, (s, _, _))| s).collect();
+        assert_eq!(
+            result,
+            vec![sentences(&[
+                "share this article on social media",
+                "hello there"
+            ])]
+        );
+    }
+
+    #[test]
+    fn test_near_dedup_invalid() {
+        let p = OscarMetadata::new(
+            vec![PathBuf::new()],
+            PathBuf::new(),
+            PathBuf::new(),
+            1,
+            None,
+        );
+        assert!(p.with_near_dedup(0, 0.8).is_err());
+    }
+
+    #[test]
+    fn test_record_chunks() {
+        use rayon::prelude::*;
+
+        let p = OscarMetadata::new(
+            vec![PathBuf::new()],
+            PathBuf::new(),
+            PathBuf::new(),
+            1,
+            None,
+        );
+        assert!(p.with_record_chunk_size(0).is_err());
+
+        for chunk_size in [1, 3, 8, 100] {
+            let p = OscarMetadata::new(
+                vec![PathBuf::new()],
+                PathBuf::new(),
+                PathBuf::new(),
+                1,
+                None,
+            )
+            .with_record_chunk_size(chunk_size)
+            .unwrap();
+            let mut items: Vec<usize> = p.record_chunks(0..20).collect();
+            items.sort_unstable();
+            assert_eq!(items, (0..20).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn test_decode_body() {
+        let invalid = b"caf\xe9 ok \xff\xfe";
+        assert_eq!(OscarMetadata::decode_body(invalid, false), None);
+        assert_eq!(
+            OscarMetadata::decode_body(invalid, true),
+            Some("caf\u{FFFD} ok \u{FFFD}\u{FFFD}".to_string())
+        );
+        assert_eq!(
+            OscarMetadata::decode_body("café".as_bytes(), false),
+            Some("café".to_string())
+        );
+    }
+
+    #[test]
+    fn test_merge_record_min_confidence() {
+        let record = || {
+            (
+                vec![
+                    ("bonjour".to_string(), "fr", 0.9, 0),
+                    ("hello".to_string(), "en", 0.4, 1),
+                ],
+                HashMap::new(),
+                Vec::new(),
+            )
+        };
+
+        let p = OscarMetadata::new(
+            vec![PathBuf::new()],
+            PathBuf::new(),
+            PathBuf::new(),
+            1,
+            None,
+        );
+        assert_eq!(p.merge_record(record()).len(), 2);
+
+        let p = p.with_min_confidence(0.5);
+        let pieces = p.merge_record(record());
+        assert_eq!(pieces.len(), 1);
+        assert_eq!(pieces[0].identification(), "fr");
+    }
+
+    #[test]
+    fn test_merge_record_text_transform() {
+        let record = (
+            vec![
+                ("Bonjour À Tous".to_string(), "fr", 0.9, 0),
+                ("Ça Va".to_string(), "fr", 0.9, 1),
+            ],
+            HashMap::new(),
+            Vec::new(),
+        );
+
+        let p = OscarMetadata::new(
+            vec![PathBuf::new()],
+            PathBuf::new(),
+            PathBuf::new(),
+            1,
+            None,
+        )
+        .with_text_transform(Some(TextTransform::Lowercase))
+        .unwrap();
+        let pieces = p.merge_record(record);
+        assert_eq!(pieces.len(), 1);
+        assert_eq!(pieces[0].sentences, "bonjour à tous\nça va");
+        assert_eq!(pieces[0].nb_sentences, 2);
+        assert_eq!(pieces[0].line_ranges, vec![(0, 2)]);
+    }
+
+    #[test]
+    fn test_merge_record_source() {
+        let record = || {
+            let headers = vec![(WarcHeader::TargetURI, b"http://example.com".to_vec())]
+                .into_iter()
+                .collect();
+            (
+                vec![("bonjour".to_string(), "fr", 0.9, 0)],
+                headers,
+                Vec::new(),
+            )
+        };
+
+        let p = OscarMetadata::new(
+            vec![PathBuf::new()],
+            PathBuf::new(),
+            PathBuf::new(),
+            1,
+            None,
+        );
+        assert_eq!(p.merge_record(record())[0].source, None);
+
+        let p = p.with_source(true);
+        let pieces = p.merge_record(record());
+        assert_eq!(
+            pieces[0].source,
+            Some(Source {
+                url: Some("http://example.com".to_string()),
+                date: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_merge_record_gap_bridging() {
+        let body = "bonjour\nshort\nsalut\nhello\nçava\nhi";
+        let lines: Vec<&str> = body.lines().collect();
+        // "short" has been discarded, for being too short or for another reason
+        let record = |short_lines| {
+            (
+                [(0, "fr"), (2, "fr"), (3, "en"), (4, "fr"), (5, "en")]
+                    .iter()
+                    .map(|(line, lang)| (lines[*line].to_string(), *lang, 1.0, *line))
+                    .collect(),
+                HashMap::new(),
+                short_lines,
+            )
+        };
+
+        let p = OscarMetadata::new(
+            vec![PathBuf::new()],
+            PathBuf::new(),
+            PathBuf::new(),
+            1,
+            None,
+        )
+        .with_gap_bridging(1);
+        let mut pieces = p.merge_record(record(vec![1]));
+        pieces.sort_unstable_by_key(|piece| piece.identification());
+
+        // the range spans "short", but the piece doesn't hold it
+        assert_eq!(pieces[0].line_ranges, vec![(3, 4), (5, 6)]);
+        assert_eq!(pieces[1].line_ranges, vec![(0, 3), (4, 5)]);
+        assert_eq!(pieces[1].sentences, "bonjour\nsalut\nçava");
+
+        // lines dropped for another reason than their length are not bridged
+        let mut pieces = p.merge_record(record(Vec::new()));
+        pieces.sort_unstable_by_key(|piece| piece.identification());
+        assert_eq!(pieces[1].line_ranges, vec![(0, 1), (2, 3), (4, 5)]);
+    }
+
+    #[test]
+    fn test_merge_record_line_ranges() {
+        let body = "bonjour\nshort\nsalut\nhello\nçava\nhi";
+        let lines: Vec<&str> = body.lines().collect();
+        // "short" has been discarded
+        let record = (
+            [(0, "fr"), (2, "fr"), (3, "en"), (4, "fr"), (5, "en")]
+                .iter()
+                .map(|(line, lang)| (lines[*line].to_string(), *lang, 1.0, *line))
+                .collect(),
+            HashMap::new(),
+            Vec::new(),
+        );
+
+        let p = OscarMetadata::new(
+            vec![PathBuf::new()],
+            PathBuf::new(),
+            PathBuf::new(),
+            1,
+            None,
+        );
+        let mut pieces = p.merge_record(record);
+        pieces.sort_unstable_by_key(|piece| piece.identification());
+
+        assert_eq!(pieces[0].line_ranges, vec![(3, 4), (5, 6)]);
+        assert_eq!(pieces[1].line_ranges, vec![(0, 1), (2, 3), (4, 5)]);
+        for piece in pieces {
+            let sliced: Vec<&str> = piece
+                .line_ranges
+                .iter()
+                .flat_map(|(start, end)| lines[*start..*end].iter().copied())
+                .collect();
+            assert_eq!(sliced.join("\n"), piece.sentences);
+        }
+    }
+
+    #[test]
+    fn test_write_records_in_memory() {
+        let record = |sentences: &[(&str, &'static str)]| {
+            let sentences = sentences
+                .iter()
+                .enumerate()
+                .map(|(line, (sentence, lang))| (sentence.to_string(), *lang, 1.0, line))
+                .collect();
+            (sentences, HashMap::new(), Vec::new())
+        };
+        // records are out of shard order
+        let shard_results = vec![
+            (1, record(&[("hello", "en"), ("bonjour", "fr")])),
+            (0, record(&[("salut", "fr"), ("ça va", "fr")])),
+        ];
+
+        let languages = vec!["en", "fr"].into_iter().collect();
+        let (langfiles, written) = LangFiles::in_memory(&languages);
+        let p = OscarMetadata::new(
+            vec![PathBuf::new()],
+            PathBuf::new(),
+            PathBuf::new(),
+            1,
+            None,
+        );
+        let lang_pieces = p.merge_records(3, shard_results).unwrap();
+        let (counts, _) = OscarMetadata::write_pieces(lang_pieces, Some(&langfiles)).unwrap();
+        assert_eq!(counts["en"], 1);
+        assert_eq!(counts["fr"], 2);
+
+        let fr: Vec<String> = written["fr"]
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|piece| piece.sentences.clone())
+            .collect();
+        assert_eq!(fr, vec!["salut\nça va", "bonjour"]);
+        let en = written["en"].lock().unwrap();
+        assert_eq!(en.len(), 1);
+        assert_eq!(en[0].line_ranges, vec![(0, 1)]);
+        assert_eq!(en[0].shard_location, Some((3, 1)));
+    }
+
+    #[test]
+    fn test_write_records_capped() {
+        let record = |sentence: &str, lang| {
+            (
+                vec![(sentence.to_string(), lang, 1.0, 0)],
+                HashMap::new(),
+                Vec::new(),
+            )
+        };
+        let shard_results = vec![
+            (0, record("salut", "fr")),
+            (1, record("hello", "en")),
+            (2, record("bonjour", "fr")),
+            (3, record("coucou", "fr")),
+        ];
+
+        let languages = vec!["en", "fr"].into_iter().collect();
+        let (langfiles, written) = LangFiles::in_memory(&languages);
+        let caps = LangCaps::new(CapUnit::Pieces, vec![("fr", 2)].into_iter().collect()).unwrap();
+        let langfiles = langfiles.with_caps(Some(caps));
+        let p = OscarMetadata::new(
+            vec![PathBuf::new()],
+            PathBuf::new(),
+            PathBuf::new(),
+            1,
+            None,
+        );
+        let lang_pieces = p.merge_records(0, shard_results).unwrap();
+        let (counts, stats) = OscarMetadata::write_pieces(lang_pieces, Some(&langfiles)).unwrap();
+
+        // capped pieces are neither written nor accounted for in language statistics
+        assert_eq!(counts["fr"], 2);
+        assert_eq!(counts["en"], 1);
+        assert_eq!(stats.langs()["fr"].nb_documents, 2);
+        assert_eq!(stats.capped_pieces(), 1);
+        assert_eq!(written["fr"].lock().unwrap().len(), 2);
+        assert_eq!(langfiles.capped_pieces()["fr"], 1);
+    }
+
+    #[test]
+    fn test_merge_record_min_piece_length() {
+        let record = || {
+            (
+                vec![
+                    ("bonjour".to_string(), "fr", 0.9, 0),
+                    ("salut".to_string(), "fr", 0.9, 1),
+                    ("hello".to_string(), "en", 0.9, 2),
+                ],
+                HashMap::new(),
+                Vec::new(),
+            )
+        };
+
+        let p = OscarMetadata::new(
+            vec![PathBuf::new()],
+            PathBuf::new(),
+            PathBuf::new(),
+            1,
+            None,
+        );
+        assert_eq!(p.merge_record(record()).len(), 2);
+
+        let p = p.with_min_piece_length(ContentLength::Lines(2));
+        let pieces = p.merge_record(record());
+        assert_eq!(pieces.len(), 1);
+        assert_eq!(pieces[0].identification(), "fr");
+
+        let p = p.with_min_piece_length(ContentLength::Chars(13));
+        assert!(p.merge_record(record()).is_empty());
+    }
+
+    #[test]
+    fn test_keep_sentence_default() {
+        let p = OscarMetadata::new(
+            vec![PathBuf::new()],
+            PathBuf::new(),
+            PathBuf::new(),
+            1,
+            None,
+        );
+
+        assert!(!p.keep_sentence(&"a".repeat(100)));
+        assert!(p.keep_sentence(&"a".repeat(101)));
+        assert!(p.keep_sentence(&"a".repeat(10_000)));
+    }
+
+    #[test]
+    fn test_keep_sentence_bounds() {
+        let p = OscarMetadata::new(
+            vec![PathBuf::new()],
+            PathBuf::new(),
+            PathBuf::new(),
+            1,
+            None,
+        )
+        .with_sentence_chars(30, Some(50));
+
+        assert!(!p.keep_sentence(&"a".repeat(30)));
+        assert!(p.keep_sentence(&"a".repeat(31)));
+        assert!(p.keep_sentence(&"a".repeat(50)));
+        assert!(!p.keep_sentence(&"a".repeat(51)));
+
+        // characters are unicode scalar values, not bytes
+        assert!(p.keep_sentence(&"é".repeat(31)));
+        assert!(p.keep_sentence(&"é".repeat(50)));
+    }
+
+    #[test]
+    fn test_process_record() {
+        let cls = FastText::new_lid().unwrap();
+
+        let p = OscarMetadata::new(
+            vec![PathBuf::new()],
+            PathBuf::new(),
+            PathBuf::new(),
+            1,
+            None,
+        );
+
+        let record: Record<EmptyBody> = Record::default();
+        let body = "english test that is longer than one hundred characters. english test that is longer than one hundred characters.
+phrase française de plus de cent caractères. Ceci est une phrase française de plus de cent caractères.";
+        println!("{}", body.len());
+        let record = record.add_body(body);
+        let state = ShardState::default();
+        let (identifications, _, _) = p.process_record(record, &cls, &state).unwrap();
+        assert_eq!(state.discarded.into_inner(), 0);
+
+        for (sentence, id, prob, _) in identifications {
+            assert!(prob > 0.0 && prob <= 1.0);
+            if id == "en" {
+                assert_eq!(sentence, "english test that is longer than one hundred characters. english test that is longer than one hundred characters.");
+            } else if id == "fr" {
+                assert_eq!(sentence, "phrase française de plus de cent caractères. Ceci est une phrase française de plus de cent caractères.");
+            }
+        }
+    }
+
+    /// identifies every sentence as being mostly french, with unknown and english candidates.
+    struct French;
+
+    impl LanguageIdentifier for French {
+        fn predict(&self, _: &str) -> Result<Option<Vec<Prediction>>, Error> {
+            Ok(Some(
+                [("fr", 0.8), ("not-a-lang", 0.5), ("en", 0.1)]
+                    .iter()
+                    .map(|(label, prob)| Prediction {
+                        label: label.to_string(),
+                        prob: *prob,
+                    })
+                    .collect(),
+            ))
+        }
+    }
+
+    #[test]
+    fn test_process_record_identifier() {
+        let p = OscarMetadata::new(
+            vec![PathBuf::new()],
+            PathBuf::new(),
+            PathBuf::new(),
+            1,
+            None,
+        )
+        .with_identifier(Arc::new(French));
+        let cls = p.classifier().unwrap();
+
+        let sentence = "a".repeat(101);
+        let record: Record<EmptyBody> = Record::default();
+        let record = record.add_body(format!("{}\nshort", sentence));
+        let state = ShardState::default();
+        let (identifications, _, _) = p.process_record(record, cls.as_ref(), &state).unwrap();
+        assert_eq!(identifications, vec![(sentence.clone(), "fr", 0.8, 0)]);
+
+        // unknown labels are dropped
+        let ids = p.identify_sentence(&sentence, cls.as_ref()).unwrap();
+        let langs: Vec<&str> = ids.iter().map(|(_, lang, _)| *lang).collect();
+        assert_eq!(langs, vec!["fr", "en"]);
+    }
+
+    #[test]
+    fn test_extra_labels() {
+        let p = OscarMetadata::new(
+            vec![PathBuf::new()],
+            PathBuf::new(),
+            PathBuf::new(),
+            1,
+            None,
+        )
+        .with_identifier(Arc::new(French));
+        let extra_labels = vec![("not-a-lang".to_string(), "not-a-lang-either")]
+            .into_iter()
+            .collect();
+        assert!(p.with_extra_labels(extra_labels).is_err());
+
+        let p = OscarMetadata::new(
+            vec![PathBuf::new()],
+            PathBuf::new(),
+            PathBuf::new(),
+            1,
+            None,
+        )
+        .with_identifier(Arc::new(French))
+        .with_extra_labels(vec![("not-a-lang".to_string(), "br")].into_iter().collect())
+        .unwrap();
+        let cls = p.classifier().unwrap();
+
+        // mapped labels are routed to their target language
+        let sentence = "a".repeat(101);
+        let ids = p.identify_sentence(&sentence, cls.as_ref()).unwrap();
+        let langs: Vec<&str> = ids.iter().map(|(_, lang, _)| *lang).collect();
+        assert_eq!(langs, vec!["fr", "br", "en"]);
+        assert_eq!(ids[1].2, 0.5);
+    }
+
+    /// emits labels with stray whitespace and casing, as some models do.
+    struct Untidy;
+
+    impl LanguageIdentifier for Untidy {
+        fn predict(&self, _: &str) -> Result<Option<Vec<Prediction>>, Error> {
+            Ok(Some(
+                [(" fr\n", 0.8), ("EN ", 0.1), (" not-a-lang ", 0.05)]
+                    .iter()
+                    .map(|(label, prob)| Prediction {
+                        label: label.to_string(),
+                        prob: *prob,
+                    })
+                    .collect(),
+            ))
+        }
+    }
+
+    #[test]
+    fn test_identify_sentence_untidy_labels() {
+        let p = OscarMetadata::new(
+            vec![PathBuf::new()],
+            PathBuf::new(),
+            PathBuf::new(),
+            1,
+            None,
+        )
+        .with_identifier(Arc::new(Untidy))
+        .with_extra_labels(vec![("not-a-lang".to_string(), "br")].into_iter().collect())
+        .unwrap();
+        let cls = p.classifier().unwrap();
+
+        let sentence = "a".repeat(101);
+        let ids = p.identify_sentence(&sentence, cls.as_ref()).unwrap();
+        let langs: Vec<&str> = ids.iter().map(|(_, lang, _)| *lang).collect();
+        assert_eq!(langs, vec!["fr", "en", "br"]);
+
+        // sentences are kept rather than discarded
+        let record: Record<EmptyBody> = Record::default();
+        let record = record.add_body(sentence.clone());
+        let (identifications, _, _) = p
+            .process_record(record, cls.as_ref(), &ShardState::default())
+            .unwrap();
+        assert_eq!(identifications, vec![(sentence, "fr", 0.8, 0)]);
+    }
+
+    #[test]
+    fn test_language_hint() {
+        let p = OscarMetadata::new(
+            vec![PathBuf::new()],
+            PathBuf::new(),
+            PathBuf::new(),
+            1,
+            None,
+        );
+        let hint = |p: &OscarMetadata, headers: Vec<(&str, &str)>| {
+            let mut record: Record<EmptyBody> = Record::default();
+            for (name, value) in headers {
+                record
+                    .set_header(WarcHeader::Unknown(name.to_string()), value)
+                    .unwrap();
+            }
+            p.language_hint(&record.add_body("").into_raw_parts().0.headers)
+        };
+        let headers = vec![("content-language", "en-US")];
+        // disabled without a bias
+        assert_eq!(hint(&p, headers.clone()), None);
+
+        let p = p.with_header_language_bias(Some(0.1)).unwrap();
+        assert_eq!(hint(&p, headers), Some("en"));
+        assert_eq!(hint(&p, vec![("content-language", "gsw, de")]), Some("als"));
+        assert_eq!(hint(&p, vec![("content-language", "xx-YY")]), None);
+        // identified languages take precedence over the http header
+        assert_eq!(
+            hint(
+                &p,
+                vec![
+                    ("content-language", "en"),
+                    ("warc-identified-content-language", "xxx,fra,eng")
+                ]
+            ),
+            Some("fr")
+        );
+    }
+
+    #[test]
+    fn test_process_record_header_language_bias() {
+        let p = OscarMetadata::new(
+            vec![PathBuf::new()],
+            PathBuf::new(),
+            PathBuf::new(),
+            1,
+            None,
+        );
+        assert!(p.with_header_language_bias(Some(1.5)).is_err());
+
+        let process = |bias| {
+            let p = OscarMetadata::new(
+                vec![PathBuf::new()],
+                PathBuf::new(),
+                PathBuf::new(),
+                1,
+                None,
+            )
+            .with_identifier(Arc::new(French))
+            .with_header_language_bias(bias)
+            .unwrap();
+            let cls = p.classifier().unwrap();
+            let mut record: Record<EmptyBody> = Record::default();
+            record
+                .set_header(WarcHeader::Unknown("content-language".to_string()), "en-US")
+                .unwrap();
+            let state = ShardState::default();
+            let (sentences, _, _) = p
+                .process_record(record.add_body("a".repeat(101)), cls.as_ref(), &state)
+                .unwrap();
+            sentences
+                .iter()
+                .map(|(_, lang, prob, _)| (*lang, *prob))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(process(None), vec![("fr", 0.8)]);
+        assert_eq!(process(Some(0.5)), vec![("fr", 0.8)]);
+        // probabilities are kept as is
+        assert_eq!(process(Some(0.75)), vec![("en", 0.1)]);
+    }
+
+    #[test]
+    fn test_process_record_splitter() {
+        let p = OscarMetadata::new(
+            vec![PathBuf::new()],
+            PathBuf::new(),
+            PathBuf::new(),
+            1,
+            None,
+        )
+        .with_identifier(Arc::new(French))
+        .with_sentence_chars(10, None)
+        .with_sentence_splitter(Box::new(Punctuation));
+        let cls = p.classifier().unwrap();
+
+        let body = "first sentence here. second sentence here! tiny.\nthird sentence here";
+        let record: Record<EmptyBody> = Record::default();
+        let state = ShardState::default();
+        let processed = p
+            .process_record(record.add_body(body), cls.as_ref(), &state)
+            .unwrap();
+
+        // split sentences keep their line number and their order
+        let sentences: Vec<(&str, usize)> = processed
+            .0
+            .iter()
+            .map(|(sentence, _, _, line)| (sentence.as_str(), *line))
+            .collect();
+        assert_eq!(
+            sentences,
+            vec![
+                ("first sentence here.", 0),
+                ("second sentence here!", 0),
+                ("third sentence here", 1)
+            ]
+        );
+        assert_eq!(state.discarded.into_inner(), 1);
+
+        let pieces = p.merge_record(processed);
+        assert_eq!(pieces.len(), 1);
+        // ranges expand to the line number of each sentence
+        assert_eq!(pieces[0].line_ranges, vec![(0, 1), (0, 2)]);
+    }
+
+    #[test]
+    fn test_is_blank() {
+        assert!(OscarMetadata::is_blank(""));
+        assert!(OscarMetadata::is_blank(" \t\u{3000}"));
+        assert!(OscarMetadata::is_blank("\u{FEFF}\u{200B} \u{2060}"));
+        assert!(!OscarMetadata::is_blank(" a "));
+    }
+
+    #[test]
+    fn test_process_record_blank() {
+        let p = OscarMetadata::new(
+            vec![PathBuf::new()],
+            PathBuf::new(),
+            PathBuf::new(),
+            1,
+            None,
+        )
+        .with_identifier(Arc::new(French));
+        let cls = p.classifier().unwrap();
+
+        // blank lines that are longer than 100 chars because of padding
+        let sentence = "a".repeat(101);
+        let blank = format!("{}\u{200B}", " ".repeat(200));
+        let body = format!("{}\n{}\n\nshort", blank, sentence);
+
+        let record: Record<EmptyBody> = Record::default();
+        let state = ShardState::default();
+        let (identifications, _, _) = p
+            .process_record(record.add_body(body.clone()), cls.as_ref(), &state)
+            .unwrap();
+        assert_eq!(identifications, vec![(sentence.clone(), "fr", 0.8, 1)]);
+        assert_eq!(state.blank.into_inner(), 2);
+        assert_eq!(state.discarded.into_inner(), 1);
+
+        // blank lines only go through the length filter
+        let p = p.with_skip_blank(false);
+        let record: Record<EmptyBody> = Record::default();
+        let state = ShardState::default();
+        let (identifications, _, _) = p
+            .process_record(record.add_body(body), cls.as_ref(), &state)
+            .unwrap();
+        assert_eq!(identifications.len(), 2);
+        assert_eq!(identifications[0].0, blank);
+        assert_eq!(state.blank.into_inner(), 0);
+        assert_eq!(state.discarded.into_inner(), 2);
+    }
+
+    /// identifies sentences as French, except sentences starting with `x` that are not reliable enough.
+    struct Unreliable;
+
+    impl LanguageIdentifier for Unreliable {
+        fn predict(&self, text: &str) -> Result<Option<Vec<Prediction>>, Error> {
+            if text.starts_with('x') {
+                return Ok(None);
+            }
+            French.predict(text)
+        }
+
+        fn predict_best(&self, _: &str) -> Result<Option<Prediction>, Error> {
+            Ok(Some(Prediction {
+                label: "fr".to_string(),
+                prob: 0.3,
+            }))
+        }
+    }
+
+    #[test]
+    fn test_process_record_rejects() {
+        let dst = tempfile::tempdir().unwrap();
+        let p = OscarMetadata::new(
+            vec![PathBuf::new()],
+            PathBuf::new(),
+            PathBuf::new(),
+            1,
+            None,
+        )
+        .with_identifier(Arc::new(Unreliable))
+        .with_sentence_chars(100, Some(200));
+        let cls = p.classifier().unwrap();
+
+        let kept = "a".repeat(101);
+        let unreliable = "x".repeat(101);
+        let body = format!("short\n{}\n\n{}\n{}", kept, unreliable, "b".repeat(201));
+        let mut record = Record::default().add_body(body);
+        record
+            .set_header(WarcHeader::RecordID, "<urn:uuid:0>")
+            .unwrap();
+
+        let state = ShardState {
+            rejects: Some(Arc::new(RejectSink::open(dst.path()).unwrap())),
+            ..Default::default()
+        };
+        let (identifications, _, _) = p.process_record(record, cls.as_ref(), &state).unwrap();
+        assert_eq!(identifications, vec![(kept, "fr", 0.8, 1)]);
+        state.rejects.unwrap().flush().unwrap();
+
+        let content = std::fs::read_to_string(dst.path().join(REJECTS_FILE)).unwrap();
+        let mut rejects: Vec<serde_json::Value> = content
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        rejects.sort_by_key(|r| r["line"].as_u64());
+        let reasons: Vec<(u64, &str)> = rejects
+            .iter()
+            .map(|r| (r["line"].as_u64().unwrap(), r["reason"].as_str().unwrap()))
+            .collect();
+        assert_eq!(
+            reasons,
+            vec![(0, "too_short"), (3, "below_threshold"), (4, "too_long")]
+        );
+        assert!(rejects.iter().all(|r| r["record_id"] == "<urn:uuid:0>"));
+        assert_eq!(rejects[0]["sentence"], "short");
+        assert_eq!(rejects[0]["prediction"], serde_json::Value::Null);
+        assert_eq!(rejects[1]["sentence"], unreliable.as_str());
+        assert_eq!(rejects[1]["prediction"]["label"], "fr");
+        assert_eq!(rejects[1]["prediction"]["prob"], 0.3);
+    }
+
+    #[test]
+    fn test_process_record_rejects_languages() {
+        let dst = tempfile::tempdir().unwrap();
+        let languages = ["en"].into_iter().collect();
+        let p = OscarMetadata::new(
+            vec![PathBuf::new()],
+            PathBuf::new(),
+            PathBuf::new(),
+            1,
+            None,
+        )
+        .with_identifier(Arc::new(Unreliable))
+        .with_languages(languages)
+        .unwrap();
+        let cls = p.classifier().unwrap();
+
+        // best prediction is French, that is not processed
+        let record: Record<EmptyBody> = Record::default();
+        let state = ShardState {
+            rejects: Some(Arc::new(RejectSink::open(dst.path()).unwrap())),
+            ..Default::default()
+        };
+        let (identifications, _, _) = p
+            .process_record(record.add_body("x".repeat(101)), cls.as_ref(), &state)
+            .unwrap();
+        assert!(identifications.is_empty());
+        state.rejects.unwrap().flush().unwrap();
+
+        let content = std::fs::read_to_string(dst.path().join(REJECTS_FILE)).unwrap();
+        assert!(content.is_empty());
+    }
+
+    #[test]
+    fn test_process_record_filter() {
+        let p = OscarMetadata::new(
+            vec![PathBuf::new()],
+            PathBuf::new(),
+            PathBuf::new(),
+            1,
+            None,
+        )
+        .with_identifier(Arc::new(French))
+        .with_record_filter(Some(Box::new(|headers: &WarcHeaders| {
+            headers
+                .get(&WarcHeader::TargetURI)
+                .is_some_and(|uri| !uri.starts_with(b"https://spam.example"))
+        })));
+        let cls = p.classifier().unwrap();
+        let body = "a".repeat(101);
+
+        let state = ShardState::default();
+        for uri in ["https://spam.example/page", "https://example.org/page"] {
+            let mut record = Record::default().add_body(body.clone());
+            record.set_header(WarcHeader::TargetURI, uri).unwrap();
+            let processed = p.process_record(record, cls.as_ref(), &state);
+            assert_eq!(processed.is_some(), uri.starts_with("https://example.org"));
+        }
+
+        // records without headers don't match
+        let record: Record<EmptyBody> = Record::default();
+        assert!(p
+            .process_record(record.add_body(body), cls.as_ref(), &state)
+            .is_none());
+        assert_eq!(state.filtered.into_inner(), 2);
+    }
+
+    #[test]
+    fn test_process_record_url_policy() {
+        let uri = "https://spam.example/page?user=me";
+        let p = OscarMetadata::new(
+            vec![PathBuf::new()],
+            PathBuf::new(),
+            PathBuf::new(),
+            1,
+            None,
+        )
+        .with_identifier(Arc::new(French))
+        .with_record_filter(Some(Box::new(move |headers: &WarcHeaders| {
+            headers
+                .get(&WarcHeader::TargetURI)
+                .is_some_and(|value| value.as_slice() == uri.as_bytes())
+        })))
+        .with_url_policy(Some(Arc::new(StripQuery)));
+        let cls = p.classifier().unwrap();
+
+        // the filter sees the original URL, metadata gets the rewritten one
+        let mut record = Record::default().add_body("a".repeat(101));
+        record.set_header(WarcHeader::TargetURI, uri).unwrap();
+        let (_, headers, _) = p
+            .process_record(record, cls.as_ref(), &ShardState::default())
+            .unwrap();
+        assert_eq!(
+            headers[&WarcHeader::TargetURI],
+            b"https://spam.example/page"
+        );
+    }
+
+    #[test]
+    fn test_process_record_normalized() {
+        let cls = FastText::new_lid().unwrap();
+        let p = OscarMetadata::new(
+            vec![PathBuf::new()],
+            PathBuf::new(),
+            PathBuf::new(),
+            1,
+            None,
+        )
+        .with_normalizer(Some(Box::new(Whitespace)));
+
+        // only longer than 100 chars because of padding
+        let sentence = "english test that is shorter than one hundred characters.";
+        let body = format!("\t\t{}\u{200B}{}\t\t", sentence, " ".repeat(50));
+        let record: Record<EmptyBody> = Record::default();
+        let record = record.add_body(body.clone());
+
+        let state = ShardState::default();
+        let (identifications, _, _) = p.process_record(record, &cls, &state).unwrap();
+        assert!(identifications.is_empty());
+        assert_eq!(state.discarded.into_inner(), 1);
+
+        let p = p.with_normalizer(None);
+        let record: Record<EmptyBody> = Record::default();
+        let record = record.add_body(body);
+        let state = ShardState::default();
+        let (identifications, _, _) = p.process_record(record, &cls, &state).unwrap();
+        assert_eq!(state.discarded.into_inner(), 0);
+        assert_eq!(identifications.len(), 1);
+    }
+
+    #[test]
+    fn test_process_record_normalizer_opt_in() {
+        let p = OscarMetadata::new(
+            vec![PathBuf::new()],
+            PathBuf::new(),
+            PathBuf::new(),
+            1,
+            None,
+        )
+        .with_identifier(Arc::new(French));
+        let cls = p.classifier().unwrap();
+        let sentence = format!("\t{}  {}\u{200B}", "a".repeat(60), "b".repeat(60));
+
+        // sentences are kept as is by default
+        let record: Record<EmptyBody> = Record::default();
+        let (identifications, _, _) = p
+            .process_record(
+                record.add_body(sentence.clone()),
+                cls.as_ref(),
+                &ShardState::default(),
+            )
+            .unwrap();
+        assert_eq!(identifications[0].0, sentence);
+
+        let p = p.with_normalizer(Some(Box::new(Whitespace)));
+        let record: Record<EmptyBody> = Record::default();
+        let (identifications, _, _) = p
+            .process_record(
+                record.add_body(sentence),
+                cls.as_ref(),
+                &ShardState::default(),
+            )
+            .unwrap();
+        assert_eq!(
+            identifications[0].0,
+            format!("{} {}", "a".repeat(60), "b".repeat(60))
+        );
+    }
+
+    #[test]
+    fn test_process_record_keep_short() {
+        let cls = FastText::new_lid().unwrap();
+        let languages = vec!["en"].into_iter().collect();
+        let p = OscarMetadata::new(
+            vec![PathBuf::new()],
+            PathBuf::new(),
+            PathBuf::new(),
+            1,
+            None,
+        )
+        .with_languages(languages)
+        .unwrap()
+        .with_keep_short(true);
+
+        let long = "english test that is longer than one hundred characters, because it has to be kept in the corpus.";
+        let body = format!(
+            "{}\nshort english sentence.\nune phrase française courte.\n{}",
+            long,
+            "a".repeat(10)
+        );
+        let record: Record<EmptyBody> = Record::default();
+        let record = record.add_body(body);
+
+        let state = ShardState::default();
+        let (identifications, _, _) = p.process_record(record, &cls, &state).unwrap();
+        assert_eq!(identifications.len(), 1);
+        assert_eq!(state.discarded.into_inner(), 3);
+
+        // french sentence is not in allowlist
+        let short = state.short.into_inner().unwrap();
+        assert_eq!(short.len(), 1);
+        assert!(short["en"][0].starts_with("short english sentence."));
+    }
+
+    #[test]
+    fn test_write_short() {
+        let dst = tempfile::tempdir().unwrap();
+        let languages = vec!["en", "fr"].into_iter().collect();
+        let langfiles = LangFiles::with_languages(
+            dst.path(),
+            &languages,
+            None,
+            OutputFormat::TextMeta,
+            None,
+            LayoutStrategy::Flat,
+            &FileNaming::default(),
+        )
+        .unwrap()
+        .with_short_writers(
+            dst.path(),
+            None,
+            LayoutStrategy::Flat,
+            &FileNaming::default(),
+        )
+        .unwrap();
+
+        let short = vec![
+            ("en", vec!["a\nb".to_string(), "c".to_string()]),
+            // no writer for de
+            ("de", vec!["d".to_string()]),
+        ]
+        .into_iter()
+        .collect();
+        OscarMetadata::write_short(short, &langfiles).unwrap();
+        langfiles.close_meta().unwrap();
+
+        let content = std::fs::read_to_string(dst.path().join("en_short.txt")).unwrap();
+        assert_eq!(content, "a\nb\n\nc\n\n");
+        assert!(!dst.path().join("fr_short.txt").exists());
+        assert!(!dst.path().join("de_short.txt").exists());
+        assert!(!dst.path().join("en.txt").exists());
+    }
+
+    #[test]
+    fn test_process_range() {
+        let bodies = [
+            "english test that is longer than one hundred characters. english test that is longer than one hundred characters.",
+            "phrase française de plus de cent caractères. Ceci est une phrase française de plus de cent caractères.",
+            "english test that is longer than one hundred characters. english test that is longer than one hundred characters.",
+        ];
+        let dir = tempfile::tempdir().unwrap();
+        let shard_path = dir.path().join("0.txt");
+        let mut writer = WarcWriter::new(std::fs::File::create(&shard_path).unwrap());
+        for body in bodies {
+            let record: Record<BufferedBody> = Record::default().add_body(body);
+            writer.write(&record).unwrap();
+        }
+        drop(writer);
+
+        let p = OscarMetadata::new(
+            vec![PathBuf::new()],
+            PathBuf::new(),
+            PathBuf::from("lid.176.bin"),
+            1,
+            None,
+        );
+        let results = p.process_range(&shard_path, 1, 2).unwrap();
+        assert_eq!(results.len(), 1);
+        let (idx, pieces) = &results[0];
+        assert_eq!(*idx, 1);
+        assert_eq!(pieces.len(), 1);
+        assert_eq!(pieces[0].identification(), "fr");
+        assert_eq!(pieces[0].sentences, bodies[1]);
+    }
+
+    #[test]
+    fn test_process_shard() {
+        let sentence = "a".repeat(101);
+        let dir = tempfile::tempdir().unwrap();
+        let shard_path = dir.path().join("0.txt");
+        let mut writer = WarcWriter::new(std::fs::File::create(&shard_path).unwrap());
+        for body in [format!("{}\nshort", sentence), sentence.clone()] {
+            let record: Record<BufferedBody> = Record::default().add_body(body);
+            writer.write(&record).unwrap();
+        }
+        drop(writer);
+
+        let p = OscarMetadata::new(
+            vec![PathBuf::new()],
+            PathBuf::new(),
+            PathBuf::new(),
+            1,
+            None,
+        )
+        .with_dedup(true);
+        let pieces = p.process_shard(0, &shard_path, &French).unwrap();
+        assert_eq!(pieces.len(), 1);
+        // the sentence of the second record is a duplicate
+        assert_eq!(pieces["fr"].len(), 1);
+        assert_eq!(pieces["fr"][0].sentences, sentence);
+
+        assert!(p
+            .process_shard(1, &dir.path().join("missing.txt"), &French)
+            .is_err());
+    }
+
+    #[test]
+    fn test_estimate() {
+        let sentence = "a".repeat(101);
+        let src = tempfile::tempdir().unwrap();
+        for shard in ["0.txt", "1.txt"] {
+            let mut writer =
+                WarcWriter::new(std::fs::File::create(src.path().join(shard)).unwrap());
+            for body in [format!("{}\nshort", sentence), sentence.clone()] {
+                let record: Record<BufferedBody> = Record::default().add_body(body);
+                writer.write(&record).unwrap();
+            }
+        }
+        // corrupt shards are left out
+        std::fs::write(src.path().join("2.txt"), b"WARC/1.0\r\ngarbage\r\n\r\n").unwrap();
+
+        let dst = tempfile::tempdir().unwrap();
+        let p = OscarMetadata::new(
+            vec![src.path().to_path_buf()],
+            dst.path().to_path_buf(),
+            PathBuf::new(),
+            1,
+            None,
+        )
+        .with_identifier(Arc::new(French));
+        let counts = p.estimate().unwrap();
+        assert_eq!(counts.len(), 1);
+        assert_eq!(counts["fr"], (4, 404));
+
+        // nothing is written
+        assert_eq!(std::fs::read_dir(dst.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_shutdown() {
+        let sentence = "a".repeat(101);
+        let src = tempfile::tempdir().unwrap();
+        for shard in ["0.txt", "1.txt"] {
+            let mut writer =
+                WarcWriter::new(std::fs::File::create(src.path().join(shard)).unwrap());
+            let record: Record<BufferedBody> = Record::default().add_body(sentence.clone());
+            writer.write(&record).unwrap();
+        }
+
+        let dst = tempfile::tempdir().unwrap();
+        let shutdown = Shutdown::new();
+        let p = OscarMetadata::new(
+            vec![src.path().to_path_buf()],
+            dst.path().to_path_buf(),
+            PathBuf::new(),
+            1,
+            None,
+        )
+        .with_identifier(Arc::new(French))
+        .with_languages(["fr"].into_iter().collect())
+        .unwrap()
+        .with_shutdown(Some(shutdown.clone()));
+
+        // nothing is picked up once shutdown is requested
+        shutdown.request();
+        let stats = p.run_with_stats().unwrap();
+        assert_eq!(stats.interrupted_shards(), 2);
+        assert_eq!(stats.failed_shards(), 0);
+        assert!(stats.shard_timings().is_empty());
+        assert!(p.completed_shards().is_empty());
+
+        // skipped shards are processed by the next run
+        let stats = p.with_shutdown(None).run_with_stats().unwrap();
+        assert_eq!(stats.interrupted_shards(), 0);
+        assert_eq!(stats.langs()["fr"].nb_documents, 2);
+    }
+
+    #[test]
+    fn test_resume_metadata() {
+        let src = tempfile::tempdir().unwrap();
+        let dst = tempfile::tempdir().unwrap();
+        let sentence = "a".repeat(101);
+        let write_shard = |idx: usize| {
+            WetBuilder::new()
+                .text(&sentence)
+                .write(&src.path().join(format!("{}.txt.gz", idx)))
+                .unwrap();
+        };
+        let p = OscarMetadata::new(
+            vec![src.path().to_path_buf()],
+            dst.path().to_path_buf(),
+            PathBuf::new(),
+            1,
+            None,
+        )
+        .with_identifier(Arc::new(French))
+        .with_languages(["fr"].into_iter().collect())
+        .unwrap();
+
+        // a first run, then a resumed one with a new shard
+        write_shard(0);
+        p.run_with_stats().unwrap();
+        write_shard(1);
+        let stats = p.run_with_stats().unwrap();
+        assert_eq!(stats.langs()["fr"].nb_documents, 1);
+
+        // the summary covers both runs
+        let summary: serde_json::Value =
+            serde_json::from_reader(std::fs::File::open(dst.path().join(SUMMARY_FILE)).unwrap())
+                .unwrap();
+        assert_eq!(summary["fr"]["nb_documents"], 2);
+        assert_eq!(summary["fr"]["nb_chars"], 202);
+
+        // metadata of both runs is kept, and offsets follow each other
+        let text = std::fs::read_to_string(dst.path().join("fr.txt")).unwrap();
+        assert_eq!(text, format!("{}\n\n{}\n\n", sentence, sentence));
+        let meta = std::fs::read_to_string(dst.path().join("fr_meta.jsonl")).unwrap();
+        let offsets: Vec<_> = meta
+            .lines()
+            .map(|line| serde_json::from_str::<Metadata>(line).unwrap().offset)
+            .collect();
+        assert_eq!(offsets, [0, 2]);
+
+        // Parquet files would be replaced
+        let err = p
+            .with_output_format(OutputFormat::Parquet { row_group_size: 10 })
+            .run_with_stats()
+            .unwrap_err();
+        assert!(err.to_string().contains("Parquet"), "{}", err);
+    }
+
+    #[test]
+    fn test_run_failed_only() {
+        let src = tempfile::tempdir().unwrap();
+        let dst = tempfile::tempdir().unwrap();
+        let sentence = "a".repeat(101);
+        let write_shard = |path: &Path| {
+            let mut writer = WarcWriter::new(std::fs::File::create(path).unwrap());
+            let record: Record<BufferedBody> = Record::default().add_body(sentence.clone());
+            writer.write(&record).unwrap();
+        };
+        write_shard(&src.path().join("0.txt"));
+        let broken = src.path().join("1.txt");
+        std::fs::write(&broken, b"WARC/1.0\r\ngarbage\r\n\r\n").unwrap();
+
+        let p = OscarMetadata::new(
+            vec![src.path().to_path_buf()],
+            dst.path().to_path_buf(),
+            PathBuf::new(),
+            1,
+            None,
+        )
+        .with_identifier(Arc::new(French))
+        .with_languages(["fr"].into_iter().collect())
+        .unwrap();
+
+        // nothing to do before the first run
+        assert_eq!(p.run_failed_only().unwrap().failed_shards(), 0);
+        assert!(!dst.path().join("fr.txt").exists());
+
+        let stats = p.run_with_stats().unwrap();
+        assert_eq!(stats.failed_shards(), 1);
+        assert_eq!(p.failed_shards(), [broken.clone()].into_iter().collect());
+
+        // shards that fail again stay failed
+        assert_eq!(p.run_failed_only().unwrap().failed_shards(), 1);
+        assert_eq!(p.failed_shards().len(), 1);
+
+        // only the fixed shard is processed, and appended to the corpus
+        write_shard(&broken);
+        let stats = p.run_failed_only().unwrap();
+        assert_eq!(stats.failed_shards(), 0);
+        assert_eq!(stats.langs()["fr"].nb_documents, 1);
+        assert!(p.failed_shards().is_empty());
+        assert_eq!(p.completed_shards().len(), 2);
+        let content = std::fs::read_to_string(dst.path().join("fr.txt")).unwrap();
+        assert_eq!(content, format!("{}\n\n{}\n\n", sentence, sentence));
+
+        // metadata of the first run is kept, and offsets of the fixed shard follow it
+        let meta = std::fs::read_to_string(dst.path().join("fr_meta.jsonl")).unwrap();
+        let metadata: Vec<Metadata> = meta
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let offsets: Vec<_> = metadata.iter().map(|m| m.offset).collect();
+        assert_eq!(offsets, [0, 2]);
+        assert!(metadata.iter().all(|m| m.nb_sentences == 1));
+    }
+
+    #[test]
+    fn test_staging() {
+        let src = tempfile::tempdir().unwrap();
+        let dst = tempfile::tempdir().unwrap();
+        WetBuilder::new()
+            .text(&"a".repeat(101))
+            .write(&src.path().join("0.txt.gz"))
+            .unwrap();
+
+        let p = OscarMetadata::new(
+            vec![src.path().to_path_buf()],
+            dst.path().to_path_buf(),
+            PathBuf::new(),
+            1,
+            None,
+        )
+        .with_identifier(Arc::new(French))
+        .with_languages(["fr"].into_iter().collect())
+        .unwrap()
+        .with_staging(true, None);
+        p.run_with_stats().unwrap();
+
+        assert!(dst.path().join("fr.txt").exists());
+        assert!(dst.path().join("fr_meta.jsonl").exists());
+        assert!(!dst.path().join(crate::io::STAGING_DIR).exists());
+
+        // completed shards can't be resumed
+        assert!(p.run_with_stats().is_err());
+    }
+
+    #[test]
+    fn test_summary() {
+        let src = tempfile::tempdir().unwrap();
+        let dst = tempfile::tempdir().unwrap();
+        let sentence = "a".repeat(101);
+        for shard in ["0.txt.gz", "1.txt.gz"] {
+            WetBuilder::new()
+                .text(&format!("{}\n{}", sentence, sentence))
+                .write(&src.path().join(shard))
+                .unwrap();
+        }
+
+        OscarMetadata::new(
+            vec![src.path().to_path_buf()],
+            dst.path().to_path_buf(),
+            PathBuf::new(),
+            1,
+            None,
+        )
+        .with_identifier(Arc::new(French))
+        .with_languages(["fr"].into_iter().collect())
+        .unwrap()
+        .run_with_stats()
+        .unwrap();
+
+        let summary: serde_json::Value =
+            serde_json::from_reader(std::fs::File::open(dst.path().join(SUMMARY_FILE)).unwrap())
+                .unwrap();
+        let fr = &summary["fr"];
+        assert_eq!(fr["nb_documents"], 2);
+        assert_eq!(fr["nb_sentences"], 4);
+        assert_eq!(fr["nb_chars"], 404);
+        assert!((fr["mean_probability"].as_f64().unwrap() - 0.8).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_max_error_rate() {
+        let src = tempfile::tempdir().unwrap();
+        WetBuilder::new()
+            .text(&"a".repeat(101))
+            .record(Vec::new(), vec![0xff; 101])
+            .record(Vec::new(), vec![0xfe; 101])
+            .record(Vec::new(), vec![0xfd; 101])
+            .write(&src.path().join("0.txt.gz"))
+            .unwrap();
+
+        let run = |max_rate| {
+            let dst = tempfile::tempdir().unwrap();
+            let p = OscarMetadata::new(
+                vec![src.path().to_path_buf()],
+                dst.path().to_path_buf(),
+                PathBuf::new(),
+                1,
+                None,
+            )
+            .with_identifier(Arc::new(French))
+            .with_languages(["fr"].into_iter().collect())
+            .unwrap();
+            let p = match max_rate {
+                Some(max_rate) => p.with_max_error_rate(max_rate, 2).unwrap(),
+                None => p,
+            };
+            let stats = p.run_with_stats();
+            (stats, p.completed_shards())
+        };
+
+        // records with invalid bodies are only skipped by default
+        let (stats, _) = run(None);
+        assert_eq!(stats.unwrap().langs()["fr"].nb_documents, 1);
+        let (stats, _) = run(Some(0.8));
+        assert!(stats.is_ok());
+
+        let (stats, completed) = run(Some(0.5));
+        let err = stats.unwrap_err();
+        assert!(err.to_string().contains("error budget exceeded"), "{}", err);
+        assert!(completed.is_empty());
+    }
+
+    /// identifies sentences as french, except for sentences holding "english" (english),
+    /// "unknown" (an unknown label) or "unsure" (no reliable prediction).
+    struct Reasons;
+
+    impl LanguageIdentifier for Reasons {
+        fn predict(&self, text: &str) -> Result<Option<Vec<Prediction>>, Error> {
+            let label = if text.contains("unsure") {
+                return Ok(None);
+            } else if text.contains("english") {
+                "en"
+            } else if text.contains("unknown") {
+                "not-a-lang"
+            } else {
+                "fr"
+            };
+            Ok(Some(vec![Prediction {
+                label: label.to_string(),
+                prob: 0.9,
+            }]))
+        }
+    }
+
+    #[test]
+    fn test_discard_reasons() {
+        let src = tempfile::tempdir().unwrap();
+        let dst = tempfile::tempdir().unwrap();
+        let line = |word: &str| format!("{} {}", word, "a".repeat(101));
+        let body = [
+            line("bonjour"),
+            line("english"),
+            line("unknown"),
+            line("unsure"),
+            line("unsure"),
+            "short".to_string(),
+            " ".repeat(120),
+            "a".repeat(300),
+        ]
+        .join("\n");
+        WetBuilder::new()
+            .text(&body)
+            .record(Vec::new(), vec![0xff; 101])
+            .write(&src.path().join("0.txt.gz"))
+            .unwrap();
+
+        let p = OscarMetadata::new(
+            vec![src.path().to_path_buf()],
+            dst.path().to_path_buf(),
+            PathBuf::new(),
+            1,
+            None,
+        )
+        .with_identifier(Arc::new(Reasons))
+        .with_languages(["fr"].into_iter().collect())
+        .unwrap()
+        .with_sentence_chars(100, Some(200));
+        let stats = p.run_with_stats().unwrap();
+
+        let expected: HashMap<_, _> = [
+            (DiscardReason::TooShort, 1),
+            (DiscardReason::TooLong, 1),
+            (DiscardReason::Blank, 1),
+            (DiscardReason::BelowThreshold, 2),
+            (DiscardReason::UnknownLabel, 1),
+            (DiscardReason::UnprocessedLanguage, 1),
+            (DiscardReason::InvalidUtf8, 1),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(stats.discard_reasons(), &expected);
+        assert_eq!(stats.langs()["fr"].nb_sentences, 1);
+    }
+
+    /// identifies everything as french, failing on sentences holding "fail".
+    struct Failing;
+
+    impl LanguageIdentifier for Failing {
+        fn predict(&self, text: &str) -> Result<Option<Vec<Prediction>>, Error> {
+            if text.contains("fail") {
+                return Err(Error::Custom("corrupt model".to_string()));
+            }
+            Ok(Some(vec![Prediction {
+                label: "fr".to_string(),
+                prob: 0.9,
+            }]))
+        }
+    }
+
+    #[test]
+    fn test_keep_short_predict_errors() {
+        let p = OscarMetadata::new(
+            vec![PathBuf::new()],
+            PathBuf::new(),
+            PathBuf::new(),
+            1,
+            None,
+        )
+        .with_identifier(Arc::new(Failing))
+        .with_keep_short(true)
+        .with_max_predict_errors(0);
+        let cls = p.classifier().unwrap();
+
+        let sentence = "a".repeat(101);
+        let body = format!("{}\nshort\nshort fail", sentence);
+        let record: Record<EmptyBody> = Record::default();
+        let state = ShardState::default();
+        let (identifications, _, _) = p
+            .process_record(record.add_body(body), cls.as_ref(), &state)
+            .unwrap();
+        assert_eq!(identifications.len(), 1);
+
+        // the failing short sentence is dropped, but doesn't fail the record or the shard
+        assert_eq!(state.short.lock().unwrap()["fr"], vec!["short".to_string()]);
+        assert_eq!(state.predict_errors.load(Ordering::Relaxed), 0);
+        assert_eq!(state.failed.load(Ordering::Relaxed), 0);
+        assert!(p.check_predict_errors(0, &state).is_ok());
+    }
+
+    /// identifies everything as french, stalling on sentences holding "stall".
+    struct Stalling;
+
+    impl LanguageIdentifier for Stalling {
+        fn predict(&self, text: &str) -> Result<Option<Vec<Prediction>>, Error> {
+            if text.contains("stall") {
+                std::thread::sleep(Duration::from_secs(2));
+            }
+            Ok(Some(vec![Prediction {
+                label: "fr".to_string(),
+                prob: 0.9,
+            }]))
+        }
+    }
+
+    #[test]
+    fn test_predict_timeout() {
+        let src = tempfile::tempdir().unwrap();
+        let dst = tempfile::tempdir().unwrap();
+        let sentence = "a".repeat(101);
+        let stalling = format!("stall {}", sentence);
+        WetBuilder::new()
+            .text(&format!("{}\n{}", sentence, stalling))
+            .text(&sentence)
+            .write(&src.path().join("0.txt.gz"))
+            .unwrap();
+
+        let p = OscarMetadata::new(
+            vec![src.path().to_path_buf()],
+            dst.path().to_path_buf(),
+            PathBuf::new(),
+            1,
+            None,
+        )
+        .with_identifier(Arc::new(Stalling))
+        .with_languages(["fr"].into_iter().collect())
+        .unwrap()
+        .with_predict_timeout(Some(Duration::from_millis(100)));
+        let stats = p.run_with_stats().unwrap();
+
+        // the stalled sentence is skipped, other sentences of its record are kept
+        assert_eq!(stats.predict_errors(), 1);
+        assert_eq!(stats.langs()["fr"].nb_documents, 2);
+        assert_eq!(stats.langs()["fr"].nb_sentences, 2);
+    }
+
+    /// identifies everything as french, recording the size of the pools it's called in.
+    #[derive(Default)]
+    struct PoolSizes(Mutex<HashSet<usize>>);
+
+    impl LanguageIdentifier for PoolSizes {
+        fn predict(&self, _: &str) -> Result<Option<Vec<Prediction>>, Error> {
+            self.0.lock().unwrap().insert(rayon::current_num_threads());
+            Ok(Some(vec![Prediction {
+                label: "fr".to_string(),
+                prob: 0.9,
+            }]))
+        }
+    }
+
+    #[test]
+    fn test_with_threads() {
+        let src = tempfile::tempdir().unwrap();
+        for shard in 0..2 {
+            WetBuilder::new()
+                .text(&"a".repeat(101))
+                .text(&"b".repeat(101))
+                .write(&src.path().join(format!("{}.txt.gz", shard)))
+                .unwrap();
+        }
+
+        let run = |threads: usize, shard_concurrency: Option<usize>| {
+            let dst = tempfile::tempdir().unwrap();
+            let identifier = Arc::new(PoolSizes::default());
+            let p = OscarMetadata::new(
+                vec![src.path().to_path_buf()],
+                dst.path().to_path_buf(),
+                PathBuf::new(),
+                1,
+                None,
+            )
+            .with_identifier(identifier.clone())
+            .with_threads(threads)
+            .with_thread_pinning(ThreadPinning::Compact);
+            let p = match shard_concurrency {
+                Some(nb) => p.with_shard_concurrency(nb),
+                None => p,
+            };
+            let stats = p.run_with_stats();
+            let sizes = identifier.0.lock().unwrap().clone();
+            (stats, sizes)
+        };
+
+        // records are identified in the dedicated pool
+        let (stats, sizes) = run(3, None);
+        assert_eq!(stats.unwrap().langs()["fr"].nb_documents, 4);
+        assert_eq!(sizes, [3].into_iter().collect());
+        let (stats, sizes) = run(3, Some(1));
+        assert_eq!(stats.unwrap().langs()["fr"].nb_documents, 4);
+        assert_eq!(sizes, [3].into_iter().collect());
+
+        assert!(run(0, None).0.is_err());
+    }
+
+    #[test]
+    fn test_process_record_wet_reader() {
+        let cls = FastText::new_lid().unwrap();
+        let p = OscarMetadata::new(
+            vec![PathBuf::new()],
+            PathBuf::new(),
+            PathBuf::new(),
+            1,
+            None,
+        );
+
+        let bodies = [
+            ("en", "english test that is longer than one hundred characters. english test that is longer than one hundred characters."),
+            ("fr", "phrase française de plus de cent caractères. Ceci est une phrase française de plus de cent caractères."),
+        ];
+        let mut buf = Vec::new();
+        let mut writer = WarcWriter::new(&mut buf);
+        for (_, body) in bodies {
+            let record: Record<BufferedBody> = Record::default().add_body(body);
+            writer.write(&record).unwrap();
+        }
+
+        let shard = Wet::from_reader(Cursor::new(buf));
+        let state = ShardState::default();
+        let results: Vec<_> = shard
+            .iter
+            .map(|record| p.process_record(record.unwrap(), &cls, &state).unwrap())
+            .collect();
+
+        assert_eq!(results.len(), 2);
+        for ((sentences, _, _), (lang, body)) in results.iter().zip(bodies) {
+            assert_eq!(sentences.len(), 1);
+            assert_eq!(sentences[0].0, body);
+            assert_eq!(sentences[0].1, lang);
+        }
+    }
+
+    #[test]
+    fn test_process_record_wet_builder() {
+        let p = OscarMetadata::new(
+            vec![PathBuf::new()],
+            PathBuf::new(),
+            PathBuf::new(),
+            1,
+            None,
+        )
+        .with_identifier(Arc::new(French));
+        let cls = p.classifier().unwrap();
+
+        let sentence = "a".repeat(101);
+        let mut invalid = sentence.clone().into_bytes();
+        invalid.push(0xff);
+        let shard = WetBuilder::new()
+            .record(
+                vec![(WarcHeader::RecordID, "<urn:uuid:0>".to_string())],
+                format!("{}\nshort", sentence),
+            )
+            .record(Vec::new(), invalid)
+            .text(&sentence)
+            .text(&sentence)
+            .with_truncated_tail(10)
+            .build()
+            .unwrap();
+
+        let state = ShardState::default();
+        let mut records = Wet::from_reader_gzip(shard.as_slice()).records();
+        let results: Vec<_> = records
+            .by_ref()
+            .take(3)
+            .map(|record| p.process_record(record.unwrap(), cls.as_ref(), &state))
+            .collect();
+
+        // non UTF-8 bodies are skipped
+        assert_eq!(results.len(), 3);
+        let (sentences, headers, _) = results[0].as_ref().unwrap();
+        assert_eq!(sentences, &vec![(sentence.clone(), "fr", 0.8, 0)]);
+        assert_eq!(headers[&WarcHeader::RecordID], b"<urn:uuid:0>".to_vec());
+        assert!(results[1].is_none());
+        assert_eq!(results[2].as_ref().unwrap().0.len(), 1);
+        assert_eq!(state.discarded.into_inner(), 1);
+
+        // last record is truncated
+        assert!(matches!(
+            records.next(),
+            Some(Err(Error::TruncatedRecord(_)))
+        ));
+        assert!(records.next().is_none());
     }
 }
@@ -0,0 +1,150 @@
+//! Rejected sentences.
+//!
+//! [RejectSink] records sentences dropped by the length filter or by the identification thresholds,
+//! so that thresholds can be tuned by looking at what has been thrown away.
+use std::{
+    borrow::Cow,
+    fs::{File, OpenOptions},
+    io::{BufWriter, Write},
+    path::Path,
+    sync::Mutex,
+};
+
+use serde::Serialize;
+use warc::WarcHeader;
+
+use super::types::WarcHeaders;
+use crate::error::Error;
+
+/// Name of the file (in `dst`) holding rejected sentences, one JSON object per line.
+pub(super) const REJECTS_FILE: &str = "rejects.jsonl";
+
+/// Why a sentence has been rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(super) enum RejectReason {
+    /// Shorter than the minimum sentence length.
+    TooShort,
+    /// Longer than the maximum sentence length.
+    TooLong,
+    /// No prediction met the identification thresholds.
+    BelowThreshold,
+}
+
+/// Best prediction of a sentence that has been rejected because of thresholds.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(super) struct RejectPrediction {
+    pub label: String,
+    pub prob: f32,
+}
+
+#[derive(Serialize)]
+struct Reject<'a> {
+    record_id: Option<Cow<'a, str>>,
+    line: usize,
+    reason: RejectReason,
+    sentence: &'a str,
+    prediction: Option<&'a RejectPrediction>,
+}
+
+/// Buffered, shared writer of rejected sentences.
+#[derive(Debug)]
+pub(super) struct RejectSink {
+    writer: Mutex<BufWriter<File>>,
+}
+
+impl RejectSink {
+    /// Open [REJECTS_FILE] in `dst`, appending to it if it already exists.
+    pub fn open(dst: &Path) -> Result<Self, Error> {
+        let f = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(dst.join(REJECTS_FILE))?;
+        Ok(Self {
+            writer: Mutex::new(BufWriter::new(f)),
+        })
+    }
+
+    /// Write a rejected sentence of line `line` of the record of provided headers.
+    ///
+    /// `prediction` is the best (unreliable) prediction of sentences that are [RejectReason::BelowThreshold].
+    pub fn write(
+        &self,
+        headers: &WarcHeaders,
+        line: usize,
+        reason: RejectReason,
+        sentence: &str,
+        prediction: Option<&RejectPrediction>,
+    ) -> Result<(), Error> {
+        let reject = Reject {
+            record_id: headers
+                .get(&WarcHeader::RecordID)
+                .map(|id| String::from_utf8_lossy(id)),
+            line,
+            reason,
+            sentence,
+            prediction,
+        };
+        let mut entry = serde_json::to_string(&reject)?;
+        entry.push('\n');
+
+        self.writer.lock().unwrap().write_all(entry.as_bytes())?;
+        Ok(())
+    }
+
+    /// Flush buffered rejects.
+    pub fn flush(&self) -> Result<(), Error> {
+        self.writer.lock().unwrap().flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde_json::Value;
+    use warc::WarcHeader;
+
+    use super::{RejectPrediction, RejectReason, RejectSink, REJECTS_FILE};
+
+    #[test]
+    fn write() {
+        let dst = tempfile::tempdir().unwrap();
+        let mut headers = HashMap::new();
+        headers.insert(WarcHeader::RecordID, b"<urn:uuid:0>".to_vec());
+
+        let sink = RejectSink::open(dst.path()).unwrap();
+        sink.write(&headers, 2, RejectReason::TooShort, "short", None)
+            .unwrap();
+        let prediction = RejectPrediction {
+            label: "fr".to_string(),
+            prob: 0.5,
+        };
+        sink.write(
+            &HashMap::new(),
+            0,
+            RejectReason::BelowThreshold,
+            "peut-être",
+            Some(&prediction),
+        )
+        .unwrap();
+        sink.flush().unwrap();
+
+        let content = std::fs::read_to_string(dst.path().join(REJECTS_FILE)).unwrap();
+        let rejects: Vec<Value> = content
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(rejects.len(), 2);
+        assert_eq!(rejects[0]["record_id"], "<urn:uuid:0>");
+        assert_eq!(rejects[0]["line"], 2);
+        assert_eq!(rejects[0]["reason"], "too_short");
+        assert_eq!(rejects[0]["prediction"], Value::Null);
+        assert_eq!(rejects[1]["record_id"], Value::Null);
+        assert_eq!(rejects[1]["reason"], "below_threshold");
+        assert_eq!(rejects[1]["sentence"], "peut-être");
+        assert_eq!(rejects[1]["prediction"]["label"], "fr");
+        assert_eq!(rejects[1]["prediction"]["prob"], 0.5);
+    }
+}
@@ -0,0 +1,472 @@
+//! Run statistics.
+//!
+//! [RunStats] holds per-language totals of the written corpus,
+//! enabling the generation of a summary without re-scanning the output,
+//! along with the timing of each shard (see [ShardTiming])
+//! and the reasons why sentences have been discarded (see [DiscardReason]).
+//!
+//! Once a run is done, per-language totals are summarized (see [RunStats::summary]).
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::types::MergedPiece;
+
+/// Totals for a given language.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct LangStats {
+    /// Number of documents (written [MergedPiece]).
+    pub nb_documents: usize,
+    /// Number of kept sentences.
+    pub nb_sentences: usize,
+    /// Number of characters (see [str::chars]) of kept sentences.
+    pub nb_chars: usize,
+    /// Number of bytes of kept sentences, excluding document separators.
+    pub nb_bytes: usize,
+    /// Sum of document confidences (see [MergedPiece::confidence]), weighted by their number of characters.
+    #[serde(skip)]
+    weighted_confidence: f64,
+}
+
+impl LangStats {
+    /// Account for a written piece.
+    fn add_piece(&mut self, piece: &MergedPiece) {
+        self.nb_documents += 1;
+        self.nb_sentences += piece.nb_sentences;
+        self.nb_chars += piece.nb_chars();
+        self.nb_bytes += piece.sentences.len();
+        self.weighted_confidence += piece.confidence as f64 * piece.nb_chars() as f64;
+    }
+
+    /// Add other's totals to self.
+    fn merge(&mut self, other: &LangStats) {
+        self.nb_documents += other.nb_documents;
+        self.nb_sentences += other.nb_sentences;
+        self.nb_chars += other.nb_chars;
+        self.nb_bytes += other.nb_bytes;
+        self.weighted_confidence += other.weighted_confidence;
+    }
+
+    /// Mean identification probability of kept sentences, weighted by their length.
+    ///
+    /// Since document confidences are weighted the same way, this is the
+    /// length-weighted mean of document confidences. Returns `1.0` if there are no characters.
+    pub fn mean_probability(&self) -> f32 {
+        if self.nb_chars == 0 {
+            1.0
+        } else {
+            (self.weighted_confidence / self.nb_chars as f64) as f32
+        }
+    }
+}
+
+/// Summary of a language, as written in the corpus summary (see [RunStats::summary]).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LangSummary {
+    /// Number of documents.
+    pub nb_documents: usize,
+    /// Number of sentences.
+    pub nb_sentences: usize,
+    /// Number of characters (see [str::chars]).
+    pub nb_chars: usize,
+    /// Mean identification probability (see [LangStats::mean_probability]).
+    pub mean_probability: f32,
+}
+
+impl LangSummary {
+    /// Add other's totals to self, such as the summary of a previous run of the same corpus.
+    ///
+    /// Mean probabilities are weighted by the number of characters of each summary.
+    pub fn merge(&mut self, other: &LangSummary) {
+        let nb_chars = self.nb_chars + other.nb_chars;
+        if nb_chars > 0 {
+            self.mean_probability = ((self.mean_probability as f64 * self.nb_chars as f64
+                + other.mean_probability as f64 * other.nb_chars as f64)
+                / nb_chars as f64) as f32;
+        }
+        self.nb_documents += other.nb_documents;
+        self.nb_sentences += other.nb_sentences;
+        self.nb_chars = nb_chars;
+    }
+}
+
+impl From<&LangStats> for LangSummary {
+    fn from(stats: &LangStats) -> Self {
+        Self {
+            nb_documents: stats.nb_documents,
+            nb_sentences: stats.nb_sentences,
+            nb_chars: stats.nb_chars,
+            mean_probability: stats.mean_probability(),
+        }
+    }
+}
+
+/// Why a sentence has been discarded (see [RunStats::discard_reasons]).
+///
+/// Reasons are serialized in snake case (such as `below_threshold`), and variants are never renamed,
+/// so that breakdowns can be compared across runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiscardReason {
+    /// Shorter than the minimum sentence length (see [super::OscarMetadata::with_sentence_chars]).
+    TooShort,
+    /// Longer than the maximum sentence length.
+    TooLong,
+    /// Empty once trimmed (see [super::OscarMetadata::with_skip_blank]).
+    Blank,
+    /// No prediction met the identification thresholds.
+    BelowThreshold,
+    /// Predicted labels are not known languages (see [crate::lang::LANG]).
+    UnknownLabel,
+    /// Predicted languages are not processed (see [super::OscarMetadata::with_languages]).
+    UnprocessedLanguage,
+    /// The record body is not valid UTF-8 (see [super::OscarMetadata::with_lossy_utf8]).
+    ///
+    /// Such bodies are never split into sentences, so records are counted instead.
+    InvalidUtf8,
+}
+
+impl DiscardReason {
+    /// Every reason, in declaration order.
+    pub const ALL: [DiscardReason; 7] = [
+        Self::TooShort,
+        Self::TooLong,
+        Self::Blank,
+        Self::BelowThreshold,
+        Self::UnknownLabel,
+        Self::UnprocessedLanguage,
+        Self::InvalidUtf8,
+    ];
+}
+
+/// Wall-clock duration of the processing of a shard, from its opening to the completion of its writes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ShardTiming {
+    /// Index of the shard.
+    pub idx: usize,
+    /// Path of the shard.
+    pub path: PathBuf,
+    /// Wall-clock duration of the processing.
+    pub duration: Duration,
+    /// Number of records that have been processed (not counting corrupt or oversized ones).
+    pub nb_records: usize,
+    /// Whether the shard could not be processed.
+    pub failed: bool,
+}
+
+/// Statistics of a pipeline run.
+///
+/// Sentences discarded by the length filter or skipped for being blank are never identified,
+/// so they are counted across all languages, as are records skipped by the record filter or for being too large.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct RunStats {
+    langs: HashMap<&'static str, LangStats>,
+    discarded_sentences: usize,
+    blank_sentences: usize,
+    filtered_records: usize,
+    oversized_records: usize,
+    predict_errors: usize,
+    failed_shards: usize,
+    interrupted_shards: usize,
+    capped_pieces: usize,
+    discard_reasons: HashMap<DiscardReason, usize>,
+    shard_timings: Vec<ShardTiming>,
+}
+
+impl RunStats {
+    /// Get a reference to the per-language statistics.
+    pub fn langs(&self) -> &HashMap<&'static str, LangStats> {
+        &self.langs
+    }
+
+    /// Get the number of sentences discarded by the length filter.
+    pub fn discarded_sentences(&self) -> usize {
+        self.discarded_sentences
+    }
+
+    /// Get the number of sentences skipped for being blank.
+    pub fn blank_sentences(&self) -> usize {
+        self.blank_sentences
+    }
+
+    /// Get the number of records skipped by the record filter.
+    pub fn filtered_records(&self) -> usize {
+        self.filtered_records
+    }
+
+    /// Get the number of records skipped for being too large
+    /// (see [crate::pipelines::OscarMetadata::with_max_record_bytes]).
+    pub fn oversized_records(&self) -> usize {
+        self.oversized_records
+    }
+
+    /// Get the number of sentences whose identification failed.
+    pub fn predict_errors(&self) -> usize {
+        self.predict_errors
+    }
+
+    /// Get the number of shards that could not be processed.
+    pub fn failed_shards(&self) -> usize {
+        self.failed_shards
+    }
+
+    /// Get the number of shards left unprocessed because of a shutdown request.
+    pub fn interrupted_shards(&self) -> usize {
+        self.interrupted_shards
+    }
+
+    /// Get the number of pieces dropped because their language reached its cap
+    /// (see [crate::io::LangFiles::with_caps]). These are not accounted for in [RunStats::langs].
+    pub fn capped_pieces(&self) -> usize {
+        self.capped_pieces
+    }
+
+    /// Get the number of discarded sentences for each reason that discarded some
+    /// (records, for [DiscardReason::InvalidUtf8]).
+    ///
+    /// Sentences discarded by the length filter (see [RunStats::discarded_sentences])
+    /// are broken down into [DiscardReason::TooShort] and [DiscardReason::TooLong],
+    /// and blank sentences (see [RunStats::blank_sentences]) are counted as [DiscardReason::Blank].
+    /// Sentences whose identification failed are not discarded but counted in [RunStats::predict_errors].
+    pub fn discard_reasons(&self) -> &HashMap<DiscardReason, usize> {
+        &self.discard_reasons
+    }
+
+    /// Get the timings of processed shards, slowest first.
+    pub fn shard_timings(&self) -> &[ShardTiming] {
+        &self.shard_timings
+    }
+
+    /// Summarize per-language totals, sorted by language.
+    pub fn summary(&self) -> BTreeMap<&'static str, LangSummary> {
+        self.langs
+            .iter()
+            .map(|(lang, stats)| (*lang, LangSummary::from(stats)))
+            .collect()
+    }
+
+    /// Account for pieces written in `lang`.
+    pub fn add_pieces(&mut self, lang: &'static str, pieces: &[MergedPiece]) {
+        let stats = self.langs.entry(lang).or_default();
+        for piece in pieces {
+            stats.add_piece(piece);
+        }
+    }
+
+    /// Account for discarded sentences.
+    pub fn add_discarded(&mut self, nb: usize) {
+        self.discarded_sentences += nb;
+    }
+
+    /// Account for blank sentences.
+    pub fn add_blank(&mut self, nb: usize) {
+        self.blank_sentences += nb;
+    }
+
+    /// Account for filtered records.
+    pub fn add_filtered(&mut self, nb: usize) {
+        self.filtered_records += nb;
+    }
+
+    /// Account for oversized records.
+    pub fn add_oversized(&mut self, nb: usize) {
+        self.oversized_records += nb;
+    }
+
+    /// Account for failed identifications.
+    pub fn add_predict_errors(&mut self, nb: usize) {
+        self.predict_errors += nb;
+    }
+
+    /// Account for failed shards.
+    pub fn add_failed_shards(&mut self, nb: usize) {
+        self.failed_shards += nb;
+    }
+
+    /// Account for shards left unprocessed because of a shutdown request.
+    pub fn add_interrupted_shards(&mut self, nb: usize) {
+        self.interrupted_shards += nb;
+    }
+
+    /// Account for `nb` sentences discarded because of `reason`.
+    pub fn add_discard_reason(&mut self, reason: DiscardReason, nb: usize) {
+        if nb > 0 {
+            *self.discard_reasons.entry(reason).or_default() += nb;
+        }
+    }
+
+    /// Account for pieces dropped by language caps.
+    pub fn add_capped(&mut self, nb: usize) {
+        self.capped_pieces += nb;
+    }
+
+    /// Account for the timing of a shard, keeping timings sorted by decreasing duration.
+    pub fn add_shard_timing(&mut self, timing: ShardTiming) {
+        let pos = self
+            .shard_timings
+            .partition_point(|t| t.duration >= timing.duration);
+        self.shard_timings.insert(pos, timing);
+    }
+
+    /// Add other's totals to self.
+    pub fn merge(&mut self, other: &RunStats) {
+        for (lang, stats) in &other.langs {
+            self.langs.entry(lang).or_default().merge(stats);
+        }
+        self.discarded_sentences += other.discarded_sentences;
+        self.blank_sentences += other.blank_sentences;
+        self.filtered_records += other.filtered_records;
+        self.oversized_records += other.oversized_records;
+        self.predict_errors += other.predict_errors;
+        self.failed_shards += other.failed_shards;
+        self.interrupted_shards += other.interrupted_shards;
+        self.capped_pieces += other.capped_pieces;
+        for (reason, nb) in &other.discard_reasons {
+            self.add_discard_reason(*reason, *nb);
+        }
+        for timing in &other.shard_timings {
+            self.add_shard_timing(timing.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn piece(sentences: &[&str], identification: &'static str) -> MergedPiece {
+        MergedPiece::new(
+            HashMap::new(),
+            sentences.iter().map(|s| s.to_string()).collect(),
+            identification,
+        )
+    }
+
+    #[test]
+    fn add_pieces() {
+        let mut stats = RunStats::default();
+        stats.add_pieces("fr", &[piece(&["héhé", "ok"], "fr"), piece(&["abc"], "fr")]);
+        stats.add_discarded(3);
+
+        let fr = &stats.langs()["fr"];
+        assert_eq!(fr.nb_documents, 2);
+        assert_eq!(fr.nb_sentences, 3);
+        assert_eq!(fr.nb_chars, 9);
+        assert_eq!(fr.nb_bytes, 12);
+        assert_eq!(stats.discarded_sentences(), 3);
+    }
+
+    #[test]
+    fn merge() {
+        let mut a = RunStats::default();
+        a.add_pieces("fr", &[piece(&["abc"], "fr")]);
+        a.add_discarded(1);
+        a.add_discard_reason(DiscardReason::TooShort, 1);
+
+        let mut b = RunStats::default();
+        b.add_pieces("fr", &[piece(&["de"], "fr")]);
+        b.add_pieces("en", &[piece(&["fgh", "ij"], "en")]);
+        b.add_discarded(2);
+        b.add_failed_shards(1);
+        b.add_predict_errors(4);
+        b.add_blank(5);
+        b.add_filtered(6);
+        b.add_interrupted_shards(7);
+        b.add_capped(8);
+        b.add_oversized(10);
+        b.add_discard_reason(DiscardReason::TooShort, 2);
+        b.add_discard_reason(DiscardReason::BelowThreshold, 9);
+        b.add_discard_reason(DiscardReason::InvalidUtf8, 0);
+
+        a.merge(&b);
+        assert_eq!(a.langs()["fr"].nb_documents, 2);
+        assert_eq!(a.langs()["fr"].nb_chars, 5);
+        assert_eq!(a.langs()["en"].nb_sentences, 2);
+        assert_eq!(a.discarded_sentences(), 3);
+        assert_eq!(a.failed_shards(), 1);
+        assert_eq!(a.predict_errors(), 4);
+        assert_eq!(a.blank_sentences(), 5);
+        assert_eq!(a.filtered_records(), 6);
+        assert_eq!(a.interrupted_shards(), 7);
+        assert_eq!(a.capped_pieces(), 8);
+        assert_eq!(a.oversized_records(), 10);
+        let expected: HashMap<_, _> = [
+            (DiscardReason::TooShort, 3),
+            (DiscardReason::BelowThreshold, 9),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(a.discard_reasons(), &expected);
+
+        // reasons are stable, machine-readable keys
+        let value = serde_json::to_value(&a).unwrap();
+        assert_eq!(value["discard_reasons"]["below_threshold"], 9);
+    }
+
+    #[test]
+    fn summary() {
+        let confident = |sentences: &[&str], confidence| {
+            let mut piece = piece(sentences, "fr");
+            piece.confidence = confidence;
+            piece
+        };
+        let mut a = RunStats::default();
+        a.add_pieces("fr", &[confident(&["abc"], 0.9)]);
+        let mut b = RunStats::default();
+        b.add_pieces("fr", &[confident(&["a"], 0.5)]);
+        b.add_pieces("en", &[piece(&["fgh", "ij"], "en")]);
+
+        // summaries of runs merge as their statistics do
+        let mut merged = a.summary()["fr"].clone();
+        merged.merge(&b.summary()["fr"]);
+        a.merge(&b);
+        assert_eq!(merged.nb_documents, a.summary()["fr"].nb_documents);
+        assert_eq!(merged.nb_chars, a.summary()["fr"].nb_chars);
+        assert!((merged.mean_probability - 0.8).abs() < 1e-6);
+
+        let summary = a.summary();
+        assert_eq!(
+            summary.keys().copied().collect::<Vec<_>>(),
+            vec!["en", "fr"]
+        );
+        assert_eq!(summary["fr"].nb_documents, 2);
+        assert_eq!(summary["fr"].nb_sentences, 2);
+        assert_eq!(summary["fr"].nb_chars, 4);
+        // (3 * 0.9 + 0.5) / 4
+        assert!((summary["fr"].mean_probability - 0.8).abs() < 1e-6);
+        assert_eq!(summary["en"].mean_probability, 1.0);
+        assert_eq!(LangStats::default().mean_probability(), 1.0);
+    }
+
+    fn timing(idx: usize, millis: u64) -> ShardTiming {
+        ShardTiming {
+            idx,
+            path: PathBuf::from(format!("{}.txt.gz", idx)),
+            duration: Duration::from_millis(millis),
+            nb_records: idx * 10,
+            failed: false,
+        }
+    }
+
+    #[test]
+    fn shard_timings() {
+        let mut a = RunStats::default();
+        a.add_shard_timing(timing(0, 20));
+        a.add_shard_timing(timing(1, 50));
+
+        let mut b = RunStats::default();
+        b.add_shard_timing(timing(2, 10));
+        b.add_shard_timing(timing(3, 30));
+
+        a.merge(&b);
+        let idxs: Vec<usize> = a.shard_timings().iter().map(|t| t.idx).collect();
+        assert_eq!(idxs, vec![1, 3, 0, 2]);
+        assert_eq!(a.shard_timings()[1].nb_records, 30);
+    }
+}
@@ -18,7 +18,7 @@ use crate::error::Error;
 use log::warn;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::convert::TryFrom;
 use std::string::FromUtf8Error;
 // use std::convert::TryFrom;
@@ -26,11 +26,13 @@ use std::string::FromUtf8Error;
 use warc::WarcHeader;
 
 /// convinience type alias for [warc::Record] headers.
-pub(super) type WarcHeaders = HashMap<WarcHeader, Vec<u8>>;
+pub type WarcHeaders = HashMap<WarcHeader, Vec<u8>>;
 /// represents a whole docuement, that is:
 /// - its header, as provided by warc library
 /// - its sentences, as an array of Strings
 /// - its identifications (one by line)
+/// - the probabilities of its identifications (one by line)
+/// - the line numbers of its sentences in the record body (one by line)
 ///
 /// a document is a filtered, annotated version of a record
 #[derive(Debug)]
@@ -39,6 +41,11 @@ pub struct Document {
     headers: HashMap<WarcHeader, Vec<u8>>,
     sentences: Vec<String>,
     identifications: Vec<&'static str>,
+    probabilities: Vec<f32>,
+    line_numbers: Vec<usize>,
+    max_gap: usize,
+    /// Dropped lines that gaps can be bridged over (see [Document::with_gap_bridging]).
+    bridgeable: HashSet<usize>,
 }
 
 /// A piece is a series of sentences from a same document
@@ -49,6 +56,29 @@ struct Piece {
     headers: HashMap<WarcHeader, Vec<u8>>,
     sentences: Vec<String>,
     identification: &'static str,
+    probabilities: Vec<f32>,
+    line_ranges: Vec<(usize, usize)>,
+}
+
+impl Piece {
+    /// Mean of sentence identification probabilities, weighted by sentence length (in unicode scalar values).
+    ///
+    /// Returns `1.0` if the piece has no characters.
+    fn confidence(&self) -> f32 {
+        let (weighted, total) = self.sentences.iter().zip(self.probabilities.iter()).fold(
+            (0f32, 0usize),
+            |(weighted, total), (sentence, prob)| {
+                let nb_chars = sentence.chars().count();
+                (weighted + prob * nb_chars as f32, total + nb_chars)
+            },
+        );
+
+        if total == 0 {
+            1.0
+        } else {
+            weighted / total as f32
+        }
+    }
 }
 
 /// Holds a merged-down version of Piece, where sentences are merged into a single String
@@ -59,11 +89,70 @@ pub struct MergedPiece {
     pub sentences: String,
     pub nb_sentences: usize,
     pub identification: &'static str,
+    /// Aggregate identification confidence (see [Document::into_merged_pieces_lang]).
+    pub confidence: f32,
+    /// Ranges of lines (start included, end excluded) of the sentences in the originating record body,
+    /// in sentence order. Empty if unknown.
+    ///
+    /// A line that has been split into several segments (see [super::OscarMetadata::with_windowed_identification])
+    /// has a range for each of its segments in the piece.
+    ///
+    /// When gaps are bridged (see [Document::with_gap_bridging]), a range can span dropped lines
+    /// that are not part of [MergedPiece::sentences].
+    pub line_ranges: Vec<(usize, usize)>,
+    /// Source page and crawl date, if enabled (see [super::OscarMetadata::with_source]).
+    pub source: Option<Source>,
+    /// Index of the shard the piece comes from (in processing order) and index of its record in the shard, if known.
+    pub shard_location: Option<(usize, usize)>,
+}
+
+/// Group line numbers into ranges of consecutive lines (start included, end excluded).
+pub fn line_ranges(line_numbers: &[usize]) -> Vec<(usize, usize)> {
+    let mut ranges = Vec::new();
+    extend_line_ranges(&mut ranges, line_numbers, 0, &HashSet::new());
+    ranges
+}
+
+/// Group line numbers of contiguous sentences into `ranges`, extending its last range if possible.
+///
+/// Ranges of lines separated by at most `max_gap` dropped lines, all of them `bridgeable`,
+/// are merged into a single range spanning the gap.
+/// The first line is only appended to the last range of `ranges` if it directly follows it,
+/// since a previous chunk of sentences isn't contiguous with `line_numbers`.
+fn extend_line_ranges(
+    ranges: &mut Vec<(usize, usize)>,
+    line_numbers: &[usize],
+    max_gap: usize,
+    bridgeable: &HashSet<usize>,
+) {
+    for (idx, &line) in line_numbers.iter().enumerate() {
+        let max_gap = if idx == 0 { 0 } else { max_gap };
+        match ranges.last_mut() {
+            Some((_, end))
+                if *end <= line
+                    && line <= *end + max_gap
+                    && (*end..line).all(|gap| bridgeable.contains(&gap)) =>
+            {
+                *end = line + 1
+            }
+            _ => ranges.push((line, line + 1)),
+        }
+    }
+}
+
+/// Expand ranges of lines (see [line_ranges]) into line numbers.
+pub fn line_numbers(line_ranges: &[(usize, usize)]) -> Vec<usize> {
+    line_ranges
+        .iter()
+        .flat_map(|(start, end)| *start..*end)
+        .collect()
 }
 
 impl MergedPiece {
     /// create a new merged piece
     /// nb_sentences is computed from sentences
+    ///
+    /// confidence is set to `1.0`, and line ranges are unknown.
     pub fn new(
         headers: HashMap<WarcHeader, Vec<u8>>,
         sentences: Vec<String>,
@@ -76,20 +165,40 @@ impl MergedPiece {
             sentences,
             nb_sentences,
             identification,
+            confidence: 1.0,
+            line_ranges: Vec::new(),
+            source: None,
+            shard_location: None,
         }
     }
 
     pub fn identification(&self) -> &'static str {
         self.identification
     }
+
+    /// Get the number of sentences.
+    pub fn nb_sentences(&self) -> usize {
+        self.nb_sentences
+    }
+
+    /// Get the number of characters (see [str::chars]) of the sentences,
+    /// excluding the newlines separating them.
+    pub fn nb_chars(&self) -> usize {
+        self.sentences.lines().map(|s| s.chars().count()).sum()
+    }
 }
 
 impl From<Piece> for MergedPiece {
     /// create a new merged piece from a piece
     ///
-    /// discards language information
+    /// sentence probabilities are aggregated into [MergedPiece::confidence],
+    /// and line ranges are kept as [MergedPiece::line_ranges].
     fn from(piece: Piece) -> Self {
-        MergedPiece::new(piece.headers, piece.sentences, piece.identification)
+        let confidence = piece.confidence();
+        let mut merged = MergedPiece::new(piece.headers, piece.sentences, piece.identification);
+        merged.confidence = confidence;
+        merged.line_ranges = piece.line_ranges;
+        merged
     }
 }
 
@@ -120,9 +229,14 @@ impl PartChunk {
         let merged_pieces_len = merged_pieces.len();
         for (idx, piece) in merged_pieces.into_iter().enumerate() {
             //build metadata
+            let nb_chars = piece.nb_chars();
             let mut m = Metadata::try_from(piece.headers)?;
             m.offset = cur_offset;
             m.nb_sentences = piece.nb_sentences;
+            m.nb_chars = nb_chars;
+            m.confidence = piece.confidence;
+            m.line_ranges = piece.line_ranges;
+            m.source = piece.source;
 
             body += &piece.sentences;
 
@@ -161,26 +275,113 @@ impl PartChunk {
 impl Document {
     /// create a new document
     ///
+    /// every identification is given a probability of `1.0`
+    /// (see [Document::with_probabilities]),
+    /// and sentences are assumed to be the contiguous lines of the record (see [Document::with_line_numbers]).
+    ///
     /// returns an error if sentences and identifications
     /// are of different length
     pub fn new(
         headers: HashMap<WarcHeader, Vec<u8>>,
         sentences: Vec<String>,
         identifications: Vec<&'static str>,
+    ) -> Result<Self, Error> {
+        let probabilities = vec![1.0; identifications.len()];
+        Self::with_probabilities(headers, sentences, identifications, probabilities)
+    }
+
+    /// create a new document, along with the probability of each identification.
+    ///
+    /// returns an error if sentences, identifications and probabilities
+    /// are of different length
+    pub fn with_probabilities(
+        headers: HashMap<WarcHeader, Vec<u8>>,
+        sentences: Vec<String>,
+        identifications: Vec<&'static str>,
+        probabilities: Vec<f32>,
     ) -> Result<Self, Error> {
         if sentences.len() != identifications.len() {
             return Err(Error::Custom(
                 "different number of sentences and identifications".to_string(),
             ));
         }
+        if sentences.len() != probabilities.len() {
+            return Err(Error::Custom(
+                "different number of sentences and probabilities".to_string(),
+            ));
+        }
 
+        let line_numbers = (0..sentences.len()).collect();
         Ok(Self {
             headers,
             sentences,
             identifications,
+            probabilities,
+            line_numbers,
+            max_gap: 0,
+            bridgeable: HashSet::new(),
         })
     }
 
+    /// set the line number of each sentence in the record body,
+    /// so that merged pieces can be traced back to their lines (see [MergedPiece::line_ranges]).
+    ///
+    /// returns an error if sentences and line numbers
+    /// are of different length
+    pub fn with_line_numbers(mut self, line_numbers: Vec<usize>) -> Result<Self, Error> {
+        if self.sentences.len() != line_numbers.len() {
+            return Err(Error::Custom(
+                "different number of sentences and line numbers".to_string(),
+            ));
+        }
+        self.line_numbers = line_numbers;
+        Ok(self)
+    }
+
+    /// bridge gaps of at most `max_gap` dropped lines between contiguous same-language sentences,
+    /// if every line of the gap is one of the `bridgeable` ones (such as short lines).
+    ///
+    /// A paragraph can be split by a dropped line in its middle: with bridging, its sentences
+    /// get a single line range spanning the gap (see [MergedPiece::line_ranges]),
+    /// instead of a range on each side of it.
+    /// Rebuilding from such a range then yields the dropped lines too,
+    /// although they're not part of the merged piece's sentences.
+    /// Sentences separated by a sentence of another language are never bridged,
+    /// nor are gaps holding a line that is not `bridgeable`.
+    ///
+    /// Defaults to `0`, not bridging any gap.
+    pub fn with_gap_bridging(mut self, max_gap: usize, bridgeable: Vec<usize>) -> Self {
+        self.max_gap = max_gap;
+        self.bridgeable = bridgeable.into_iter().collect();
+        self
+    }
+
+    /// get the dominant language of the document, along with its share of the document's characters.
+    ///
+    /// Sentence identifications are weighted by their length (in unicode scalar values).
+    /// Ties are broken by taking the lowest label in lexicographic order.
+    ///
+    /// Returns [None] if the document has no sentences (or only empty ones).
+    pub fn dominant_language(&self) -> Option<(&'static str, f32)> {
+        let mut chars_per_lang: HashMap<&'static str, usize> = HashMap::new();
+        for (sentence, lang) in self.sentences.iter().zip(self.identifications.iter()) {
+            *chars_per_lang.entry(lang).or_default() += sentence.chars().count();
+        }
+
+        let total_chars: usize = chars_per_lang.values().sum();
+        if total_chars == 0 {
+            return None;
+        }
+
+        chars_per_lang
+            .into_iter()
+            // max by count, then min by label
+            .max_by(|(lang_a, count_a), (lang_b, count_b)| {
+                count_a.cmp(count_b).then_with(|| lang_b.cmp(lang_a))
+            })
+            .map(|(lang, count)| (lang, count as f32 / total_chars as f32))
+    }
+
     /// chops the document into a vector of [MergedPiece]
     pub fn into_merged_pieces(self) -> Vec<MergedPiece> {
         let pieces = self.into_pieces();
@@ -189,6 +390,12 @@ impl Document {
 
     /// chops the document into a vector of [MergedPiece]
     /// while merging same-language sentences into a single merged piece.
+    ///
+    /// Each merged piece's confidence is the mean of its sentence probabilities,
+    /// weighted by sentence length.
+    ///
+    /// Line ranges of contiguous sentences are merged across gaps of dropped lines,
+    /// if enabled (see [Document::with_gap_bridging]).
     pub fn into_merged_pieces_lang(self) -> Vec<MergedPiece> {
         let pieces = self.into_pieces_lang();
         pieces.into_iter().map(MergedPiece::from).collect()
@@ -199,10 +406,21 @@ impl Document {
         let language_chunks = chunks::group_by(self.identifications.clone());
         let mut pieces = Vec::new();
         for (language, chunks_indices) in language_chunks {
-            let new_pieces = chunks_indices.into_iter().map(|chunk_index| Piece {
-                headers: self.headers.clone(),
-                sentences: self.sentences[chunk_index].to_vec(),
-                identification: language,
+            let new_pieces = chunks_indices.into_iter().map(|chunk_index| {
+                let mut line_ranges = Vec::new();
+                extend_line_ranges(
+                    &mut line_ranges,
+                    &self.line_numbers[chunk_index.clone()],
+                    self.max_gap,
+                    &self.bridgeable,
+                );
+                Piece {
+                    headers: self.headers.clone(),
+                    sentences: self.sentences[chunk_index.clone()].to_vec(),
+                    identification: language,
+                    probabilities: self.probabilities[chunk_index].to_vec(),
+                    line_ranges,
+                }
             });
             pieces.extend(new_pieces);
         }
@@ -214,36 +432,104 @@ impl Document {
     /// while grouping same-language sentences into a single piece.
     fn into_pieces_lang(self) -> Vec<Piece> {
         let language_chunks = chunks::group_by(self.identifications.clone());
-        let mut hm: HashMap<&'static str, Vec<String>> = HashMap::new();
+        let mut hm: HashMap<&'static str, Piece> = HashMap::new();
         for (language, chunks_indices) in language_chunks {
-            let e = hm.entry(language).or_insert_with(Vec::new);
+            let piece = hm.entry(language).or_insert_with(|| Piece {
+                headers: self.headers.clone(),
+                sentences: Vec::new(),
+                identification: language,
+                probabilities: Vec::new(),
+                line_ranges: Vec::new(),
+            });
             for chunk_index in chunks_indices {
-                e.append(&mut self.sentences[chunk_index].to_vec());
+                piece
+                    .sentences
+                    .extend_from_slice(&self.sentences[chunk_index.clone()]);
+                piece
+                    .probabilities
+                    .extend_from_slice(&self.probabilities[chunk_index.clone()]);
+                extend_line_ranges(
+                    &mut piece.line_ranges,
+                    &self.line_numbers[chunk_index],
+                    self.max_gap,
+                    &self.bridgeable,
+                );
             }
         }
 
-        hm.into_iter()
-            .map(|(lang, sentences)| Piece {
-                headers: self.headers.clone(),
-                sentences,
-                identification: lang,
-            })
-            .collect()
+        hm.into_values().collect()
+    }
+}
+
+/// Source of a paragraph, extracted from the headers of its record.
+///
+/// Missing (or invalid UTF-8) headers are kept as [None].
+#[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Debug, JsonSchema)]
+pub struct Source {
+    /// `WARC-Target-URI` of the record.
+    pub url: Option<String>,
+    /// `WARC-Date` of the record.
+    pub date: Option<String>,
+}
+
+impl Source {
+    /// Extract the source of a record from its headers.
+    pub fn from_headers(headers: &WarcHeaders) -> Self {
+        let get = |header| {
+            headers
+                .get(&header)
+                .and_then(|value| String::from_utf8(value.clone()).ok())
+        };
+        Self {
+            url: get(WarcHeader::TargetURI),
+            date: get(WarcHeader::Date),
+        }
     }
 }
 
 /// Holds record headers.
 ///
 /// Each metadata is linked to a specific paragraph/text zone
-#[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Debug, Default, JsonSchema)]
+#[derive(Clone, Serialize, Deserialize, PartialEq, Debug, JsonSchema)]
 // #[deprecated(since = "2.0.0")]
 pub struct Metadata {
     pub headers: HashMap<WarcHeader, String>,
     pub offset: usize,
     pub nb_sentences: usize,
+    /// Number of characters of the paragraph (see [MergedPiece::nb_chars]).
+    #[serde(default)]
+    pub nb_chars: usize,
+    /// Aggregate identification confidence of the paragraph (see [MergedPiece::confidence]).
+    #[serde(default = "Metadata::default_confidence")]
+    pub confidence: f32,
+    /// Line ranges of the paragraph in the originating record (see [MergedPiece::line_ranges]).
+    #[serde(default)]
+    pub line_ranges: Vec<(usize, usize)>,
+    /// Source page and crawl date of the paragraph, if enabled (see [MergedPiece::source]).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<Source>,
+}
+
+impl Default for Metadata {
+    fn default() -> Self {
+        Self {
+            headers: HashMap::new(),
+            offset: 0,
+            nb_sentences: 0,
+            nb_chars: 0,
+            confidence: Metadata::default_confidence(),
+            line_ranges: Vec::new(),
+            source: None,
+        }
+    }
 }
 
 impl Metadata {
+    /// Confidence of metadata that don't hold any.
+    fn default_confidence() -> f32 {
+        1.0
+    }
+
     pub fn get_schema() -> Result<String, Error> {
         serde_json::to_string_pretty(&schemars::schema_for!(Self)).map_err(Error::Serde)
     }
@@ -263,6 +549,10 @@ impl TryFrom<HashMap<WarcHeader, Vec<u8>>> for Metadata {
             headers,
             offset: 0,
             nb_sentences: 0,
+            nb_chars: 0,
+            confidence: Metadata::default_confidence(),
+            line_ranges: Vec::new(),
+            source: None,
         })
     }
 }
@@ -411,6 +701,160 @@ mod tests {
     //     }
     // }
 
+    #[test]
+    fn dominant_language() {
+        let (headers, sentences, identifications) = gen_test();
+        let fr_chars: usize = sentences
+            .iter()
+            .zip(identifications.iter())
+            .filter(|(_, lang)| **lang == "fr")
+            .map(|(sentence, _)| sentence.chars().count())
+            .sum();
+        let total_chars: usize = sentences.iter().map(|s| s.chars().count()).sum();
+
+        let d = Document::new(headers, sentences, identifications).unwrap();
+        let (lang, share) = d.dominant_language().unwrap();
+        assert_eq!(lang, "fr");
+        assert_eq!(share, fr_chars as f32 / total_chars as f32);
+    }
+
+    #[test]
+    fn dominant_language_empty() {
+        let d = Document::new(HashMap::new(), Vec::new(), Vec::new()).unwrap();
+        assert!(d.dominant_language().is_none());
+    }
+
+    #[test]
+    fn dominant_language_tie() {
+        let sentences = vec!["same".to_string(), "size".to_string()];
+        let d = Document::new(HashMap::new(), sentences.clone(), vec!["fr", "en"]).unwrap();
+        assert_eq!(d.dominant_language(), Some(("en", 0.5)));
+
+        let d = Document::new(HashMap::new(), sentences, vec!["en", "fr"]).unwrap();
+        assert_eq!(d.dominant_language(), Some(("en", 0.5)));
+    }
+
+    #[test]
+    fn merged_pieces_confidence() {
+        let sentences = vec!["aaa".to_string(), "b".to_string(), "cc".to_string()];
+        let d = Document::with_probabilities(
+            HashMap::new(),
+            sentences,
+            vec!["fr", "en", "fr"],
+            vec![0.9, 0.5, 0.6],
+        )
+        .unwrap();
+
+        let pieces: HashMap<&str, MergedPiece> = d
+            .into_merged_pieces_lang()
+            .into_iter()
+            .map(|piece| (piece.identification(), piece))
+            .collect();
+
+        // (3 * 0.9 + 2 * 0.6) / 5
+        assert!((pieces["fr"].confidence - 0.78).abs() < 1e-6);
+        assert!((pieces["en"].confidence - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn merged_piece_counts() {
+        let sentences = vec![
+            "Bonjour !".to_string(),
+            "Ça va ?".to_string(),
+            "très bien".to_string(),
+        ];
+        let piece = MergedPiece::new(HashMap::new(), sentences, "fr");
+        assert_eq!(piece.nb_sentences(), 3);
+        assert_eq!(piece.nb_chars(), 25);
+
+        let other = MergedPiece::new(HashMap::new(), vec!["abc".to_string()], "fr");
+        let chunk = PartChunk::new(vec![piece, other]).unwrap();
+        assert_eq!(chunk.metadata[0].nb_sentences, 3);
+        assert_eq!(chunk.metadata[0].nb_chars, 25);
+        assert_eq!(chunk.metadata[1].nb_chars, 3);
+    }
+
+    #[test]
+    fn document_incorrect_probabilities_length() {
+        let (headers, sentences, identifications) = gen_test();
+        let d = Document::with_probabilities(headers, sentences, identifications, vec![1.0]);
+        assert!(d.is_err());
+    }
+
+    #[test]
+    fn merged_pieces_line_ranges() {
+        let (headers, sentences, identifications) = gen_test();
+        let d = Document::new(headers, sentences, identifications).unwrap();
+        let pieces: HashMap<&str, MergedPiece> = d
+            .into_merged_pieces_lang()
+            .into_iter()
+            .map(|piece| (piece.identification(), piece))
+            .collect();
+
+        // lines are contiguous by default
+        assert_eq!(pieces["fr"].line_ranges, vec![(0, 3), (4, 5)]);
+        assert_eq!(pieces["en"].line_ranges, vec![(3, 4), (5, 6)]);
+        assert_eq!(pieces["de"].line_ranges, vec![(6, 7)]);
+    }
+
+    #[test]
+    fn merged_pieces_gap_bridging() {
+        let (headers, sentences, identifications) = gen_test();
+        // lines 1 and 4 have been dropped, and the sentence on line 8 has been split in two
+        let line_numbers = vec![0, 2, 3, 5, 6, 8, 8];
+        let pieces = |max_gap, bridgeable: &[usize]| -> HashMap<&str, MergedPiece> {
+            Document::new(headers.clone(), sentences.clone(), identifications.clone())
+                .unwrap()
+                .with_line_numbers(line_numbers.clone())
+                .unwrap()
+                .with_gap_bridging(max_gap, bridgeable.to_vec())
+                .into_merged_pieces_lang()
+                .into_iter()
+                .map(|piece| (piece.identification(), piece))
+                .collect()
+        };
+
+        let unbridged = pieces(0, &[1, 4]);
+        assert_eq!(unbridged["fr"].line_ranges, vec![(0, 1), (2, 4), (6, 7)]);
+
+        // only bridgeable lines are bridged
+        let unbridged = pieces(1, &[4]);
+        assert_eq!(unbridged["fr"].line_ranges, vec![(0, 1), (2, 4), (6, 7)]);
+
+        // gaps are only bridged between contiguous sentences
+        let bridged = pieces(1, &[1, 4]);
+        assert_eq!(bridged["fr"].line_ranges, vec![(0, 4), (6, 7)]);
+        assert_eq!(bridged["fr"].nb_sentences(), 4);
+        assert_eq!(bridged["en"].line_ranges, vec![(5, 6), (8, 9)]);
+        assert_eq!(bridged["de"].line_ranges, vec![(8, 9)]);
+
+        let bridged = Document::new(headers, sentences, identifications)
+            .unwrap()
+            .with_line_numbers(line_numbers)
+            .unwrap()
+            .with_gap_bridging(1, vec![1, 4])
+            .into_merged_pieces();
+        assert!(bridged
+            .iter()
+            .any(|piece| piece.identification() == "fr" && piece.line_ranges == vec![(0, 4)]));
+    }
+
+    #[test]
+    fn document_incorrect_line_numbers_length() {
+        let (headers, sentences, identifications) = gen_test();
+        let d = Document::new(headers, sentences, identifications).unwrap();
+        assert!(d.with_line_numbers(vec![0]).is_err());
+    }
+
+    #[test]
+    fn line_ranges_roundtrip() {
+        let numbers = vec![0, 1, 2, 5, 7, 8];
+        let ranges = line_ranges(&numbers);
+        assert_eq!(ranges, vec![(0, 3), (5, 6), (7, 9)]);
+        assert_eq!(line_numbers(&ranges), numbers);
+        assert!(line_ranges(&[]).is_empty());
+    }
+
     #[test]
     fn document_by_lang() {
         let (headers, sentences, identifications) = gen_test();
@@ -468,6 +912,10 @@ mod tests {
             headers,
             offset: 0,
             nb_sentences: 0,
+            nb_chars: 0,
+            confidence: 1.0,
+            line_ranges: Vec::new(),
+            source: None,
         };
 
         assert!(serde_json::to_string(&metadata).is_ok());
@@ -487,6 +935,10 @@ mod tests {
             headers,
             offset: 0,
             nb_sentences: 0,
+            nb_chars: 0,
+            confidence: 1.0,
+            line_ranges: Vec::new(),
+            source: None,
         };
         let result: Metadata = serde_json::from_str(&meta_json).unwrap();
         assert_eq!(result, expected);
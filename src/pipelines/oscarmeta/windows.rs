@@ -0,0 +1,198 @@
+//! Sliding windows for the identification of long sentences.
+//!
+//! A long sentence is split into overlapping windows that are identified separately.
+//! Each window is then reduced to its core, which ends (and the next one begins) in the middle of the overlap,
+//! on a whitespace if there is one in the overlap.
+//! Cores are finally stitched into segments of the same language.
+use std::ops::Range;
+
+use crate::error::Error;
+
+/// Identified `(byte range, language, probability)` segment of a sentence.
+pub type Segment = (Range<usize>, &'static str, f32);
+
+/// `(byte range, language and probability)` core of a window, if it has been identified.
+pub type Core = (Range<usize>, Option<(&'static str, f32)>);
+
+/// Sliding windows configuration, in characters (see [str::chars]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlidingWindows {
+    threshold: usize,
+    size: usize,
+    overlap: usize,
+}
+
+impl SlidingWindows {
+    /// Split sentences that are longer than `threshold` into windows of `size` characters,
+    /// overlapping by `overlap` characters.
+    ///
+    /// # Errors
+    /// Returns an error if `size` is 0 or if `overlap` is not smaller than `size`.
+    pub fn new(threshold: usize, size: usize, overlap: usize) -> Result<Self, Error> {
+        if size == 0 || overlap >= size {
+            return Err(Error::Custom(format!(
+                "invalid windows of {} chars overlapping by {} chars: overlap has to be smaller than window size",
+                size, overlap
+            )));
+        }
+        Ok(Self {
+            threshold,
+            size,
+            overlap,
+        })
+    }
+
+    /// Check if `sentence` is long enough to be split.
+    pub fn applies(&self, sentence: &str) -> bool {
+        sentence.chars().count() > self.threshold
+    }
+
+    /// Split `sentence` into `(window, core)` byte ranges.
+    ///
+    /// Cores are contiguous and cover the whole sentence.
+    pub fn split(&self, sentence: &str) -> Vec<(Range<usize>, Range<usize>)> {
+        let chars: Vec<char> = sentence.chars().collect();
+        // byte offset of each char, along with the end of the sentence
+        let offsets: Vec<usize> = sentence
+            .char_indices()
+            .map(|(offset, _)| offset)
+            .chain(std::iter::once(sentence.len()))
+            .collect();
+
+        // windows, in chars
+        let step = self.size - self.overlap;
+        let mut windows = Vec::new();
+        let mut start = 0;
+        loop {
+            let end = (start + self.size).min(chars.len());
+            windows.push(start..end);
+            if end == chars.len() {
+                break;
+            }
+            start += step;
+        }
+
+        // core boundaries, in chars
+        let mut boundaries = vec![0];
+        boundaries.extend(windows.windows(2).map(|pair| {
+            let overlap = pair[1].start..pair[0].end;
+            let middle = (overlap.start + overlap.end) / 2;
+            overlap
+                .filter(|idx| chars[*idx].is_whitespace())
+                .min_by_key(|idx| idx.abs_diff(middle))
+                .unwrap_or(middle)
+        }));
+        boundaries.push(chars.len());
+
+        windows
+            .into_iter()
+            .zip(boundaries.windows(2))
+            .map(|(window, core)| {
+                (
+                    offsets[window.start]..offsets[window.end],
+                    offsets[core[0]]..offsets[core[1]],
+                )
+            })
+            .collect()
+    }
+}
+
+/// Stitch identified cores (in sentence order) into `(range, language, probability)` segments.
+///
+/// Adjacent cores of the same language are merged, and their probabilities are averaged, weighted by core length.
+/// Unidentified cores are merged into the previous segment (or the next one for leading cores),
+/// so that no part of the sentence is lost.
+/// Returns an empty vector if no core is identified.
+pub fn stitch(cores: Vec<Core>) -> Vec<Segment> {
+    // (range, language, probability sum weighted by length, total length)
+    let mut segments: Vec<(Range<usize>, &'static str, f32, usize)> = Vec::new();
+    let mut leading = None;
+    for (core, prediction) in cores {
+        match (prediction, segments.last_mut()) {
+            (Some((lang, prob)), Some(last)) if last.1 == lang => {
+                last.0.end = core.end;
+                last.2 += prob * core.len() as f32;
+                last.3 += core.len();
+            }
+            (Some((lang, prob)), _) => {
+                let start = leading.take().unwrap_or(core.start);
+                segments.push((start..core.end, lang, prob * core.len() as f32, core.len()));
+            }
+            (None, Some(last)) => last.0.end = core.end,
+            (None, None) => {
+                leading.get_or_insert(core.start);
+            }
+        }
+    }
+
+    segments
+        .into_iter()
+        .map(|(range, lang, weighted, len)| (range, lang, weighted / len.max(1) as f32))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid() {
+        assert!(SlidingWindows::new(10, 0, 0).is_err());
+        assert!(SlidingWindows::new(10, 5, 5).is_err());
+        assert!(SlidingWindows::new(10, 5, 4).is_ok());
+    }
+
+    #[test]
+    fn applies() {
+        let w = SlidingWindows::new(5, 4, 2).unwrap();
+        assert!(!w.applies("héhé!"));
+        assert!(w.applies("héhé!!"));
+    }
+
+    #[test]
+    fn split() {
+        let w = SlidingWindows::new(0, 4, 2).unwrap();
+        let sentence = "abcdefgh";
+        let split = w.split(sentence);
+        assert_eq!(split, vec![(0..4, 0..3), (2..6, 3..5), (4..8, 5..8)]);
+    }
+
+    #[test]
+    fn split_whitespace() {
+        let w = SlidingWindows::new(0, 6, 3).unwrap();
+        let sentence = "abcdé fgh";
+        let split = w.split(sentence);
+        let cores: Vec<&str> = split
+            .iter()
+            .map(|(_, core)| &sentence[core.clone()])
+            .collect();
+        assert_eq!(cores, vec!["abcdé", " fgh"]);
+        for (window, core) in split {
+            assert!(window.start <= core.start && core.end <= window.end);
+        }
+    }
+
+    #[test]
+    fn split_short() {
+        let w = SlidingWindows::new(0, 4, 2).unwrap();
+        assert_eq!(w.split("abc"), vec![(0..3, 0..3)]);
+    }
+
+    #[test]
+    fn stitch_langs() {
+        let cores = vec![
+            (0..2, None),
+            (2..4, Some(("en", 1.0))),
+            (4..8, Some(("en", 0.25))),
+            (8..10, None),
+            (10..12, Some(("fr", 0.9))),
+        ];
+        let segments = stitch(cores);
+        assert_eq!(segments, vec![(0..10, "en", 0.5), (10..12, "fr", 0.9)]);
+    }
+
+    #[test]
+    fn stitch_unidentified() {
+        assert!(stitch(vec![(0..2, None), (2..4, None)]).is_empty());
+    }
+}
@@ -3,10 +3,13 @@
 //! produces a corpus identical to OSCAR 2018
 use std::{collections::HashMap, io::Write, path::PathBuf};
 
-use crate::lang::LANG;
+use crate::lang::label_from_model;
 use crate::pipelines::pipeline::Pipeline;
 use crate::{error::Error, lang::LangFiles};
-use crate::{identifiers::FastText, sources::commoncrawl::Wet};
+use crate::{
+    identifiers::{FastText, LanguageIdentifier},
+    sources::commoncrawl::Wet,
+};
 use itertools::Itertools;
 use log::{debug, info, warn};
 use rayon::prelude::*;
@@ -75,7 +78,7 @@ impl RayonAll {
     /// then groups identified sentences by language.
     fn process_record(
         record: Record<BufferedBody>,
-        cls: &FastText,
+        cls: &dyn LanguageIdentifier,
     ) -> Option<Vec<(String, &'static str)>> {
         let body = String::from_utf8(record.body().to_vec()).ok();
 
@@ -102,8 +105,8 @@ impl RayonAll {
 
                         // check if fasttext provided lang exists
                         // return None if not
-                        match LANG.get(lang.label.as_str()) {
-                            Some(lang) => Some((sentence.to_string(), *lang)),
+                        match label_from_model(&lang.label) {
+                            Some(lang) => Some((sentence.to_string(), lang)),
                             None => {
                                 warn!("lang {} does not exist!", lang.label);
                                 None
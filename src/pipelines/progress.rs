@@ -0,0 +1,115 @@
+//! Progress reporting.
+//!
+//! Pipelines report their progress to a [ProgressObserver], which decouples them from any particular UI:
+//! [IndicatifProgress] displays a progress bar, but an observer can also update monitoring counters.
+//!
+//! Observers are shared by the threads processing shards and records, so hooks have to be cheap.
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use indicatif::{ProgressBar, ProgressStyle};
+
+/// Observer of a run progress.
+///
+/// Every hook does nothing by default.
+pub trait ProgressObserver: Send + Sync {
+    /// Shard `idx` (at `path`) is about to be processed.
+    fn on_shard_start(&self, _idx: usize, _path: &Path) {}
+
+    /// Shard `idx` has been processed, successfully or not.
+    fn on_shard_done(&self, _idx: usize, _success: bool) {}
+
+    /// `n` more records have been processed.
+    fn on_records(&self, _n: usize) {}
+}
+
+/// Progress bar of processed shards, along with the number of processed records and failed shards.
+pub struct IndicatifProgress {
+    bar: ProgressBar,
+    records: AtomicUsize,
+    failed: AtomicUsize,
+}
+
+impl IndicatifProgress {
+    /// Create a new progress bar, drawn on stderr.
+    ///
+    /// `nb_shards` is the expected number of shards. If it is unknown, a spinner is displayed instead of a bar.
+    pub fn new(nb_shards: Option<u64>) -> Self {
+        let bar = match nb_shards {
+            Some(nb_shards) => {
+                let bar = ProgressBar::new(nb_shards);
+                bar.set_style(
+                    ProgressStyle::with_template(
+                        "[{elapsed_precise}] {bar:40} {pos}/{len} shards ({eta}) {msg}",
+                    )
+                    .unwrap(),
+                );
+                bar
+            }
+            None => ProgressBar::new_spinner(),
+        };
+
+        Self::with_bar(bar)
+    }
+
+    /// Create a new progress reporter using `bar` (such as a hidden [ProgressBar]).
+    pub fn with_bar(bar: ProgressBar) -> Self {
+        Self {
+            bar,
+            records: AtomicUsize::new(0),
+            failed: AtomicUsize::new(0),
+        }
+    }
+
+    /// Get the underlying progress bar.
+    pub fn bar(&self) -> &ProgressBar {
+        &self.bar
+    }
+
+    fn update_message(&self) {
+        self.bar.set_message(format!(
+            "{} records, {} failed shards",
+            self.records.load(Ordering::Relaxed),
+            self.failed.load(Ordering::Relaxed)
+        ));
+    }
+}
+
+impl ProgressObserver for IndicatifProgress {
+    fn on_shard_done(&self, _idx: usize, success: bool) {
+        if !success {
+            self.failed.fetch_add(1, Ordering::Relaxed);
+        }
+        self.bar.inc(1);
+        self.update_message();
+    }
+
+    fn on_records(&self, n: usize) {
+        self.records.fetch_add(n, Ordering::Relaxed);
+        self.update_message();
+    }
+}
+
+impl Drop for IndicatifProgress {
+    fn drop(&mut self) {
+        self.bar.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn indicatif() {
+        let progress = IndicatifProgress::with_bar(ProgressBar::hidden());
+        progress.on_shard_start(0, Path::new("0.txt.gz"));
+        progress.on_records(3);
+        progress.on_records(2);
+        progress.on_shard_done(0, true);
+        progress.on_shard_done(1, false);
+
+        assert_eq!(progress.bar().position(), 2);
+        assert_eq!(progress.bar().message(), "5 records, 1 failed shards");
+    }
+}
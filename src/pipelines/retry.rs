@@ -0,0 +1,166 @@
+//! Retries of transient failures.
+//!
+//! Shards that live on networked filesystems can fail to open because of transient I/O errors.
+//! A [Retry] policy retries such operations with an exponential backoff,
+//! while permanent errors (such as a missing file) are returned right away.
+use std::io::ErrorKind;
+use std::thread;
+use std::time::Duration;
+
+use log::warn;
+
+use crate::error::Error;
+
+/// Retry policy: at most `attempts` attempts, waiting `base_delay * 2^n` after the `n`th failed attempt (from 0).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Retry {
+    attempts: usize,
+    base_delay: Duration,
+}
+
+impl Default for Retry {
+    /// A single attempt, without retry.
+    fn default() -> Self {
+        Self {
+            attempts: 1,
+            base_delay: Duration::ZERO,
+        }
+    }
+}
+
+impl Retry {
+    /// Create a new policy making at most `attempts` attempts (at least one is always made).
+    pub fn new(attempts: usize, base_delay: Duration) -> Self {
+        Self {
+            attempts: attempts.max(1),
+            base_delay,
+        }
+    }
+
+    /// Get the maximum number of attempts.
+    pub fn attempts(&self) -> usize {
+        self.attempts
+    }
+
+    /// Get the delay after the first failed attempt.
+    pub fn base_delay(&self) -> Duration {
+        self.base_delay
+    }
+
+    /// Check if `error` may not happen again.
+    ///
+    /// Only I/O errors are transient, except the ones that are caused by the file itself
+    /// (missing file, denied permission, invalid or truncated data).
+    pub fn is_transient(error: &Error) -> bool {
+        match error {
+            Error::Io(e) => !matches!(
+                e.kind(),
+                ErrorKind::NotFound
+                    | ErrorKind::PermissionDenied
+                    | ErrorKind::InvalidInput
+                    | ErrorKind::InvalidData
+                    | ErrorKind::UnexpectedEof
+                    | ErrorKind::Unsupported
+            ),
+            _ => false,
+        }
+    }
+
+    /// Run `op` until it succeeds, fails with a permanent error or runs out of attempts.
+    ///
+    /// `what` describes the operation in logs.
+    /// Returns the last error if every attempt failed.
+    pub fn run<T, F>(&self, what: &str, mut op: F) -> Result<T, Error>
+    where
+        F: FnMut() -> Result<T, Error>,
+    {
+        let mut attempt = 0;
+        loop {
+            match op() {
+                Ok(value) => return Ok(value),
+                Err(e) if attempt + 1 < self.attempts && Self::is_transient(&e) => {
+                    let delay = self.base_delay * 2u32.saturating_pow(attempt as u32);
+                    warn!(
+                        "{} failed (attempt {}/{}), retrying in {:?}: {:?}",
+                        what,
+                        attempt + 1,
+                        self.attempts,
+                        delay,
+                        e
+                    );
+                    thread::sleep(delay);
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::*;
+
+    fn io_error(kind: ErrorKind) -> Error {
+        Error::Io(io::Error::new(kind, "test"))
+    }
+
+    #[test]
+    fn is_transient() {
+        assert!(Retry::is_transient(&io_error(ErrorKind::TimedOut)));
+        assert!(Retry::is_transient(&io_error(ErrorKind::Other)));
+        assert!(!Retry::is_transient(&io_error(ErrorKind::NotFound)));
+        assert!(!Retry::is_transient(&io_error(ErrorKind::InvalidData)));
+        assert!(!Retry::is_transient(&Error::Custom("test".to_string())));
+    }
+
+    #[test]
+    fn retry_transient() {
+        let retry = Retry::new(3, Duration::ZERO);
+        let mut calls = 0;
+        let result = retry.run("test", || {
+            calls += 1;
+            if calls < 3 {
+                Err(io_error(ErrorKind::TimedOut))
+            } else {
+                Ok(calls)
+            }
+        });
+        assert_eq!(result.unwrap(), 3);
+
+        // out of attempts
+        let mut calls = 0;
+        let result: Result<(), Error> = retry.run("test", || {
+            calls += 1;
+            Err(io_error(ErrorKind::TimedOut))
+        });
+        assert!(result.is_err());
+        assert_eq!(calls, 3);
+    }
+
+    #[test]
+    fn retry_permanent() {
+        let retry = Retry::new(3, Duration::ZERO);
+        let mut calls = 0;
+        let result: Result<(), Error> = retry.run("test", || {
+            calls += 1;
+            Err(io_error(ErrorKind::NotFound))
+        });
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn default_single_attempt() {
+        let mut calls = 0;
+        let result: Result<(), Error> = Retry::default().run("test", || {
+            calls += 1;
+            Err(io_error(ErrorKind::TimedOut))
+        });
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+        assert_eq!(Retry::new(0, Duration::ZERO), Retry::default());
+    }
+}
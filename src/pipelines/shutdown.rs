@@ -0,0 +1,150 @@
+//! Graceful shutdown.
+//!
+//! A [Shutdown] is a shared flag that pipelines check before picking up a new shard.
+//! Once it is requested, shards that are being processed are finished, writers are closed
+//! and the run returns, leaving a valid (if partial) corpus that a later run can complete.
+//!
+//! [Shutdown::on_signals] requests it on `SIGINT`/`SIGTERM` (or Ctrl-C on non-unix platforms).
+//!
+//! Only [super::OscarMetadata] runs support it (see [super::OscarMetadata::with_shutdown]), since they can be resumed.
+//! [super::OscarDoc], and thus the `pipeline` command, can't skip the shards of a previous run:
+//! signals stop them right away, as before, and no handler is installed unless a caller does so.
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::thread;
+
+use log::{error, warn};
+
+use crate::error::Error;
+
+/// Exit code used when a second signal is received while shutting down (128 + SIGINT).
+const FORCED_EXIT_CODE: i32 = 130;
+
+/// Shared shutdown flag. Clones refer to the same flag.
+#[derive(Debug, Clone, Default)]
+pub struct Shutdown {
+    requested: Arc<AtomicBool>,
+}
+
+impl Shutdown {
+    /// Create a flag that is only requested through [Shutdown::request].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a flag that is requested on the first `SIGINT`/`SIGTERM`.
+    ///
+    /// Signals are handled by a background thread for the rest of the process,
+    /// and a second signal exits the process right away.
+    ///
+    /// # Errors
+    /// Returns an error if the signal handlers can't be installed.
+    pub fn on_signals() -> Result<Self, Error> {
+        let shutdown = Self::new();
+
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_io()
+            .build()?;
+        // register handlers now, so that installation errors are reported here
+        let mut signals = {
+            let _guard = rt.enter();
+            Signals::new()?
+        };
+
+        let flag = shutdown.clone();
+        thread::Builder::new()
+            .name("ungoliant-shutdown".to_string())
+            .spawn(move || {
+                rt.block_on(async move {
+                    signals.recv().await;
+                    warn!(
+                        "shutdown requested: finishing in-flight shards (signal again to exit now)"
+                    );
+                    flag.request();
+
+                    signals.recv().await;
+                    error!("exiting without finishing in-flight shards");
+                    std::process::exit(FORCED_EXIT_CODE);
+                })
+            })?;
+
+        Ok(shutdown)
+    }
+
+    /// Request the shutdown.
+    pub fn request(&self) {
+        self.requested.store(true, Ordering::SeqCst);
+    }
+
+    /// Check if the shutdown has been requested.
+    pub fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+}
+
+/// Handled signals.
+#[cfg(unix)]
+struct Signals {
+    interrupt: tokio::signal::unix::Signal,
+    terminate: tokio::signal::unix::Signal,
+}
+
+#[cfg(unix)]
+impl Signals {
+    fn new() -> Result<Self, Error> {
+        use tokio::signal::unix::{signal, SignalKind};
+        Ok(Self {
+            interrupt: signal(SignalKind::interrupt())?,
+            terminate: signal(SignalKind::terminate())?,
+        })
+    }
+
+    async fn recv(&mut self) {
+        tokio::select! {
+            _ = self.interrupt.recv() => {},
+            _ = self.terminate.recv() => {},
+        }
+    }
+}
+
+/// Handled signals.
+#[cfg(not(unix))]
+struct Signals;
+
+#[cfg(not(unix))]
+impl Signals {
+    fn new() -> Result<Self, Error> {
+        Ok(Self)
+    }
+
+    async fn recv(&mut self) {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            error!("could not listen to Ctrl-C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Shutdown;
+
+    #[test]
+    fn request() {
+        let shutdown = Shutdown::new();
+        let other = shutdown.clone();
+        assert!(!other.is_requested());
+
+        shutdown.request();
+        assert!(other.is_requested());
+        assert!(!Shutdown::new().is_requested());
+    }
+
+    #[test]
+    fn on_signals() {
+        let shutdown = Shutdown::on_signals().unwrap();
+        assert!(!shutdown.is_requested());
+    }
+}
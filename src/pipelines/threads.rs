@@ -0,0 +1,226 @@
+//! Dedicated thread pools.
+//!
+//! By default, pipelines run in the global rayon pool, that has a thread per available core.
+//! On NUMA machines, spreading decompression and identification over every core can thrash memory bandwidth:
+//! [pool] builds a pool of a given size, whose threads can be pinned to some cores (see [ThreadPinning]).
+use log::warn;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+
+use crate::error::Error;
+
+/// Pinning of pool threads to CPU cores.
+///
+/// Pinning is only supported on Linux. On other platforms, threads are not pinned and a warning is logged.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum ThreadPinning {
+    /// Threads are scheduled by the OS.
+    #[default]
+    None,
+    /// Thread `i` is pinned to the `i`-th core the process may run on (modulo the number of such cores).
+    Compact,
+    /// Thread `i` is pinned to the `i`-th provided core (modulo the number of cores),
+    /// for example the cores of a single NUMA node.
+    Cores(Vec<usize>),
+}
+
+impl ThreadPinning {
+    /// Get the cores threads are pinned to, in order (none if threads are not pinned).
+    ///
+    /// # Errors
+    /// Returns an error if the cores the process may run on can't be read (compact pinning).
+    fn cores(&self) -> Result<Vec<usize>, Error> {
+        match self {
+            Self::None => Ok(Vec::new()),
+            Self::Compact => allowed_cores(),
+            Self::Cores(cores) => Ok(cores.clone()),
+        }
+    }
+
+    /// Check that provided cores can be pinned to.
+    ///
+    /// # Errors
+    /// Returns an error if a core is out of the range of CPU sets (`libc::CPU_SETSIZE`).
+    #[cfg(target_os = "linux")]
+    fn check(&self) -> Result<(), Error> {
+        match self {
+            Self::Cores(cores) => match cores.iter().find(|core| !fits_cpu_set(**core)) {
+                Some(core) => Err(Error::Custom(format!(
+                    "can't pin threads to core {}: cores should be lower than {}",
+                    core,
+                    libc::CPU_SETSIZE
+                ))),
+                None => Ok(()),
+            },
+            _ => Ok(()),
+        }
+    }
+
+    /// Check that provided cores can be pinned to (pinning is not supported, so they always can).
+    #[cfg(not(target_os = "linux"))]
+    fn check(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Get the core of thread `idx` among `cores`, if it has to be pinned.
+fn core(cores: &[usize], idx: usize) -> Option<usize> {
+    if cores.is_empty() {
+        None
+    } else {
+        Some(cores[idx % cores.len()])
+    }
+}
+
+/// Get the cores the current thread may run on.
+#[cfg(target_os = "linux")]
+fn allowed_cores() -> Result<Vec<usize>, Error> {
+    // SAFETY: the set is zeroed before use, sched_getaffinity is given its size
+    // and CPU_ISSET is only called on cores that fit in it.
+    let set = unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        if libc::sched_getaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &mut set) != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        set
+    };
+    Ok((0..libc::CPU_SETSIZE as usize)
+        .filter(|core| unsafe { libc::CPU_ISSET(*core, &set) })
+        .collect())
+}
+
+/// Get the cores the current thread may run on (every available core, since affinity is not supported).
+#[cfg(not(target_os = "linux"))]
+fn allowed_cores() -> Result<Vec<usize>, Error> {
+    let nb_cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+    Ok((0..nb_cores).collect())
+}
+
+/// Check if `core` can be put in a `libc::cpu_set_t` (`CPU_SET` panics otherwise).
+#[cfg(target_os = "linux")]
+fn fits_cpu_set(core: usize) -> bool {
+    core < libc::CPU_SETSIZE as usize
+}
+
+/// Pin the current thread to `core`.
+#[cfg(target_os = "linux")]
+fn pin_current(core: usize) -> Result<(), Error> {
+    // cores of the pinning are checked when building the pool, this is a safety net for CPU_SET
+    if !fits_cpu_set(core) {
+        return Err(Error::Custom(format!(
+            "core {} is out of the range of CPU sets",
+            core
+        )));
+    }
+    // SAFETY: the set is zeroed before use and `core` fits in it (checked above, CPU_SET panics otherwise),
+    // and sched_setaffinity is given the size of the set.
+    let ret = unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_ZERO(&mut set);
+        libc::CPU_SET(core, &mut set);
+        libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set)
+    };
+    if ret != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(())
+}
+
+/// Pin the current thread to `core`.
+#[cfg(not(target_os = "linux"))]
+fn pin_current(_core: usize) -> Result<(), Error> {
+    Err(Error::Custom(
+        "thread pinning is only supported on Linux".to_string(),
+    ))
+}
+
+/// Build a pool of `nb_threads` threads (or of a thread per available core), pinned following `pinning`.
+///
+/// Threads that can't be pinned keep running unpinned, and a warning is logged.
+///
+/// # Errors
+/// Returns an error if `nb_threads` is 0, if a core of `pinning` is out of the range of CPU sets
+/// (`libc::CPU_SETSIZE`, on Linux), if the cores the process may run on can't be read (compact pinning)
+/// or if the pool can't be built.
+pub fn pool(nb_threads: Option<usize>, pinning: &ThreadPinning) -> Result<ThreadPool, Error> {
+    if nb_threads == Some(0) {
+        return Err(Error::Custom(
+            "thread pools need at least one thread".to_string(),
+        ));
+    }
+    pinning.check()?;
+    let mut builder = ThreadPoolBuilder::new();
+    if let Some(nb_threads) = nb_threads {
+        builder = builder.num_threads(nb_threads);
+    }
+    // compact pinning reads the affinity of the current thread, so it is done once, before threads are pinned
+    let cores = pinning.cores()?;
+    if !cores.is_empty() {
+        builder = builder.start_handler(move |idx| {
+            if let Some(core) = core(&cores, idx) {
+                if let Err(e) = pin_current(core) {
+                    warn!("could not pin thread {} to core {}: {:?}", idx, core, e);
+                }
+            }
+        });
+    }
+    Ok(builder.build()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use rayon::prelude::*;
+
+    use super::{core, pool, ThreadPinning};
+
+    #[test]
+    fn cores() {
+        assert_eq!(ThreadPinning::None.cores().unwrap(), Vec::<usize>::new());
+        let cores = ThreadPinning::Cores(vec![4, 6]).cores().unwrap();
+        assert_eq!(core(&cores, 3), Some(6));
+        assert_eq!(core(&[], 3), None);
+        assert!(!ThreadPinning::Compact.cores().unwrap().is_empty());
+    }
+
+    #[test]
+    fn pool_threads() {
+        let p = pool(Some(2), &ThreadPinning::Compact).unwrap();
+        assert_eq!(p.current_num_threads(), 2);
+        let sum: usize = p.install(|| (0..100usize).into_par_iter().sum());
+        assert_eq!(sum, 4950);
+
+        assert!(pool(Some(0), &ThreadPinning::None).is_err());
+    }
+
+    /// get the cores the current thread may run on.
+    #[cfg(target_os = "linux")]
+    fn affinity() -> Vec<usize> {
+        super::allowed_cores().unwrap()
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn pinned() {
+        // the test process may not be allowed to run on every core
+        let core = affinity()[0];
+        let p = pool(Some(1), &ThreadPinning::Cores(vec![core])).unwrap();
+        assert_eq!(p.install(affinity), vec![core]);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn pinned_compact() {
+        // compact pinning only uses cores the process may run on, that may not start at 0
+        let allowed = affinity();
+        assert_eq!(ThreadPinning::Compact.cores().unwrap(), allowed);
+        let p = pool(Some(1), &ThreadPinning::Compact).unwrap();
+        assert_eq!(p.install(affinity), vec![allowed[0]]);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn pinned_out_of_range() {
+        let core = libc::CPU_SETSIZE as usize;
+        assert!(pool(Some(1), &ThreadPinning::Cores(vec![0, core])).is_err());
+        assert!(super::pin_current(core).is_err());
+    }
+}
@@ -0,0 +1,254 @@
+//! Rewriting of record URLs.
+//!
+//! The `WARC-Target-URI` header of records ends up in metadata (and, for [super::OscarDoc], in rebuild files
+//! if it is used as extra metadata). For privacy reasons, some URLs have to be redacted before they're written:
+//! a [UrlPolicy] rewrites them (see [super::OscarMetadata::with_url_policy] and [super::OscarDoc::with_url_policy]).
+//!
+//! Built-in policies are [Identity] (that leaves URLs as is), [StripQuery] and [HashHost].
+//! They can be combined with a [Chain], and closures taking and returning a URL are policies too.
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use log::debug;
+use sha2::{Digest, Sha256};
+use url::Url;
+use warc::WarcHeader;
+
+/// Rewriting of the URLs written in metadata.
+///
+/// Policies should leave invalid URLs unchanged rather than fail (logging them at the debug level):
+/// a record is never dropped because of its URL.
+pub trait UrlPolicy: Send + Sync {
+    /// Rewrite `url`.
+    fn transform(&self, url: &str) -> String;
+
+    /// Rewrite the `WARC-Target-URI` header of `headers`, if any.
+    ///
+    /// URLs that are not valid UTF-8 are left unchanged.
+    fn rewrite_headers(&self, headers: &mut HashMap<WarcHeader, Vec<u8>>) {
+        if let Some(value) = headers.get_mut(&WarcHeader::TargetURI) {
+            match std::str::from_utf8(value) {
+                Ok(url) => *value = self.transform(url).into_bytes(),
+                Err(e) => debug!("URL is not valid UTF-8, leaving it unchanged: {:?}", e),
+            }
+        }
+    }
+}
+
+impl<F> UrlPolicy for F
+where
+    F: Fn(&str) -> String + Send + Sync,
+{
+    fn transform(&self, url: &str) -> String {
+        self(url)
+    }
+}
+
+/// Parse `url`, logging invalid ones.
+fn parse(url: &str) -> Option<Url> {
+    match Url::parse(url) {
+        Ok(url) => Some(url),
+        Err(e) => {
+            debug!("invalid URL {:?}, leaving it unchanged: {:?}", url, e);
+            None
+        }
+    }
+}
+
+/// Leaves URLs unchanged.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Identity;
+
+impl UrlPolicy for Identity {
+    fn transform(&self, url: &str) -> String {
+        url.to_string()
+    }
+}
+
+/// Removes the query string of URLs (`https://example.com/page?user=me` becomes `https://example.com/page`).
+///
+/// Fragments are kept.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StripQuery;
+
+impl UrlPolicy for StripQuery {
+    fn transform(&self, url: &str) -> String {
+        match parse(url) {
+            Some(mut parsed) => {
+                parsed.set_query(None);
+                parsed.to_string()
+            }
+            None => url.to_string(),
+        }
+    }
+}
+
+/// Replaces the host of URLs by a hash of it, keeping the rest of the URL
+/// (`https://example.com/page` becomes `https://<hash>/page`).
+///
+/// The hash is the first 16 bytes of the sha256 of the salt followed by the host, in hexadecimal.
+/// A given host always gets the same hash, so documents of a host can still be grouped together.
+/// Without a salt, hashes of known hosts can be computed by anyone: use a secret salt to prevent it.
+///
+/// URLs without a host (such as `mailto:` ones) are left unchanged.
+#[derive(Debug, Clone, Default)]
+pub struct HashHost {
+    salt: String,
+}
+
+impl HashHost {
+    /// Hash hosts with `salt`.
+    pub fn new(salt: &str) -> Self {
+        Self {
+            salt: salt.to_string(),
+        }
+    }
+
+    /// Hash `host`.
+    fn hash(&self, host: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.salt.as_bytes());
+        hasher.update(host.as_bytes());
+        hasher.finalize()[..16]
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+}
+
+impl UrlPolicy for HashHost {
+    fn transform(&self, url: &str) -> String {
+        let mut parsed = match parse(url) {
+            Some(parsed) => parsed,
+            None => return url.to_string(),
+        };
+        let hash = match parsed.host_str() {
+            Some(host) => self.hash(host),
+            None => {
+                debug!("URL {:?} has no host, leaving it unchanged", url);
+                return url.to_string();
+            }
+        };
+        match parsed.set_host(Some(&hash)) {
+            Ok(()) => parsed.to_string(),
+            Err(e) => {
+                debug!(
+                    "could not hash host of {:?}, leaving it unchanged: {:?}",
+                    url, e
+                );
+                url.to_string()
+            }
+        }
+    }
+}
+
+/// Applies policies one after the other.
+#[derive(Clone, Default)]
+pub struct Chain {
+    policies: Vec<Arc<dyn UrlPolicy>>,
+}
+
+impl Chain {
+    /// Apply `policies` in order.
+    pub fn new(policies: Vec<Arc<dyn UrlPolicy>>) -> Self {
+        Self { policies }
+    }
+}
+
+impl UrlPolicy for Chain {
+    fn transform(&self, url: &str) -> String {
+        self.policies
+            .iter()
+            .fold(url.to_string(), |url, policy| policy.transform(&url))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use warc::WarcHeader;
+
+    use super::{Chain, HashHost, Identity, StripQuery, UrlPolicy};
+
+    #[test]
+    fn identity() {
+        assert_eq!(Identity.transform("not a url"), "not a url");
+        assert_eq!(
+            Identity.transform("https://example.com/?a=1"),
+            "https://example.com/?a=1"
+        );
+    }
+
+    #[test]
+    fn strip_query() {
+        assert_eq!(
+            StripQuery.transform("https://example.com/page?user=me#top"),
+            "https://example.com/page#top"
+        );
+        assert_eq!(
+            StripQuery.transform("https://example.com/page"),
+            "https://example.com/page"
+        );
+        // invalid URLs are left unchanged
+        assert_eq!(StripQuery.transform("/page?user=me"), "/page?user=me");
+    }
+
+    #[test]
+    fn hash_host() {
+        let policy = HashHost::default();
+        let hashed = policy.transform("https://example.com:8080/page?a=1");
+        assert!(hashed.starts_with("https://"));
+        assert!(hashed.ends_with(":8080/page?a=1"));
+        assert!(!hashed.contains("example"));
+
+        // hashes are stable, and depend on the salt
+        assert_eq!(
+            policy.transform("http://example.com/other").len(),
+            7 + 32 + 6
+        );
+        assert!(policy
+            .transform("http://example.com/other")
+            .contains(&hashed[8..40]));
+        assert!(!HashHost::new("secret")
+            .transform("https://example.com/page?a=1")
+            .contains(&hashed[8..40]));
+
+        assert_eq!(
+            policy.transform("mailto:me@example.com"),
+            "mailto:me@example.com"
+        );
+        assert_eq!(policy.transform("not a url"), "not a url");
+    }
+
+    #[test]
+    fn chain() {
+        let policy = Chain::new(vec![
+            Arc::new(StripQuery),
+            Arc::new(|url: &str| url.replace("https", "http")),
+        ]);
+        assert_eq!(
+            policy.transform("https://example.com/?a=1"),
+            "http://example.com/"
+        );
+    }
+
+    #[test]
+    fn rewrite_headers() {
+        let mut headers: HashMap<_, _> = vec![
+            (WarcHeader::TargetURI, b"https://example.com/?a=1".to_vec()),
+            (WarcHeader::ContentType, b"text/plain?a=1".to_vec()),
+        ]
+        .into_iter()
+        .collect();
+        StripQuery.rewrite_headers(&mut headers);
+        assert_eq!(headers[&WarcHeader::TargetURI], b"https://example.com/");
+        assert_eq!(headers[&WarcHeader::ContentType], b"text/plain?a=1");
+
+        // invalid UTF-8 is left unchanged
+        headers.insert(WarcHeader::TargetURI, vec![0xff]);
+        StripQuery.rewrite_headers(&mut headers);
+        assert_eq!(headers[&WarcHeader::TargetURI], vec![0xff]);
+    }
+}
@@ -7,7 +7,7 @@ use crate::io::reader::reader::{PieceMeta, Reader};
 use crate::io::reader::Corpus;
 use crate::io::writer::WriterTrait;
 use crate::io::Writer;
-use crate::pipelines::oscarmeta::types::MergedPiece;
+use crate::pipelines::oscarmeta::types::{self, MergedPiece};
 use log::info;
 use rayon::prelude::*;
 use runiq::filters::Filter;
@@ -21,16 +21,24 @@ pub trait Dedup {
 /// deduplicates a piece.
 ///
 /// returns the provided offset if the piece is only composed of duplicate data.
+///
+/// line ranges are updated to only cover kept sentences.
 pub fn dedup_piece(
     piece: &mut PieceMeta,
     new_offset: usize,
     filter: &mut impl Filter,
 ) -> Option<usize> {
+    let keep: Vec<bool> = piece
+        .sentences
+        .iter()
+        .map(|sentence| filter.detect(sentence.as_bytes()))
+        .collect();
     let filtered: Vec<String> = piece
         .sentences
         .iter()
-        .filter(|sentence| filter.detect(sentence.as_bytes()))
-        .map(String::from)
+        .zip(keep.iter())
+        .filter(|(_, keep)| **keep)
+        .map(|(sentence, _)| sentence.to_string())
         .collect();
     let nb_sentences = filtered.len();
 
@@ -38,8 +46,16 @@ pub fn dedup_piece(
         return None;
     }
 
+    let line_numbers: Vec<usize> = types::line_numbers(&piece.headers.line_ranges)
+        .into_iter()
+        .zip(keep.iter())
+        .filter(|(_, keep)| **keep)
+        .map(|(line, _)| line)
+        .collect();
+
     piece.headers.offset = new_offset;
     piece.headers.nb_sentences = nb_sentences;
+    piece.headers.line_ranges = types::line_ranges(&line_numbers);
     piece.sentences = filtered;
 
     Some(new_offset + nb_sentences + 1)
@@ -142,6 +158,26 @@ mod tests {
         assert_eq!(piece, expected);
     }
 
+    #[test]
+    fn test_dedup_piece_line_ranges() {
+        let mut filter = runiq::filters::DigestFilter::new();
+        let mut piece = PieceMeta {
+            sentences: ["hello", "goodbye!", "goodbye!", "bye"]
+                .iter()
+                .map(|x| x.to_string())
+                .collect(),
+            identification: "en",
+            headers: Metadata {
+                nb_sentences: 4,
+                line_ranges: vec![(0, 3), (5, 6)],
+                ..Default::default()
+            },
+        };
+
+        dedup_piece(&mut piece, 0, &mut filter);
+        assert_eq!(piece.headers.line_ranges, vec![(0, 2), (5, 6)]);
+    }
+
     #[test]
     fn test_dedup_piece_multiple() {
         let mut filter = runiq::filters::DigestFilter::new();
@@ -0,0 +1,441 @@
+/*! Merging of outputs from multiple runs
+
+Runs on separate batches of shards produce separate outputs, that can be merged back into a single one:
+- Text and metadata files (`<lang>.txt`, `<lang>_meta.jsonl`) are rewritten one language at a time,
+  so that offsets in metadata are continuous in the merged files.
+- Document files of [crate::pipelines::OscarDoc] (`<lang>_meta.jsonl` without a text file)
+  and short sentences files (`<lang>_short.txt`) are concatenated, since they don't hold offsets.
+- Rebuild files (`*.avro`, either at the root of inputs or in their `rebuild/` folder) are concatenated,
+  since their locations refer to shards rather than to output files.
+  Merged files use the codec and quantization (see [crate::pipelines::oscardoc::types::ProbQuantization])
+  of the first input holding them.
+
+Inputs are merged in the provided order, and a file is merged if it's present in at least one input.
+Only flat, uncompressed and non-split outputs of text and metadata files or documents are supported
+(see [crate::io::LayoutStrategy] and [crate::processing::split]): other outputs make the merge fail before anything is written.
+!*/
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::path::{Path, PathBuf};
+
+use log::{debug, info};
+use rayon::prelude::*;
+
+use crate::error::Error;
+use crate::io::reader::reader::Reader;
+use crate::io::writer::{WriterTrait, COMBINED_FILE};
+use crate::io::{Writer, STAGING_DIR};
+use crate::lang::{label_from_iso639_3, LANG};
+use crate::pipelines::oscardoc::types::{RebuildReader, RebuildWriter};
+use crate::pipelines::oscarmeta::types::MergedPiece;
+
+/// Number of pieces in a bulk write.
+const BUFSIZE: usize = 500;
+
+/// Folders (relative to inputs) that can hold rebuild files.
+const REBUILD_DIRS: [&str; 2] = ["", "rebuild"];
+
+/// Mergeable files of an input.
+#[derive(Debug, Default)]
+struct InputFiles {
+    /// Languages with text and metadata files.
+    text_meta: Vec<&'static str>,
+    /// Names of the files that are concatenated.
+    concatenated: Vec<String>,
+}
+
+/// Check if `name` is a language label or an ISO 639-3 code (see [crate::lang::LangNaming]).
+fn is_lang(name: &str) -> bool {
+    LANG.contains(name) || label_from_iso639_3(name).is_some()
+}
+
+/// List the mergeable files of `input`, ignoring files that are not language files (such as manifests).
+///
+/// # Errors
+/// Returns an error if `input` holds files that can't be merged: compressed or split files,
+/// per-language folders, JSON Lines or Parquet pieces, text files without metadata or of unknown languages.
+fn scan_input(input: &Path) -> Result<InputFiles, Error> {
+    let unsupported = |name: &str, reason: &str| {
+        Err(Error::Custom(format!(
+            "can't merge {:?} of {:?}: {}",
+            name, input, reason
+        )))
+    };
+
+    let (mut texts, mut metas) = (HashSet::new(), Vec::new());
+    let mut files = InputFiles::default();
+    for entry in std::fs::read_dir(input)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if entry.file_type()?.is_dir() {
+            if is_lang(&name) {
+                return unsupported(&name, "per-language folders are not supported");
+            }
+            if name != "rebuild" && name != STAGING_DIR {
+                debug!("ignoring folder {:?} of {:?}", name, input);
+            }
+            continue;
+        }
+
+        if name.ends_with(".gz") {
+            return unsupported(&name, "compressed files are not supported");
+        }
+        if name.contains("_part_") {
+            return unsupported(&name, "split files are not supported");
+        }
+        if let Some(stem) = name.strip_suffix("_meta.jsonl") {
+            metas.push(stem.to_string());
+        } else if let Some(stem) = name.strip_suffix(".txt") {
+            match (stem.strip_suffix("_short"), LANG.get(stem)) {
+                (Some(_), _) => files.concatenated.push(name),
+                (None, Some(lang)) => {
+                    texts.insert(*lang);
+                }
+                (None, None) => return unsupported(&name, "unknown language"),
+            }
+        } else if name == COMBINED_FILE
+            || name
+                .strip_suffix(".jsonl")
+                .or_else(|| name.strip_suffix(".parquet"))
+                .is_some_and(is_lang)
+        {
+            return unsupported(
+                &name,
+                "only text and metadata files or documents are supported",
+            );
+        } else if !name.ends_with(".avro") {
+            debug!("ignoring {:?} of {:?}", name, input);
+        }
+    }
+
+    for stem in metas {
+        match LANG.get(stem.as_str()) {
+            Some(lang) if texts.remove(lang) => files.text_meta.push(*lang),
+            // documents
+            _ => files.concatenated.push(format!("{}_meta.jsonl", stem)),
+        }
+    }
+    if let Some(lang) = texts.into_iter().next() {
+        return unsupported(&format!("{}.txt", lang), "metadata file is missing");
+    }
+
+    Ok(files)
+}
+
+/// Merge the text and metadata files of `lang` from `readers` into `dst`.
+fn merge_lang(dst: &Path, lang: &'static str, readers: Vec<Reader>) -> Result<(), Error> {
+    info!("[{}] merging {} inputs", lang, readers.len());
+    let mut writer = Writer::new(dst, lang, None)?;
+
+    for reader in readers {
+        let mut buf = Vec::with_capacity(BUFSIZE);
+        for piece in reader {
+            buf.push(MergedPiece::from(piece?));
+            if buf.len() == BUFSIZE {
+                writer.write(std::mem::take(&mut buf))?;
+            }
+        }
+        if !buf.is_empty() {
+            writer.write(buf)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Concatenate `srcs` files into `dst`.
+fn concatenate(dst: &Path, srcs: &[PathBuf]) -> Result<(), Error> {
+    debug!("concatenating {:?} into {:?}", srcs, dst);
+    let mut writer = File::create(dst)?;
+    for src in srcs {
+        std::io::copy(&mut File::open(src)?, &mut writer)?;
+    }
+
+    Ok(())
+}
+
+/// Concatenate `srcs` rebuild files into `dst`.
+///
+/// The first non-empty file is copied, so that its codec and quantization are kept, and the other ones are appended to it.
+fn merge_rebuild(dst: &Path, srcs: &[PathBuf]) -> Result<(), Error> {
+    debug!("merging {:?} into {:?}", srcs, dst);
+    let mut copy = OpenOptions::new().write(true).create_new(true).open(dst)?;
+    let mut srcs = srcs.iter().filter(|src| match std::fs::metadata(src) {
+        Ok(metadata) => metadata.len() > 0,
+        Err(_) => true,
+    });
+    let first = match srcs.next() {
+        Some(first) => first,
+        // only empty files
+        None => return Ok(()),
+    };
+    std::io::copy(&mut File::open(first)?, &mut copy)?;
+    drop(copy);
+
+    let codec = RebuildWriter::path_codec(first)?;
+    let mut writer = RebuildWriter::append_path(dst, codec)?;
+    for src in srcs {
+        for shard_result in RebuildReader::from_path(src)? {
+            writer.append_ser(shard_result?)?;
+        }
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Merge per-language outputs of `inputs` into `dst`.
+///
+/// `dst` is created if it doesn't exist.
+///
+/// # Errors
+/// Returns an error if `dst` is not empty, if an input can't be merged (see the [module documentation](self)),
+/// or if inputs mix text and metadata files and documents of a language.
+pub fn merge_outputs(inputs: &[PathBuf], dst: &Path) -> Result<(), Error> {
+    if dst.exists() && std::fs::read_dir(dst)?.next().is_some() {
+        return Err(Error::Custom(format!(
+            "merge destination {:?} is not empty",
+            dst
+        )));
+    }
+
+    // check every input before writing anything
+    let scanned = inputs
+        .iter()
+        .map(|input| scan_input(input))
+        .collect::<Result<Vec<_>, Error>>()?;
+    let mut readers: HashMap<&'static str, Vec<Reader>> = HashMap::new();
+    let mut concatenated: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+    for (input, files) in inputs.iter().zip(scanned) {
+        for lang in files.text_meta {
+            readers
+                .entry(lang)
+                .or_default()
+                .push(Reader::new(input, lang)?);
+        }
+        for name in files.concatenated {
+            concatenated
+                .entry(name.clone())
+                .or_default()
+                .push(input.join(name));
+        }
+    }
+    if let Some(lang) = readers
+        .keys()
+        .find(|lang| concatenated.contains_key(&format!("{}_meta.jsonl", lang)))
+    {
+        return Err(Error::Custom(format!(
+            "inputs hold both text files and documents of {}",
+            lang
+        )));
+    }
+
+    std::fs::create_dir_all(dst)?;
+
+    // text and metadata files
+    readers
+        .into_par_iter()
+        .map(|(lang, readers)| merge_lang(dst, lang, readers))
+        .collect::<Result<(), Error>>()?;
+
+    // documents and short sentences files
+    concatenated
+        .into_par_iter()
+        .map(|(name, srcs)| concatenate(&dst.join(name), &srcs))
+        .collect::<Result<(), Error>>()?;
+
+    // rebuild files, merged by name
+    for dir in REBUILD_DIRS {
+        let mut rebuild: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+        for input in inputs {
+            let input_dir = input.join(dir);
+            if !input_dir.is_dir() {
+                continue;
+            }
+            for entry in std::fs::read_dir(&input_dir)? {
+                let entry = entry?;
+                let name = entry.file_name().to_string_lossy().into_owned();
+                if name.ends_with(".avro") && entry.file_type()?.is_file() {
+                    rebuild.entry(name).or_default().push(entry.path());
+                }
+            }
+        }
+        if rebuild.is_empty() {
+            continue;
+        }
+
+        let dst_dir = dst.join(dir);
+        std::fs::create_dir_all(&dst_dir)?;
+        rebuild
+            .into_par_iter()
+            .map(|(name, srcs)| merge_rebuild(&dst_dir.join(name), &srcs))
+            .collect::<Result<(), Error>>()?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::Path;
+
+    use crate::identifiers::Identification;
+    use crate::io::reader::reader::Reader;
+    use crate::io::writer::WriterTrait;
+    use crate::io::Writer;
+    use crate::lang::Lang;
+    use crate::pipelines::oscardoc::types::{
+        Codec, Location, Metadata, RebuildReader, RebuildWriter, ShardResult,
+    };
+    use crate::pipelines::oscarmeta::types::MergedPiece;
+
+    use super::merge_outputs;
+
+    fn write_pieces(dst: &Path, lang: &'static str, pieces: &[&str]) {
+        let mut writer = Writer::new(dst, lang, None).unwrap();
+        let pieces = pieces
+            .iter()
+            .map(|p| MergedPiece::new(HashMap::new(), p.lines().map(String::from).collect(), lang))
+            .collect();
+        writer.write(pieces).unwrap();
+    }
+
+    fn write_rebuild(path: &Path, shard_id: u64, codec: Codec) {
+        let id = Identification::new(Lang::Fr, 0.9);
+        let loc = Location::new(shard_id, "record-0".to_string(), 0, 1, 0);
+        let sr = ShardResult::new(shard_id, vec![loc], vec![Metadata::new(&id, &[])]);
+        let mut writer = RebuildWriter::from_path(path, codec).unwrap();
+        writer.append_ser(sr).unwrap();
+        writer.flush().unwrap();
+    }
+
+    #[test]
+    fn merge() {
+        let inputs: Vec<_> = (0..2).map(|_| tempfile::tempdir().unwrap()).collect();
+        write_pieces(inputs[0].path(), "fr", &["a\nb", "c"]);
+        write_pieces(inputs[1].path(), "fr", &["d\ne\nf"]);
+        // only in first input
+        write_pieces(inputs[0].path(), "en", &["g"]);
+        // rebuild folder only in second input
+        std::fs::create_dir(inputs[1].path().join("rebuild")).unwrap();
+        write_rebuild(
+            &inputs[1].path().join("rebuild").join("fr.avro"),
+            1,
+            Codec::Null,
+        );
+        write_rebuild(&inputs[0].path().join("fr.avro"), 0, Codec::Deflate);
+        write_rebuild(&inputs[1].path().join("fr.avro"), 2, Codec::Null);
+
+        let dst = tempfile::tempdir().unwrap();
+        let paths: Vec<_> = inputs.iter().map(|i| i.path().to_path_buf()).collect();
+        merge_outputs(&paths, dst.path()).unwrap();
+
+        let text = std::fs::read_to_string(dst.path().join("fr.txt")).unwrap();
+        assert_eq!(text, "a\nb\n\nc\n\nd\ne\nf\n\n");
+
+        let pieces: Vec<_> = Reader::new(dst.path(), "fr")
+            .unwrap()
+            .map(|p| p.unwrap())
+            .collect();
+        let offsets: Vec<_> = pieces.iter().map(|p| p.headers.offset).collect();
+        assert_eq!(offsets, vec![0, 3, 5]);
+        assert_eq!(pieces[2].sentences, vec!["d", "e", "f"]);
+
+        assert!(dst.path().join("en.txt").is_file());
+
+        let shard_ids = |path: &Path| -> Vec<u64> {
+            RebuildReader::from_path(path)
+                .unwrap()
+                .map(|sr| sr.unwrap().shard_id())
+                .collect()
+        };
+        assert_eq!(shard_ids(&dst.path().join("fr.avro")), vec![0, 2]);
+        // codec of the first input
+        assert_eq!(
+            RebuildWriter::path_codec(&dst.path().join("fr.avro")).unwrap(),
+            Codec::Deflate
+        );
+        assert_eq!(
+            shard_ids(&dst.path().join("rebuild").join("fr.avro")),
+            vec![1]
+        );
+    }
+
+    #[test]
+    fn merge_concatenated() {
+        let inputs: Vec<_> = (0..2).map(|_| tempfile::tempdir().unwrap()).collect();
+        for (input, content) in inputs.iter().zip(["{\"a\": 0}\n", "{\"a\": 1}\n"]) {
+            std::fs::write(input.path().join("en_meta.jsonl"), content).unwrap();
+            std::fs::write(input.path().join("en_short.txt"), content).unwrap();
+        }
+        // not merged
+        std::fs::write(inputs[0].path().join("manifest.json"), "{}").unwrap();
+
+        let dst = tempfile::tempdir().unwrap();
+        let paths: Vec<_> = inputs.iter().map(|i| i.path().to_path_buf()).collect();
+        merge_outputs(&paths, dst.path()).unwrap();
+
+        for name in ["en_meta.jsonl", "en_short.txt"] {
+            let content = std::fs::read_to_string(dst.path().join(name)).unwrap();
+            assert_eq!(content, "{\"a\": 0}\n{\"a\": 1}\n");
+        }
+        assert!(!dst.path().join("en.txt").exists());
+        assert!(!dst.path().join("manifest.json").exists());
+    }
+
+    #[test]
+    fn merge_unsupported() {
+        let merge = |create: &dyn Fn(&Path)| {
+            let input = tempfile::tempdir().unwrap();
+            write_pieces(input.path(), "fr", &["a"]);
+            create(input.path());
+            let dst = tempfile::tempdir().unwrap();
+            let result = merge_outputs(&[input.path().to_path_buf()], dst.path());
+            // nothing is written on failure
+            if result.is_err() {
+                assert_eq!(std::fs::read_dir(dst.path()).unwrap().count(), 0);
+            }
+            result
+        };
+
+        assert!(merge(&|_| ()).is_ok());
+        assert!(merge(&|input| std::fs::write(input.join("en.txt.gz"), "").unwrap()).is_err());
+        assert!(merge(&|input| std::fs::write(input.join("en_part_1.txt"), "").unwrap()).is_err());
+        assert!(merge(&|input| std::fs::create_dir(input.join("en")).unwrap()).is_err());
+        assert!(merge(&|input| std::fs::write(input.join("en.jsonl"), "").unwrap()).is_err());
+        assert!(merge(&|input| std::fs::write(input.join("en.parquet"), "").unwrap()).is_err());
+        // text file without metadata
+        assert!(merge(&|input| std::fs::write(input.join("en.txt"), "").unwrap()).is_err());
+    }
+
+    #[test]
+    fn merge_mixed() {
+        let inputs: Vec<_> = (0..2).map(|_| tempfile::tempdir().unwrap()).collect();
+        write_pieces(inputs[0].path(), "fr", &["a"]);
+        std::fs::write(inputs[1].path().join("fr_meta.jsonl"), "{}\n").unwrap();
+
+        let dst = tempfile::tempdir().unwrap();
+        let paths: Vec<_> = inputs.iter().map(|i| i.path().to_path_buf()).collect();
+        assert!(merge_outputs(&paths, dst.path()).is_err());
+    }
+
+    #[test]
+    fn merge_nonempty_dst() {
+        let input = tempfile::tempdir().unwrap();
+        write_pieces(input.path(), "fr", &["a"]);
+        let dst = tempfile::tempdir().unwrap();
+        std::fs::write(dst.path().join("fr.txt"), "b").unwrap();
+
+        assert!(merge_outputs(&[input.path().to_path_buf()], dst.path()).is_err());
+        assert_eq!(
+            std::fs::read_to_string(dst.path().join("fr.txt")).unwrap(),
+            "b"
+        );
+
+        // missing destinations are created
+        let dst = dst.path().join("merged");
+        merge_outputs(&[input.path().to_path_buf()], &dst).unwrap();
+        assert!(dst.join("fr.txt").is_file());
+    }
+}
@@ -7,6 +7,7 @@ This module is for now only compatible with CommonCrawl extracted content, but w
 pub mod check;
 pub mod compress;
 pub mod dedup;
+pub mod merge;
 pub mod package;
 pub mod rebuild;
 pub mod split;
@@ -7,13 +7,15 @@
  * [RecordIterator] iteratively returns [Document]s from a **single** avro record (which corresponds to a **single** shard).
  * [SRIterator] iteratively returns [RecordIterator]s from a **single** avro file (which corresponds to several shards).
  * [todo] calls [Iterator::next] on [SRIterator] and uses `n` threads to retrieve [Document]s and do IO to recreate the corpus.
+ * [verify_rebuild] checks that rebuild information still matches a shard.
 * !*/
 use crate::io::writer::WriterDoc;
 use crate::io::writer::WriterTrait;
+use crate::pipelines::oscardoc::types::split_lines;
 use crate::pipelines::oscardoc::types::Document;
 use crate::pipelines::oscardoc::types::RebuildInformation;
-use crate::pipelines::oscardoc::types::ShardResult;
-use crate::sources::commoncrawl::Wet;
+use crate::pipelines::oscardoc::types::RebuildReader;
+use crate::sources::commoncrawl::{Wet, WetIter};
 use std::fs::File;
 use std::io::BufRead;
 use std::io::BufReader;
@@ -24,12 +26,10 @@ use std::sync::Mutex;
 use std::vec::IntoIter;
 
 use flate2::read::MultiGzDecoder;
-use itertools::Itertools;
 use log::debug;
 use log::error;
 use rayon::iter::ParallelBridge;
 use rayon::iter::ParallelIterator;
-use warc::RecordIter;
 
 use crate::error::Error;
 use crate::lang::Lang;
@@ -43,8 +43,8 @@ where
     I: Iterator<Item = RebuildInformation>,
 {
     rebuild_iter: I,
-    shard_iter: RecordIter<T>,
-    shard_id: usize,
+    shard_iter: WetIter<T>,
+    shard_id: u64,
 
     prev_loc: usize,
 }
@@ -54,7 +54,7 @@ where
     T: BufRead,
     I: Iterator<Item = RebuildInformation>,
 {
-    fn new(rebuild_iter: I, shard_iter: RecordIter<T>, shard_id: usize) -> Self {
+    fn new(rebuild_iter: I, shard_iter: WetIter<T>, shard_id: u64) -> Self {
         debug!("opening iterator on shard {}", shard_id);
         Self {
             rebuild_iter,
@@ -65,7 +65,7 @@ where
     }
 
     /// Get a reference to the record iterator's shard id.
-    pub fn shard_id(&self) -> usize {
+    pub fn shard_id(&self) -> u64 {
         self.shard_id
     }
 }
@@ -106,14 +106,8 @@ where
             // separate raw parts
             let (headers, body) = record.into_raw_parts();
 
-            // compute line bounds and get them
-            let nb_skip = rb_info.line_start();
-            let nb_take = rb_info.line_end() - rb_info.line_start();
-            let body = String::from_utf8_lossy(&body)
-                .lines()
-                .skip(nb_skip)
-                .take(nb_take)
-                .join("\n");
+            // get lines within bounds
+            let body = rb_info.extract_lines(&String::from_utf8_lossy(&body));
 
             // create document and update prev_loc
             let document = Document::new(body, headers.headers, rb_info.metadata().clone());
@@ -131,7 +125,7 @@ where
 /// When calling [Iterator::next], an avro record and a shard are read and a [RecordIterator] is built on them.
 pub struct SRIterator<'a> {
     src_shards: &'a Path,
-    rebuild_reader: avro_rs::Reader<'a, BufReader<File>>,
+    rebuild_reader: RebuildReader<'static, BufReader<File>>,
 }
 
 impl<'a> SRIterator<'a> {
@@ -159,7 +153,7 @@ impl<'a> SRIterator<'a> {
         // open avro reader
         let f = File::open(src_rebuild)?;
         let f = BufReader::new(f);
-        let rebuild_reader = avro_rs::Reader::new(f)?;
+        let rebuild_reader = RebuildReader::new(f)?;
 
         Ok(Self {
             src_shards,
@@ -172,9 +166,9 @@ impl<'a> Iterator for SRIterator<'a> {
     type Item = RecordIterator<BufReader<MultiGzDecoder<File>>, IntoIter<RebuildInformation>>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        // get next entry in avro file
-        let next_rebuild = match self.rebuild_reader.next() {
-            Some(Ok(nr)) => nr,
+        // get next entry in avro file, deserialized into a shard result
+        let shard_result = match self.rebuild_reader.next() {
+            Some(Ok(sr)) => sr,
             None => return None,
             Some(Err(e)) => {
                 error!("{}", e);
@@ -182,23 +176,13 @@ impl<'a> Iterator for SRIterator<'a> {
             }
         };
 
-        // deserialize entry into a shard result
-        let shard_result: ShardResult = match avro_rs::from_value(&next_rebuild) {
-            Ok(sr) => sr,
-            Err(e) => {
-                error!("{}", e);
-                return None;
-            }
-        };
-
         debug!(
             "shard {}: {} records to rebuild",
             shard_result.shard_id(),
             shard_result.rebuild_info().len()
         );
 
-        //TODO remove as keyword
-        let shard_id = shard_result.shard_id() as usize;
+        let shard_id = shard_result.shard_id();
 
         // forge shard path
         let mut shard_path = PathBuf::from(self.src_shards);
@@ -213,6 +197,105 @@ impl<'a> Iterator for SRIterator<'a> {
     }
 }
 
+/// Reason of a [Mismatch].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MismatchReason {
+    /// There's no record at `loc_in_shard`.
+    MissingRecord,
+    /// The record at `loc_in_shard` could not be read.
+    InvalidRecord(String),
+    /// The record at `loc_in_shard` has another record id.
+    RecordIdMismatch(String),
+    /// `[line_start, line_end]` is empty (`line_end` is before `line_start`).
+    EmptyRange,
+    /// `line_end` is past the last line of the record.
+    OutOfBounds(usize),
+    /// The line range is not UTF-8 valid.
+    InvalidUtf8,
+}
+
+/// Rebuild information that does not match the shard content.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    pub record_id: String,
+    pub reason: MismatchReason,
+}
+
+impl Mismatch {
+    fn new(record_id: &str, reason: MismatchReason) -> Self {
+        Self {
+            record_id: record_id.to_string(),
+            reason,
+        }
+    }
+}
+
+/// Check the (inclusive) line range of a record body, whose lines are split as in
+/// [RebuildInformation::extract_lines].
+fn check_lines(body: &[u8], rb_info: &RebuildInformation) -> Option<MismatchReason> {
+    if rb_info.line_end() < rb_info.line_start() {
+        return Some(MismatchReason::EmptyRange);
+    }
+
+    let lines = split_lines(body);
+    if rb_info.line_end() >= lines.len() {
+        return Some(MismatchReason::OutOfBounds(lines.len()));
+    }
+
+    if lines[rb_info.line_start()..=rb_info.line_end()]
+        .iter()
+        .any(|line| std::str::from_utf8(line).is_err())
+    {
+        return Some(MismatchReason::InvalidUtf8);
+    }
+
+    None
+}
+
+/// Walk `shard` and check each provided [RebuildInformation] against the record located at `loc_in_shard`.
+///
+/// Checks that the record exists and has the same record id, and that the line range is non-empty and UTF-8 valid.
+/// Every mismatch is returned, rather than stopping on the first one.
+///
+/// # Errors
+/// Returns an error if the shard can't be opened.
+pub fn verify_rebuild(shard: &Path, info: &[RebuildInformation]) -> Result<Vec<Mismatch>, Error> {
+    let mut info: Vec<&RebuildInformation> = info.iter().collect();
+    info.sort_by_key(|rb_info| rb_info.loc_in_shard());
+    let mut info = info.into_iter().peekable();
+
+    let mut mismatches = Vec::new();
+    let shard_iter = Wet::from_path(shard)?.iter;
+
+    for (loc, record) in shard_iter.enumerate() {
+        // stop reading the shard once every information is checked
+        if info.peek().is_none() {
+            break;
+        }
+
+        while let Some(rb_info) = info.next_if(|rb_info| rb_info.loc_in_shard() == loc) {
+            let reason = match &record {
+                Err(e) => Some(MismatchReason::InvalidRecord(format!("{:?}", e))),
+                Ok(record) if record.warc_id() != rb_info.record_id() => Some(
+                    MismatchReason::RecordIdMismatch(record.warc_id().to_string()),
+                ),
+                Ok(record) => check_lines(record.body(), rb_info),
+            };
+
+            if let Some(reason) = reason {
+                mismatches.push(Mismatch::new(rb_info.record_id(), reason));
+            }
+        }
+    }
+
+    // remaining information points past the end of the shard
+    mismatches.extend(
+        info.map(|rb_info| Mismatch::new(rb_info.record_id(), MismatchReason::MissingRecord)),
+    );
+
+    Ok(mismatches)
+}
+
 /// Corpus rebuilder for a single language.
 pub struct Rebuilder<'a> {
     src_rebuild: &'a Path,
@@ -273,17 +356,99 @@ impl<'a> Rebuilder<'a> {
 mod tests {
     use std::{
         collections::HashMap,
+        fs::File,
         io::{BufReader, Cursor},
+        path::Path,
     };
 
-    use warc::WarcReader;
+    use flate2::{write::GzEncoder, Compression};
+    use warc::{BufferedBody, Record, WarcReader, WarcWriter};
 
     use crate::{
         identifiers::Identification,
         lang::Lang,
-        pipelines::oscardoc::types::{Document, Metadata},
+        pipelines::oscardoc::types::{Document, Location, Metadata, RebuildInformation},
     };
 
+    use super::{verify_rebuild, Mismatch, MismatchReason};
+
+    /// write a gzipped shard at `path`, returning record ids.
+    fn write_shard(path: &Path, bodies: &[&[u8]]) -> Vec<String> {
+        let mut enc = GzEncoder::new(File::create(path).unwrap(), Compression::default());
+        let mut ids = Vec::new();
+        {
+            let mut writer = WarcWriter::new(&mut enc);
+            for body in bodies {
+                let record: Record<BufferedBody> = Record::default().add_body(body.to_vec());
+                ids.push(record.warc_id().to_string());
+                writer.write(&record).unwrap();
+            }
+        }
+        enc.finish().unwrap();
+        ids
+    }
+
+    fn rb_info(
+        record_id: &str,
+        line_start: usize,
+        line_end: usize,
+        loc: usize,
+    ) -> RebuildInformation {
+        let loc = Location::new(0, record_id.to_string(), line_start, line_end, loc);
+        RebuildInformation::new(loc, Metadata::default())
+    }
+
+    #[test]
+    fn verify_rebuild_ok() {
+        let dst = tempfile::tempdir().unwrap();
+        let path = dst.path().join("0.txt.gz");
+        let ids = write_shard(&path, &[b"a\nb\nc", b"d\ne"]);
+
+        let info = vec![rb_info(&ids[1], 0, 1, 1), rb_info(&ids[0], 1, 2, 0)];
+        assert!(verify_rebuild(&path, &info).unwrap().is_empty());
+    }
+
+    #[test]
+    fn verify_rebuild_mismatches() {
+        let dst = tempfile::tempdir().unwrap();
+        let path = dst.path().join("0.txt.gz");
+        let ids = write_shard(&path, &[b"a\nb\nc", b"d\n\xff", b"f"]);
+
+        let info = vec![
+            rb_info(&ids[0], 1, 0, 0),
+            rb_info(&ids[0], 0, 1, 2),
+            rb_info(&ids[1], 0, 1, 1),
+            rb_info(&ids[2], 0, 1, 2),
+            rb_info("unknown", 0, 1, 10),
+        ];
+        let mismatches = verify_rebuild(&path, &info).unwrap();
+
+        assert_eq!(
+            mismatches,
+            vec![
+                Mismatch::new(&ids[0], MismatchReason::EmptyRange),
+                Mismatch::new(&ids[1], MismatchReason::InvalidUtf8),
+                Mismatch::new(&ids[0], MismatchReason::RecordIdMismatch(ids[2].clone())),
+                Mismatch::new(&ids[2], MismatchReason::OutOfBounds(1)),
+                Mismatch::new("unknown", MismatchReason::MissingRecord),
+            ]
+        );
+    }
+
+    #[test]
+    fn verify_rebuild_line_endings() {
+        let dst = tempfile::tempdir().unwrap();
+        let path = dst.path().join("0.txt.gz");
+        let ids = write_shard(&path, &[b"a\r\nb\r\n", b"c\nd\n"]);
+
+        // final line endings don't start a new line
+        let info = vec![rb_info(&ids[0], 0, 1, 0), rb_info(&ids[1], 1, 2, 1)];
+        assert_eq!(
+            verify_rebuild(&path, &info).unwrap(),
+            vec![Mismatch::new(&ids[1], MismatchReason::OutOfBounds(2))]
+        );
+    }
+
     fn test_from_loc_meta() {
         let raw = b"\
             WARC/1.0\r\n\
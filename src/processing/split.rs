@@ -1,20 +1,27 @@
 /*! splitting
 
-Offline corpus splitting.
+Offline corpus splitting, and splitting of large WET shards into smaller ones (see [split_wet]).
 
 `part_size` has to be specified in Bytes here.
 !*/
 use crate::{
+    error::Error,
     io::{
         reader::{reader::Reader, Corpus},
         writer::WriterTrait,
         Writer,
     },
     pipelines::oscarmeta::types::MergedPiece,
+    sources::commoncrawl::RawRecords,
 };
-use log::info;
+use flate2::{write::GzEncoder, Compression};
+use log::{info, warn};
 use rayon::prelude::*;
-use std::path::Path;
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
 
 /// Split language in chunks of provided `part_size` (bytes).
 ///
@@ -75,3 +82,153 @@ pub fn split(src: &Path, dst: &Path, part_size: u64, bufsize: Option<usize>) {
         split_lang(dst, lang, reader, part_size * 1_000_000, bufsize);
     });
 }
+
+/// Split the WET shard at `src` into gzipped WET files of at most `records_per_part` records, in `dst_dir`.
+///
+/// Parts are named after the shard name (up to its first `.`) and their index: `0.txt.gz` is split into
+/// `0_0.txt.gz`, `0_1.txt.gz`…
+/// Records are copied byte for byte (see [RawRecords]), in shard order.
+/// A truncated last record (see [crate::sources::commoncrawl::Records]) is left out.
+///
+/// Returns the paths of written parts. A shard without records has no parts.
+///
+/// # Errors
+/// Returns an error if `records_per_part` is 0, if files can't be read or written, or on corrupt records.
+/// Parts that have already been written are kept then.
+pub fn split_wet(
+    src: &Path,
+    dst_dir: &Path,
+    records_per_part: usize,
+) -> Result<Vec<PathBuf>, Error> {
+    if records_per_part == 0 {
+        return Err(Error::Custom(
+            "records per part has to be strictly positive".to_string(),
+        ));
+    }
+    let stem = src
+        .file_name()
+        .and_then(|name| name.to_str())
+        .and_then(|name| name.split('.').next())
+        .filter(|stem| !stem.is_empty())
+        .ok_or_else(|| Error::Custom(format!("invalid shard name: {:?}", src)))?;
+    std::fs::create_dir_all(dst_dir)?;
+
+    let mut parts = Vec::new();
+    let mut part: Option<GzEncoder<BufWriter<File>>> = None;
+    let mut nb_records = 0;
+    for record in RawRecords::from_path(src)? {
+        let raw = match record {
+            Ok((_, raw)) => raw,
+            Err(Error::TruncatedRecord(e)) => {
+                warn!("leaving out truncated last record of {:?}: {:?}", src, e);
+                break;
+            }
+            Err(e) => return Err(e),
+        };
+
+        if nb_records % records_per_part == 0 {
+            if let Some(part) = part.take() {
+                part.finish()?.flush()?;
+            }
+            let path = dst_dir.join(format!("{}_{}.txt.gz", stem, parts.len()));
+            part = Some(GzEncoder::new(
+                BufWriter::new(File::create(&path)?),
+                Compression::default(),
+            ));
+            parts.push(path);
+        }
+        if let Some(part) = &mut part {
+            part.write_all(&raw)?;
+        }
+        nb_records += 1;
+    }
+    if let Some(part) = part {
+        part.finish()?.flush()?;
+    }
+
+    info!(
+        "split {:?} into {} parts ({} records)",
+        src,
+        parts.len(),
+        nb_records
+    );
+    Ok(parts)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::File, io::Read};
+
+    use flate2::read::MultiGzDecoder;
+
+    use crate::sources::commoncrawl::{RawRecords, Wet};
+    use crate::testing::WetBuilder;
+
+    use super::split_wet;
+
+    #[test]
+    fn split_wet_parts() {
+        let src = tempfile::tempdir().unwrap();
+        let dst = tempfile::tempdir().unwrap();
+        let shard =
+            WetBuilder::new().records((0..5).map(|idx| (Vec::new(), format!("record {}", idx))));
+        let shard_path = src.path().join("3.txt.gz");
+        shard.write(&shard_path).unwrap();
+
+        let parts = split_wet(&shard_path, dst.path(), 2).unwrap();
+        let names: Vec<_> = parts
+            .iter()
+            .map(|part| part.file_name().unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(names, vec!["3_0.txt.gz", "3_1.txt.gz", "3_2.txt.gz"]);
+
+        // parts are valid shards holding the exact bytes of their records
+        let mut bodies = Vec::new();
+        let mut raw = Vec::new();
+        for part in &parts {
+            let records: Vec<_> = RawRecords::from_path(part)
+                .unwrap()
+                .collect::<Result<_, _>>()
+                .unwrap();
+            assert!(records.len() <= 2);
+            for (record, record_raw) in records {
+                bodies.push(String::from_utf8(record.body().to_vec()).unwrap());
+                raw.extend(record_raw);
+            }
+        }
+        let expected: Vec<_> = (0..5).map(|idx| format!("record {}", idx)).collect();
+        assert_eq!(bodies, expected);
+        let mut uncompressed = Vec::new();
+        MultiGzDecoder::new(File::open(&shard_path).unwrap())
+            .read_to_end(&mut uncompressed)
+            .unwrap();
+        assert_eq!(raw, uncompressed);
+
+        let records: Vec<_> = Wet::from_path(&parts[2]).unwrap().records().collect();
+        assert_eq!(records.len(), 1);
+    }
+
+    #[test]
+    fn split_wet_truncated() {
+        let src = tempfile::tempdir().unwrap();
+        let dst = tempfile::tempdir().unwrap();
+        let shard_path = src.path().join("0.txt.gz");
+        WetBuilder::new()
+            .records(vec![(Vec::new(), "hello"), (Vec::new(), "world")])
+            .with_truncated_tail(3)
+            .write(&shard_path)
+            .unwrap();
+
+        let parts = split_wet(&shard_path, dst.path(), 10).unwrap();
+        assert_eq!(parts.len(), 1);
+        let records: Vec<_> = Wet::from_path(&parts[0])
+            .unwrap()
+            .records()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].body(), b"hello");
+
+        assert!(split_wet(&shard_path, dst.path(), 0).is_err());
+    }
+}
@@ -0,0 +1,396 @@
+//! WARC/HTML utils.
+//!
+//! Sibling of [super::Wet] for raw WARC files: only `response` records are kept,
+//! and their HTML payload is converted to plain text by a [TextExtractor].
+//!
+//! [Warc] implements [Iterator] over [warc::Record], with the extracted text as body,
+//! so that records can be processed the same way WET ones are.
+use std::{fs::File, io::BufRead, io::BufReader, path::Path};
+
+use crate::error::Error;
+use flate2::read::MultiGzDecoder;
+use log::debug;
+use warc::{BufferedBody, Record, RecordIter, RecordType, WarcReader};
+
+/// Tags whose content is never kept.
+const SKIPPED_TAGS: [&str; 4] = ["script", "style", "noscript", "template"];
+
+/// Tags that end a line of text.
+const BLOCK_TAGS: [&str; 24] = [
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "br",
+    "dd",
+    "div",
+    "dl",
+    "dt",
+    "footer",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "hr",
+    "li",
+    "p",
+    "pre",
+    "section",
+    "title",
+    "tr",
+];
+
+/// Converts an HTML document into plain text.
+///
+/// Lines of the returned text are expected to be separated by `\n`,
+/// since sentences are split on newlines further down the pipeline.
+pub trait TextExtractor {
+    fn extract(&self, html: &str) -> String;
+}
+
+/// Default, dependency-free [TextExtractor].
+///
+/// Removes tags, comments and `script`/`style` content,
+/// breaks lines on block-level tags and decodes the most common entities.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TagStripper;
+
+impl TagStripper {
+    /// decode a handful of common named/numeric entities.
+    fn decode_entities(text: &str) -> String {
+        text.replace("&nbsp;", " ")
+            .replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&quot;", "\"")
+            .replace("&#39;", "'")
+            .replace("&apos;", "'")
+            .replace("&amp;", "&")
+    }
+
+    /// Find the start of the first closing tag of `name` (ex. `</script`) in `html`, ignoring ASCII case.
+    fn find_closing(html: &str, name: &str) -> Option<usize> {
+        let mut offset = 0;
+        while let Some(idx) = html[offset..].find("</") {
+            let start = offset + idx;
+            let tag = html.as_bytes().get(start + 2..start + 2 + name.len());
+            if tag.is_some_and(|tag| tag.eq_ignore_ascii_case(name.as_bytes())) {
+                return Some(start);
+            }
+            offset = start + 2;
+        }
+        None
+    }
+
+    /// push text segment, replacing source whitespace (including newlines) by spaces.
+    fn push_segment(text: &mut String, segment: &str) {
+        let decoded = Self::decode_entities(segment);
+        text.extend(
+            decoded
+                .chars()
+                .map(|c| if c.is_whitespace() { ' ' } else { c }),
+        );
+    }
+}
+
+impl TextExtractor for TagStripper {
+    fn extract(&self, html: &str) -> String {
+        let mut text = String::with_capacity(html.len());
+        let mut rest = html;
+
+        while let Some(start) = rest.find('<') {
+            Self::push_segment(&mut text, &rest[..start]);
+            rest = &rest[start..];
+
+            if rest.starts_with("<!--") {
+                rest = rest.find("-->").map_or("", |end| &rest[end + 3..]);
+                continue;
+            }
+
+            let end = match rest.find('>') {
+                Some(end) => end,
+                None => {
+                    rest = "";
+                    break;
+                }
+            };
+
+            let tag = &rest[1..end];
+            let is_closing = tag.starts_with('/');
+            let name = tag
+                .trim_start_matches('/')
+                .chars()
+                .take_while(|c| c.is_ascii_alphanumeric())
+                .collect::<String>()
+                .to_ascii_lowercase();
+            rest = &rest[end + 1..];
+
+            if !is_closing && SKIPPED_TAGS.contains(&name.as_str()) {
+                rest = match Self::find_closing(rest, &name) {
+                    Some(idx) => {
+                        let after = &rest[idx..];
+                        after.find('>').map_or("", |end| &after[end + 1..])
+                    }
+                    None => "",
+                };
+                continue;
+            }
+
+            if BLOCK_TAGS.contains(&name.as_str()) {
+                text.push('\n');
+            }
+        }
+        Self::push_segment(&mut text, rest);
+
+        text.lines()
+            .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// Media types of the HTTP payloads that text is extracted from.
+const HTML_TYPES: [&str; 2] = ["text/html", "application/xhtml+xml"];
+
+/// Iterator over `response` records of a WARC file.
+///
+/// Other record types (`request`, `metadata`, `warcinfo`...) are skipped,
+/// as are responses whose HTTP `Content-Type` is not HTML (such as PDFs or images).
+/// Responses without a `Content-Type` are kept.
+/// The HTTP payload of kept responses is replaced by its extracted text.
+pub struct ResponseIter<T, E> {
+    records: RecordIter<T>,
+    extractor: E,
+}
+
+impl<T: BufRead, E: TextExtractor> ResponseIter<T, E> {
+    /// Get the media type of the `Content-Type` of HTTP `headers`, if any.
+    fn content_type(headers: &[u8]) -> Option<String> {
+        headers
+            .split(|chr| *chr == b'\n')
+            .find_map(|line| {
+                let colon = line.iter().position(|chr| *chr == b':')?;
+                let (name, value) = (&line[..colon], &line[colon + 1..]);
+                name.trim_ascii()
+                    .eq_ignore_ascii_case(b"content-type")
+                    .then_some(value)
+            })
+            .map(|value| {
+                let media_type = value.split(|chr| *chr == b';').next().unwrap_or(value);
+                String::from_utf8_lossy(media_type.trim_ascii()).to_ascii_lowercase()
+            })
+    }
+
+    /// Strip HTTP headers from body and extract text from HTML payload.
+    ///
+    /// Returns the media type of payloads that are not HTML instead.
+    fn extract(&self, body: &[u8]) -> Result<String, String> {
+        let (headers, payload) = body
+            .windows(4)
+            .position(|w| w == b"\r\n\r\n")
+            .map_or((&body[..0], body), |idx| (&body[..idx], &body[idx + 4..]));
+        match Self::content_type(headers) {
+            Some(media_type) if !HTML_TYPES.contains(&media_type.as_str()) => Err(media_type),
+            _ => Ok(self.extractor.extract(&String::from_utf8_lossy(payload))),
+        }
+    }
+}
+
+impl<T: BufRead, E: TextExtractor> Iterator for ResponseIter<T, E> {
+    type Item = Result<Record<BufferedBody>, warc::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let mut record = match self.records.next()? {
+                Ok(record) => record,
+                Err(e) => return Some(Err(e)),
+            };
+
+            if record.warc_type() != &RecordType::Response {
+                debug!(
+                    "skipping {:?} record {}",
+                    record.warc_type(),
+                    record.warc_id()
+                );
+                continue;
+            }
+
+            let text = match self.extract(record.body()) {
+                Ok(text) => text,
+                Err(media_type) => {
+                    debug!(
+                        "skipping {} response record {}",
+                        media_type,
+                        record.warc_id()
+                    );
+                    continue;
+                }
+            };
+            record.replace_body(text);
+            return Some(Ok(record));
+        }
+    }
+}
+
+/// Warc/Shard instance, generic over reader and [TextExtractor] type.
+///
+/// Like [super::Wet], CommonCrawl WARC files are gzipped and need
+/// a multi gz decoder (such as [MultiGzDecoder]).
+pub struct Warc<T, E = TagStripper> {
+    pub iter: ResponseIter<T, E>,
+}
+
+/// Warc reader using [MultiGzDecoder] over a [File].
+impl Warc<BufReader<MultiGzDecoder<File>>> {
+    /// Create a new reader from a gzipped WARC file, using [TagStripper].
+    pub fn from_path_gzip<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::from_path_gzip_with_extractor(path, TagStripper)
+    }
+}
+
+impl<E: TextExtractor> Warc<BufReader<MultiGzDecoder<File>>, E> {
+    /// Create a new reader from a gzipped WARC file, using the provided extractor.
+    pub fn from_path_gzip_with_extractor<P: AsRef<Path>>(
+        path: P,
+        extractor: E,
+    ) -> Result<Self, Error> {
+        let gzip_file = File::open(path)?;
+        let gzip_stream = MultiGzDecoder::new(gzip_file);
+        let bufreader = BufReader::new(gzip_stream);
+
+        Ok(Self::with_extractor(bufreader, extractor))
+    }
+}
+
+impl<T: BufRead> Warc<T> {
+    pub fn new(reader: T) -> Self {
+        Self::with_extractor(reader, TagStripper)
+    }
+}
+
+impl<T: BufRead, E: TextExtractor> Warc<T, E> {
+    pub fn with_extractor(reader: T, extractor: E) -> Self {
+        let records = WarcReader::new(reader).iter_records();
+        Self {
+            iter: ResponseIter { records, extractor },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use warc::{BufferedBody, Record, RecordType, WarcWriter};
+
+    use super::{TagStripper, TextExtractor, Warc};
+
+    fn record(warc_type: RecordType, body: &str) -> Record<BufferedBody> {
+        let mut record = Record::default().add_body(body);
+        record.set_warc_type(warc_type);
+        record
+    }
+
+    fn shard(records: &[Record<BufferedBody>]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        {
+            let mut writer = WarcWriter::new(&mut buf);
+            for r in records {
+                writer.write(r).unwrap();
+            }
+        }
+        buf
+    }
+
+    #[test]
+    fn strip_tags() {
+        let html = r#"<html><head><title>Title</title>
+        <style>body { color: red; }</style>
+        <script type="text/javascript">var a = "<p>";</script></head>
+        <body><!-- comment <p> --><p>Hello
+        <b>world</b> &amp; friends</p><div>second&nbsp;line</div><br/>third</body></html>"#;
+
+        let text = TagStripper.extract(html);
+        assert_eq!(text, "Title\nHello world & friends\nsecond line\nthird");
+    }
+
+    #[test]
+    fn strip_tags_unclosed() {
+        assert_eq!(TagStripper.extract("<p>foo<script>bar"), "foo");
+        assert_eq!(TagStripper.extract("foo <b"), "foo");
+    }
+
+    #[test]
+    fn responses_only() {
+        let records = [
+            record(RecordType::WarcInfo, "software: test"),
+            record(
+                RecordType::Request,
+                "GET / HTTP/1.1\r\nHost: example.com\r\n\r\n",
+            ),
+            record(
+                RecordType::Response,
+                "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\n\r\n<p>Hello</p><p>World</p>",
+            ),
+            record(RecordType::Metadata, "fetchTimeMs: 10"),
+        ];
+        let buf = shard(&records);
+
+        let warc = Warc::new(buf.as_slice());
+        let bodies: Vec<String> = warc
+            .iter
+            .map(|r| String::from_utf8(r.unwrap().body().to_vec()).unwrap())
+            .collect();
+
+        assert_eq!(bodies, vec!["Hello\nWorld".to_string()]);
+    }
+
+    #[test]
+    fn html_responses_only() {
+        let records = [
+            record(
+                RecordType::Response,
+                "HTTP/1.1 200 OK\r\nContent-Type: image/png\r\n\r\n\u{89}PNG",
+            ),
+            record(
+                RecordType::Response,
+                "HTTP/1.1 200 OK\r\ncontent-type: Application/XHTML+XML; charset=utf-8\r\n\r\n<p>Hello</p>",
+            ),
+        ];
+        let buf = shard(&records);
+
+        let bodies: Vec<Vec<u8>> = Warc::new(buf.as_slice())
+            .iter
+            .map(|r| r.unwrap().body().to_vec())
+            .collect();
+        assert_eq!(bodies, vec![b"Hello".to_vec()]);
+    }
+
+    #[test]
+    fn strip_skipped_tags_case() {
+        assert_eq!(
+            TagStripper.extract("a<SCRIPT>x</p></Script>b<style>y</STYLE >c"),
+            "abc"
+        );
+    }
+
+    #[test]
+    fn custom_extractor() {
+        struct Upper;
+        impl TextExtractor for Upper {
+            fn extract(&self, html: &str) -> String {
+                html.to_uppercase()
+            }
+        }
+
+        let records = [record(RecordType::Response, "HTTP/1.1 200 OK\r\n\r\nhello")];
+        let buf = shard(&records);
+
+        let mut warc = Warc::with_extractor(buf.as_slice(), Upper);
+        let r = warc.iter.next().unwrap().unwrap();
+        assert_eq!(r.body(), b"HELLO");
+        assert_eq!(r.content_length(), 5);
+        assert!(warc.iter.next().is_none());
+    }
+}
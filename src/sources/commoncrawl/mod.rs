@@ -1,6 +1,8 @@
 /*!
 Contains files relative to CommonCrawl.
 !*/
+mod html;
 mod shard;
 
-pub use shard::Wet;
+pub use html::{ResponseIter, TagStripper, TextExtractor, Warc};
+pub use shard::{RawRecords, RecordOffsets, Records, Wet, WetIter};
@@ -1,15 +1,29 @@
 //! Shard/WET utils.
 //!
-//! Mainly exists to wrap warc's library [warc::WarcReader] and an efficient gzip library.
+//! Mainly exists to wrap warc's library [warc::WarcReader] and efficient gzip/zstd/bzip2 libraries.
 //!
 //! [wet::Wet] implements [Iterator] over contained [warc::RawRecord].
-use std::{fs::File, io::BufReader, path::Path};
+use std::{
+    collections::HashSet,
+    convert::TryFrom,
+    fs::File,
+    io::BufReader,
+    iter::Peekable,
+    ops::Range,
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
 
 use crate::error::Error;
+use bzip2::read::MultiBzDecoder;
 use flate2::read::MultiGzDecoder;
-use std::io::BufRead;
-use warc::RecordIter;
+use log::warn;
+use std::io::{BufRead, Read};
 use warc::WarcReader;
+use warc::{BufferedBody, EmptyBody, RawRecordHeader, Record, RecordIter};
 
 /// Wet/Shard instance, generic over reader type.
 ///
@@ -18,8 +32,12 @@ use warc::WarcReader;
 ///
 /// Be aware that CommonCrawl files are gzipped and need
 /// a multi gz decoder (such as [MultiGzDecoder]).
+/// Zstd (`.zst`), bzip2 (`.bz2`) compressed files and uncompressed files (`.wet`)
+/// are also supported (see [Wet::from_path]).
+///
+/// Records with malformed headers are errors, unless [Wet::with_lenient_headers] is enabled.
 pub struct Wet<T> {
-    pub iter: RecordIter<T>,
+    pub iter: WetIter<T>,
 }
 
 // pub struct RecordIter<T: Iterator<Item = BufReader<MultiGzDecoder<File>>>> {
@@ -34,36 +52,826 @@ impl Wet<BufReader<MultiGzDecoder<File>>> {
     pub fn from_path_gzip<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
         // TODO: ensure that path is dir.
         let gzip_file = File::open(path)?;
-        let gzip_stream = MultiGzDecoder::new(gzip_file);
+        Ok(Self::from_reader_gzip(gzip_file))
+    }
+}
+
+/// Wet reader using [MultiGzDecoder] over any reader.
+impl<R: Read> Wet<BufReader<MultiGzDecoder<R>>> {
+    /// Create a new reader from a gzipped WET stream (such as a network stream).
+    pub fn from_reader_gzip(reader: R) -> Self {
+        let gzip_stream = MultiGzDecoder::new(reader);
 
         // we use a different reader from the default one in the warc crate to
         // manage multipart gzipped content.
         let bufreader = BufReader::new(gzip_stream);
 
-        let reader = WarcReader::new(bufreader);
+        Self::new(bufreader)
+    }
+}
+
+/// Wet reader over any uncompressed reader.
+impl<R: Read> Wet<BufReader<R>> {
+    /// Create a new reader from an uncompressed WET stream (such as an in-memory [std::io::Cursor]).
+    pub fn from_reader(reader: R) -> Self {
+        Self::new(BufReader::new(reader))
+    }
+}
+
+/// Wet reader using a zstd decoder over a [File].
+impl Wet<BufReader<zstd::Decoder<'static, BufReader<File>>>> {
+    /// Create a new reader from a zstd compressed WET file.
+    pub fn from_path_zstd<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let zstd_file = File::open(path)?;
+        let zstd_stream = zstd::Decoder::new(zstd_file)?;
+        let bufreader = BufReader::new(zstd_stream);
+
+        Ok(Self::new(bufreader))
+    }
+}
+
+/// Compression of a WET file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Compression {
+    Gzip,
+    Zstd,
+    Bzip2,
+    None,
+}
+
+impl Compression {
+    /// Get the compression from a known file extension.
+    fn from_extension(path: &Path) -> Option<Self> {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("gz") => Some(Self::Gzip),
+            Some("zst") => Some(Self::Zstd),
+            Some("bz2") => Some(Self::Bzip2),
+            Some("wet") => Some(Self::None),
+            _ => None,
+        }
+    }
+
+    /// Get the compression from the first bytes of a file.
+    ///
+    /// Content that doesn't start with a known magic number is assumed to be uncompressed.
+    fn from_magic(bytes: &[u8]) -> Self {
+        if bytes.starts_with(&[0x1f, 0x8b]) {
+            Self::Gzip
+        } else if bytes.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Self::Zstd
+        } else if bytes.starts_with(b"BZh") {
+            Self::Bzip2
+        } else {
+            Self::None
+        }
+    }
+}
+
+/// Wet reader over a decompressor chosen at runtime.
+impl Wet<BufReader<Box<dyn Read + Send>>> {
+    /// Create a new reader from a (possibly compressed) WET file,
+    /// picking the decompressor from the file extension:
+    ///
+    /// - `.gz` files are read using a [MultiGzDecoder],
+    /// - `.zst` files are read using zstd,
+    /// - `.bz2` files are read using a [MultiBzDecoder],
+    /// - `.wet` files are read as is.
+    ///
+    /// For files with a missing or unknown extension, the compression is guessed from the first bytes of the file,
+    /// and files that don't match any known format are read as is.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let bufreader = Self::open(path.as_ref())?;
+
+        Ok(Self::new(bufreader))
+    }
+
+    /// Open a (possibly compressed) WET file, returning a reader over its decompressed content
+    /// (see [Wet::from_path]).
+    fn open(path: &Path) -> Result<BufReader<Box<dyn Read + Send>>, Error> {
+        let mut file = BufReader::new(File::open(path)?);
+        let compression = match Compression::from_extension(path) {
+            Some(compression) => compression,
+            None => Compression::from_magic(file.fill_buf()?),
+        };
+        let stream: Box<dyn Read + Send> = match compression {
+            Compression::Gzip => Box::new(MultiGzDecoder::new(file)),
+            Compression::Zstd => Box::new(zstd::Decoder::with_buffer(file)?),
+            Compression::Bzip2 => Box::new(MultiBzDecoder::new(file)),
+            Compression::None => Box::new(file),
+        };
+        Ok(BufReader::new(stream))
+    }
+}
+
+/// [BufRead] wrapper counting consumed bytes.
+struct Counted<R> {
+    inner: R,
+    consumed: Arc<AtomicUsize>,
+}
+
+impl<R: Read> Read for Counted<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.consumed.fetch_add(read, Ordering::Relaxed);
+        Ok(read)
+    }
+}
+
+impl<R: BufRead> BufRead for Counted<R> {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.consumed.fetch_add(amt, Ordering::Relaxed);
+        self.inner.consume(amt)
+    }
+}
+
+/// [BufRead] wrapper keeping a copy of consumed bytes.
+struct Captured<R> {
+    inner: R,
+    captured: Arc<Mutex<Vec<u8>>>,
+}
+
+impl<R: Read> Read for Captured<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.captured
+            .lock()
+            .unwrap()
+            .extend_from_slice(&buf[..read]);
+        Ok(read)
+    }
+}
+
+impl<R: BufRead> BufRead for Captured<R> {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        // consumed bytes are still buffered, so this doesn't read anything
+        if let Ok(buf) = self.inner.fill_buf() {
+            let amt = amt.min(buf.len());
+            self.captured.lock().unwrap().extend_from_slice(&buf[..amt]);
+        }
+        self.inner.consume(amt)
+    }
+}
+
+/// Iterator over the records of a WET file, along with their exact bytes in the decompressed content.
+///
+/// Records are read as with [Wet::records], and bytes are the ones consumed by the WET reader for each record
+/// (headers, body and the trailing `\r\n\r\n`), so that records can be copied without being serialized again.
+pub struct RawRecords<T: BufRead> {
+    records: Records<Captured<T>>,
+    captured: Arc<Mutex<Vec<u8>>>,
+}
 
-        let x = reader.iter_records();
-        Ok(Self { iter: x })
+impl<T: BufRead> RawRecords<T> {
+    /// Create a new iterator over an uncompressed WET stream.
+    pub fn new(reader: T) -> Self {
+        let captured = Arc::new(Mutex::new(Vec::new()));
+        let reader = Captured {
+            inner: reader,
+            captured: captured.clone(),
+        };
+        Self {
+            records: Wet::new(reader).records(),
+            captured,
+        }
+    }
+}
+
+impl RawRecords<BufReader<Box<dyn Read + Send>>> {
+    /// Create a new iterator over a (possibly compressed) WET file (see [Wet::from_path]).
+    ///
+    /// Bytes are the decompressed ones.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Ok(Self::new(Wet::open(path.as_ref())?))
+    }
+}
+
+impl<T: BufRead> Iterator for RawRecords<T> {
+    type Item = Result<(Record<BufferedBody>, Vec<u8>), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let record = self.records.next()?;
+        let raw = std::mem::take(&mut *self.captured.lock().unwrap());
+        Some(record.map(|record| (record, raw)))
+    }
+}
+
+/// Iterator over the records of a WET file, along with the byte range of their body in the decompressed content.
+///
+/// Ranges are computed from the bytes consumed by the WARC reader:
+/// a body ends 4 bytes (`\r\n\r\n`) before the end of its record.
+pub struct RecordOffsets<T> {
+    iter: RecordIter<Counted<T>>,
+    consumed: Arc<AtomicUsize>,
+}
+
+impl<T: BufRead> RecordOffsets<T> {
+    /// Create a new iterator over an uncompressed WET stream.
+    pub fn new(reader: T) -> Self {
+        let consumed = Arc::new(AtomicUsize::new(0));
+        let reader = Counted {
+            inner: reader,
+            consumed: consumed.clone(),
+        };
+        Self {
+            iter: WarcReader::new(reader).iter_records(),
+            consumed,
+        }
+    }
+}
+
+impl RecordOffsets<BufReader<Box<dyn Read + Send>>> {
+    /// Create a new iterator over a (possibly compressed) WET file (see [Wet::from_path]).
+    ///
+    /// Ranges are relative to the decompressed content.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Ok(Self::new(Wet::open(path.as_ref())?))
+    }
+}
+
+impl<T: BufRead> Iterator for RecordOffsets<T> {
+    type Item = Result<(Record<BufferedBody>, Range<usize>), warc::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let record = match self.iter.next()? {
+            Ok(record) => record,
+            Err(e) => return Some(Err(e)),
+        };
+
+        let end = self.consumed.load(Ordering::Relaxed);
+        let len = record.body().len();
+        // empty bodies are not followed by a line break
+        let body_end = if len == 0 { end } else { end - 4 };
+        Some(Ok((record, body_end - len..body_end)))
     }
 }
 
 #[allow(dead_code)]
 impl<T: BufRead> Wet<T> {
     pub fn new(reader: T) -> Self {
-        let reader = WarcReader::new(reader);
-        let iter = reader.iter_records();
-        Self { iter }
+        Self {
+            iter: WetIter {
+                reader,
+                lenient_headers: false,
+            },
+        }
+    }
+
+    /// Enable or disable lenient parsing of record headers.
+    ///
+    /// When enabled, minor header malformations are repaired rather than failing the whole record:
+    /// - lines ending with a bare `\n` (rather than `\r\n`) are accepted, including the line ending the header block,
+    /// - header lines that can't be parsed (such as lines without a `:`, or with an invalid name) are skipped,
+    /// - headers that appear more than once (names are case-insensitive) keep their first value.
+    ///
+    /// Repaired records still have to hold the mandatory WARC headers, and records that still can't be parsed
+    /// are errors, as with strict parsing.
+    /// Bodies are read the same way in both modes.
+    /// Defaults to `false`.
+    pub fn with_lenient_headers(mut self, lenient_headers: bool) -> Self {
+        self.iter.lenient_headers = lenient_headers;
+        self
+    }
+
+    /// Iterate over records, telling truncated trailing records apart from corrupt ones (see [Records]).
+    pub fn records(self) -> Records<T> {
+        Records {
+            iter: self.iter.peekable(),
+        }
+    }
+
+    /// Iterate over records of index `start..end`, along with their index in the shard.
+    ///
+    /// Records before `start` still have to be read, since WET files can't be seeked.
+    pub fn range(
+        self,
+        start: usize,
+        end: usize,
+    ) -> impl Iterator<Item = (usize, Result<Record<BufferedBody>, Error>)> {
+        self.records()
+            .enumerate()
+            .skip(start)
+            .take(end.saturating_sub(start))
+    }
+}
+
+/// Iterator over the records of a [Wet].
+///
+/// Errors on the last record of a shard that ends unexpectedly (such as a shard whose download has been interrupted)
+/// are reported as [Error::TruncatedRecord], and iteration stops there.
+/// Other errors are reported as [Error::Warc], and mean that the shard is corrupt.
+///
+/// Note that a compressed stream that is corrupt can't be read further,
+/// so it's reported as truncated at the point of corruption.
+pub struct Records<T: BufRead> {
+    iter: Peekable<WetIter<T>>,
+}
+
+impl<T: BufRead> Records<T> {
+    /// Check if `e` can come from content ending unexpectedly.
+    ///
+    /// Decompressors report truncated streams as read errors of various kinds
+    /// (ex. [MultiGzDecoder] reports a `corrupt deflate stream`), so every read error is accepted.
+    fn is_eof(e: &warc::Error) -> bool {
+        matches!(e, warc::Error::UnexpectedEOB | warc::Error::ReadData(_))
+    }
+
+    /// Check if there's nothing left to read, skipping errors caused by the end of content.
+    fn at_end(&mut self) -> bool {
+        loop {
+            match self.iter.peek() {
+                None => return true,
+                Some(Err(e)) if Self::is_eof(e) => {
+                    self.iter.next();
+                }
+                Some(_) => return false,
+            }
+        }
+    }
+}
+
+impl<T: BufRead> Iterator for Records<T> {
+    type Item = Result<Record<BufferedBody>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.iter.next()? {
+            Ok(record) => Some(Ok(record)),
+            Err(e) if Self::is_eof(&e) && self.at_end() => Some(Err(Error::TruncatedRecord(e))),
+            Err(e) => Some(Err(Error::Warc(e))),
+        }
+    }
+}
+
+/// Iterator over the records of a [Wet] (see [Wet::with_lenient_headers]).
+///
+/// Records are read as [warc::RecordIter] does, and yield the same errors.
+pub struct WetIter<T> {
+    reader: T,
+    lenient_headers: bool,
+}
+
+impl<T: BufRead> WetIter<T> {
+    /// Check if `chr` can be part of a header name (see [warc::parser]).
+    fn is_header_token_char(chr: u8) -> bool {
+        !matches!(
+            chr,
+            0..=32 | 128..=255 | b'(' | b')' | b'<' | b'>' | b'@' | b',' | b';' | b':' | b'"'
+                | b'/' | b'[' | b']' | b'?' | b'=' | b'{' | b'}' | b'\\'
+        )
+    }
+
+    /// Check if `line` ends a header block.
+    fn is_header_end(&self, line: &[u8]) -> bool {
+        line == b"\r\n" || (self.lenient_headers && line == b"\n")
+    }
+
+    /// Rewrite a header block, skipping malformed and duplicate header lines
+    /// and normalizing line endings (see [Wet::with_lenient_headers]).
+    ///
+    /// The version line is kept as is, so that a block without a version still fails to parse.
+    fn repair_headers(block: &[u8]) -> Vec<u8> {
+        let mut lines = block
+            .split(|chr| *chr == b'\n')
+            .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+            .filter(|line| !line.is_empty());
+
+        let mut repaired = Vec::with_capacity(block.len());
+        if let Some(version) = lines.next() {
+            repaired.extend_from_slice(version);
+            repaired.extend_from_slice(b"\r\n");
+        }
+
+        let mut seen = HashSet::new();
+        for line in lines {
+            let (name, value) = match line.iter().position(|chr| *chr == b':') {
+                Some(colon) => (line[..colon].trim_ascii(), line[colon + 1..].trim_ascii()),
+                None => {
+                    warn!(
+                        "skipping malformed header line {:?}",
+                        String::from_utf8_lossy(line)
+                    );
+                    continue;
+                }
+            };
+            if name.is_empty() || !name.iter().all(|chr| Self::is_header_token_char(*chr)) {
+                warn!(
+                    "skipping malformed header line {:?}",
+                    String::from_utf8_lossy(line)
+                );
+                continue;
+            }
+            if !seen.insert(name.to_ascii_lowercase()) {
+                warn!(
+                    "skipping duplicate header {:?}",
+                    String::from_utf8_lossy(name)
+                );
+                continue;
+            }
+
+            repaired.extend_from_slice(name);
+            repaired.extend_from_slice(b": ");
+            repaired.extend_from_slice(value);
+            repaired.extend_from_slice(b"\r\n");
+        }
+        // the parser needs the empty line to know that the header block is complete
+        repaired.extend_from_slice(b"\r\n");
+        repaired
+    }
+}
+
+impl<T: BufRead> Iterator for WetIter<T> {
+    type Item = Result<Record<BufferedBody>, warc::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // read header block, up to (and including) the empty line ending it
+        let mut header_buffer: Vec<u8> = Vec::with_capacity(64 * 1024);
+        loop {
+            let start = header_buffer.len();
+            let bytes_read = match self.reader.read_until(b'\n', &mut header_buffer) {
+                Err(io) => return Some(Err(warc::Error::ReadData(io))),
+                Ok(len) => len,
+            };
+            if bytes_read == 0 {
+                // a shard ending inside a header block has a truncated trailing record
+                if !header_buffer.is_empty() {
+                    return Some(Err(warc::Error::UnexpectedEOB));
+                }
+                return None;
+            }
+            if self.is_header_end(&header_buffer[start..]) {
+                break;
+            }
+        }
+
+        let header_buffer = if self.lenient_headers {
+            Self::repair_headers(&header_buffer)
+        } else {
+            header_buffer
+        };
+        let (version, headers, expected_body_len) = match warc::parser::headers(&header_buffer) {
+            Err(e) => return Some(Err(warc::Error::ParseHeaders(e.to_owned()))),
+            Ok((_, parsed)) => parsed,
+        };
+
+        // read body, followed by "\r\n\r\n"
+        let mut body_buffer: Vec<u8> = Vec::with_capacity(expected_body_len + 4);
+        let mut found_body = expected_body_len == 0;
+        let mut body_bytes_read = 0;
+        let maximum_read_range = expected_body_len + 4;
+        while !found_body {
+            let bytes_read = match self.reader.read_until(b'\n', &mut body_buffer) {
+                Err(io) => return Some(Err(warc::Error::ReadData(io))),
+                Ok(len) => len,
+            };
+            body_bytes_read += bytes_read;
+
+            if bytes_read == 2 && body_bytes_read == maximum_read_range {
+                found_body = true;
+            }
+            if bytes_read == 0 {
+                return Some(Err(warc::Error::UnexpectedEOB));
+            }
+            if body_bytes_read > maximum_read_range {
+                return Some(Err(warc::Error::ReadOverflow));
+            }
+        }
+        body_buffer.truncate(expected_body_len);
+
+        let headers = RawRecordHeader {
+            version: version.to_owned(),
+            headers: headers
+                .into_iter()
+                .map(|(token, value)| (token.into(), value.to_owned()))
+                .collect(),
+        };
+        Some(Record::<EmptyBody>::try_from(headers).map(|record| record.add_body(body_buffer)))
     }
 }
 
 #[cfg(test)]
 mod tests {
 
+    use flate2::{write::GzEncoder, Compression};
     use serde_json;
-    use std::collections::HashMap;
-    use warc::WarcHeader;
+    use std::{
+        collections::HashMap,
+        fs::File,
+        io::{Cursor, Write},
+        path::Path,
+    };
+    use warc::{BufferedBody, Record, RecordType, WarcHeader, WarcWriter};
+
+    use super::{Compression as WetCompression, RecordOffsets, Wet};
+    use crate::error::Error;
+
+    fn write_records<W: Write>(w: W) {
+        let mut writer = WarcWriter::new(w);
+        for body in ["foo", "bar"] {
+            let record: Record<BufferedBody> = Record::default().add_body(body);
+            writer.write(&record).unwrap();
+        }
+    }
+
+    fn bodies<T: std::io::BufRead>(shard: Wet<T>) -> Vec<Vec<u8>> {
+        shard.iter.map(|r| r.unwrap().body().to_vec()).collect()
+    }
+
+    fn write_zstd(path: &Path) {
+        let mut enc = zstd::Encoder::new(File::create(path).unwrap(), 0).unwrap();
+        write_records(&mut enc);
+        enc.finish().unwrap();
+    }
+
+    fn write_gzip(path: &Path) {
+        let mut enc = GzEncoder::new(File::create(path).unwrap(), Compression::default());
+        write_records(&mut enc);
+        enc.finish().unwrap();
+    }
+
+    #[test]
+    fn test_from_reader() {
+        let mut buf = Vec::new();
+        write_records(&mut buf);
+
+        let shard = Wet::from_reader(Cursor::new(buf));
+        assert_eq!(bodies(shard), vec![b"foo".to_vec(), b"bar".to_vec()]);
+    }
 
-    use super::Wet;
+    #[test]
+    fn test_range() {
+        let mut buf = Vec::new();
+        write_records(&mut buf);
+
+        let shard = Wet::from_reader(Cursor::new(buf.clone()));
+        let records: Vec<_> = shard.range(1, 5).map(|(i, r)| (i, r.unwrap())).collect();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].0, 1);
+        assert_eq!(records[0].1.body(), b"bar");
+
+        let shard = Wet::from_reader(Cursor::new(buf));
+        assert_eq!(shard.range(1, 0).count(), 0);
+    }
+
+    #[test]
+    fn test_record_offsets() {
+        let mut buf = Vec::new();
+        write_records(&mut buf);
+
+        let records: Vec<_> = RecordOffsets::new(Cursor::new(buf.clone()))
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(records.len(), 2);
+        for (record, range) in records {
+            assert_eq!(&buf[range], record.body());
+        }
+    }
+
+    #[test]
+    fn test_record_offsets_from_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("0.txt.gz");
+        write_gzip(&path);
+        let mut decompressed = Vec::new();
+        write_records(&mut decompressed);
+
+        let records = RecordOffsets::from_path(&path).unwrap();
+        let bodies: Vec<&[u8]> = records.map(|r| &decompressed[r.unwrap().1]).collect();
+        assert_eq!(bodies, vec![&b"foo"[..], &b"bar"[..]]);
+    }
+
+    #[test]
+    fn test_from_reader_gzip() {
+        let mut enc = GzEncoder::new(Vec::new(), Compression::default());
+        write_records(&mut enc);
+        let buf = enc.finish().unwrap();
+
+        let shard = Wet::from_reader_gzip(Cursor::new(buf));
+        assert_eq!(bodies(shard), vec![b"foo".to_vec(), b"bar".to_vec()]);
+    }
+
+    #[test]
+    fn test_from_path_zstd() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("0.txt.zst");
+        write_zstd(&path);
+
+        let shard = Wet::from_path_zstd(&path).unwrap();
+        assert_eq!(bodies(shard), vec![b"foo".to_vec(), b"bar".to_vec()]);
+    }
+
+    #[test]
+    fn test_from_path_dispatch() {
+        let dir = tempfile::tempdir().unwrap();
+        let zst = dir.path().join("0.txt.zst");
+        let gz = dir.path().join("1.txt.gz");
+        write_zstd(&zst);
+        write_gzip(&gz);
+
+        for path in [zst, gz] {
+            let shard = Wet::from_path(&path).unwrap();
+            assert_eq!(bodies(shard), vec![b"foo".to_vec(), b"bar".to_vec()]);
+        }
+    }
+
+    fn write_bzip2(path: &Path) {
+        let mut enc = bzip2::write::BzEncoder::new(
+            File::create(path).unwrap(),
+            bzip2::Compression::default(),
+        );
+        write_records(&mut enc);
+        enc.finish().unwrap();
+    }
+
+    fn write_plain(path: &Path) {
+        write_records(File::create(path).unwrap());
+    }
+
+    #[test]
+    fn test_from_path_dispatch_bzip2_plain() {
+        let dir = tempfile::tempdir().unwrap();
+        let bz2 = dir.path().join("0.txt.bz2");
+        let wet = dir.path().join("1.wet");
+        write_bzip2(&bz2);
+        write_plain(&wet);
+
+        for path in [bz2, wet] {
+            let shard = Wet::from_path(&path).unwrap();
+            assert_eq!(bodies(shard), vec![b"foo".to_vec(), b"bar".to_vec()]);
+        }
+    }
+
+    #[test]
+    fn test_from_path_sniff() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths: Vec<_> = (0..4).map(|i| dir.path().join(i.to_string())).collect();
+        write_gzip(&paths[0]);
+        write_zstd(&paths[1]);
+        write_bzip2(&paths[2]);
+        write_plain(&paths[3]);
+
+        for path in paths {
+            let shard = Wet::from_path(&path).unwrap();
+            assert_eq!(bodies(shard), vec![b"foo".to_vec(), b"bar".to_vec()]);
+        }
+    }
+
+    #[test]
+    fn test_compression_from_magic() {
+        assert_eq!(WetCompression::from_magic(b""), WetCompression::None);
+        assert_eq!(
+            WetCompression::from_magic(b"WARC/1.0"),
+            WetCompression::None
+        );
+        assert_eq!(
+            WetCompression::from_magic(b"BZh91AY"),
+            WetCompression::Bzip2
+        );
+    }
+
+    /// Get the bytes of `nb_records` records, along with the length of the first one.
+    fn records_bytes(nb_records: usize) -> (Vec<u8>, usize) {
+        let mut buf = Vec::new();
+        let mut first_len = 0;
+        for idx in 0..nb_records {
+            let record: Record<BufferedBody> = Record::default().add_body(format!("body {}", idx));
+            WarcWriter::new(&mut buf).write(&record).unwrap();
+            if idx == 0 {
+                first_len = buf.len();
+            }
+        }
+        (buf, first_len)
+    }
+
+    #[test]
+    fn test_records() {
+        let (buf, _) = records_bytes(2);
+        let records: Vec<_> = Wet::from_reader(Cursor::new(buf)).records().collect();
+        assert_eq!(records.len(), 2);
+        assert!(records.iter().all(|r| r.is_ok()));
+    }
+
+    #[test]
+    fn test_records_truncated() {
+        let (mut buf, _) = records_bytes(2);
+        buf.truncate(buf.len() - 5);
+
+        let records: Vec<_> = Wet::from_reader(Cursor::new(buf)).records().collect();
+        assert_eq!(records.len(), 2);
+        assert!(records[0].is_ok());
+        assert!(matches!(records[1], Err(Error::TruncatedRecord(_))));
+    }
+
+    #[test]
+    fn test_records_truncated_header() {
+        let (mut buf, first_len) = records_bytes(2);
+        // cut the second record inside its header block
+        buf.truncate(first_len + 20);
+
+        let records: Vec<_> = Wet::from_reader(Cursor::new(buf)).records().collect();
+        assert_eq!(records.len(), 2);
+        assert!(records[0].is_ok());
+        assert!(matches!(records[1], Err(Error::TruncatedRecord(_))));
+    }
+
+    #[test]
+    fn test_records_truncated_gzip() {
+        let (buf, _) = records_bytes(3);
+        let mut enc = GzEncoder::new(Vec::new(), Compression::default());
+        enc.write_all(&buf).unwrap();
+        let mut gz = enc.finish().unwrap();
+        gz.truncate(gz.len() - 10);
+
+        let records: Vec<_> = Wet::from_reader_gzip(Cursor::new(gz)).records().collect();
+        assert!(matches!(
+            records.last().unwrap(),
+            Err(Error::TruncatedRecord(_))
+        ));
+        assert!(records[..records.len() - 1].iter().all(|r| r.is_ok()));
+    }
+
+    #[test]
+    fn test_records_corrupt() {
+        let (buf, first_len) = records_bytes(3);
+        // make the first record body overflow
+        let mut corrupt = buf[..first_len - 4].to_vec();
+        corrupt.extend_from_slice(b"garbage\r\n\r\n");
+        corrupt.extend_from_slice(&buf[first_len..]);
+
+        let records: Vec<_> = Wet::from_reader(Cursor::new(corrupt)).records().collect();
+        assert!(matches!(records[0], Err(Error::Warc(_))));
+    }
+
+    #[test]
+    fn test_lenient_headers() {
+        let mut buf = b"WARC/1.0\r
+WARC-Type: conversion\r
+WARC-Record-ID: <urn:uuid:0>
+this is not a header\r
+WARC-Date: 2021-02-24T17:02:28Z\r
+warc-type: resource\r
+Content-Length: 5\r
+\r
+hello\r
+\r
+"
+        .to_vec();
+        let (valid, _) = records_bytes(1);
+        buf.extend_from_slice(&valid);
+
+        let records: Vec<_> = Wet::from_reader(Cursor::new(buf.clone()))
+            .records()
+            .collect();
+        assert!(matches!(records[0], Err(Error::Warc(_))));
+
+        let records: Vec<_> = Wet::from_reader(Cursor::new(buf))
+            .with_lenient_headers(true)
+            .records()
+            .collect();
+        assert_eq!(records.len(), 2);
+        let record = records[0].as_ref().unwrap();
+        assert_eq!(record.body(), b"hello");
+        assert_eq!(record.warc_id(), "<urn:uuid:0>");
+        assert_eq!(record.warc_type(), &RecordType::Conversion);
+        assert_eq!(records[1].as_ref().unwrap().body(), b"body 0");
+    }
+
+    #[test]
+    fn test_lenient_headers_lf() {
+        let buf = b"WARC/1.0\nWARC-Type: conversion\nWARC-Record-ID: <urn:uuid:0>\n\
+            WARC-Date: 2021-02-24T17:02:28Z\nContent-Length: 5\n\nhello\r\n\r\n"
+            .to_vec();
+        let mut shard = Wet::from_reader(Cursor::new(buf)).with_lenient_headers(true);
+        assert_eq!(shard.iter.next().unwrap().unwrap().body(), b"hello");
+        assert!(shard.iter.next().is_none());
+    }
+
+    #[test]
+    fn test_lenient_headers_invalid() {
+        // no version line
+        let buf = b"WARC-Type: conversion\r\nContent-Length: 0\r\n\r\n".to_vec();
+        let mut shard = Wet::from_reader(Cursor::new(buf)).with_lenient_headers(true);
+        assert!(matches!(
+            shard.iter.next(),
+            Some(Err(warc::Error::ParseHeaders(_)))
+        ));
+    }
+
+    #[test]
+    fn test_from_path_zstd_invalid() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("0.txt.zst");
+        std::fs::write(&path, b"not zstd").unwrap();
+
+        let mut shard = Wet::from_path(&path).unwrap();
+        assert!(shard.iter.next().unwrap().is_err());
+    }
 
     // #[test]
     // fn test_folder() {
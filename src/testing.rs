@@ -0,0 +1,160 @@
+//! Test utilities.
+//!
+//! Only available with the `testing` feature (and in the crate's own tests).
+//!
+//! [WetBuilder] builds synthetic WET shards, so that tests don't have to
+//! write and compress records by hand.
+use std::{fs::File, io::Write, path::Path};
+
+use flate2::{write::GzEncoder, Compression};
+use warc::{BufferedBody, Record, RecordType, WarcHeader, WarcWriter};
+
+use crate::error::Error;
+
+/// Headers and body of a record.
+type RecordParts = (Vec<(WarcHeader, String)>, Vec<u8>);
+
+/// Builder of gzipped WET shards, readable by [crate::sources::commoncrawl::Wet::from_reader_gzip].
+///
+/// Records are `conversion` records, unless a [WarcHeader::WarcType] header is provided.
+/// Missing mandatory headers (record ID, date) are generated.
+#[derive(Debug, Default, Clone)]
+pub struct WetBuilder {
+    records: Vec<RecordParts>,
+    truncated_tail: usize,
+}
+
+impl WetBuilder {
+    /// Create a builder of an empty shard.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a record of provided headers and body. The body doesn't have to be valid UTF-8.
+    pub fn record<B: Into<Vec<u8>>>(mut self, headers: Vec<(WarcHeader, String)>, body: B) -> Self {
+        self.records.push((headers, body.into()));
+        self
+    }
+
+    /// Add a record of provided body, with default headers.
+    pub fn text(self, body: &str) -> Self {
+        self.record(Vec::new(), body)
+    }
+
+    /// Add records from `(headers, body)` tuples.
+    pub fn records<I, B>(self, records: I) -> Self
+    where
+        I: IntoIterator<Item = (Vec<(WarcHeader, String)>, B)>,
+        B: Into<Vec<u8>>,
+    {
+        records.into_iter().fold(self, |builder, (headers, body)| {
+            builder.record(headers, body)
+        })
+    }
+
+    /// Cut the last `nb_bytes` bytes of the (uncompressed) shard, as an interrupted download would.
+    pub fn with_truncated_tail(mut self, nb_bytes: usize) -> Self {
+        self.truncated_tail = nb_bytes;
+        self
+    }
+
+    /// Build the uncompressed shard.
+    ///
+    /// # Errors
+    /// Returns an error if a header value is invalid (ex. a malformed date).
+    pub fn build_uncompressed(&self) -> Result<Vec<u8>, Error> {
+        let mut buf = Vec::new();
+        {
+            let mut writer = WarcWriter::new(&mut buf);
+            for (headers, body) in &self.records {
+                let mut record: Record<BufferedBody> = Record::default().add_body(body.clone());
+                record.set_warc_type(RecordType::Conversion);
+                for (header, value) in headers {
+                    record.set_header(header.clone(), value.as_str())?;
+                }
+                writer.write(&record)?;
+            }
+        }
+        buf.truncate(buf.len().saturating_sub(self.truncated_tail));
+        Ok(buf)
+    }
+
+    /// Build the gzipped shard.
+    ///
+    /// # Errors
+    /// Returns an error if a header value is invalid (ex. a malformed date).
+    pub fn build(&self) -> Result<Vec<u8>, Error> {
+        let mut enc = GzEncoder::new(Vec::new(), Compression::default());
+        enc.write_all(&self.build_uncompressed()?)?;
+        Ok(enc.finish()?)
+    }
+
+    /// Write the gzipped shard at `path` (ex. `0.txt.gz`).
+    ///
+    /// # Errors
+    /// Returns an error if a header value is invalid or if the file can't be written.
+    pub fn write(&self, path: &Path) -> Result<(), Error> {
+        File::create(path)?.write_all(&self.build()?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use warc::WarcHeader;
+
+    use super::WetBuilder;
+    use crate::error::Error;
+    use crate::sources::commoncrawl::Wet;
+
+    #[test]
+    fn build() {
+        let shard = WetBuilder::new()
+            .record(
+                vec![(WarcHeader::TargetURI, "https://example.com".to_string())],
+                "hello",
+            )
+            .record(Vec::new(), vec![0xff, 0xfe, b'\n', b'a'])
+            .text("world")
+            .build()
+            .unwrap();
+
+        let records: Vec<_> = Wet::from_reader_gzip(shard.as_slice())
+            .records()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(records.len(), 3);
+        assert_eq!(
+            records[0].header(WarcHeader::TargetURI).unwrap(),
+            "https://example.com"
+        );
+        assert_eq!(
+            records[0].header(WarcHeader::WarcType).unwrap(),
+            "conversion"
+        );
+        assert_eq!(records[0].body(), b"hello");
+        assert_eq!(records[1].body(), &[0xff, 0xfe, b'\n', b'a']);
+        assert_eq!(records[2].body(), b"world");
+    }
+
+    #[test]
+    fn truncated_tail() {
+        let shard = WetBuilder::new()
+            .records(vec![(Vec::new(), "hello"), (Vec::new(), "world")])
+            .with_truncated_tail(3)
+            .build()
+            .unwrap();
+
+        let records: Vec<_> = Wet::from_reader_gzip(shard.as_slice()).records().collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].as_ref().unwrap().body(), b"hello");
+        assert!(matches!(records[1], Err(Error::TruncatedRecord(_))));
+    }
+
+    #[test]
+    fn invalid_header() {
+        let builder =
+            WetBuilder::new().record(vec![(WarcHeader::Date, "yesterday".to_string())], "");
+        assert!(builder.build().is_err());
+    }
+}
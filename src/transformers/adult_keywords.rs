@@ -0,0 +1,138 @@
+/*! Adult content keyword annotator
+
+Annotator that flags documents containing too many words from a provided keyword list, adding an `adult` annotation.
+This complements [super::ContentDetector], which only relies on the document URL.
+!*/
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use crate::error::Error;
+use crate::pipelines::oscardoc::types::Document;
+
+use super::Annotate;
+
+/// Adult content keyword annotator.
+///
+/// Words are lowercased and split on non-alphanumeric characters before being looked up.
+pub struct AdultKeywords {
+    keywords: HashSet<String>,
+    threshold: f64,
+}
+
+impl AdultKeywords {
+    /// New [AdultKeywords] annotator.
+    ///
+    /// - `keywords`: words that are considered adult content (matched lowercased).
+    /// - `threshold`: minimum ratio of keywords over the total number of words to flag the document.
+    pub fn new(keywords: HashSet<String>, threshold: f64) -> Self {
+        let keywords = keywords.into_iter().map(|k| k.to_lowercase()).collect();
+        Self {
+            keywords,
+            threshold,
+        }
+    }
+
+    /// Read keywords from a file (one keyword per line, empty lines are ignored).
+    pub fn from_path(path: &Path, threshold: f64) -> Result<Self, Error> {
+        let f = BufReader::new(File::open(path)?);
+        let lines = f.lines().collect::<Result<Vec<String>, std::io::Error>>()?;
+        let keywords = lines
+            .iter()
+            .map(|l| l.trim())
+            .filter(|l| !l.is_empty())
+            .map(String::from)
+            .collect();
+
+        Ok(Self::new(keywords, threshold))
+    }
+}
+
+impl Annotate for AdultKeywords {
+    fn annotate(&self, doc: &mut Document) {
+        // don't tag twice if another annotator already flagged the document
+        if doc
+            .metadata()
+            .annotation()
+            .is_some_and(|a| a.iter().any(|tag| tag == "adult"))
+        {
+            return;
+        }
+
+        let content = doc.content().to_lowercase();
+        let words = content
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|w| !w.is_empty());
+
+        let mut nb_words = 0;
+        let mut nb_keywords = 0;
+        for word in words {
+            nb_words += 1;
+            if self.keywords.contains(word) {
+                nb_keywords += 1;
+            }
+        }
+
+        if nb_keywords > 0 && nb_keywords as f64 / nb_words as f64 >= self.threshold {
+            doc.metadata_mut().set_annotation("adult".to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+    use std::io::Write;
+
+    use crate::{
+        pipelines::oscardoc::types::{Document, Metadata},
+        transformers::Annotate,
+    };
+
+    use super::AdultKeywords;
+
+    fn annotator(threshold: f64) -> AdultKeywords {
+        let keywords: HashSet<String> = vec!["foo".to_string(), "Bar".to_string()]
+            .into_iter()
+            .collect();
+        AdultKeywords::new(keywords, threshold)
+    }
+
+    #[test]
+    fn test_annotation() {
+        let content = "this document talks about FOO.\nand bar, a lot".to_string();
+        let mut d = Document::new(content, HashMap::new(), Metadata::default());
+        annotator(0.2).annotate(&mut d);
+
+        assert_eq!(d.metadata().annotation(), Some(&vec!["adult".to_string()]));
+    }
+
+    #[test]
+    fn test_no_annotation() {
+        let content = "this document talks about foo once, in many many words".to_string();
+        let mut d = Document::new(content, HashMap::new(), Metadata::default());
+        annotator(0.2).annotate(&mut d);
+
+        assert_eq!(d.metadata().annotation(), None);
+    }
+
+    #[test]
+    fn test_no_duplicate_annotation() {
+        let mut d = Document::new("foo bar".to_string(), HashMap::new(), Metadata::default());
+        d.metadata_mut().set_annotation("adult".to_string());
+        annotator(0.2).annotate(&mut d);
+
+        assert_eq!(d.metadata().annotation(), Some(&vec!["adult".to_string()]));
+    }
+
+    #[test]
+    fn test_from_path() {
+        let mut f = tempfile::NamedTempFile::new().unwrap();
+        writeln!(f, "foo\n\n  bar  ").unwrap();
+        let a = AdultKeywords::from_path(f.path(), 0.5).unwrap();
+
+        assert_eq!(a.keywords.len(), 2);
+        assert!(a.keywords.contains("bar"));
+    }
+}
@@ -0,0 +1,88 @@
+/*! Duplicated content annotator
+
+Annotator that flags documents where too many lines are repeated, adding a `duplicated` annotation.
+!*/
+use std::collections::HashSet;
+
+use crate::pipelines::oscardoc::types::Document;
+
+use super::Annotate;
+
+/// Duplicated lines annotator.
+pub struct Duplicates {
+    threshold: f64,
+}
+
+impl Duplicates {
+    /// New [Duplicates] annotator.
+    ///
+    /// `threshold` is the minimum ratio of duplicated lines (lines that already appeared in the document)
+    /// over the total number of (non-empty) lines to flag the document.
+    pub fn new(threshold: f64) -> Self {
+        Self { threshold }
+    }
+}
+
+impl Default for Duplicates {
+    /// Default value is flagging if >=50% of the lines are duplicates.
+    fn default() -> Self {
+        Self { threshold: 0.5 }
+    }
+}
+
+impl Annotate for Duplicates {
+    fn annotate(&self, doc: &mut Document) {
+        let mut seen = HashSet::new();
+        let mut nb_lines = 0;
+        let mut nb_duplicates = 0;
+
+        for line in doc
+            .content()
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty())
+        {
+            nb_lines += 1;
+            if !seen.insert(line) {
+                nb_duplicates += 1;
+            }
+        }
+
+        if nb_duplicates > 0 && nb_duplicates as f64 / nb_lines as f64 >= self.threshold {
+            doc.metadata_mut().set_annotation("duplicated".to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::{
+        pipelines::oscardoc::types::{Document, Metadata},
+        transformers::Annotate,
+    };
+
+    use super::Duplicates;
+
+    #[test]
+    fn test_annotation() {
+        let content = "accept cookies\nhello\naccept cookies\naccept cookies".to_string();
+        let mut d = Document::new(content, HashMap::new(), Metadata::default());
+        Duplicates::default().annotate(&mut d);
+
+        assert_eq!(
+            d.metadata().annotation(),
+            Some(&vec!["duplicated".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_no_annotation() {
+        let content = "accept cookies\nhello\nworld\naccept cookies".to_string();
+        let mut d = Document::new(content, HashMap::new(), Metadata::default());
+        Duplicates::default().annotate(&mut d);
+
+        assert_eq!(d.metadata().annotation(), None);
+    }
+}
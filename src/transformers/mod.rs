@@ -7,17 +7,21 @@ Transformers can either (or both) [Annotate] content or [Transform] it:
   It (for now) should only remove sentences without altering them.
 !*/
 
+mod adult_keywords;
 mod annotate;
 mod content_detector;
+mod duplicates;
 mod header;
 mod sentence_filter;
 mod tiny;
 mod transform;
 
 mod noisy;
+pub use adult_keywords::AdultKeywords;
 pub use annotate::Annotate;
 pub use annotate::Annotator;
 pub use content_detector::ContentDetector;
+pub use duplicates::Duplicates;
 pub use header::Header;
 pub use noisy::Noisy;
 pub use sentence_filter::Conv;
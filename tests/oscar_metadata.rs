@@ -25,7 +25,7 @@ fn pipeline_no_folders() {
     let dst = PathBuf::from("fzjoijzoecijzoiej");
     let lid_path = PathBuf::from("lid.176.bin");
 
-    let p = OscarMetadata::new(src, dst, lid_path);
+    let p = OscarMetadata::new(vec![src], dst, lid_path, 1, None);
     assert!(p.run().is_err());
 }
 
@@ -91,7 +91,7 @@ fn assert_meta_final_offset() {
         .expect("ensure to have a folder named result_1 containing 0.txt.gz as test shard.");
     let lid_path = PathBuf::from("lid.176.bin");
 
-    let p = OscarMetadata::new(src.clone(), dst.clone(), lid_path);
+    let p = OscarMetadata::new(vec![src.clone()], dst.clone(), lid_path, 1, None);
     p.run().unwrap();
 
     for lang in LANG.iter() {
@@ -142,7 +142,7 @@ fn assert_meta_successive_offsets() {
         .expect("ensure to have a folder named result_1 containing 0.txt.gz as test shard.");
     let lid_path = PathBuf::from("lid.176.bin");
 
-    let p = OscarMetadata::new(src.clone(), dst.clone(), lid_path);
+    let p = OscarMetadata::new(vec![src.clone()], dst.clone(), lid_path, 1, None);
     p.run().unwrap();
 
     for lang in LANG.iter() {
@@ -193,7 +193,7 @@ fn assert_meta_validity() {
         .expect("ensure to have a folder named result_1 containing 0.txt.gz as test shard.");
     let lid_path = PathBuf::from("lid.176.bin");
 
-    let p = OscarMetadata::new(src.clone(), dst.clone(), lid_path);
+    let p = OscarMetadata::new(vec![src.clone()], dst.clone(), lid_path, 1, None);
     p.run().unwrap();
 
     // get data and metadata from shard
@@ -304,7 +304,7 @@ fn assert_meta_final_offset_multishard() {
     gen_test_shards(&src_gen, &src)
         .expect("ensure to have a folder named result_1 containing 0.txt.gz as test shard.");
     let lid_path = PathBuf::from("lid.176.bin");
-    let p = OscarMetadata::new(src.clone(), dst.clone(), lid_path);
+    let p = OscarMetadata::new(vec![src.clone()], dst.clone(), lid_path, 1, None);
     p.run().unwrap();
 
     for lang in LANG.iter() {
@@ -361,7 +361,7 @@ fn assert_meta_successive_offsets_multishard() {
     gen_test_shards(&src_gen, &src)
         .expect("ensure to have a folder named result_1 containing 0.txt.gz as test shard.");
     let lid_path = PathBuf::from("lid.176.bin");
-    let p = OscarMetadata::new(src.clone(), dst.clone(), lid_path);
+    let p = OscarMetadata::new(vec![src.clone()], dst.clone(), lid_path, 1, None);
     p.run().unwrap();
 
     for lang in LANG.iter() {
@@ -410,7 +410,7 @@ fn assert_meta_validity_multishard() {
     gen_test_shards(&src_gen, &src)
         .expect("ensure to have a folder named result_5 containing 0.txt.gz as test shard.");
     let lid_path = PathBuf::from("lid.176.bin");
-    let p = OscarMetadata::new(src.clone(), dst.clone(), lid_path);
+    let p = OscarMetadata::new(vec![src.clone()], dst.clone(), lid_path, 1, None);
     p.run().unwrap();
 
     let mut record_index = HashMap::new();
@@ -468,7 +468,7 @@ fn pipeline_single_shard() {
     let dst = PathBuf::from("temp_1/");
 
     let lid_path = PathBuf::from("lid.176.bin");
-    let p = OscarMetadata::new(src.clone(), dst.clone(), lid_path);
+    let p = OscarMetadata::new(vec![src.clone()], dst.clone(), lid_path, 1, None);
     let res = p.run();
     assert!(res.is_ok());
 
@@ -0,0 +1,293 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use fasttext::Prediction;
+use ungoliant::error::Error;
+use ungoliant::identifiers::LanguageIdentifier;
+use ungoliant::pipelines::oscardoc::types::{Document, RebuildLayout, RebuildReader};
+use ungoliant::pipelines::url_policy::StripQuery;
+use ungoliant::pipelines::{OscarDoc, Pipeline};
+use ungoliant::sources::commoncrawl::Wet;
+use ungoliant::testing::WetBuilder;
+use warc::WarcHeader;
+
+/// identifies lines holding "bonjour" as french, and every other line as english.
+struct Bonjour;
+
+impl LanguageIdentifier for Bonjour {
+    fn predict(&self, text: &str) -> Result<Option<Vec<Prediction>>, Error> {
+        let label = if text.contains("bonjour") { "fr" } else { "en" };
+        Ok(Some(vec![Prediction {
+            prob: 0.9,
+            label: label.to_string(),
+        }]))
+    }
+}
+
+/// a line that is long enough to be kept by the pipeline.
+fn long_line(word: &str, idx: usize) -> String {
+    format!(
+        "{} {} {}",
+        word,
+        idx,
+        "lorem ipsum dolor sit amet ".repeat(5)
+    )
+}
+
+fn long_lines(word: &str, nb: usize) -> Vec<String> {
+    (0..nb).map(|idx| long_line(word, idx)).collect()
+}
+
+/// read the documents written for `lang`, by record id.
+fn written_documents(dst: &Path, lang: &str) -> HashMap<String, Document> {
+    let f = File::open(dst.join(format!("{}_meta.jsonl", lang))).unwrap();
+    BufReader::new(f)
+        .lines()
+        .map(|line| {
+            let document: Document = serde_json::from_str(&line.unwrap()).unwrap();
+            (document.warc_id().to_string(), document)
+        })
+        .collect()
+}
+
+#[test]
+fn rebuild_round_trip() {
+    let src = tempfile::tempdir().unwrap();
+    let dst = tempfile::tempdir().unwrap();
+
+    // short lines at the start and the end of a body are not kept
+    let trimmed = format!("menu\n{}\nfooter\n", long_lines("hello", 6).join("\n"));
+    let french = long_lines("bonjour", 5).join("\n");
+    let short = "too\nshort\nto\nbe\nkept";
+    let whole = long_lines("world", 7).join("\n");
+    let shard = WetBuilder::new()
+        .text(&trimmed)
+        .text(&french)
+        .text(short)
+        .text(&whole);
+    let shard_path = src.path().join("0.txt.gz");
+    shard.write(&shard_path).unwrap();
+
+    let p = OscarDoc::new(
+        vec![src.path().to_path_buf()],
+        dst.path().to_path_buf(),
+        PathBuf::new(),
+        None,
+    )
+    .with_identifier(Arc::new(Bonjour));
+    p.run().unwrap();
+
+    let records: Vec<_> = Wet::from_path_gzip(&shard_path)
+        .unwrap()
+        .iter
+        .collect::<Result<_, _>>()
+        .unwrap();
+    let uncompressed = shard.build_uncompressed().unwrap();
+
+    let mut nb_documents = HashMap::new();
+    for lang in ["en", "fr"] {
+        let documents = written_documents(dst.path(), lang);
+        let rebuild_path = dst.path().join("rebuild").join(format!("{}.avro", lang));
+        let rebuild_info: Vec<_> = RebuildReader::from_path(&rebuild_path)
+            .unwrap()
+            .rebuild_info()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(rebuild_info.len(), documents.len());
+
+        for rb_info in &rebuild_info {
+            assert_eq!(rb_info.shard_id(), 0);
+            let record = &records[rb_info.loc_in_shard()];
+            assert_eq!(record.warc_id(), rb_info.record_id());
+
+            let document = &documents[rb_info.record_id()];
+            let body = String::from_utf8_lossy(record.body());
+            assert_eq!(&rb_info.extract_lines(&body), document.content());
+
+            // byte offsets point to the same text in the decompressed shard
+            let (start, end) = (rb_info.byte_start().unwrap(), rb_info.byte_end().unwrap());
+            assert_eq!(
+                String::from_utf8_lossy(&uncompressed[start..end]),
+                document.content().as_str()
+            );
+        }
+        nb_documents.insert(lang, documents.len());
+    }
+
+    assert_eq!(nb_documents["en"], 2);
+    assert_eq!(nb_documents["fr"], 1);
+    // trimmed lines are not part of the rebuilt text
+    let en = written_documents(dst.path(), "en");
+    let trimmed_doc = &en[records[0].warc_id()];
+    assert_eq!(trimmed_doc.content(), &long_lines("hello", 6).join("\n"));
+}
+
+#[test]
+fn rebuild_per_shard() {
+    let src = tempfile::tempdir().unwrap();
+    let dst = tempfile::tempdir().unwrap();
+
+    let english = long_lines("hello", 3).join("\n");
+    let french = long_lines("bonjour", 3).join("\n");
+    for shard_id in 0..2 {
+        WetBuilder::new()
+            .text(&english)
+            .text(&french)
+            .write(&src.path().join(format!("{}.txt.gz", shard_id)))
+            .unwrap();
+    }
+
+    let p = OscarDoc::new(
+        vec![src.path().to_path_buf()],
+        dst.path().to_path_buf(),
+        PathBuf::new(),
+        None,
+    )
+    .with_identifier(Arc::new(Bonjour))
+    .with_rebuild_layout(RebuildLayout::PerShard);
+    p.run().unwrap();
+
+    let rebuild_dst = dst.path().join("rebuild");
+    assert!(!rebuild_dst.join("en.avro").exists());
+    for shard_id in 0..2u64 {
+        let rebuild_path = rebuild_dst.join(format!("shard_{}.avro", shard_id));
+        let rebuild_info: Vec<_> = RebuildReader::from_path(&rebuild_path)
+            .unwrap()
+            .rebuild_info()
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        // one document of each language, sorted by language
+        let labels: Vec<_> = rebuild_info
+            .iter()
+            .map(|rb_info| rb_info.metadata().identification().label().to_string())
+            .collect();
+        assert_eq!(labels, vec!["en", "fr"]);
+        assert!(rebuild_info
+            .iter()
+            .all(|rb_info| rb_info.shard_id() == shard_id));
+    }
+}
+
+#[test]
+fn rebuild_extra_metadata() {
+    let src = tempfile::tempdir().unwrap();
+    let dst = tempfile::tempdir().unwrap();
+
+    let header = WarcHeader::Unknown("x-source-metadata".to_string());
+    let shard = WetBuilder::new()
+        .record(
+            vec![(header, r#"{"source": "forum"}"#.to_string())],
+            long_lines("hello", 3).join("\n"),
+        )
+        .text(&long_lines("world", 3).join("\n"));
+    shard.write(&src.path().join("0.txt.gz")).unwrap();
+
+    let p = OscarDoc::new(
+        vec![src.path().to_path_buf()],
+        dst.path().to_path_buf(),
+        PathBuf::new(),
+        None,
+    )
+    .with_identifier(Arc::new(Bonjour))
+    .with_extra_header(Some("X-Source-Metadata"));
+    p.run().unwrap();
+
+    let documents = written_documents(dst.path(), "en");
+    let rebuild_path = dst.path().join("rebuild").join("en.avro");
+    let rebuild_info: Vec<_> = RebuildReader::from_path(&rebuild_path)
+        .unwrap()
+        .rebuild_info()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(rebuild_info.len(), 2);
+    for rb_info in &rebuild_info {
+        let extra = documents[rb_info.record_id()].metadata().extra();
+        assert_eq!(rb_info.metadata().extra(), extra);
+        let expected = (rb_info.loc_in_shard() == 0).then_some(r#"{"source": "forum"}"#);
+        assert_eq!(extra, expected);
+    }
+}
+
+#[test]
+fn rebuild_multiple_sources() {
+    let sources: Vec<_> = (0..2).map(|_| tempfile::tempdir().unwrap()).collect();
+    let english = long_lines("hello", 3).join("\n");
+    for (shard_id, src) in sources.iter().enumerate() {
+        WetBuilder::new()
+            .text(&english)
+            .write(&src.path().join(format!("{}.txt.gz", shard_id)))
+            .unwrap();
+    }
+
+    let run = |dst: &Path| {
+        OscarDoc::new(
+            sources.iter().map(|src| src.path().to_path_buf()).collect(),
+            dst.to_path_buf(),
+            PathBuf::new(),
+            None,
+        )
+        .with_identifier(Arc::new(Bonjour))
+        .run()
+    };
+    let dst = tempfile::tempdir().unwrap();
+    run(dst.path()).unwrap();
+
+    let rebuild_path = dst.path().join("rebuild").join("en.avro");
+    let mut shard_ids: Vec<_> = RebuildReader::from_path(&rebuild_path)
+        .unwrap()
+        .rebuild_info()
+        .map(|rb_info| rb_info.unwrap().shard_id())
+        .collect();
+    shard_ids.sort_unstable();
+    assert_eq!(shard_ids, vec![0, 1]);
+    assert_eq!(written_documents(dst.path(), "en").len(), 2);
+
+    // shards of different sources can't share a number
+    WetBuilder::new()
+        .text(&english)
+        .write(&sources[1].path().join("0.txt.gz"))
+        .unwrap();
+    let dst = tempfile::tempdir().unwrap();
+    let err = run(dst.path()).unwrap_err();
+    assert!(err.to_string().contains("same number"), "{}", err);
+}
+
+#[test]
+fn rebuild_url_policy() {
+    let src = tempfile::tempdir().unwrap();
+    let dst = tempfile::tempdir().unwrap();
+
+    WetBuilder::new()
+        .record(
+            vec![(
+                WarcHeader::TargetURI,
+                "https://example.com/page?user=me".to_string(),
+            )],
+            long_lines("hello", 3).join("\n"),
+        )
+        .write(&src.path().join("0.txt.gz"))
+        .unwrap();
+
+    let p = OscarDoc::new(
+        vec![src.path().to_path_buf()],
+        dst.path().to_path_buf(),
+        PathBuf::new(),
+        None,
+    )
+    .with_identifier(Arc::new(Bonjour))
+    .with_url_policy(Some(Arc::new(StripQuery)));
+    p.run().unwrap();
+
+    let documents = written_documents(dst.path(), "en");
+    assert_eq!(documents.len(), 1);
+    for document in documents.values() {
+        assert_eq!(
+            document.warc_headers()[&WarcHeader::TargetURI],
+            b"https://example.com/page"
+        );
+    }
+}
@@ -2,8 +2,7 @@ use std::collections::HashMap;
 use std::path::Path;
 
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
-use ungoliant::io::writer::WriterTrait;
-use ungoliant::io::LangFiles;
+use ungoliant::io::{FileNaming, LangFiles, LayoutStrategy, OutputFormat};
 use ungoliant::pipelines::oscarmeta::types::MergedPiece;
 use warc::WarcHeader;
 
@@ -57,7 +56,15 @@ fn french_mergedparts(nb: i32) -> Vec<MergedPiece> {
 fn single_lang() {
     let dst = Path::new("intg_single_lang_monothread");
     std::fs::create_dir(dst).unwrap();
-    let langfiles = LangFiles::new(dst, Some(1000)).unwrap();
+    let langfiles = LangFiles::new(
+        dst,
+        Some(1000),
+        OutputFormat::TextMeta,
+        None,
+        LayoutStrategy::Flat,
+        &FileNaming::default(),
+    )
+    .unwrap();
 
     let parts = english_mergedparts(10).into_par_iter();
     println!("{:#?}", parts);
@@ -73,7 +80,15 @@ fn single_lang() {
 fn multiple_langs() {
     let dst = Path::new("intg_multiple_langs");
     std::fs::create_dir(dst).unwrap();
-    let langfiles = LangFiles::new(dst, Some(1000)).unwrap();
+    let langfiles = LangFiles::new(
+        dst,
+        Some(1000),
+        OutputFormat::TextMeta,
+        None,
+        LayoutStrategy::Flat,
+        &FileNaming::default(),
+    )
+    .unwrap();
 
     // assume they are shuffled
     let mut parts = english_mergedparts(10);